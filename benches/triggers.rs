@@ -0,0 +1,135 @@
+//! Trigger latency and throughput: how long a due step takes to reach its
+//! instrument through the dispatcher, what the scheduler spends per tick
+//! at different pattern densities, and how long starting a voice takes.
+//!
+//! Run with `cargo bench`; compare against a saved baseline with
+//! `cargo bench -- --save-baseline main` and `--baseline main`.
+
+use std::{
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use four_on_the_floor::{
+    audio::{self, VoiceParams},
+    dispatch::{Dispatcher, TriggerJob},
+    instrument::{Instrument, Playback},
+    meter::LevelMeter,
+    model::{Pattern, PatternBuilder, Ticks, PPQN},
+    Events, TICK,
+};
+
+/// Hits fired on the same tick, from a single kick up to a dense kit.
+const DENSITIES: [usize; 4] = [1, 4, 16, 64];
+
+/// Reports when it fires instead of making a sound.
+struct Probe {
+    fired: Mutex<Sender<Instant>>,
+}
+
+impl Instrument for Probe {
+    fn trigger(&self, _note: u8, _velocity: f32, _duration: f32, _playback: &Playback) {
+        let _ = self.fired.lock().unwrap().send(Instant::now());
+    }
+}
+
+fn job(instrument: &Arc<dyn Instrument>) -> TriggerJob {
+    TriggerJob {
+        instrument: Arc::clone(instrument),
+        track: "bench".to_string(),
+        note: 60,
+        velocity: 100.0,
+        duration: 0.25,
+        playback: Playback { pan: 0.0, meter: Arc::new(LevelMeter::default()), bpm: 120 },
+        offset_secs: 0.0,
+        ratchet: 1,
+        interval_secs: 0.0,
+    }
+}
+
+/// Time from dispatching a tick's hits to the last of them firing.
+fn dispatch_latency(c: &mut Criterion) {
+    let dispatcher = Dispatcher::new(Arc::new(Events::default()), 4, 16);
+    let (fired, on_fire) = channel();
+    let probe: Arc<dyn Instrument> = Arc::new(Probe { fired: Mutex::new(fired) });
+
+    let mut group = c.benchmark_group("dispatch_latency");
+    for density in DENSITIES {
+        group.throughput(Throughput::Elements(density as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(density), &density, |b, &density| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let due = Instant::now();
+                    for _ in 0..density {
+                        dispatcher.dispatch(job(&probe));
+                    }
+                    let last = (0..density).map(|_| on_fire.recv().unwrap()).max().unwrap();
+                    total += last - due;
+                }
+                total
+            });
+        });
+    }
+    group.finish();
+}
+
+fn patterns(count: usize) -> Vec<Pattern> {
+    (0..count)
+        .map(|n| {
+            PatternBuilder::new()
+                .sound(&format!("sound{}", n))
+                .beats((0..16).map(|step| step as f32 * 0.25).collect())
+                .build()
+        })
+        .collect()
+}
+
+/// Scheduler work per tick: finding the due steps of every pattern and
+/// their step settings, without playing them.
+fn tick_planning(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick_planning");
+    for density in DENSITIES {
+        let patterns = patterns(density);
+        group.throughput(Throughput::Elements(density as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(density), &patterns, |b, patterns| {
+            let mut position = Ticks(0);
+            b.iter(|| {
+                position = Ticks((position.0 + TICK.0) % (4 * PPQN));
+                patterns
+                    .iter()
+                    .filter(|pattern| pattern.is_enabled("", 0, false))
+                    .flat_map(|pattern| pattern.due_steps(0, position, TICK, 4))
+                    .count()
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Time to start a voice on the output, up to the sink playing it.
+fn voice_start(c: &mut Criterion) {
+    if !cfg!(feature = "audio") {
+        eprintln!("voice_start: built without audio, skipped");
+        return;
+    }
+    let Ok((_stream, stream_handle)) = audio::open_output_stream(None) else {
+        eprintln!("voice_start: no audio output, skipped");
+        return;
+    };
+    // 100 ms of stereo silence at 44.1 kHz, a typical one-shot
+    let samples = vec![0i16; 8820];
+    c.bench_function("voice_start", |b| {
+        b.iter(|| {
+            let params = VoiceParams { gain: 1.0, speed: 1.0, pan: Some(0.0), limit: None, fader: None };
+            audio::start_voice(&stream_handle, samples.clone(), 2, 44100, params, None).stop();
+        });
+    });
+}
+
+criterion_group!(benches, dispatch_latency, tick_planning, voice_start);
+criterion_main!(benches);
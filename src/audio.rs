@@ -0,0 +1,369 @@
+//! Audio output. The `audio` feature plays through rodio on the system's
+//! default cpal host, or on JACK with the `jack` feature; without it voices
+//! are dropped, leaving MIDI output and offline rendering.
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::meter::LevelMeter;
+
+#[cfg(feature = "audio")]
+pub use rodio::{OutputStream, Sink};
+#[cfg(not(feature = "audio"))]
+pub use silent::{OutputStream, OutputStreamHandle, Sink};
+
+#[cfg(feature = "audio")]
+use rodio::{
+    cpal::traits::{DeviceTrait, HostTrait},
+    dynamic_mixer::{DynamicMixer, DynamicMixerController},
+    source::ChannelVolume,
+    Source,
+};
+
+#[cfg(feature = "audio")]
+use crate::diagnostics::{Metered, DIAGNOSTICS};
+#[cfg(feature = "audio")]
+use crate::meter::MeterTap;
+#[cfg(feature = "audio")]
+use crate::mixer::pan_volumes;
+
+/// Where voices are played: an opened output stream, or nowhere for
+/// engines running without a sound card.
+#[cfg(feature = "audio")]
+pub struct OutputStreamHandle(Option<rodio::OutputStreamHandle>);
+
+#[cfg(feature = "audio")]
+impl OutputStreamHandle {
+    /// A handle that drops every voice, without opening a device.
+    pub fn silent() -> Self {
+        OutputStreamHandle(None)
+    }
+}
+
+/// Stand-ins for the rodio output types in builds without audio.
+#[cfg(not(feature = "audio"))]
+mod silent {
+    pub struct OutputStream;
+
+    pub struct OutputStreamHandle;
+
+    impl OutputStreamHandle {
+        pub fn silent() -> Self {
+            OutputStreamHandle
+        }
+    }
+
+    pub struct Sink;
+
+    impl Sink {
+        pub fn stop(&self) {}
+
+        pub fn detach(self) {}
+    }
+}
+
+/// Level applied to every voice as f32 bits, faded out when playback stops.
+static MASTER_GAIN: AtomicU32 = AtomicU32::new(0x3f80_0000); // 1.0
+
+pub fn master_gain() -> f32 {
+    f32::from_bits(MASTER_GAIN.load(Ordering::Relaxed))
+}
+
+pub fn set_master_gain(gain: f32) {
+    MASTER_GAIN.store(gain.to_bits(), Ordering::Relaxed);
+}
+
+/// Mix every voice and click goes through on the way to the output, and
+/// the meter reading it; None until an output is opened.
+#[cfg(feature = "audio")]
+static MASTER_BUS: std::sync::Mutex<Option<MasterBus>> = std::sync::Mutex::new(None);
+
+#[cfg(feature = "audio")]
+struct MasterBus {
+    controller: Arc<DynamicMixerController<f32>>,
+    meter: Arc<LevelMeter>,
+}
+
+/// (peak, rms) of the summed output since the previous call, after the
+/// master gain and faders.
+pub fn take_master_levels() -> (f32, f32) {
+    #[cfg(feature = "audio")]
+    if let Some(bus) = MASTER_BUS.lock().unwrap().as_ref() {
+        return bus.meter.take();
+    }
+    (0.0, 0.0)
+}
+
+/// Plays `source` through the master bus, or straight on the stream before
+/// one is opened. Silent handles play nothing.
+#[cfg(feature = "audio")]
+fn play_on_bus(stream_handle: &OutputStreamHandle, source: impl Source<Item = i16> + Send + 'static) -> Result<Sink, rodio::PlayError> {
+    let Some(stream_handle) = &stream_handle.0 else {
+        return Ok(Sink::new_idle().0);
+    };
+    let Some(controller) = MASTER_BUS.lock().unwrap().as_ref().map(|bus| Arc::clone(&bus.controller)) else {
+        let sink = Sink::try_new(stream_handle)?;
+        sink.append(source);
+        return Ok(sink);
+    };
+    let (sink, queue) = Sink::new_idle();
+    sink.append(source);
+    controller.add(queue);
+    Ok(sink)
+}
+
+/// Output end of the master bus: plays silence while no voice sounds, so
+/// it stays on the stream.
+#[cfg(feature = "audio")]
+struct BusOutput(DynamicMixer<f32>);
+
+#[cfg(feature = "audio")]
+impl Iterator for BusOutput {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.0.next().unwrap_or(0.0))
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Source for BusOutput {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.0.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Puts a master bus with the device's layout on the stream and sends the
+/// voices that follow through it.
+#[cfg(feature = "audio")]
+fn open_master_bus(stream_handle: &rodio::OutputStreamHandle, channels: u16, sample_rate: u32) -> Result<(), rodio::PlayError> {
+    let (controller, mixer) = rodio::dynamic_mixer::mixer::<f32>(channels, sample_rate);
+    let meter = Arc::new(LevelMeter::default());
+    let tapped = MeterTap::new(BusOutput(mixer).convert_samples::<i16>(), Arc::clone(&meter));
+    stream_handle.play_raw(tapped.convert_samples())?;
+    *MASTER_BUS.lock().unwrap() = Some(MasterBus { controller, meter });
+    Ok(())
+}
+
+/// Ramps the master gain down to silence over `duration`, blocking meanwhile.
+pub fn fade_out(duration: Duration) {
+    const STEPS: u32 = 50;
+    let start = master_gain();
+    for step in 1..=STEPS {
+        thread::sleep(duration / STEPS);
+        set_master_gain(start * (1.0 - step as f32 / STEPS as f32));
+    }
+}
+
+/// A gain shared by a group of voices, such as the loops of a scene, and
+/// ramped while they play.
+pub struct Fader(AtomicU32);
+
+impl Fader {
+    pub fn new(gain: f32) -> Self {
+        Fader(AtomicU32::new(gain.to_bits()))
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Ramps `outgoing` down to silence and `incoming` up to full gain over
+/// `duration`, blocking meanwhile.
+pub fn crossfade(outgoing: &Fader, incoming: &Fader, duration: Duration) {
+    const STEPS: u32 = 50;
+    for step in 1..=STEPS {
+        thread::sleep(duration / STEPS);
+        let position = step as f32 / STEPS as f32;
+        outgoing.set(1.0 - position);
+        incoming.set(position);
+    }
+}
+
+/// Scales a source by the master gain and its fader, read per sample so
+/// fades reach voices that are already playing.
+#[cfg(feature = "audio")]
+struct MasterGain<S>(S, Option<Arc<Fader>>);
+
+#[cfg(feature = "audio")]
+impl<S: Source<Item = i16>> Iterator for MasterGain<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let fader = self.1.as_ref().map_or(1.0, |fader| fader.gain());
+        self.0.next().map(|sample| (sample as f32 * master_gain() * fader) as i16)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: Source<Item = i16>> Source for MasterGain<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.0.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.0.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.0.total_duration()
+    }
+}
+
+/// How a voice is played: gain, speed (1.0 keeps the pitch), an optional
+/// stereo position, a cap on the source time played and the fader of its
+/// group.
+pub struct VoiceParams {
+    pub gain: f32,
+    pub speed: f32,
+    /// None leaves the channels as they are, as for previews.
+    pub pan: Option<f32>,
+    pub limit: Option<Duration>,
+    pub fader: Option<Arc<Fader>>,
+}
+
+#[cfg(feature = "audio")]
+fn host() -> rodio::cpal::Host {
+    #[cfg(feature = "jack")]
+    match rodio::cpal::host_from_id(rodio::cpal::HostId::Jack) {
+        Ok(host) => return host,
+        Err(e) => log_error!("JACK unavailable ({}), using the default audio host", e),
+    }
+    rodio::cpal::default_host()
+}
+
+/// Names of the audio output devices.
+pub fn output_device_names() -> Vec<String> {
+    #[cfg(feature = "audio")]
+    if let Ok(devices) = host().output_devices() {
+        return devices.filter_map(|d| d.name().ok()).collect();
+    }
+    Vec::new()
+}
+
+/// Opens the named output device, falling back to the system default.
+#[cfg(feature = "audio")]
+pub fn open_output_stream(
+    device_name: Option<&str>,
+) -> Result<(OutputStream, OutputStreamHandle), Box<dyn std::error::Error>> {
+    let host = host();
+    let named = device_name.and_then(|name| {
+        let device = host.output_devices().ok()?.find(|d| d.name().is_ok_and(|n| n == name));
+        if device.is_none() {
+            log_error!("Audio device '{}' not found, using the default", name);
+        }
+        device
+    });
+    let (stream, stream_handle, layout) = match named.or_else(|| host.default_output_device()) {
+        Some(device) => {
+            let (stream, stream_handle) = OutputStream::try_from_device(&device)?;
+            let layout = device.default_output_config().map(|c| (c.channels(), c.sample_rate().0));
+            (stream, stream_handle, layout.unwrap_or((2, 44_100)))
+        }
+        None => {
+            let (stream, stream_handle) = OutputStream::try_default()?;
+            (stream, stream_handle, (2, 44_100))
+        }
+    };
+    open_master_bus(&stream_handle, layout.0, layout.1)?;
+    Ok((stream, OutputStreamHandle(Some(stream_handle))))
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn open_output_stream(
+    _device_name: Option<&str>,
+) -> Result<(OutputStream, OutputStreamHandle), Box<dyn std::error::Error>> {
+    log!("Built without audio output; samples and loops are not played");
+    Ok((OutputStream, OutputStreamHandle))
+}
+
+/// Starts a voice on its own sink, tapping it into the track meter when
+/// given, and returns the sink so it can be cut off or detached.
+#[cfg(feature = "audio")]
+pub fn start_voice(
+    stream_handle: &OutputStreamHandle,
+    samples: Vec<i16>,
+    channels: u16,
+    sample_rate: u32,
+    params: VoiceParams,
+    meter: Option<Arc<LevelMeter>>,
+) -> Sink {
+    let mut source: Box<dyn Source<Item = i16> + Send> =
+        Box::new(rodio::buffer::SamplesBuffer::new(channels, sample_rate, samples).amplify(params.gain));
+    if let Some(limit) = params.limit {
+        source = Box::new(source.take_duration(limit));
+    }
+    if params.speed != 1.0 {
+        source = Box::new(source.speed(params.speed));
+    }
+    if let Some(pan) = params.pan {
+        source = Box::new(ChannelVolume::new(source, pan_volumes(pan)));
+    }
+    let source = MasterGain(source, params.fader);
+    let played = match meter {
+        Some(meter) => play_on_bus(stream_handle, Metered::new(MeterTap::new(source, meter))),
+        None => play_on_bus(stream_handle, Metered::new(source)),
+    };
+    played.unwrap_or_else(|e| {
+        // The device went away; drop the voice rather than the playback thread
+        log_error!("Could not play a voice: {}", e);
+        DIAGNOSTICS.record_dropped();
+        Sink::new_idle().0
+    })
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn start_voice(
+    _stream_handle: &OutputStreamHandle,
+    _samples: Vec<i16>,
+    _channels: u16,
+    _sample_rate: u32,
+    _params: VoiceParams,
+    _meter: Option<Arc<LevelMeter>>,
+) -> Sink {
+    Sink
+}
+
+/// Plays a short metronome click, higher pitched on the downbeat.
+pub fn play_click(accent: bool, stream_handle: &OutputStreamHandle) {
+    #[cfg(feature = "audio")]
+    {
+        let frequency = if accent { 1500.0 } else { 1000.0 };
+        let source = rodio::source::SineWave::new(frequency)
+            .take_duration(Duration::from_millis(30))
+            .amplify(0.3 * master_gain());
+        match play_on_bus(stream_handle, source.convert_samples()) {
+            Ok(sink) => sink.detach(),
+            Err(e) => log_error!("Could not play the metronome: {}", e),
+        }
+    }
+    #[cfg(not(feature = "audio"))]
+    let _ = (accent, stream_handle);
+}
@@ -0,0 +1,11 @@
+//! Entry point of the browser build; see `four_on_the_floor::web`.
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    four_on_the_floor::web::start("grid");
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("The web grid runs in a browser: build it with `trunk serve web/index.html`");
+}
@@ -0,0 +1,193 @@
+use std::{fs, path::PathBuf, sync::Arc, thread, time::Duration};
+
+use eframe::egui;
+use crate::audio::OutputStreamHandle;
+use crate::error;
+
+use crate::meter::LevelMeter;
+use crate::model::Pattern;
+use crate::{is_loop_filename, play_file, play_loop, play_sound, LoopBank, LoopPlay, SoundBank};
+
+/// How many beats of a loop are played when auditioning it.
+const PREVIEW_BEATS: f32 = 4.0;
+
+#[derive(Clone)]
+pub enum BrowserItem {
+    Sound(String),
+    Loop(String),
+}
+
+impl BrowserItem {
+    fn label(&self) -> &str {
+        match self {
+            BrowserItem::Sound(label) | BrowserItem::Loop(label) => label,
+        }
+    }
+}
+
+/// Side panel listing the loaded banks and the filesystem, with
+/// click-to-audition and drag-to-grid support.
+pub struct SampleBrowser {
+    sound_bank: Arc<SoundBank>,
+    loop_bank: Arc<LoopBank>,
+    stream_handle: Arc<OutputStreamHandle>,
+    bpm: u32,
+    current_dir: PathBuf,
+    entries: Vec<PathBuf>,
+    dragging: Option<BrowserItem>,
+}
+
+impl SampleBrowser {
+    pub fn new(
+        sound_bank: Arc<SoundBank>,
+        loop_bank: Arc<LoopBank>,
+        stream_handle: Arc<OutputStreamHandle>,
+        bpm: u32,
+        start_dir: &str,
+    ) -> Self {
+        let mut browser = Self {
+            sound_bank,
+            loop_bank,
+            stream_handle,
+            bpm,
+            current_dir: PathBuf::from(start_dir),
+            entries: Vec::new(),
+            dragging: None,
+        };
+        browser.refresh_entries();
+        browser
+    }
+
+    /// Renders the browser and returns the item that was dropped this frame, if any.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<BrowserItem> {
+        let mut dropped = None;
+
+        ui.heading("Browser");
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.collapsing("Samples", |ui| {
+                for label in self.sound_bank.labels() {
+                    if let Some(item) = self.item_row(ui, BrowserItem::Sound(label)) {
+                        dropped = Some(item);
+                    }
+                }
+            });
+            ui.collapsing("Loops", |ui| {
+                for label in self.loop_bank.labels() {
+                    if let Some(item) = self.item_row(ui, BrowserItem::Loop(label)) {
+                        dropped = Some(item);
+                    }
+                }
+            });
+            ui.collapsing("Files", |ui| self.show_files(ui));
+        });
+
+        if let Some(item) = &self.dragging {
+            egui::show_tooltip_at_pointer(ui.ctx(), egui::Id::new("browser_drag"), |ui| {
+                ui.label(item.label());
+            });
+        }
+
+        dropped
+    }
+
+    /// Loads a WAV file dropped onto the window into the matching bank.
+    pub fn import_file(&self, path: &str) -> error::Result<BrowserItem> {
+        if is_loop_filename(path) {
+            Ok(BrowserItem::Loop(self.loop_bank.load_file(path)?))
+        } else {
+            Ok(BrowserItem::Sound(self.sound_bank.load_file(path)?))
+        }
+    }
+
+    fn item_row(&mut self, ui: &mut egui::Ui, item: BrowserItem) -> Option<BrowserItem> {
+        let response = ui.add(egui::Label::new(item.label()).sense(egui::Sense::click_and_drag()));
+        if response.clicked() {
+            self.preview(&item);
+        }
+        if response.drag_started() {
+            self.dragging = Some(item);
+        }
+        if response.drag_released() {
+            return self.dragging.take();
+        }
+        None
+    }
+
+    pub fn sound_labels(&self) -> Vec<String> {
+        self.sound_bank.labels()
+    }
+
+    /// Plays a pattern's sound or loop once, as a live pad hit, after `delay`.
+    pub fn trigger(&self, pattern: &Pattern, velocity: f32, pan: f32, meter: Arc<LevelMeter>, delay: Duration) {
+        let (sound_bank, loop_bank, stream_handle) = (Arc::clone(&self.sound_bank), Arc::clone(&self.loop_bank), Arc::clone(&self.stream_handle));
+        let (sound, loop_name, duration, bpm) = (pattern.sound.clone(), pattern.loop_name.clone(), pattern.duration, self.bpm);
+        let play = move || {
+            if let Some(label) = &sound {
+                play_sound(label, velocity, pan, Some(meter), &sound_bank, &stream_handle);
+            } else if let Some(label) = &loop_name {
+                let play = LoopPlay { duration, velocity, pan, meter: Some(meter), fader: None, bpm };
+                play_loop(label, play, &loop_bank, &stream_handle);
+            }
+        };
+        if delay.is_zero() {
+            play();
+        } else {
+            thread::spawn(move || {
+                thread::sleep(delay);
+                play();
+            });
+        }
+    }
+
+    fn preview(&self, item: &BrowserItem) {
+        match item {
+            BrowserItem::Sound(label) => {
+                play_sound(label, 100.0, 0.0, None, &self.sound_bank, &self.stream_handle);
+            }
+            BrowserItem::Loop(label) => {
+                let play = LoopPlay { duration: PREVIEW_BEATS, velocity: 100.0, pan: 0.0, meter: None, fader: None, bpm: self.bpm };
+                play_loop(label, play, &self.loop_bank, &self.stream_handle);
+            }
+        }
+    }
+
+    fn show_files(&mut self, ui: &mut egui::Ui) {
+        ui.label(self.current_dir.display().to_string());
+
+        let mut next_dir = None;
+        if ui.button("..").clicked() {
+            next_dir = Some(self.current_dir.join(".."));
+        }
+        for path in self.entries.iter() {
+            let name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+            if path.is_dir() {
+                if ui.button(format!("{}/", name)).clicked() {
+                    next_dir = Some(path.clone());
+                }
+            } else if ui.add(egui::Label::new(name).sense(egui::Sense::click())).clicked() {
+                if let Some(path_str) = path.to_str() {
+                    play_file(path_str, &self.stream_handle);
+                }
+            }
+        }
+
+        if let Some(dir) = next_dir {
+            self.current_dir = dir;
+            self.refresh_entries();
+        }
+    }
+
+    fn refresh_entries(&mut self) {
+        self.entries = match fs::read_dir(&self.current_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_dir() || path.extension().is_some_and(|ext| ext == "wav"))
+                .collect(),
+            Err(e) => {
+                log_error!("Failed to read directory {}: {}", self.current_dir.display(), e);
+                Vec::new()
+            }
+        };
+        self.entries.sort();
+    }
+}
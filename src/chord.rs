@@ -0,0 +1,144 @@
+use crate::model::{Pattern, PatternBuilder};
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Chord suffixes with their intervals above the root, in semitones.
+const QUALITIES: &[(&str, &[u8])] = &[
+    ("", &[0, 4, 7]),
+    ("maj", &[0, 4, 7]),
+    ("m", &[0, 3, 7]),
+    ("min", &[0, 3, 7]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("5", &[0, 7]),
+    ("6", &[0, 4, 7, 9]),
+    ("m6", &[0, 3, 7, 9]),
+    ("7", &[0, 4, 7, 10]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("m7", &[0, 3, 7, 10]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("add9", &[0, 4, 7, 14]),
+    ("9", &[0, 4, 7, 10, 14]),
+];
+
+/// Name of a MIDI note, such as "C#4" for 61.
+pub fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[(note % 12) as usize], note as i32 / 12 - 1)
+}
+
+/// How the notes of a chord are stacked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Voicing {
+    /// How many of the lowest notes move up an octave.
+    pub inversion: u32,
+    /// Open voicing: every second note goes up an octave.
+    pub spread: bool,
+}
+
+/// A chord such as "C#m" or "Fmaj7" rooted in a given octave, where
+/// octave 4 starts at middle C (MIDI note 60).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    root: i32,
+    intervals: &'static [u8],
+}
+
+impl Chord {
+    pub fn parse(name: &str, octave: i8) -> Result<Self, String> {
+        let letter = name.chars().next().ok_or("Empty chord name")?;
+        let natural = NOTE_NAMES
+            .iter()
+            .position(|n| n.len() == 1 && n.starts_with(letter.to_ascii_uppercase()))
+            .ok_or(format!("'{}' does not start with a note", name))? as i32;
+        let rest = &name[1..];
+        let (accidental, quality) = match rest.chars().next() {
+            Some('#') => (1, &rest[1..]),
+            Some('b') => (-1, &rest[1..]),
+            _ => (0, rest),
+        };
+        let (_, intervals) = QUALITIES
+            .iter()
+            .find(|(suffix, _)| *suffix == quality)
+            .ok_or(format!("Unknown chord quality '{}' in '{}'", quality, name))?;
+        let root = (octave as i32 + 1) * 12 + natural + accidental;
+        if !(0..=127).contains(&root) {
+            return Err(format!("'{}' in octave {} is outside the MIDI range", name, octave));
+        }
+        Ok(Chord { root, intervals })
+    }
+
+    /// MIDI notes of the chord, lowest first; notes pushed past the MIDI
+    /// range are left out.
+    pub fn notes(&self, voicing: Voicing) -> Vec<u8> {
+        let mut notes: Vec<i32> = self.intervals.iter().map(|i| self.root + *i as i32).collect();
+        for _ in 0..voicing.inversion {
+            notes[0] += 12;
+            notes.sort();
+        }
+        if voicing.spread {
+            for note in notes.iter_mut().skip(1).step_by(2) {
+                *note += 12;
+            }
+            notes.sort();
+        }
+        notes.into_iter().filter_map(|note| u8::try_from(note).ok().filter(|n| *n <= 127)).collect()
+    }
+}
+
+/// Chords played one after the other, each on its own beats, like the
+/// chord sheet of a song.
+pub struct ChordProgression {
+    octave: i8,
+    voicing: Voicing,
+    velocity: f32,
+    duration: f32,
+    chords: Vec<(String, Vec<f32>)>,
+}
+
+impl ChordProgression {
+    pub fn new(octave: i8) -> Self {
+        let defaults = PatternBuilder::new().build();
+        Self { octave, voicing: Voicing::default(), velocity: defaults.velocity, duration: defaults.duration, chords: Vec::new() }
+    }
+
+    pub fn chord(mut self, chord: &str, beats: Vec<f32>) -> Self {
+        self.chords.push((chord.to_string(), beats));
+        self
+    }
+
+    pub fn voicing(mut self, voicing: Voicing) -> Self {
+        self.voicing = voicing;
+        self
+    }
+
+    pub fn velocity(mut self, velocity: f32) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn duration(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// One pattern per note of every chord.
+    pub fn build(&self) -> Result<Vec<Pattern>, String> {
+        let mut patterns = Vec::new();
+        for (chord, beats) in self.chords.iter() {
+            patterns.extend(
+                PatternBuilder::new()
+                    .beats(beats.clone())
+                    .velocity(self.velocity)
+                    .duration(self.duration)
+                    .chord(chord, self.octave)
+                    .inversion(self.voicing.inversion)
+                    .spread(self.voicing.spread)
+                    .build_chord()?,
+            );
+        }
+        Ok(patterns)
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+/// Rust 4x4 groovebox: plays sample, loop and MIDI patterns in a loop.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub paths: Paths,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Project files, so several projects can live side by side.
+#[derive(Args)]
+pub struct Paths {
+    /// Config file to read
+    #[arg(long, global = true, env = "FOUR_ON_THE_FLOOR_CONFIG", default_value = "config.json")]
+    pub config: String,
+    /// Patterns file to play and watch for changes
+    #[arg(long, global = true, env = "FOUR_ON_THE_FLOOR_PATTERNS", default_value = "patterns.json")]
+    pub patterns: String,
+    /// Project file (.fotf) holding both the config and the patterns
+    #[arg(long, global = true, env = "FOUR_ON_THE_FLOOR_PROJECT")]
+    pub project: Option<String>,
+}
+
+impl Paths {
+    /// Points the config and patterns at the project file, when one is given.
+    pub fn resolve_project(mut self) -> Self {
+        if let Some(project) = self.project.clone() {
+            self.config = project.clone();
+            self.patterns = project;
+        }
+        self
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Play the patterns, with the grid editor unless told otherwise
+    Play(PlayArgs),
+    /// Render one or more passes of the loop to a WAV file
+    Render(RenderArgs),
+    /// Re-perform a trigger history recorded with `play --history`
+    Replay(ReplayArgs),
+    /// List MIDI ports and audio output devices
+    Ports,
+    /// Create a starter project with a config, a four-on-the-floor pattern
+    /// and empty sample and loop directories
+    Init(InitArgs),
+    /// Unpack a Hydrogen .h2drumkit archive as a sample kit
+    ImportKit(ImportKitArgs),
+    /// Check the config and patterns for errors without playing
+    Validate,
+    /// List the samples and loops found in the configured directories
+    ListSamples,
+}
+
+#[derive(Args)]
+pub struct PlayArgs {
+    /// Tempo in beats per minute
+    #[arg(value_parser = clap::value_parser!(u32).range(20..=300))]
+    pub bpm: u32,
+    /// Play headless, without any front end
+    #[arg(long)]
+    pub no_gui: bool,
+    /// Sequence MIDI hardware only: no window, no sample or loop banks
+    /// loaded and only the MIDI patterns played. Always on in builds with
+    /// the headless feature
+    #[arg(long)]
+    pub headless: bool,
+    /// Use the terminal front end instead of the window
+    #[arg(long, conflicts_with = "no_gui")]
+    pub tui: bool,
+    /// Restore the state saved by the last run without asking
+    #[arg(long)]
+    pub restore_session: bool,
+    /// Run headless, stopped, taking line commands (load, play, stop, bpm,
+    /// mute, samples, loops, next, song, status, quit) on the socket
+    #[arg(long, conflicts_with = "tui")]
+    pub daemon: bool,
+    /// Live-code from a prompt in the terminal instead of the window
+    #[arg(long, conflicts_with_all = ["tui", "daemon"])]
+    pub repl: bool,
+    /// Unix socket path, or host:port for TCP, the daemon listens on
+    #[arg(long, default_value = "four_on_the_floor.sock")]
+    pub socket: String,
+    /// Record every trigger (time, track, note, velocity) to this log file
+    #[arg(long, value_name = "FILE")]
+    pub history: Option<String>,
+    /// Play the songs of a setlist file, starting with the first; `next`
+    /// from the daemon or REPL switches at the next loop pass
+    #[arg(long, value_name = "FILE")]
+    pub setlist: Option<String>,
+    /// Hold playback until Play is pressed in the window, `play` is typed
+    /// in the REPL, `p` in the terminal front end, or else Enter
+    #[arg(long, conflicts_with = "daemon")]
+    pub wait: bool,
+    /// Start playback after counting down this many seconds
+    #[arg(long, value_name = "SECS", conflicts_with_all = ["daemon", "wait"])]
+    pub countdown: Option<u32>,
+    /// Stop after this many bars and exit, for scripted runs
+    #[arg(long, value_name = "N", conflicts_with = "daemon")]
+    pub bars: Option<u32>,
+    /// Stop once this much time has passed, e.g. 90s or 2m, and exit
+    #[arg(long, value_parser = parse_duration, conflicts_with_all = ["daemon", "bars"])]
+    pub duration: Option<Duration>,
+}
+
+/// Parses "90", "90s", "2m" or "1h" into a duration.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(at) => value.split_at(at),
+        None => (value, "s"),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("Invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("Unknown unit '{}' in '{}', use s, m or h", unit, value)),
+    };
+    if seconds <= 0.0 {
+        return Err(format!("Duration '{}' must be positive", value));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Tempo in beats per minute
+    #[arg(value_parser = clap::value_parser!(u32).range(20..=300))]
+    pub bpm: u32,
+    /// WAV file to write
+    #[arg(short, long, default_value = "render.wav")]
+    pub output: String,
+    /// Number of loop passes to render
+    #[arg(long, default_value_t = 1)]
+    pub loops: u32,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// History log to replay
+    pub log: String,
+    /// Render the samples and loops to this WAV file instead of playing
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// Directory to create the project in
+    #[arg(default_value = ".")]
+    pub dir: String,
+    /// Overwrite an existing config.json and patterns.json
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct ImportKitArgs {
+    /// The .h2drumkit archive
+    pub archive: String,
+    /// Directory the kit is unpacked into
+    #[arg(long, default_value = "sounds/kits")]
+    pub into: String,
+}
@@ -1,8 +1,21 @@
-use std::{fs::File, io::BufReader};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+use crate::error::{self, Error};
+use crate::formats::Format;
+use crate::labels::DuplicateLabels;
+use crate::model::{Pattern, Track};
+use crate::scene::Scene;
+use crate::transport::LiveQuantize;
+use crate::song::SongSection;
+use crate::threads;
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct MidiTrackConfig {
     pub midi_file: String,
     pub track_name: String,
@@ -10,23 +23,351 @@ pub struct MidiTrackConfig {
     pub end_beat: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct SoundConfig {
+    /// Sample directories, several separated like PATH entries.
     pub samples: String,
+    /// Loop directories, several separated like PATH entries.
     pub loops: String,
+    /// Library roots searched for sample, loop and MIDI paths that are not
+    /// found next to the config, after those in `FOUR_ON_THE_FLOOR_SAMPLE_PATH`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_path: Vec<String>,
+    /// Decode only the samples the patterns use at startup and the rest in
+    /// the background, for big libraries.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lazy: bool,
+    /// Directory decoded samples and loops are cached in, so later starts
+    /// skip decoding; off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>,
+    /// What to do with a label an earlier file took: keep the first file,
+    /// or number or prefix the later ones.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub duplicates: DuplicateLabels,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct GuiConfig {
+    #[serde(default)]
+    pub theme: Theme,
+    /// Track name -> hex color, e.g. "bd": "#ff8800"
+    #[serde(default)]
+    pub track_colors: HashMap<String, String>,
+    /// Computer key -> sample label played in pad mode, e.g. "a": "bd";
+    /// when empty the home row plays the first samples.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pads: BTreeMap<String, String>,
+    /// Output latency of the audio device in milliseconds, which the
+    /// playhead is held back by on top of the measured mixing delay.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub latency_ms: u32,
+}
+
+/// How playback ends when it is stopped.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ShutdownConfig {
+    /// Play on to the end of the current bar instead of stopping at the next step.
+    #[serde(default = "default_finish_bar")]
+    pub finish_bar: bool,
+    /// Fade-out of the master output once the last step played, in milliseconds.
+    #[serde(default = "default_fade_ms")]
+    pub fade_ms: u64,
+}
+
+fn default_finish_bar() -> bool {
+    true
+}
+
+fn default_fade_ms() -> u64 {
+    500
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { finish_bar: default_finish_bar(), fade_ms: default_fade_ms() }
+    }
 }
 
-#[derive(Deserialize)]
+/// Worker thread counts; unset counts use one thread per CPU.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct ThreadConfig {
+    /// Threads decoding samples when the sound bank loads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_loaders: Option<usize>,
+    /// Threads decoding loops when the loop bank loads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_loaders: Option<usize>,
+    /// Threads starting sample and loop voices during playback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_workers: Option<usize>,
+    /// Threads sending MIDI notes to the ports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midi_workers: Option<usize>,
+    /// Run the scheduler at real-time priority where the OS allows it.
+    #[serde(default)]
+    pub realtime: bool,
+}
+
+/// MIDI workers only send note-ons, the note-offs are timed separately.
+const DEFAULT_MIDI_WORKERS: usize = 2;
+
+impl ThreadConfig {
+    pub fn sample_loaders(&self) -> usize {
+        self.sample_loaders.filter(|count| *count > 0).unwrap_or_else(threads::cpu_count)
+    }
+
+    pub fn loop_loaders(&self) -> usize {
+        self.loop_loaders.filter(|count| *count > 0).unwrap_or_else(threads::cpu_count)
+    }
+
+    pub fn audio_workers(&self) -> usize {
+        self.audio_workers.unwrap_or_else(threads::cpu_count)
+    }
+
+    pub fn midi_workers(&self) -> usize {
+        self.midi_workers.unwrap_or(DEFAULT_MIDI_WORKERS)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub midi_port: String,
+    /// MIDI input whose notes from C1 (36) up play the grid rows as pads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midi_input_port: Option<String>,
+    /// Output device name; the system default when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_device: Option<String>,
+    /// UDP port for OSC control (/bpm, /mute/<track>, ...); off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osc_port: Option<u16>,
+    /// Address the OSC control port binds to. Anyone who can reach it can
+    /// drive the sequencer, so only set e.g. "0.0.0.0" on a trusted network.
+    #[serde(default = "default_bind", skip_serializing_if = "is_default_bind")]
+    pub osc_bind: String,
+    /// `host:port` that beat and trigger events are sent to over OSC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osc_out: Option<String>,
+    /// TCP port of the HTTP/WebSocket remote control API; off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_port: Option<u16>,
+    /// Address the remote control API binds to. It has no authentication,
+    /// so only set e.g. "0.0.0.0" on a trusted network.
+    #[serde(default = "default_bind", skip_serializing_if = "is_default_bind")]
+    pub remote_bind: String,
     pub midi_track: MidiTrackConfig,
     pub sounds: SoundConfig,
     pub loop_beats: u32,
+    /// Semitones added to the notes of every MIDI pattern.
+    #[serde(default)]
+    pub transpose: i8,
+    /// Musical key of the project, like "Am" or "F#".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Pitch-shift loops with a key of their own to the project key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub match_key: bool,
+    /// Grid live pad hits wait for: off, 16th, 8th or beat.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub live_quantize: LiveQuantize,
+    #[serde(default)]
+    pub gui: GuiConfig,
+    /// Arrangement for song mode, played in order and looped.
+    #[serde(default)]
+    pub song: Vec<SongSection>,
+    /// Scenes that can be launched from the GUI, REPL or Python module.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scenes: Vec<Scene>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub threads: ThreadConfig,
+    /// Directory of the file the config was read from; relative paths start here.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+/// Environment variable with extra library roots, separated like PATH.
+const SAMPLE_PATH_VAR: &str = "FOUR_ON_THE_FLOOR_SAMPLE_PATH";
+
+impl Config {
+    /// Resolves an asset path: absolute paths as they are, relative ones next
+    /// to the config file, then under each search path root.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        let local = self.base_dir.join(path);
+        if local.exists() {
+            return local;
+        }
+        let env_roots = std::env::var_os(SAMPLE_PATH_VAR)
+            .map(|value| std::env::split_paths(&value).collect::<Vec<_>>())
+            .unwrap_or_default();
+        env_roots
+            .into_iter()
+            .chain(self.sounds.search_path.iter().map(|root| self.base_dir.join(root)))
+            .map(|root| root.join(path))
+            .find(|candidate| candidate.exists())
+            .unwrap_or(local)
+    }
+
+    /// Resolves each entry of a PATH-style directory list.
+    pub fn resolve_dirs(&self, dirs: &str) -> String {
+        let resolved: Vec<PathBuf> = std::env::split_paths(dirs).map(|dir| self.resolve(&dir.to_string_lossy())).collect();
+        std::env::join_paths(resolved)
+            .map(|joined| joined.to_string_lossy().into_owned())
+            .unwrap_or(dirs.to_string())
+    }
+
+    pub fn sample_dirs(&self) -> String {
+        self.resolve_dirs(&self.sounds.samples)
+    }
+
+    pub fn loop_dirs(&self) -> String {
+        self.resolve_dirs(&self.sounds.loops)
+    }
+
+    /// Key loops are shifted to, when matching is on.
+    pub fn loop_key(&self) -> Option<String> {
+        self.key.clone().filter(|_| self.match_key)
+    }
+
+    /// Resolved sample cache directory, relative to the config's.
+    pub fn sample_cache_dir(&self) -> Option<PathBuf> {
+        self.sounds.cache.as_ref().map(|dir| self.base_dir.join(dir))
+    }
+
+    /// Resolved MIDI file to import, empty when there is none.
+    pub fn midi_file(&self) -> String {
+        if self.midi_track.midi_file.is_empty() {
+            return String::new();
+        }
+        self.resolve(&self.midi_track.midi_file).to_string_lossy().into_owned()
+    }
+}
+
+/// Extension of project files, which bundle the config and the patterns.
+pub const PROJECT_EXTENSION: &str = "fotf";
+
+/// A `.fotf` project: the config fields plus the patterns in one JSON file.
+#[derive(Deserialize, Serialize)]
+pub struct Project {
+    #[serde(flatten)]
+    pub config: Config,
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<Track>,
+}
+
+/// Network services listen on localhost only unless configured otherwise.
+fn default_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn is_default_bind(bind: &String) -> bool {
+    *bind == default_bind()
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
-pub fn read_config(file_path: &str) -> Result<Config, Box<dyn std::error::Error>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let config: Config = serde_json::from_reader(reader)?;
+pub fn is_project(file_path: &str) -> bool {
+    Path::new(file_path).extension().is_some_and(|e| e == PROJECT_EXTENSION)
+}
+
+/// Directory containing a config or project file.
+fn project_dir(file_path: &str) -> PathBuf {
+    Path::new(file_path).parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Reads `file_path`, with the path in the error.
+fn read_file(file_path: &str) -> error::Result<String> {
+    fs::read_to_string(file_path).map_err(|e| Error::io(Path::new(file_path), e))
+}
+
+fn read_project(file_path: &str) -> error::Result<Project> {
+    let mut project: Project = Format::Json.parse(file_path, &read_file(file_path)?)?;
+    project.config.base_dir = project_dir(file_path);
+    Ok(project)
+}
+
+/// Saves a project. Relative asset paths are rewritten to stay valid when
+/// it goes to another directory than the config came from.
+pub fn write_project(
+    file_path: &str,
+    config: &Config,
+    patterns: &[Pattern],
+    tracks: &[Track],
+) -> error::Result<()> {
+    let dir = project_dir(file_path);
+    let mut config = config.clone();
+    if dir != config.base_dir {
+        let rebase = |path: &str| -> String {
+            let joined = config.base_dir.join(path);
+            if Path::new(path).is_absolute() || !joined.exists() {
+                return path.to_string(); // Absolute, or found on the search path
+            }
+            match joined.strip_prefix(&dir) {
+                Ok(relative) => relative.to_string_lossy().into_owned(),
+                Err(_) => fs::canonicalize(&joined).unwrap_or(joined).to_string_lossy().into_owned(),
+            }
+        };
+        let rebase_dirs = |dirs: &str| -> String {
+            let rebased: Vec<String> = std::env::split_paths(dirs).map(|d| rebase(&d.to_string_lossy())).collect();
+            std::env::join_paths(rebased).map_or(dirs.to_string(), |j| j.to_string_lossy().into_owned())
+        };
+        let sounds = SoundConfig {
+            samples: rebase_dirs(&config.sounds.samples),
+            loops: rebase_dirs(&config.sounds.loops),
+            search_path: config.sounds.search_path.iter().map(|root| rebase(root)).collect(),
+            lazy: config.sounds.lazy,
+            cache: config.sounds.cache.as_ref().map(|dir| rebase(dir)),
+            duplicates: config.sounds.duplicates,
+        };
+        config.midi_track.midi_file = rebase(&config.midi_track.midi_file);
+        config.sounds = sounds;
+    }
+    let project = Project { config, patterns: patterns.to_vec(), tracks: tracks.to_vec() };
+    let content = Format::Json.to_string(file_path, &project)?;
+    fs::write(file_path, content).map_err(|e| Error::io(Path::new(file_path), e))
+}
+
+/// Reads the config as JSON, TOML or YAML depending on the file extension,
+/// or from a project file.
+pub fn read_config(file_path: &str) -> error::Result<Config> {
+    if is_project(file_path) {
+        return Ok(read_project(file_path)?.config);
+    }
+    let mut config: Config = Format::from_path(file_path).parse(file_path, &read_file(file_path)?)?;
+    config.base_dir = project_dir(file_path);
     Ok(config)
-}
\ No newline at end of file
+}
+
+/// Writes the config; in a project file the patterns are kept.
+pub fn write_config(file_path: &str, config: &Config) -> error::Result<()> {
+    if is_project(file_path) {
+        // A project that can't be read is left alone rather than saved without its patterns
+        let (patterns, tracks) = if Path::new(file_path).exists() {
+            let project = read_project(file_path)?;
+            (project.patterns, project.tracks)
+        } else {
+            Default::default()
+        };
+        return write_project(file_path, config, &patterns, &tracks);
+    }
+    let content = Format::from_path(file_path).to_string(file_path, config)?;
+    fs::write(file_path, content).map_err(|e| Error::io(Path::new(file_path), e))
+}
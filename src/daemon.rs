@@ -0,0 +1,138 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener},
+    str::FromStr,
+    sync::mpsc::{channel, Sender},
+    thread,
+};
+
+use crate::error::{self, Error};
+
+/// Commands read one per line from daemon clients, e.g. `bpm 128`.
+pub enum DaemonCommand {
+    /// Switch to another patterns file.
+    Load(String),
+    Play,
+    Stop,
+    Bpm(u32),
+    /// Toggle a track's mute.
+    Mute(String),
+    /// Switch to other sample directories, swapped in at the next loop.
+    Samples(String),
+    /// Switch to other loop directories, swapped in at the next loop.
+    Loops(String),
+    /// Switch to the next song of the setlist at the next loop.
+    Next,
+    /// Switch to a song of the setlist by its number, from 1.
+    Song(usize),
+    /// Reply with the tempo, whether playing and the patterns file.
+    Status,
+    /// Stop and end the daemon.
+    Quit,
+}
+
+impl FromStr for DaemonCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, String> {
+        let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let argument = argument.trim();
+        let required = || if argument.is_empty() { Err(format!("'{}' needs an argument", command)) } else { Ok(argument.to_string()) };
+        match command {
+            "load" => Ok(DaemonCommand::Load(required()?)),
+            "play" => Ok(DaemonCommand::Play),
+            "stop" => Ok(DaemonCommand::Stop),
+            "bpm" => required()?.parse().map(DaemonCommand::Bpm).map_err(|_| format!("Invalid bpm '{}'", argument)),
+            "mute" => Ok(DaemonCommand::Mute(required()?)),
+            "samples" => Ok(DaemonCommand::Samples(required()?)),
+            "loops" => Ok(DaemonCommand::Loops(required()?)),
+            "next" => Ok(DaemonCommand::Next),
+            "song" => required()?.parse().map(DaemonCommand::Song).map_err(|_| format!("Invalid song number '{}'", argument)),
+            "status" => Ok(DaemonCommand::Status),
+            "quit" => Ok(DaemonCommand::Quit),
+            _ => Err(format!("Unknown command '{}'; try load, play, stop, bpm, mute, samples, loops, next, song, status or quit", command)),
+        }
+    }
+}
+
+/// A command with the channel its reply goes back on.
+pub type Request = (DaemonCommand, Sender<Result<String, String>>);
+
+/// Answers a client: every line is parsed, handed to the daemon loop and
+/// answered with `ok [info]` or `error <message>`.
+fn serve(reader: impl BufRead, mut writer: impl Write, requests: &Sender<Request>) -> std::io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse::<DaemonCommand>() {
+            Ok(command) => {
+                let (reply_to, reply) = channel();
+                if requests.send((command, reply_to)).is_err() {
+                    break; // The daemon is shutting down
+                }
+                reply.recv().unwrap_or(Err("No reply".to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(info) if info.is_empty() => writeln!(writer, "ok")?,
+            Ok(info) => writeln!(writer, "ok {}", info)?,
+            Err(e) => writeln!(writer, "error {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+/// Listens for clients on `address`: `host:port` for TCP, otherwise the
+/// path of a unix socket, replacing a stale one.
+pub fn spawn_server(address: &str, requests: Sender<Request>) -> error::Result<()> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        let listener = TcpListener::bind(addr).map_err(|source| Error::Network { address: address.to_string(), source })?;
+        log!("Daemon listening on {}", addr);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    let result = stream.try_clone().and_then(|reader| serve(BufReader::new(reader), stream, &requests));
+                    if let Err(e) = result {
+                        log_warn!("Daemon connection failed: {}", e);
+                    }
+                });
+            }
+        });
+        return Ok(());
+    }
+    spawn_unix_server(address, requests)
+}
+
+#[cfg(unix)]
+fn spawn_unix_server(path: &str, requests: Sender<Request>) -> error::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    let socket = std::path::Path::new(path);
+    if socket.exists() {
+        std::fs::remove_file(socket).map_err(|e| Error::io(socket, e))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|source| Error::Network { address: path.to_string(), source })?;
+    log!("Daemon listening on {}", path);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                let result = stream.try_clone().and_then(|reader| serve(BufReader::new(reader), stream, &requests));
+                if let Err(e) = result {
+                    log_warn!("Daemon connection failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_unix_server(path: &str, _requests: Sender<Request>) -> error::Result<()> {
+    let reason = "not a host:port address; unix sockets need a unix system";
+    Err(Error::Network { address: path.to_string(), source: std::io::Error::new(std::io::ErrorKind::Unsupported, reason) })
+}
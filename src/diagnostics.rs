@@ -0,0 +1,170 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+#[cfg(feature = "audio")]
+use rodio::Source;
+#[cfg(feature = "audio")]
+use std::time::Instant;
+
+/// Only every Nth sample is timed; the measurement is scaled back up.
+#[cfg(feature = "audio")]
+const CPU_SAMPLE_EVERY: u32 = 64;
+
+/// Engine health counters, updated from the scheduler and audio threads.
+pub struct Diagnostics {
+    jitter_micros: AtomicU32,
+    max_jitter_micros: AtomicU32,
+    active_voices: AtomicUsize,
+    dropped_events: AtomicUsize,
+    late_ticks: AtomicUsize,
+    audio_nanos: AtomicU64,
+    /// Smoothed time from starting a voice to the output pulling its first sample.
+    start_latency_micros: AtomicU32,
+}
+
+pub static DIAGNOSTICS: Diagnostics = Diagnostics::new();
+
+impl Diagnostics {
+    const fn new() -> Self {
+        Self {
+            jitter_micros: AtomicU32::new(0),
+            max_jitter_micros: AtomicU32::new(0),
+            active_voices: AtomicUsize::new(0),
+            dropped_events: AtomicUsize::new(0),
+            late_ticks: AtomicUsize::new(0),
+            audio_nanos: AtomicU64::new(0),
+            start_latency_micros: AtomicU32::new(0),
+        }
+    }
+
+    /// Records how late a scheduler tick fired; a tick later than a whole
+    /// tick period counts as late.
+    pub fn record_tick(&self, lateness: Duration, tick: Duration) {
+        let micros = lateness.as_micros().min(u32::MAX as u128) as u32;
+        self.jitter_micros.store(micros, Ordering::Relaxed);
+        self.max_jitter_micros.fetch_max(micros, Ordering::Relaxed);
+        if lateness > tick {
+            self.late_ticks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset_max_jitter(&self) {
+        self.max_jitter_micros.store(0, Ordering::Relaxed);
+    }
+
+    pub fn jitter_ms(&self) -> f32 {
+        self.jitter_micros.load(Ordering::Relaxed) as f32 / 1000.0
+    }
+
+    pub fn max_jitter_ms(&self) -> f32 {
+        self.max_jitter_micros.load(Ordering::Relaxed) as f32 / 1000.0
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.active_voices.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_events(&self) -> usize {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    pub fn late_ticks(&self) -> usize {
+        self.late_ticks.load(Ordering::Relaxed)
+    }
+
+    /// Folds in how long a voice waited for the output to pull it, keeping
+    /// a running average so a single slow start doesn't jump the playhead.
+    pub fn record_start_latency(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u32::MAX as u128) as u32;
+        let average = self.start_latency_micros.load(Ordering::Relaxed);
+        let next = if average == 0 { micros } else { (average as u64 * 7 / 8 + micros as u64 / 8) as u32 };
+        self.start_latency_micros.store(next, Ordering::Relaxed);
+    }
+
+    /// Measured time from starting a voice to it being mixed; the device
+    /// buffer after that is not included.
+    pub fn start_latency(&self) -> Duration {
+        Duration::from_micros(self.start_latency_micros.load(Ordering::Relaxed) as u64)
+    }
+
+    /// Takes the estimated time spent rendering voices since the last call.
+    pub fn take_audio_time(&self) -> Duration {
+        Duration::from_nanos(self.audio_nanos.swap(0, Ordering::Relaxed))
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Source wrapper counting itself as an active voice and estimating the
+/// time the output callback spends pulling samples from it.
+pub struct Metered<S> {
+    inner: S,
+    counter: u32,
+    /// When the voice started, until its first sample is pulled.
+    started: Option<Instant>,
+}
+
+#[cfg(feature = "audio")]
+impl<S> Metered<S> {
+    pub fn new(inner: S) -> Self {
+        DIAGNOSTICS.active_voices.fetch_add(1, Ordering::Relaxed);
+        Self { inner, counter: 0, started: Some(Instant::now()) }
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S> Drop for Metered<S> {
+    fn drop(&mut self) {
+        DIAGNOSTICS.active_voices.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: Source> Iterator for Metered<S>
+where
+    S::Item: rodio::Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(started) = self.started.take() {
+            DIAGNOSTICS.record_start_latency(started.elapsed());
+        }
+        self.counter = self.counter.wrapping_add(1);
+        if !self.counter.is_multiple_of(CPU_SAMPLE_EVERY) {
+            return self.inner.next();
+        }
+        let start = Instant::now();
+        let sample = self.inner.next();
+        let nanos = start.elapsed().as_nanos() as u64 * CPU_SAMPLE_EVERY as u64;
+        DIAGNOSTICS.audio_nanos.fetch_add(nanos, Ordering::Relaxed);
+        sample
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: Source> Source for Metered<S>
+where
+    S::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
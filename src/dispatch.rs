@@ -0,0 +1,275 @@
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+
+use crate::beats_to_millis;
+use crate::diagnostics::DIAGNOSTICS;
+use crate::instrument::{Instrument, Playback};
+use crate::{EngineEvent, Events};
+
+/// Triggers that can wait for a worker before new ones are dropped.
+const QUEUE_SIZE: usize = 256;
+
+/// A step ready to play: waits out its micro-offset, then fires once per
+/// ratchet repeat.
+pub struct TriggerJob {
+    pub instrument: Arc<dyn Instrument>,
+    pub track: String,
+    pub note: u8,
+    pub velocity: f32,
+    pub duration: f32,
+    pub playback: Playback,
+    pub offset_secs: f32,
+    pub ratchet: u32,
+    pub interval_secs: f32,
+}
+
+impl TriggerJob {
+    /// The job's hits, each with the time it is due.
+    fn hits(self, now: Instant) -> impl Iterator<Item = (Instant, Hit)> {
+        let start = now + Duration::from_secs_f32(self.offset_secs.max(0.0));
+        let interval = Duration::from_secs_f32(self.interval_secs.max(0.0));
+        (0..self.ratchet.max(1)).map(move |n| {
+            let hit = Hit {
+                instrument: Arc::clone(&self.instrument),
+                track: self.track.clone(),
+                note: self.note,
+                velocity: self.velocity,
+                duration: self.duration,
+                playback: self.playback.clone(),
+            };
+            (start + interval * n, hit)
+        })
+    }
+}
+
+/// A single note of a job, played by a worker once it is due.
+struct Hit {
+    instrument: Arc<dyn Instrument>,
+    track: String,
+    note: u8,
+    velocity: f32,
+    duration: f32,
+    playback: Playback,
+}
+
+impl Hit {
+    fn run(self, events: &Events, timer: &Sender<TimerMessage>, pending: &AtomicUsize) {
+        events.publish(EngineEvent::Trigger {
+            track: self.track,
+            note: self.note,
+            velocity: self.velocity,
+            duration: self.duration,
+            pan: self.playback.pan,
+            at: Instant::now(),
+        });
+        self.instrument.trigger(self.note, self.velocity, self.duration, &self.playback);
+        if self.instrument.holds_note() {
+            // Counted until the note-off, so draining waits for held notes
+            pending.fetch_add(1, Ordering::SeqCst);
+            let at = Instant::now() + Duration::from_millis(beats_to_millis(self.duration, self.playback.bpm));
+            let release = Timed::Release { instrument: self.instrument, note: self.note };
+            if let Err(e) = timer.send(TimerMessage::Schedule(at, release)) {
+                // The timer has stopped, so end the note right away
+                if let TimerMessage::Schedule(_, release) = e.into_inner() {
+                    release.fire(pending);
+                }
+            }
+        }
+    }
+}
+
+/// Work waiting on the timer thread for its deadline.
+enum Timed {
+    /// A hit to hand to its worker queue.
+    Hit { hit: Hit, queue: Sender<Hit> },
+    /// The end of a held note.
+    Release { instrument: Arc<dyn Instrument>, note: u8 },
+}
+
+impl Timed {
+    fn fire(self, pending: &AtomicUsize) {
+        match self {
+            Timed::Hit { hit, queue } => enqueue(&queue, hit, pending),
+            Timed::Release { instrument, note } => {
+                instrument.release(note);
+                pending.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// What the timer thread is sent.
+enum TimerMessage {
+    Schedule(Instant, Timed),
+    /// Ends the timer: note-offs still waiting are sent right away.
+    Stop,
+}
+
+/// Heap entry of the timer, earliest deadline first and in order of
+/// scheduling between equal deadlines.
+struct Deadline {
+    at: Instant,
+    seq: u64,
+    timed: Timed,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // Reversed, so the max-heap pops the earliest deadline
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// Worker threads spawned once and fed over bounded channels, audio and
+/// MIDI apart so a slow port never delays a sample. Offsets, ratchet
+/// repeats and note-offs wait on a timer thread instead of a worker.
+pub struct Dispatcher {
+    audio: Sender<Hit>,
+    midi: Sender<Hit>,
+    timer: Sender<TimerMessage>,
+    pending: Arc<AtomicUsize>,
+    workers: Vec<thread::JoinHandle<()>>,
+    timer_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    /// Spawns `audio_workers` threads for samples and loops and
+    /// `midi_workers` for MIDI notes, all of which return as soon as the
+    /// note started.
+    pub fn new(events: Arc<Events>, audio_workers: usize, midi_workers: usize) -> Self {
+        let pending = Arc::new(AtomicUsize::new(0));
+        let (audio, audio_jobs) = bounded(QUEUE_SIZE);
+        let (midi, midi_jobs) = bounded(QUEUE_SIZE);
+        let (timer, deadlines) = unbounded();
+        let mut workers = Vec::new();
+        for (jobs, count) in [(audio_jobs, audio_workers), (midi_jobs, midi_workers)] {
+            for _ in 0..count.max(1) {
+                workers.push(spawn_worker(jobs.clone(), Arc::clone(&events), timer.clone(), Arc::clone(&pending)));
+            }
+        }
+        let timer_thread = Some(spawn_timer(deadlines, Arc::clone(&pending)));
+        Dispatcher { audio, midi, timer, pending, workers, timer_thread }
+    }
+
+    /// Queues a trigger without blocking the scheduler; it is dropped when
+    /// the workers are that far behind.
+    pub fn dispatch(&self, job: TriggerJob) {
+        let queue = if job.instrument.holds_note() { &self.midi } else { &self.audio };
+        let now = Instant::now();
+        for (at, hit) in job.hits(now) {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if at <= now {
+                enqueue(queue, hit, &self.pending);
+            } else if self.timer.send(TimerMessage::Schedule(at, Timed::Hit { hit, queue: queue.clone() })).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Waits until every queued trigger has played and its note ended.
+    pub fn drain(&self) {
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        // Stopping the timer sends the note-offs still waiting; closing the
+        // channels then lets the workers run out of jobs and exit
+        let _ = self.timer.send(TimerMessage::Stop);
+        if let Some(timer) = self.timer_thread.take() {
+            let _ = timer.join();
+        }
+        let (audio, _) = bounded(0);
+        let (midi, _) = bounded(0);
+        drop(std::mem::replace(&mut self.audio, audio));
+        drop(std::mem::replace(&mut self.midi, midi));
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Hands `hit` to a worker, dropping it when the queue is full.
+fn enqueue(queue: &Sender<Hit>, hit: Hit, pending: &AtomicUsize) {
+    if let Err(TrySendError::Full(hit) | TrySendError::Disconnected(hit)) = queue.try_send(hit) {
+        pending.fetch_sub(1, Ordering::SeqCst);
+        log_warn!("Trigger queue full, dropping a hit on {}", hit.track);
+        DIAGNOSTICS.record_dropped();
+    }
+}
+
+fn spawn_worker(
+    jobs: Receiver<Hit>,
+    events: Arc<Events>,
+    timer: Sender<TimerMessage>,
+    pending: Arc<AtomicUsize>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for hit in jobs {
+            hit.run(&events, &timer, &pending);
+            pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    })
+}
+
+/// Holds scheduled work until its deadline, earliest first.
+fn spawn_timer(messages: Receiver<TimerMessage>, pending: Arc<AtomicUsize>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut heap = BinaryHeap::new();
+        let mut seq = 0;
+        loop {
+            let received = match heap.peek() {
+                Some(Deadline { at, .. }) => messages.recv_deadline(*at),
+                None => messages.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(TimerMessage::Schedule(at, timed)) => {
+                    heap.push(Deadline { at, seq, timed });
+                    seq += 1;
+                }
+                Ok(TimerMessage::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            let now = Instant::now();
+            while heap.peek().is_some_and(|deadline| deadline.at <= now) {
+                heap.pop().unwrap().timed.fire(&pending);
+            }
+        }
+        // Nothing plays from here on, but held notes still get their note-off
+        for deadline in heap {
+            match deadline.timed {
+                Timed::Hit { .. } => {
+                    pending.fetch_sub(1, Ordering::SeqCst);
+                }
+                release => release.fire(&pending),
+            }
+        }
+    })
+}
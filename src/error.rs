@@ -0,0 +1,68 @@
+use thiserror::Error;
+
+/// Failures loading the files the sequencer plays from, opening its ports
+/// and servers, and switching songs. Callers skip what failed (a sample
+/// label, the MIDI track) and report it instead of aborting.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("{path}: {source}")]
+    Midi { path: String, source: midly::Error },
+    #[error("{path}: only metrical (ticks per beat) MIDI timing is supported")]
+    MidiTiming { path: String },
+    #[error("{path}: no track named '{track}'")]
+    MidiTrackNotFound { path: String, track: String },
+    #[error("{path}: {source}")]
+    Sample { path: String, source: hound::Error },
+    #[error("{path}: {reason}")]
+    Loop { path: String, reason: String },
+    /// A kit.json, loops.json or drumkit.xml that can't be read.
+    #[error("{path}: {reason}")]
+    Manifest { path: String, reason: String },
+    /// A file whose name can't be turned into a label.
+    #[error("{path}: {reason}")]
+    FileName { path: String, reason: String },
+    #[error("audio output: {reason}")]
+    Audio { reason: String },
+    #[error("MIDI port '{port}': {reason}")]
+    MidiPort { port: String, reason: String },
+    #[error("MIDI output: {reason}")]
+    MidiSend { reason: String },
+    /// A config, pattern, setlist or history file that doesn't parse.
+    #[error("{path}: {reason}")]
+    Parse { path: String, reason: String },
+    /// Settings or patterns that can't be written in their file's format.
+    #[error("{path}: {reason}")]
+    Serialize { path: String, reason: String },
+    #[error("pattern script: {reason}")]
+    Script { reason: String },
+    #[error("{path}: {reason}")]
+    Archive { path: String, reason: String },
+    /// An address a server can't listen on or send to.
+    #[error("{address}: {source}")]
+    Network { address: String, source: std::io::Error },
+    /// A patterns file a client asked for that is outside the project or
+    /// not readable as patterns; deliberately says no more than that.
+    #[error("'{path}' is not a pattern file in the project")]
+    PatternFileRejected { path: String },
+    #[error("setlist: {reason}")]
+    Setlist { reason: String },
+    /// A setlist song that failed to load.
+    #[error("{title}: {source}")]
+    Song { title: String, source: Box<Error> },
+}
+
+impl Error {
+    /// Wraps an I/O error with the path it happened on.
+    pub fn io(path: &std::path::Path, source: std::io::Error) -> Self {
+        Error::Io { path: path.display().to_string(), source }
+    }
+
+    /// A parse failure in the file at `path`.
+    pub fn parse(path: &str, reason: impl std::fmt::Display) -> Self {
+        Error::Parse { path: path.to_string(), reason: reason.to_string() }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::config;
+use crate::error::{self, Error};
+use crate::model::{Pattern, Track};
+use crate::notation;
+use crate::presets::{self, Preset};
+use crate::scripting;
+
+/// Serialization formats accepted for config and pattern files.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Picks the format from the file extension, defaulting to JSON.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+
+    /// Parses `content`, read from `path`.
+    pub fn parse<T: DeserializeOwned>(self, path: &str, content: &str) -> error::Result<T> {
+        match self {
+            Format::Json => serde_json::from_str(content).map_err(|e| Error::parse(path, e)),
+            Format::Toml => toml::from_str(content).map_err(|e| Error::parse(path, e)),
+            Format::Yaml => serde_yaml::from_str(content).map_err(|e| Error::parse(path, e)),
+        }
+    }
+
+    /// Lays out `value` to be written to `path`.
+    pub fn to_string<T: Serialize>(self, path: &str, value: &T) -> error::Result<String> {
+        let serialize_error = |reason: String| Error::Serialize { path: path.to_string(), reason };
+        match self {
+            Format::Json => serde_json::to_string_pretty(value).map_err(|e| serialize_error(e.to_string())),
+            Format::Toml => toml::to_string_pretty(value).map_err(|e| serialize_error(e.to_string())),
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| serialize_error(e.to_string())),
+        }
+    }
+}
+
+/// Indent of hand-written JSON pattern files.
+const JSON_INDENT: &[u8] = b"    ";
+
+#[derive(Serialize)]
+struct PatternList<'a> {
+    patterns: &'a [Pattern],
+}
+
+/// Patterns laid out as a hand-written pattern file in the format given by
+/// the extension of `path`: a bare list in JSON and YAML, under `patterns`
+/// in TOML. Reading the result back gives the same patterns.
+pub fn patterns_to_string(path: &str, patterns: &[Pattern]) -> error::Result<String> {
+    match Format::from_path(path) {
+        Format::Json => {
+            let mut json = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(JSON_INDENT);
+            patterns
+                .serialize(&mut serde_json::Serializer::with_formatter(&mut json, formatter))
+                .map_err(|e| Error::Serialize { path: path.to_string(), reason: e.to_string() })?;
+            // serde_json only writes UTF-8
+            Ok(String::from_utf8(json).expect("JSON is UTF-8"))
+        }
+        Format::Toml => Format::Toml.to_string(path, &PatternList { patterns }),
+        Format::Yaml => Format::Yaml.to_string(path, &patterns),
+    }
+}
+
+pub fn write_patterns(path: &str, patterns: &[Pattern]) -> error::Result<()> {
+    fs::write(path, patterns_to_string(path, patterns)?).map_err(|e| Error::io(Path::new(path), e))
+}
+
+/// How deep includes may nest, which also stops include cycles.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Pattern file as a table: loose `patterns`, named `tracks` owning
+/// theirs, user-defined rhythm `presets` and other pattern files to `include`. TOML has no top-level
+/// arrays so always uses it, and project files keep their patterns under
+/// the same keys.
+#[derive(Deserialize, Default)]
+struct PatternTable {
+    #[serde(default)]
+    patterns: Vec<Pattern>,
+    #[serde(default)]
+    tracks: Vec<Track>,
+    #[serde(default)]
+    presets: BTreeMap<String, Preset>,
+    /// Pattern files merged in, relative to this one.
+    #[serde(default)]
+    include: Vec<String>,
+}
+
+impl PatternTable {
+    /// Loose patterns followed by the flattened track patterns.
+    fn into_patterns(self) -> Vec<Pattern> {
+        let mut patterns = self.patterns;
+        patterns.extend(self.tracks.iter().flat_map(Track::flatten));
+        patterns
+    }
+}
+
+/// Whether a JSON or YAML pattern file is a bare list of patterns rather than a table.
+fn is_list(content: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim_start)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .is_none_or(|line| line.starts_with('[') || line.starts_with('-'))
+}
+
+/// Reads a pattern file and, recursively, the files it includes. Scripts
+/// only run when `loop_beats` is given.
+fn load(path: &str, content: &str, loop_beats: Option<u32>, depth: usize) -> error::Result<PatternTable> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Error::parse(path, format!("includes nested more than {} deep", MAX_INCLUDE_DEPTH)));
+    }
+    let mut table = match Format::from_path(path) {
+        _ if path.ends_with(".rhai") => PatternTable {
+            patterns: match loop_beats {
+                Some(loop_beats) => scripting::eval_patterns(content, loop_beats).map_err(|e| Error::parse(path, e))?,
+                None => Vec::new(),
+            },
+            ..Default::default()
+        },
+        _ if config::is_project(path) => Format::Json.parse(path, content)?,
+        Format::Toml => Format::Toml.parse(path, content)?,
+        format if is_list(content) => PatternTable { patterns: format.parse(path, content)?, ..Default::default() },
+        format => format.parse(path, content)?,
+    };
+
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    for include in std::mem::take(&mut table.include) {
+        let include_path = dir.join(&include).to_string_lossy().into_owned();
+        let content = fs::read_to_string(&include_path).map_err(|e| Error::io(Path::new(&include_path), e))?;
+        let included = load(&include_path, &content, loop_beats, depth + 1)?;
+        table.patterns.extend(included.patterns);
+        table.tracks.extend(included.tracks);
+        for (name, preset) in included.presets {
+            table.presets.entry(name).or_insert(preset);
+        }
+    }
+    Ok(table)
+}
+
+/// Named tracks declared in a pattern file and its includes, for their
+/// mixer and color settings.
+pub fn parse_tracks(path: &str, content: &str) -> error::Result<Vec<Track>> {
+    Ok(load(path, content, None, 0)?.tracks)
+}
+
+/// Presets declared in a pattern file and its includes.
+pub fn parse_presets(path: &str, content: &str) -> error::Result<BTreeMap<String, Preset>> {
+    Ok(load(path, content, None, 0)?.presets)
+}
+
+/// Parses a pattern file in the format given by its extension, or runs it
+/// when it is a `.rhai` script, merging in included files and expanding
+/// presets and mini-notation rows across a loop of `loop_beats`. Included files are
+/// read again on every call, so they are watched along with the main one.
+pub fn parse_patterns(path: &str, content: &str, loop_beats: u32) -> error::Result<Vec<Pattern>> {
+    let mut table = load(path, content, Some(loop_beats), 0)?;
+    let user_presets = std::mem::take(&mut table.presets);
+    let patterns = presets::expand(table.into_patterns(), &user_presets).map_err(|e| Error::parse(path, e))?;
+    notation::expand(patterns, loop_beats).map_err(|e| Error::parse(path, e))
+}
@@ -1,98 +1,1385 @@
-use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock}, time::Duration};
+use std::{collections::{BTreeMap, HashMap}, ops::RangeInclusive, sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock}, time::{Duration, Instant}};
 
+use arc_swap::ArcSwap;
 use eframe::egui;
 
-use crate::model::Pattern;
+use crate::config::{GuiConfig, Theme};
+use crate::browser::{BrowserItem, SampleBrowser};
+use crate::diagnostics::DIAGNOSTICS;
+use crate::keyboard::PianoKeyboard;
+use crate::recorder::{PadHit, Recorder};
+use crate::settings::SettingsDialog;
+use crate::toasts::{level_color, Toasts};
+use crate::mixer::Mixer;
+use crate::scene::Scene;
+use crate::notation;
+use crate::presets::{self, Preset};
+use crate::model::{bank_names, Pattern, PatternBuilder, Step, Ticks};
+use crate::selection::{Clipboard, Selection};
+use crate::session::Session;
+use crate::song::SongSection;
+use crate::transport::{LiveQuantize, TapTempo, Transport};
+use crate::step_grid::{self, RESOLUTION};
+use crate::edit_patterns;
+use crate::logging;
+
+const BROWSER_WIDTH: f32 = 200.0;
+const MIXER_HEIGHT: f32 = 180.0;
+const REPAINT_INTERVAL: Duration = Duration::from_millis(16);
+const BEATS_PER_BAR: f32 = 4.0;
+/// The scheduler publishes the current beat in 1/8 beat steps.
+const SCHEDULER_STEP: f32 = 0.125;
+const TRACK_KEYS: [egui::Key; 9] = [
+    egui::Key::Num1,
+    egui::Key::Num2,
+    egui::Key::Num3,
+    egui::Key::Num4,
+    egui::Key::Num5,
+    egui::Key::Num6,
+    egui::Key::Num7,
+    egui::Key::Num8,
+    egui::Key::Num9,
+];
+const VARIATION_COUNT: u32 = 4;
+/// Keys playing the first nine rows as pads while record is armed.
+const PAD_KEYS: [egui::Key; 9] = [
+    egui::Key::Q,
+    egui::Key::W,
+    egui::Key::E,
+    egui::Key::R,
+    egui::Key::T,
+    egui::Key::Y,
+    egui::Key::U,
+    egui::Key::I,
+    egui::Key::O,
+];
+/// Keys that can be mapped to samples in pad mode, by name.
+const MAPPABLE_KEYS: [egui::Key; 36] = [
+    egui::Key::A,
+    egui::Key::B,
+    egui::Key::C,
+    egui::Key::D,
+    egui::Key::E,
+    egui::Key::F,
+    egui::Key::G,
+    egui::Key::H,
+    egui::Key::I,
+    egui::Key::J,
+    egui::Key::K,
+    egui::Key::L,
+    egui::Key::M,
+    egui::Key::N,
+    egui::Key::O,
+    egui::Key::P,
+    egui::Key::Q,
+    egui::Key::R,
+    egui::Key::S,
+    egui::Key::T,
+    egui::Key::U,
+    egui::Key::V,
+    egui::Key::W,
+    egui::Key::X,
+    egui::Key::Y,
+    egui::Key::Z,
+    egui::Key::Num0,
+    egui::Key::Num1,
+    egui::Key::Num2,
+    egui::Key::Num3,
+    egui::Key::Num4,
+    egui::Key::Num5,
+    egui::Key::Num6,
+    egui::Key::Num7,
+    egui::Key::Num8,
+    egui::Key::Num9,
+];
+/// Home row keys given the first samples when no pads are configured.
+const DEFAULT_PAD_KEYS: [egui::Key; 9] = [
+    egui::Key::A,
+    egui::Key::S,
+    egui::Key::D,
+    egui::Key::F,
+    egui::Key::G,
+    egui::Key::H,
+    egui::Key::J,
+    egui::Key::K,
+    egui::Key::L,
+];
+/// Window size on startup; afterwards the layout follows whatever size the user picks.
+pub const INITIAL_WINDOW_SIZE: egui::Vec2 = egui::vec2(1200.0, 720.0);
+const KEYBOARD_WINDOW_SIZE: egui::Vec2 = egui::vec2(340.0, 150.0);
+/// Width reserved for the row labels in front of the cells.
+const LABEL_WIDTH: f32 = 50.0;
+
+/// Pad keys from the config's key names, or the home row on the first
+/// samples when there are none.
+fn pad_keys(pads: &BTreeMap<String, String>, sound_labels: Vec<String>) -> Vec<(egui::Key, String)> {
+    if pads.is_empty() {
+        return DEFAULT_PAD_KEYS.into_iter().zip(sound_labels).collect();
+    }
+    pads.iter()
+        .filter_map(|(name, label)| {
+            let key = MAPPABLE_KEYS.into_iter().find(|key| key.name().eq_ignore_ascii_case(name));
+            if key.is_none() {
+                log_error!("Invalid pad key '{}' for '{}'; use a letter or digit", name, label);
+            }
+            key.map(|key| (key, label.clone()))
+        })
+        .collect()
+}
+
+/// Parses "#rrggbb" into a color, returning None for anything else.
+fn parse_hex_color(hex: &str) -> Option<egui::Color32> {
+    let hex = hex.strip_prefix('#')?;
+    // Checked digit by digit, as from_str_radix takes a sign and slicing needs ASCII
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let [_, r, g, b] = u32::from_str_radix(hex, 16).ok()?.to_be_bytes();
+    Some(egui::Color32::from_rgb(r, g, b))
+}
+
+/// Vertical meter: RMS as a filled bar, peak as a line, turning red near clipping.
+fn level_meter(ui: &mut egui::Ui, peak: f32, rms: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(8.0, 100.0), egui::Sense::hover());
+    let height_for = |level: f32| rect.bottom() - rect.height() * level.clamp(0.0, 1.0);
+
+    let mut fill_rect = rect;
+    fill_rect.set_top(height_for(rms));
+    let color = if peak >= 0.95 { egui::Color32::RED } else { egui::Color32::GREEN };
+    ui.painter().rect_filled(rect, 0.0, egui::Color32::DARK_GRAY);
+    ui.painter().rect_filled(fill_rect, 0.0, color);
+    ui.painter().hline(rect.x_range(), height_for(peak), egui::Stroke::new(2.0, color));
+}
+
+/// Shows a panel in its own native window so it can live on another monitor,
+/// falling back to a floating window where the backend has a single viewport.
+/// Returns false once the user closes it.
+fn show_detached(
+    ctx: &egui::Context,
+    id: &str,
+    title: &str,
+    size: egui::Vec2,
+    add_contents: impl FnOnce(&mut egui::Ui),
+) -> bool {
+    let builder = egui::ViewportBuilder::default().with_title(title).with_inner_size(size);
+    ctx.show_viewport_immediate(egui::ViewportId::from_hash_of(id), builder, |ctx, class| {
+        if class == egui::ViewportClass::Embedded {
+            let mut open = true;
+            egui::Window::new(title).open(&mut open).show(ctx, add_contents);
+            return open;
+        }
+        egui::CentralPanel::default().show(ctx, add_contents);
+        !ctx.input(|i| i.viewport().close_requested())
+    })
+}
+
+fn visuals(theme: Theme) -> egui::Visuals {
+    match theme {
+        Theme::Dark => egui::Visuals::dark(),
+        Theme::Light => egui::Visuals::light(),
+    }
+}
 
 pub struct PatternVisualizerApp {
-    patterns: Arc<RwLock<Vec<Pattern>>>,
+    patterns: Arc<ArcSwap<Vec<Pattern>>>,
     current_beat: Arc<RwLock<f32>>,
-    gui_ready: Arc<AtomicBool>,
-    bpm: u32,
+    /// Start the transport once the window is up, instead of on Play.
+    start_on_first_frame: bool,
+    /// Cleared when the window closes, stopping playback; closes the window when cleared by Ctrl+C.
+    running: Arc<AtomicBool>,
+    session: Arc<RwLock<Session>>,
+    /// Presets declared in the patterns file, listed after the built-in ones.
+    user_presets: Arc<RwLock<BTreeMap<String, Preset>>>,
+    mixer: Arc<RwLock<Mixer>>,
+    transport: Arc<Transport>,
+    tap_tempo: TapTempo,
+    browser: SampleBrowser,
+    keyboard: PianoKeyboard,
+    show_keyboard: bool,
+    /// The computer keyboard plays samples: (key, sound label).
+    pad_mode: bool,
+    pads: Vec<(egui::Key, String)>,
+    recorder: Recorder,
+    settings: SettingsDialog,
+    toasts: Toasts,
+    loop_beats: u32,
+    zoom: f32,
+    /// Keep the playhead in view by scrolling the grid a page at a time.
+    follow_playhead: bool,
+    /// Large-type fullscreen view for the stage.
+    performance_mode: bool,
+    /// Mixer shown in its own window instead of the bottom panel.
+    mixer_detached: bool,
+    /// Tracker-style entry cursor as (row, column); number keys set velocity while it is shown.
+    cursor: Option<(usize, usize)>,
+    theme: Theme,
+    track_colors: HashMap<String, egui::Color32>,
+    last_beat: f32,
+    last_beat_time: Instant,
+    /// Configured device latency the playhead is held back by.
+    output_latency: Duration,
+    selection: Option<Selection>,
+    selecting: bool,
+    clipboard: Clipboard,
+    /// Pattern indices of the rows currently shown, top to bottom.
+    visible_rows: Vec<usize>,
+    dragging_section: Option<usize>,
+    show_diagnostics: bool,
+    audio_load: f32,
+    audio_load_time: Instant,
+}
+
+/// What the window is built from: the engine state it shows and edits,
+/// and the panels set up for it.
+pub struct GridSetup {
+    pub patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    pub current_beat: Arc<RwLock<f32>>,
+    pub start_on_first_frame: bool,
+    pub running: Arc<AtomicBool>,
+    pub session: Arc<RwLock<Session>>,
+    /// Presets declared in the patterns file.
+    pub user_presets: Arc<RwLock<BTreeMap<String, Preset>>>,
+    pub mixer: Arc<RwLock<Mixer>>,
+    pub transport: Arc<Transport>,
+    pub browser: SampleBrowser,
+    pub keyboard: PianoKeyboard,
+    pub recorder: Recorder,
+    pub settings: SettingsDialog,
+    pub loop_beats: u32,
+    pub gui_config: GuiConfig,
 }
 
 impl PatternVisualizerApp {
-    pub fn new(
-        patterns: Arc<RwLock<Vec<Pattern>>>,
-        current_beat: Arc<RwLock<f32>>,
-        gui_ready: Arc<AtomicBool>,
-        bpm: u32,
-    ) -> Self {
+    pub fn new(setup: GridSetup) -> Self {
+        let GridSetup {
+            patterns,
+            current_beat,
+            start_on_first_frame,
+            running,
+            session,
+            user_presets,
+            mixer,
+            transport,
+            browser,
+            keyboard,
+            recorder,
+            settings,
+            loop_beats,
+            gui_config,
+        } = setup;
+        let track_colors = gui_config
+            .track_colors
+            .iter()
+            .filter_map(|(name, hex)| match parse_hex_color(hex) {
+                Some(color) => Some((name.clone(), color)),
+                None => {
+                    log_error!("Invalid color '{}' for track '{}'", hex, name);
+                    None
+                }
+            })
+            .collect();
+        let pads = pad_keys(&gui_config.pads, browser.sound_labels());
         Self {
             patterns,
             current_beat,
-            gui_ready,
-            bpm,
+            start_on_first_frame,
+            running,
+            session,
+            user_presets,
+            mixer,
+            transport,
+            tap_tempo: TapTempo::default(),
+            browser,
+            keyboard,
+            show_keyboard: false,
+            pad_mode: false,
+            pads,
+            recorder,
+            settings,
+            toasts: Toasts::default(),
+            loop_beats,
+            zoom: 1.0,
+            follow_playhead: true,
+            performance_mode: false,
+            mixer_detached: false,
+            cursor: None,
+            theme: gui_config.theme,
+            track_colors,
+            last_beat: 0.0,
+            last_beat_time: Instant::now(),
+            output_latency: Duration::from_millis(gui_config.latency_ms as u64),
+            selection: None,
+            selecting: false,
+            clipboard: Clipboard::default(),
+            visible_rows: Vec::new(),
+            dragging_section: None,
+            show_diagnostics: false,
+            audio_load: 0.0,
+            audio_load_time: Instant::now(),
+        }
+    }
+
+    /// Returns the scheduler position, interpolated from the wall clock since
+    /// the scheduler last published a beat.
+    fn scheduler_beat(&mut self) -> f32 {
+        let current_beat = *self.current_beat.read().unwrap();
+        if current_beat != self.last_beat {
+            self.last_beat = current_beat;
+            self.last_beat_time = Instant::now();
+        }
+        let elapsed_beats = self.last_beat_time.elapsed().as_secs_f32() * self.transport.bpm() as f32 / 60.0;
+        self.last_beat + elapsed_beats.min(SCHEDULER_STEP)
+    }
+
+    /// Returns the playhead position as heard: the scheduler position held
+    /// back by the measured and configured output latency.
+    pub fn update_grid(&mut self) -> f32 {
+        let latency = DIAGNOSTICS.start_latency() + self.output_latency;
+        let latency_beats = latency.as_secs_f32() * self.transport.bpm() as f32 / 60.0;
+        (self.scheduler_beat() - latency_beats).rem_euclid(self.loop_beats.max(1) as f32)
+    }
+
+    fn total_cols(&self) -> usize {
+        (self.loop_beats as f32 / RESOLUTION) as usize
+    }
+
+    fn is_step_on(&self, row: usize, col: usize) -> bool {
+        let beat = col as f32 * RESOLUTION;
+        self.visible_rows
+            .get(row)
+            .and_then(|index| self.patterns.load().get(*index).map(|p| p.has_step(beat)))
+            .unwrap_or(false)
+    }
+
+    /// Switches a grid cell on or off, recording the edit in the session.
+    fn set_step(&self, row: usize, col: usize, on: bool) {
+        let Some(index) = self.visible_rows.get(row) else { return };
+        let beat = col as f32 * RESOLUTION;
+        edit_patterns(&self.patterns, |patterns| {
+            if let Some(pattern) = patterns.get_mut(*index) {
+                pattern.set_beat(beat, on);
+                self.session.write().unwrap().record_beat_edit(pattern.track_name(), beat, on);
+            }
+        });
+    }
+
+    /// Runs `edit` on the pattern of each visible row in `rows`, with the
+    /// row, all in one pattern edit.
+    fn edit_rows(&self, rows: RangeInclusive<usize>, mut edit: impl FnMut(usize, &mut Pattern, &mut Session)) {
+        edit_patterns(&self.patterns, |patterns| {
+            let mut session = self.session.write().unwrap();
+            for row in rows.clone() {
+                if let Some(pattern) = self.visible_rows.get(row).and_then(|index| patterns.get_mut(*index)) {
+                    edit(row, pattern, &mut session);
+                }
+            }
+        });
+    }
+
+    fn copy_selection(&mut self, ctx: &egui::Context) {
+        let Some(selection) = self.selection else { return };
+        let (origin_row, origin_col) = selection.origin();
+        let mut clipboard = Clipboard {
+            rows: selection.rows().count(),
+            cols: selection.cols().count(),
+            cells: Vec::new(),
+        };
+        let patterns = self.patterns.load();
+        for row in selection.rows() {
+            let Some(pattern) = self.visible_rows.get(row).and_then(|index| patterns.get(*index)) else { continue };
+            for col in selection.cols() {
+                let beat = col as f32 * RESOLUTION;
+                if pattern.has_step(beat) {
+                    clipboard.cells.push((row - origin_row, col - origin_col, pattern.step_at(beat)));
+                }
+            }
+        }
+        ctx.output_mut(|o| o.copied_text = clipboard.to_text());
+        self.clipboard = clipboard;
+    }
+
+    fn clear_selection(&self) {
+        let Some(selection) = self.selection else { return };
+        self.edit_rows(selection.rows(), |_, pattern, session| {
+            let track = pattern.track_name().to_string();
+            for col in selection.cols() {
+                let beat = col as f32 * RESOLUTION;
+                if pattern.remove_step(beat).is_some() {
+                    session.record_beat_edit(&track, beat, false);
+                }
+            }
+        });
+    }
+
+    /// Pastes the clipboard with its top-left corner at the selection origin.
+    fn paste(&mut self) {
+        let Some(selection) = self.selection else { return };
+        if self.clipboard.rows == 0 {
+            return;
+        }
+        let (origin_row, origin_col) = selection.origin();
+        let total_cols = self.total_cols();
+        let clipboard = &self.clipboard;
+        self.edit_rows(origin_row..=origin_row + clipboard.rows - 1, |row, pattern, session| {
+            let track = pattern.track_name().to_string();
+            for col in 0..clipboard.cols.min(total_cols.saturating_sub(origin_col)) {
+                let beat = (origin_col + col) as f32 * RESOLUTION;
+                match clipboard.step(row - origin_row, col) {
+                    Some(step) => {
+                        let step = Step { position: Ticks::from_beats(beat), ..step.clone() };
+                        pattern.insert_step(step.clone());
+                        session.record_beat_edit(&track, beat, true);
+                        session.record_step_edit(&track, step);
+                    }
+                    None => {
+                        if pattern.remove_step(beat).is_some() {
+                            session.record_beat_edit(&track, beat, false);
+                        }
+                    }
+                }
+            }
+        });
+        self.selection = Some(Selection {
+            anchor: (origin_row, origin_col),
+            end: (
+                origin_row + self.clipboard.rows - 1,
+                (origin_col + self.clipboard.cols.max(1) - 1).min(total_cols - 1),
+            ),
+        });
+    }
+
+    /// Moves the selected steps (and the selection) by `delta` columns.
+    fn shift_selection(&mut self, delta: isize) {
+        let Some(selection) = self.selection else { return };
+        let total_cols = self.total_cols() as isize;
+        self.edit_rows(selection.rows(), |_, pattern, session| {
+            let track = pattern.track_name().to_string();
+            let mut moved = Vec::new();
+            for col in selection.cols() {
+                let beat = col as f32 * RESOLUTION;
+                if let Some(step) = pattern.remove_step(beat) {
+                    session.record_beat_edit(&track, beat, false);
+                    moved.push((col as isize + delta, step));
+                }
+            }
+            for (target, step) in moved {
+                if (0..total_cols).contains(&target) {
+                    let beat = target as f32 * RESOLUTION;
+                    let step = Step { position: Ticks::from_beats(beat), ..step };
+                    pattern.insert_step(step.clone());
+                    session.record_beat_edit(&track, beat, true);
+                    session.record_step_edit(&track, step);
+                }
+            }
+        });
+        self.selection = Some(selection.shifted(delta, total_cols as usize));
+    }
+
+    /// Performance shortcuts: 1-9 mute (shift: solo) tracks, M metronome,
+    /// T tap tempo, V next variation, F fill on the next loop pass, D diagnostics,
+    /// K keyboard, L follow playhead lock, P pad mode, F11 fullscreen performance view. While record is armed Q-O play the rows as pads and Esc disarms.
+    /// In pad mode the pad keys play their samples and Esc leaves it.
+    /// Editing: Ctrl+C/X/V copy, cut and paste the selection, Shift+arrows
+    /// shift it by a 16th, Delete clears it; arrows start tracker-style step entry.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            self.play_pads(Vec::new());
+            return;
+        }
+        let pressed: Vec<(egui::Key, egui::Modifiers, bool)> = ctx.input(|i| {
+            i.events
+                .iter()
+                .filter_map(|event| match event {
+                    egui::Event::Key { key, pressed: true, modifiers, repeat } => Some((*key, *modifiers, *repeat)),
+                    _ => None,
+                })
+                .collect()
+        });
+
+        let mut hits = Vec::new();
+        let mut sample_hits = Vec::new();
+        for (key, modifiers, repeat) in pressed {
+            if self.pad_mode && !modifiers.command {
+                if let Some((_, label)) = self.pads.iter().find(|(k, _)| *k == key) {
+                    if !repeat {
+                        sample_hits.push(label.clone());
+                    }
+                    continue;
+                }
+                if key == egui::Key::Escape {
+                    self.pad_mode = false;
+                    continue;
+                }
+            }
+            if self.recorder.armed() && !modifiers.command {
+                if let Some(row) = PAD_KEYS.iter().position(|k| *k == key) {
+                    if !repeat {
+                        hits.push((row, 100.0));
+                    }
+                    continue;
+                }
+                if key == egui::Key::Escape {
+                    self.recorder.set_armed(false);
+                    continue;
+                }
+            }
+            if !modifiers.command && !modifiers.shift && self.handle_cursor_key(key) {
+                continue;
+            }
+            if modifiers.command {
+                match key {
+                    egui::Key::C => self.copy_selection(ctx),
+                    egui::Key::X => {
+                        self.copy_selection(ctx);
+                        self.clear_selection();
+                    }
+                    egui::Key::V => self.paste(),
+                    _ => {}
+                }
+                continue;
+            }
+            match key {
+                egui::Key::ArrowRight if modifiers.shift => self.shift_selection(1),
+                egui::Key::ArrowLeft if modifiers.shift => self.shift_selection(-1),
+                egui::Key::Delete | egui::Key::Backspace => self.clear_selection(),
+                _ => {}
+            }
+            if !repeat {
+                self.handle_performance_key(key, modifiers.shift);
+            }
+        }
+        self.play_pads(hits);
+        self.play_sample_pads(sample_hits);
+    }
+
+    fn frame_shown(&self) {
+        if self.start_on_first_frame {
+            self.transport.start();
+        }
+    }
+
+    /// How long a live hit now waits for the live quantize grid, and the
+    /// grid column it lands on.
+    fn live_timing(&mut self) -> (Duration, usize) {
+        // Hits go through the same output latency as the sequence, so they line up with the scheduler
+        let beat = self.scheduler_beat();
+        let delay_beats = self.transport.live_quantize().delay(beat);
+        let delay = Duration::from_secs_f32(delay_beats * 60.0 / self.transport.bpm() as f32);
+        (delay, ((beat + delay_beats) / RESOLUTION).round() as usize % self.total_cols())
+    }
+
+    /// Plays the samples of the pad keys hit in pad mode, on their own
+    /// tracks, and while record is armed writes them into the rows playing
+    /// those samples.
+    fn play_sample_pads(&mut self, labels: Vec<String>) {
+        if labels.is_empty() {
+            return;
+        }
+        let (delay, col) = self.live_timing();
+        for label in labels {
+            let pattern = PatternBuilder::new().sound(&label).build();
+            let (gain, pan, meter) = {
+                let mut mixer = self.mixer.write().unwrap();
+                (mixer.gain(&label), mixer.pan(&label), mixer.meter(&label))
+            };
+            if gain > 0.0 {
+                self.browser.trigger(&pattern, 100.0 * gain, pan, meter, delay);
+            }
+            if self.recorder.armed() {
+                let patterns = self.patterns.load();
+                let row = self.visible_rows.iter().position(|index| patterns.get(*index).is_some_and(|p| p.sound.as_deref() == Some(label.as_str())));
+                if let Some(row) = row {
+                    self.set_step(row, col, true);
+                }
+            }
+        }
+    }
+
+    /// Plays pad hits, held back to the live quantize grid, and while record
+    /// is armed writes them into their rows at the grid step they play on.
+    fn play_pads(&mut self, mut hits: Vec<PadHit>) {
+        hits.extend(self.recorder.take_midi_hits());
+        if hits.is_empty() {
+            return;
+        }
+        let (delay, col) = self.live_timing();
+        for (row, velocity) in hits {
+            let Some(index) = self.visible_rows.get(row).copied() else { continue };
+            let Some(pattern) = self.patterns.load().get(index).cloned() else { continue };
+            let track = pattern.track_name();
+            let (gain, pan, meter) = {
+                let mut mixer = self.mixer.write().unwrap();
+                (mixer.gain(track), mixer.pan(track), mixer.meter(track))
+            };
+            if gain > 0.0 {
+                self.browser.trigger(&pattern, velocity * gain, pan, meter, delay);
+            }
+            if self.recorder.armed() {
+                self.set_step(row, col, true);
+            }
+        }
+    }
+
+    /// Tracker-style step entry: arrows move the cursor, Space toggles the
+    /// step under it, 1-9 set it to a velocity bucket and Esc leaves entry.
+    /// Returns whether the key was consumed.
+    fn handle_cursor_key(&mut self, key: egui::Key) -> bool {
+        let rows = self.visible_rows.len();
+        let cols = self.total_cols();
+        if rows == 0 || cols == 0 {
+            return false;
+        }
+        let (row, col) = match (self.cursor, key) {
+            (None, egui::Key::ArrowUp | egui::Key::ArrowDown | egui::Key::ArrowLeft | egui::Key::ArrowRight) => {
+                self.cursor = Some((0, 0));
+                return true;
+            }
+            (None, _) => return false,
+            (Some((row, col)), _) => (row.min(rows - 1), col.min(cols - 1)),
+        };
+        match key {
+            egui::Key::ArrowUp => self.cursor = Some((row.saturating_sub(1), col)),
+            egui::Key::ArrowDown => self.cursor = Some(((row + 1).min(rows - 1), col)),
+            egui::Key::ArrowLeft => self.cursor = Some((row, (col + cols - 1) % cols)),
+            egui::Key::ArrowRight => self.cursor = Some((row, (col + 1) % cols)),
+            egui::Key::Space => self.set_step(row, col, !self.is_step_on(row, col)),
+            egui::Key::Escape => self.cursor = None,
+            _ => match TRACK_KEYS.iter().position(|k| *k == key) {
+                Some(bucket) => {
+                    self.set_step(row, col, true);
+                    self.set_step_velocity(row, col, (bucket + 1) as f32 / TRACK_KEYS.len() as f32 * 127.0);
+                }
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// Overrides the velocity of one step, recording the edit in the session.
+    fn set_step_velocity(&self, row: usize, col: usize, velocity: f32) {
+        let Some(index) = self.visible_rows.get(row) else { return };
+        let beat = col as f32 * RESOLUTION;
+        edit_patterns(&self.patterns, |patterns| {
+            if let Some(pattern) = patterns.get_mut(*index) {
+                let mut settings = pattern.step_at(beat);
+                settings.velocity = Some(velocity.round());
+                pattern.set_step(settings.clone());
+                self.session.write().unwrap().record_step_edit(pattern.track_name(), settings);
+            }
+        });
+    }
+
+    fn handle_performance_key(&mut self, key: egui::Key, shift: bool) {
+        if let Some(index) = TRACK_KEYS.iter().position(|k| *k == key) {
+            let mut mixer = self.mixer.write().unwrap();
+            if shift {
+                mixer.toggle_solo(index);
+            } else {
+                mixer.toggle_mute(index);
+            }
+            return;
+        }
+        match key {
+            egui::Key::M => self.transport.toggle_metronome(),
+            egui::Key::T => {
+                if let Some(bpm) = self.tap_tempo.tap() {
+                    self.transport.set_bpm(bpm);
+                    log!("Tap tempo: {} BPM", bpm);
+                }
+            }
+            egui::Key::V => {
+                let next = (self.transport.variation() + 1) % VARIATION_COUNT;
+                self.transport.set_variation(next);
+            }
+            egui::Key::F => self.transport.queue_fill(),
+            egui::Key::D => self.show_diagnostics = !self.show_diagnostics,
+            egui::Key::K => self.show_keyboard = !self.show_keyboard,
+            egui::Key::P => self.pad_mode = !self.pad_mode,
+            egui::Key::L => self.follow_playhead = !self.follow_playhead,
+            egui::Key::F11 => self.performance_mode = !self.performance_mode,
+            _ => {}
+        }
+    }
+
+    /// Bank tabs; selecting one queues it for the next loop boundary.
+    fn show_bank_tabs(&self, ui: &mut egui::Ui) {
+        let banks = bank_names(&self.patterns.load());
+        if banks.is_empty() {
+            return;
+        }
+        let active = self.transport.active_bank();
+        let queued = self.transport.queued_bank();
+        ui.horizontal(|ui| {
+            ui.label("Banks");
+            for bank in banks.iter() {
+                let text = if queued.as_ref() == Some(bank) {
+                    format!("{} (queued)", bank)
+                } else {
+                    bank.clone()
+                };
+                if ui.selectable_label(*bank == active, text).clicked() && *bank != active {
+                    self.transport.queue_bank(bank);
+                }
+            }
+        });
+    }
+
+    /// Scene buttons; clicking one launches it at the next bar, and the +
+    /// button captures the bank and mixer as they are into a new scene.
+    fn show_scene_tabs(&self, ui: &mut egui::Ui) {
+        let queued = self.transport.queued_scene();
+        let names: Vec<String> = self.transport.scenes.read().unwrap().iter().map(|s| s.name.clone()).collect();
+        ui.horizontal(|ui| {
+            ui.label("Scenes");
+            for name in names.iter() {
+                let text = if queued.as_ref() == Some(name) { format!("{} (queued)", name) } else { name.clone() };
+                if ui.button(text).clicked() {
+                    if let Err(e) = self.transport.queue_scene(name) {
+                        log_error!("{}", e);
+                    }
+                }
+            }
+            if ui.button("+").on_hover_text("Capture the bank and mixer as a scene").clicked() {
+                let name = format!("Scene {}", names.len() + 1);
+                let scene = Scene::capture(&name, &self.transport.active_bank(), &self.mixer.read().unwrap());
+                self.transport.scenes.write().unwrap().push(scene);
+                log!("Captured {}", name);
+            }
+        });
+    }
+
+    /// Song view: sections on a horizontal timeline, sized by repeat count,
+    /// drag a section onto another to reorder.
+    fn show_arrangement(&mut self, ui: &mut egui::Ui) {
+        let banks = bank_names(&self.patterns.load());
+        let mut song = self.transport.song.write().unwrap();
+        let playing = song.position();
+        let mut drop_target = None;
+        let mut removed = None;
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut song.enabled, "Song mode");
+            ui.separator();
+            let enabled = song.enabled;
+            for (index, section) in song.sections.iter_mut().enumerate() {
+                let fill = if enabled && index == playing {
+                    ui.visuals().selection.bg_fill
+                } else {
+                    ui.visuals().faint_bg_color
+                };
+                let response = egui::Frame::group(ui.style())
+                    .fill(fill)
+                    .show(ui, |ui| {
+                        ui.set_width(60.0 * section.repeats.max(1) as f32);
+                        ui.horizontal(|ui| {
+                            ui.label(&section.bank);
+                            ui.add(egui::DragValue::new(&mut section.repeats).clamp_range(1..=64).suffix("x"));
+                            if ui.small_button("x").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    })
+                    .response;
+                let response = ui.interact(response.rect, ui.id().with(("section", index)), egui::Sense::drag());
+                if response.drag_started() {
+                    self.dragging_section = Some(index);
+                }
+                if self.dragging_section.is_some() && ui.rect_contains_pointer(response.rect) {
+                    drop_target = Some(index);
+                }
+            }
+            ui.menu_button("+", |ui| {
+                for bank in banks.iter() {
+                    if ui.button(bank).clicked() {
+                        song.sections.push(SongSection { bank: bank.clone(), repeats: 1 });
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+
+        if let Some(index) = removed {
+            song.sections.remove(index);
+        }
+        if ui.input(|i| i.pointer.any_released()) {
+            if let (Some(from), Some(to)) = (self.dragging_section.take(), drop_target) {
+                song.move_section(from, to);
+            }
+        }
+    }
+
+    /// Floating overlay with scheduler and audio health numbers.
+    fn show_diagnostics_overlay(&mut self, ctx: &egui::Context) {
+        let elapsed = self.audio_load_time.elapsed();
+        if elapsed.as_secs_f32() >= 0.5 {
+            self.audio_load = DIAGNOSTICS.take_audio_time().as_secs_f32() / elapsed.as_secs_f32();
+            self.audio_load_time = Instant::now();
+        }
+        egui::Window::new("Diagnostics")
+            .resizable(false)
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+            .show(ctx, |ui| {
+                egui::Grid::new("diagnostics").num_columns(2).show(ui, |ui| {
+                    ui.label("Scheduler jitter");
+                    ui.label(format!("{:.2} ms (max {:.2} ms)", DIAGNOSTICS.jitter_ms(), DIAGNOSTICS.max_jitter_ms()));
+                    ui.end_row();
+                    ui.label("Late ticks");
+                    ui.label(DIAGNOSTICS.late_ticks().to_string());
+                    ui.end_row();
+                    ui.label("Active voices");
+                    ui.label(DIAGNOSTICS.active_voices().to_string());
+                    ui.end_row();
+                    ui.label("Audio CPU (est.)");
+                    ui.label(format!("{:.1} %", self.audio_load * 100.0));
+                    ui.end_row();
+                    ui.label("Dropped events");
+                    ui.label(DIAGNOSTICS.dropped_events().to_string());
+                    ui.end_row();
+                    ui.label("Output latency");
+                    ui.label(format!(
+                        "{:.1} ms mixing + {} ms device",
+                        DIAGNOSTICS.start_latency().as_secs_f32() * 1000.0,
+                        self.output_latency.as_millis()
+                    ));
+                    ui.end_row();
+                });
+            });
+    }
+
+    /// Applies the current theme to the egui context.
+    pub fn apply_theme(&self, ctx: &egui::Context) {
+        ctx.set_visuals(visuals(self.theme));
+    }
+
+    /// Color from the GUI config, else from the track in the pattern file.
+    fn track_color(&self, track: &str) -> egui::Color32 {
+        self.track_colors
+            .get(track)
+            .copied()
+            .or_else(|| self.mixer.read().unwrap().color(track).and_then(parse_hex_color))
+            .unwrap_or(egui::Color32::RED)
+    }
+
+    /// Fullscreen stage view: BPM, current section, a big beat indicator and
+    /// the active rows, sized to be read from a distance.
+    fn show_performance(&self, ctx: &egui::Context, current_beat: f32) {
+        let active_bank = self.transport.active_bank();
+        let section = {
+            let song = self.transport.song.read().unwrap();
+            if song.enabled && !song.sections.is_empty() {
+                format!("Section {}/{}", song.position() + 1, song.sections.len())
+            } else {
+                String::new()
+            }
+        };
+        let patterns: Vec<Pattern> = self
+            .patterns
+            .load()
+            .iter()
+            .filter(|p| p.sound.is_some() || p.loop_name.is_some())
+            .filter(|p| p.bank.as_ref().is_none_or(|b| *b == active_bank))
+            .cloned()
+            .collect();
+        let total_cols = self.total_cols();
+        let playing_col = (current_beat / RESOLUTION) as usize;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(format!("{} BPM", self.transport.bpm())).size(64.0).strong());
+                ui.add_space(40.0);
+                let bank = if active_bank.is_empty() { "-" } else { active_bank.as_str() };
+                ui.label(egui::RichText::new(format!("{} {}", bank, section)).size(40.0));
+            });
+            ui.add_space(20.0);
+
+            // One big light per beat of the bar, the current one lit
+            let beat_in_bar = (current_beat % BEATS_PER_BAR) as usize;
+            ui.horizontal(|ui| {
+                for beat in 0..BEATS_PER_BAR as usize {
+                    let (rect, _) = ui.allocate_exact_size(egui::vec2(120.0, 120.0), egui::Sense::hover());
+                    let color = match (beat == beat_in_bar, beat == 0) {
+                        (true, true) => egui::Color32::RED,
+                        (true, false) => egui::Color32::YELLOW,
+                        (false, _) => ui.visuals().faint_bg_color,
+                    };
+                    ui.painter().circle_filled(rect.center(), 55.0, color);
+                }
+            });
+            ui.add_space(20.0);
+
+            let label_width = 160.0;
+            let cell = ((ui.available_width() - label_width) / total_cols as f32).min(60.0);
+            for pattern in patterns.iter() {
+                let track = pattern.track_name();
+                let color = self.track_color(track);
+                ui.horizontal(|ui| {
+                    ui.add_sized(egui::vec2(label_width, cell), egui::Label::new(egui::RichText::new(track).size(28.0)));
+                    let (rect, _) = ui.allocate_exact_size(egui::vec2(cell * total_cols as f32, cell), egui::Sense::hover());
+                    for col in 0..total_cols {
+                        let cell_rect = egui::Rect::from_min_size(
+                            rect.min + egui::vec2(col as f32 * cell, 0.0),
+                            egui::vec2(cell, cell),
+                        )
+                        .shrink(2.0);
+                        let on = pattern.has_step(col as f32 * RESOLUTION);
+                        let mut fill = if on { color } else { ui.visuals().faint_bg_color };
+                        if col == playing_col {
+                            fill = if on { egui::Color32::WHITE } else { egui::Color32::DARK_GRAY };
+                        }
+                        ui.painter().rect_filled(cell_rect, 4.0, fill);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Loads WAV files dropped onto the window and gives each an empty row.
+    fn import_dropped_files(&mut self, ctx: &egui::Context) {
+        let files = ctx.input(|i| i.raw.dropped_files.clone());
+        for file in files {
+            let Some(path) = file.path.as_ref().and_then(|p| p.to_str()) else {
+                continue;
+            };
+            if !path.to_lowercase().ends_with(".wav") {
+                log_warn!("Ignoring dropped file '{}': not a WAV file", path);
+                continue;
+            }
+            match self.browser.import_file(path) {
+                Ok(item) => self.add_row(item),
+                Err(e) => log_error!("Failed to load dropped file '{}': {}", path, e),
+            }
+        }
+    }
+
+    /// Adds an empty pattern row for an item dropped from the browser.
+    fn add_row(&self, item: BrowserItem) {
+        let pattern = match item {
+            BrowserItem::Sound(label) => PatternBuilder::new().sound(&label).build(),
+            BrowserItem::Loop(label) => PatternBuilder::new().loop_name(&label).build(),
+        };
+        self.session.write().unwrap().add_pattern(pattern.clone());
+        edit_patterns(&self.patterns, |patterns| patterns.push(pattern.clone()));
+    }
+
+    /// Adds the rows of a rhythm preset as a starting point.
+    fn insert_preset(&self, preset: &Preset) {
+        match notation::expand(presets::patterns(preset), self.loop_beats) {
+            Ok(added) => {
+                for pattern in &added {
+                    self.session.write().unwrap().add_pattern(pattern.clone());
+                }
+                edit_patterns(&self.patterns, |patterns| patterns.extend(added.iter().cloned()));
+            }
+            Err(e) => log_error!("Failed to insert preset: {}", e),
+        }
+    }
+
+    /// Context menu contents for editing a single step of the pattern at `index`.
+    fn step_menu(&self, ui: &mut egui::Ui, index: usize, beat: f32) {
+        let (track, mut settings, default_velocity, default_duration) =
+            match self.patterns.load().get(index) {
+                Some(p) => (p.track_name().to_string(), p.step_at(beat), p.velocity, p.duration),
+                None => return,
+            };
+
+        ui.label(format!("{} @ beat {}", track, beat));
+        let mut velocity = settings.velocity.unwrap_or(default_velocity);
+        let mut duration = settings.duration.unwrap_or(default_duration);
+        let mut changed = false;
+        egui::Grid::new("step_menu").num_columns(2).show(ui, |ui| {
+            ui.label("Velocity");
+            changed |= ui.add(egui::Slider::new(&mut velocity, 0.0..=127.0)).changed();
+            ui.end_row();
+            ui.label("Duration");
+            changed |= ui.add(egui::DragValue::new(&mut duration).speed(0.01).clamp_range(0.0..=16.0)).changed();
+            ui.end_row();
+            ui.label("Probability");
+            changed |= ui.add(egui::Slider::new(&mut settings.probability, 0.0..=1.0)).changed();
+            ui.end_row();
+            ui.label("Ratchet");
+            changed |= ui.add(egui::Slider::new(&mut settings.ratchet, 1..=8)).changed();
+            ui.end_row();
+            ui.label("Pitch");
+            changed |= ui.add(egui::Slider::new(&mut settings.pitch, -24..=24).suffix(" st")).changed();
+            ui.end_row();
+            ui.label("Delay");
+            changed |= ui.add(egui::Slider::new(&mut settings.offset, 0.0..=0.125)).changed();
+            ui.end_row();
+            ui.label("Every");
+            changed |= ui.add(egui::Slider::new(&mut settings.every, 1..=8).suffix(" passes")).changed();
+            ui.end_row();
+        });
+        if ui.button("Reset").clicked() {
+            settings = Step::new(beat);
+            changed = true;
+        } else if changed {
+            settings.velocity = (velocity != default_velocity).then_some(velocity);
+            settings.duration = (duration != default_duration).then_some(duration);
+        }
+
+        if changed {
+            edit_patterns(&self.patterns, |patterns| {
+                if let Some(pattern) = patterns.get_mut(index) {
+                    pattern.set_step(settings.clone());
+                }
+            });
+            self.session.write().unwrap().record_step_edit(&track, settings);
         }
     }
 
-    pub fn update_grid(&self) -> f32 {
-        let current_beat = self.current_beat.read().unwrap();
-        *current_beat
+    /// One channel strip per track: fader, pan, mute/solo and a peak/RMS
+    /// meter, preceded by the master meter.
+    fn show_mixer(&self, ui: &mut egui::Ui) {
+        let mut mixer = self.mixer.write().unwrap();
+        {
+            let snapshot = self.patterns.load();
+            mixer.ensure_channels(snapshot.iter().map(|p| p.track_name()));
+        }
+        let (master_peak, master_rms) = mixer.update_meters();
+
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.set_width(70.0);
+                    ui.label("Master");
+                    level_meter(ui, master_peak, master_rms);
+                    let clip_color = if master_peak >= 1.0 { egui::Color32::RED } else { egui::Color32::DARK_GRAY };
+                    ui.colored_label(clip_color, "CLIP");
+                });
+                ui.separator();
+                for (name, strip) in mixer.channels_mut() {
+                    ui.vertical(|ui| {
+                        ui.set_width(70.0);
+                        ui.label(name.as_str());
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Slider::new(&mut strip.gain, 0.0..=1.5).vertical().show_value(false));
+                            level_meter(ui, strip.peak(), strip.rms());
+                        });
+                        ui.add(egui::Slider::new(&mut strip.pan, -1.0..=1.0).show_value(false));
+                        ui.horizontal(|ui| {
+                            ui.toggle_value(&mut strip.mute, "M");
+                            ui.toggle_value(&mut strip.solo, "S");
+                        });
+                    });
+                    ui.separator();
+                }
+            });
+        });
     }
 }
 
 impl eframe::App for PatternVisualizerApp {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        let loop_beats = 8;
-        let resolution = 0.25;
-        let total_eighth_beats = (loop_beats as f32 / resolution) as i32;
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let loop_beats = self.loop_beats;
+        let resolution = RESOLUTION;
+        let total_eighth_beats = self.total_cols() as i32;
+        if ctx.input(|i| i.viewport().close_requested()) {
+            self.running.store(false, Ordering::SeqCst);
+        } else if !self.running.load(Ordering::SeqCst) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        let was_performing = self.performance_mode;
+        self.handle_shortcuts(ctx);
         let current_beat = self.update_grid();
 
-        let beat_duration = 60.0 / self.bpm as f32;
-        let delay_time = Duration::from_secs_f32((beat_duration * resolution) - 0.15);
+        if self.performance_mode != was_performing {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(self.performance_mode));
+        }
+        if self.performance_mode {
+            self.show_performance(ctx, current_beat);
+            self.frame_shown();
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+            return;
+        }
+
+        let dropped = egui::SidePanel::left("browser")
+            .exact_width(BROWSER_WIDTH)
+            .show(ctx, |ui| self.browser.show(ui))
+            .inner;
 
-        egui::CentralPanel::default().show(ctx, |ui| {
+        egui::TopBottomPanel::top("arrangement").show(ctx, |ui| self.show_arrangement(ui));
+
+        self.toasts.update();
+        let progress = logging::progress();
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| match (&progress, self.toasts.latest()) {
+            (Some(progress), _) => {
+                ui.label(progress);
+            }
+            (None, Some((level, message))) => {
+                ui.colored_label(level_color(*level), message);
+            }
+            (None, None) => {
+                ui.label("Ready");
+            }
+        });
+        if progress.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(200));
+        }
+
+        if self.pad_mode {
+            let mut clicked = Vec::new();
+            egui::TopBottomPanel::bottom("pads").show(ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for (key, label) in &self.pads {
+                        if ui.button(format!("{}\n{}", key.name(), label)).clicked() {
+                            clicked.push(label.clone());
+                        }
+                    }
+                });
+            });
+            self.play_sample_pads(clicked);
+        }
+
+        if self.mixer_detached {
+            let size = egui::vec2(INITIAL_WINDOW_SIZE.x, MIXER_HEIGHT);
+            self.mixer_detached = show_detached(ctx, "mixer", "Mixer", size, |ui| self.show_mixer(ui));
+        } else {
+            egui::TopBottomPanel::bottom("mixer")
+                .exact_height(MIXER_HEIGHT)
+                .show(ctx, |ui| self.show_mixer(ui));
+        }
+
+        let grid_rect = egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.heading("Rust 4x4 Groovebox");
+                ui.horizontal(|ui| {
+                    if !self.transport.started() && ui.button("▶ Play").clicked() {
+                        self.transport.start();
+                    }
+                    let dark = self.theme == Theme::Dark;
+                    if ui.selectable_label(dark, "Dark").clicked() && !dark {
+                        self.theme = Theme::Dark;
+                        self.apply_theme(ui.ctx());
+                    }
+                    if ui.selectable_label(!dark, "Light").clicked() && dark {
+                        self.theme = Theme::Light;
+                        self.apply_theme(ui.ctx());
+                    }
+                    ui.separator();
+                    ui.label(format!(
+                        "{} BPM | variation {} | metronome {}",
+                        self.transport.bpm(),
+                        self.transport.variation() + 1,
+                        if self.transport.metronome() { "on" } else { "off" },
+                    ));
+                    ui.separator();
+                    let mut quantize = self.transport.live_quantize();
+                    egui::ComboBox::from_label("Pad quantize")
+                        .selected_text(quantize.name())
+                        .show_ui(ui, |ui| {
+                            for option in LiveQuantize::ALL {
+                                ui.selectable_value(&mut quantize, option, option.name());
+                            }
+                        });
+                    if quantize != self.transport.live_quantize() {
+                        self.transport.set_live_quantize(quantize);
+                    }
+                    ui.separator();
+                    ui.label("Zoom");
+                    if ui.button("-").clicked() {
+                        self.zoom = (self.zoom / 1.25).max(0.25);
+                    }
+                    ui.add(egui::Slider::new(&mut self.zoom, 0.25..=3.0).show_value(false));
+                    if ui.button("+").clicked() {
+                        self.zoom = (self.zoom * 1.25).min(3.0);
+                    }
+                    ui.toggle_value(&mut self.follow_playhead, "Follow");
+                    ui.separator();
+                    ui.toggle_value(&mut self.show_keyboard, "Keyboard");
+                    ui.toggle_value(&mut self.pad_mode, "Pads");
+                    ui.toggle_value(&mut self.mixer_detached, "Mixer window");
+                    let mut armed = self.recorder.armed();
+                    let rec = egui::RichText::new("● Rec").color(if armed {
+                        egui::Color32::RED
+                    } else {
+                        ui.visuals().text_color()
+                    });
+                    if ui.toggle_value(&mut armed, rec).changed() {
+                        self.recorder.set_armed(armed);
+                    }
+                    ui.separator();
+                    ui.menu_button("Presets", |ui| {
+                        let library = presets::library(&self.user_presets.read().unwrap());
+                        for (name, preset) in library {
+                            if ui.button(name.replace('_', " ")).clicked() {
+                                self.insert_preset(&preset);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    if ui.button("Settings").clicked() {
+                        self.settings.show_dialog();
+                    }
+                    if ui.button("Exit").clicked() {
+                        self.running.store(false, Ordering::SeqCst);
+                    }
+                });
+                self.show_bank_tabs(ui);
+                self.show_scene_tabs(ui);
                 let spacing = ui.spacing_mut();
                 spacing.item_spacing = egui::vec2(5.0, 5.0); // No spacing between items
 
-                let cell_size = 20.0;
+                // Fit the loop to the available width at zoom 1.0, scrolling when zoomed in
+                let spacing_x = ui.spacing().item_spacing.x;
+                let fit_cell_size = (ui.available_width() - LABEL_WIDTH - 2.0 * spacing_x)
+                    / total_eighth_beats as f32
+                    - spacing_x;
+                let cell_size = fit_cell_size.clamp(8.0, 40.0) * self.zoom;
+                let empty_color = match self.theme {
+                    Theme::Dark => egui::Color32::from_gray(60),
+                    Theme::Light => egui::Color32::WHITE,
+                };
+                let outline_color = ui.visuals().widgets.noninteractive.bg_stroke.color;
 
-                let sample_patterns: Vec<_> = {
-                    let patterns_lock = self.patterns.read().unwrap();
-                    patterns_lock
+                let active_bank = self.transport.active_bank();
+                let sample_patterns: Vec<(usize, Pattern)> = {
+                    let snapshot = self.patterns.load();
+                    snapshot
                         .iter()
-                        .filter(|pattern| pattern.sound.is_some()) // Example: Filter non-empty sound
-                        .cloned()
+                        .enumerate()
+                        .filter(|(_, pattern)| pattern.sound.is_some() || pattern.loop_name.is_some())
+                        .filter(|(_, pattern)| pattern.bank.as_ref().is_none_or(|b| *b == active_bank))
+                        .map(|(index, pattern)| (index, pattern.clone()))
                         .collect()
                 };
 
-                let grid_width = 50.0 + total_eighth_beats as f32 * (cell_size + 5.0);
-                let grid_height = 100.0 + sample_patterns.len() as f32 * (cell_size + 5.0);
-        
-                // Adjust the window size to fit the grid
-                frame.set_window_size(egui::vec2(grid_width, grid_height));
-
-                for pattern in sample_patterns.iter() {
-                    ui.horizontal(|ui| {
-                        for col_index in 0..total_eighth_beats {
-                            let beat = col_index as f32 * resolution;
-                            let is_active = pattern.beats.contains(&beat);
-                            let is_playing = current_beat == beat; // Highlight current beat
-
-                            let color = if is_playing && is_active {
-                                egui::Color32::YELLOW
-                            } else if is_active {
-                                egui::Color32::RED
-                            } else {
-                                egui::Color32::WHITE
+
+                self.visible_rows = sample_patterns.iter().map(|(index, _)| *index).collect();
+                let selection_stroke = egui::Stroke::new(2.0, ui.visuals().selection.stroke.color);
+
+                egui::ScrollArea::both().show(ui, |ui| {
+                    let mut cells_rect: Option<egui::Rect> = None;
+                    let mut row_rects: Vec<Option<egui::Rect>> = Vec::new();
+                    for (row_index, (pattern_index, pattern)) in sample_patterns.iter().enumerate() {
+                        let mut track_color = self.track_color(pattern.track_name());
+                        if pattern.muted {
+                            track_color = track_color.gamma_multiply(0.3);
+                        }
+                        let mut row_rect: Option<egui::Rect> = None;
+                        ui.horizontal(|ui| {
+                            let mut name = pattern.id().to_string();
+                            if let Some(variation) = pattern.variation {
+                                name.push_str(&format!(" v{}", variation));
+                            }
+                            if pattern.fill {
+                                name.push_str(" fill");
+                            }
+                            let mut instrument = pattern.sound.as_deref().or(pattern.loop_name.as_deref()).unwrap_or_default().to_string();
+                            if pattern.name.is_some() {
+                                instrument = format!("{} on {}", instrument, pattern.track_name());
+                            }
+                            if pattern.muted {
+                                instrument.push_str(" (muted in the pattern file)");
+                            }
+                            let name = if pattern.muted { egui::RichText::new(name).strikethrough().weak() } else { egui::RichText::new(name) };
+                            let label = ui.add_sized(egui::vec2(LABEL_WIDTH, cell_size), egui::Label::new(name));
+                            // How far the playing loop got, as a bar along the bottom of the label
+                            let position = pattern.loop_name.as_ref().and_then(|_| self.mixer.read().unwrap().loop_position(pattern.track_name()));
+                            let hover = match position {
+                                Some((elapsed, length)) => {
+                                    let progress = elapsed.as_secs_f32() / length.as_secs_f32().max(f32::EPSILON);
+                                    let bar = egui::Rect::from_min_size(
+                                        label.rect.left_bottom() - egui::vec2(0.0, 3.0),
+                                        egui::vec2(label.rect.width() * progress, 3.0),
+                                    );
+                                    ui.painter().rect_filled(bar, 0.0, track_color);
+                                    let remaining = length.saturating_sub(elapsed).as_secs_f32();
+                                    format!("{}\n{:.1}s of {:.1}s left", instrument, remaining, length.as_secs_f32())
+                                }
+                                None => instrument,
                             };
+                            label.on_hover_text(hover);
+                            for col_index in 0..total_eighth_beats {
+                                let cell = (row_index, col_index as usize);
+                                let beat = col_index as f32 * resolution;
+                                let color = step_grid::cell_color(pattern, beat, Some(current_beat), track_color, empty_color);
+
+                                let is_selected = self.selection.is_some_and(|sel| sel.contains(cell.0, cell.1));
+                                let stroke = if self.cursor == Some(cell) {
+                                    egui::Stroke::new(3.0, ui.visuals().strong_text_color())
+                                } else if is_selected {
+                                    selection_stroke
+                                } else {
+                                    egui::Stroke::new(1.0, outline_color)
+                                };
 
-                            egui::Frame::default()
-                                .fill(color)
-                                .stroke(egui::Stroke::new(1.0, egui::Color32::BLACK))
-                                .show(ui, |ui| {
-                                    ui.allocate_space(egui::vec2(cell_size, cell_size));
-                                });
+                                let cell_rect = step_grid::step_cell(ui, pattern, beat, color, stroke, cell_size, empty_color);
+                                let response = ui.interact(cell_rect, ui.id().with((pattern_index, col_index)), egui::Sense::click_and_drag());
+                                // Rubber-band selection: start on a cell, extend to whichever cell the pointer is over
+                                if response.clicked() || response.drag_started() {
+                                    if self.cursor.is_some() {
+                                        self.cursor = Some(cell);
+                                    }
+                                    self.selection = Some(Selection::new(cell));
+                                    self.selecting = response.drag_started();
+                                }
+                                if self.selecting && ui.rect_contains_pointer(cell_rect) {
+                                    if let Some(selection) = self.selection.as_mut() {
+                                        selection.end = cell;
+                                    }
+                                }
+                                response.context_menu(|ui| self.step_menu(ui, *pattern_index, beat));
+                                row_rect = Some(row_rect.map_or(cell_rect, |r| r.union(cell_rect)));
+                            }
+                        });
+                        if let Some(row) = row_rect {
+                            cells_rect = Some(cells_rect.map_or(row, |r| r.union(row)));
                         }
-                    });
-                }
+                        row_rects.push(row_rect);
+                    }
+
+                    if let Some(rect) = cells_rect {
+                        let pitch = step_grid::cell_pitch(ui, rect, total_eighth_beats as usize);
+                        for ((_, pattern), row_rect) in sample_patterns.iter().zip(row_rects.iter()) {
+                            let Some(row_rect) = row_rect else { continue };
+                            let bar_color = self.track_color(pattern.track_name()).gamma_multiply(0.6);
+                            step_grid::paint_lengths(ui, pattern, *row_rect, pitch, loop_beats, bar_color);
+                        }
+                        step_grid::paint_bar_lines(ui, rect, pitch, loop_beats);
+                        let x = step_grid::paint_playhead(ui, rect, pitch, current_beat);
+
+                        // Flip to the next page once the playhead leaves the visible area
+                        let visible = ui.clip_rect();
+                        if self.follow_playhead && !visible.x_range().contains(x) {
+                            let target = egui::Rect::from_x_y_ranges(x..=x, visible.y_range());
+                            ui.scroll_to_rect(target, Some(egui::Align::Min));
+                        }
+                    }
+                });
             });
-        });
-        self.gui_ready.store(true, Ordering::SeqCst);
-        std::thread::sleep(delay_time);
-        ctx.request_repaint(); // Ensure continuous UI updates
+        }).response.rect;
+
+        if ctx.input(|i| i.pointer.any_released()) {
+            self.selecting = false;
+        }
+
+        if self.show_diagnostics {
+            self.show_diagnostics_overlay(ctx);
+        }
+
+        self.toasts.show(ctx);
+
+        if self.settings.open {
+            self.settings.show(ctx, self.transport.bpm());
+        }
+
+        if self.show_keyboard {
+            let keyboard = &mut self.keyboard;
+            self.show_keyboard = show_detached(ctx, "keyboard", "Keyboard", KEYBOARD_WINDOW_SIZE, |ui| keyboard.show(ui));
+        }
+        if !self.show_keyboard {
+            self.keyboard.note_off();
+        }
+
+        self.import_dropped_files(ctx);
+
+        if let Some(item) = dropped {
+            if ctx.pointer_latest_pos().is_some_and(|pos| grid_rect.contains(pos)) {
+                self.add_row(item);
+            }
+        }
+
+        self.frame_shown();
+        ctx.request_repaint_after(REPAINT_INTERVAL); // Keep the playhead moving without blocking input
     }
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{mpsc::Receiver, Arc},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Error};
+use crate::transport::Transport;
+use crate::EngineEvent;
+
+/// A trigger as it was played, `time` seconds after the transport started.
+/// The log is one entry per line as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub time: f64,
+    pub track: String,
+    pub note: u8,
+    pub velocity: f32,
+    pub duration: f32,
+    pub pan: f32,
+    pub bpm: u32,
+}
+
+/// Appends every trigger of `events` to a new log at `path` in the
+/// background, until the engine goes away. Lines are flushed as they are
+/// written, so a crashed session still leaves its log behind.
+pub fn record(path: &Path, events: Receiver<EngineEvent>, transport: Arc<Transport>) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let path = path.display().to_string();
+    log!("Recording the triggers to {}", path);
+    thread::spawn(move || {
+        for event in events {
+            let EngineEvent::Trigger { track, note, velocity, duration, pan, at } = event else { continue };
+            // Timed when the voice fired, however long the event queued
+            let start = transport.started_at().unwrap_or(at);
+            let entry = HistoryEntry {
+                time: at.saturating_duration_since(start).as_secs_f64(),
+                track,
+                note,
+                velocity,
+                duration,
+                pan,
+                bpm: transport.bpm(),
+            };
+            let written = serde_json::to_string(&entry)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(out, "{}", line))
+                .and_then(|_| out.flush());
+            if let Err(e) = written {
+                log_error!("Stopped recording to {}: {}", path, e);
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reads a log written by `record`, in the order the triggers played.
+pub fn read(path: &Path) -> error::Result<Vec<HistoryEntry>> {
+    let mut entries = Vec::new();
+    for (index, line) in fs::read_to_string(path).map_err(|e| Error::io(path, e))?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(line).map_err(|e| Error::parse(&format!("{}:{}", path.display(), index + 1), e))?;
+        entries.push(entry);
+    }
+    // Workers publish in parallel, so neighbouring lines can be swapped
+    entries.sort_by(|a: &HistoryEntry, b| a.time.total_cmp(&b.time));
+    Ok(entries)
+}
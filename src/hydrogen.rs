@@ -0,0 +1,200 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::error::{self, Error};
+use crate::kit::{KitLayer, KitManifest, KitSample, KIT_FILE};
+
+/// Kit description inside Hydrogen drumkits.
+pub const DRUMKIT_FILE: &str = "drumkit.xml";
+
+const TAR_BLOCK: usize = 512;
+
+/// Minimal XML element tree, enough to walk drumkit.xml.
+#[derive(Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn text_of(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.trim())
+    }
+
+    fn number(&self, name: &str) -> Option<f32> {
+        self.text_of(name).and_then(|t| t.parse().ok())
+    }
+}
+
+fn parse_xml(xml: &str) -> Result<Element, String> {
+    let mut stack = vec![Element::default()];
+    for event in EventReader::new(xml.as_bytes()) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement { name, .. } => stack.push(Element { name: name.local_name, ..Default::default() }),
+            XmlEvent::EndElement { .. } => {
+                let element = stack.pop().ok_or("Unbalanced XML")?;
+                stack.last_mut().ok_or("Unbalanced XML")?.children.push(element);
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut document = stack.pop().ok_or("Empty XML")?;
+    document.children.pop().ok_or("Empty XML".to_string())
+}
+
+/// Turns an instrument name into a label usable in patterns and mini-notation.
+fn label(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Reads a Hydrogen drumkit.xml as a kit manifest. Instrument names become
+/// labels, volume the gain, mute groups choke groups, and layers keep
+/// their velocity ranges. Handles layers inside `instrumentComponent`
+/// (0.9.7 and later), directly in the instrument, and single filenames.
+pub fn parse_drumkit(path: &Path, xml: &str) -> error::Result<KitManifest> {
+    let manifest_error = |reason: String| Error::Manifest { path: path.display().to_string(), reason };
+    let root = parse_xml(xml).map_err(manifest_error)?;
+    let instruments = root.child("instrumentList").ok_or_else(|| manifest_error("no instrumentList".to_string()))?;
+    let mut manifest = KitManifest::new();
+    for instrument in instruments.children("instrument") {
+        let name = instrument.text_of("name").unwrap_or_default();
+        let layer_elements: Vec<&Element> = instrument
+            .children("instrumentComponent")
+            .flat_map(|component| component.children("layer"))
+            .chain(instrument.children("layer"))
+            .collect();
+        let mut layers: Vec<KitLayer> = layer_elements
+            .iter()
+            .filter_map(|layer| {
+                Some(KitLayer {
+                    file: layer.text_of("filename")?.to_string(),
+                    min_velocity: layer.number("min").unwrap_or(0.0) * 127.0,
+                })
+            })
+            .collect();
+        let file = match (layers.len(), instrument.text_of("filename")) {
+            (0, Some(file)) => Some(file.to_string()),
+            (1, _) => Some(layers.remove(0).file),
+            _ => None,
+        };
+        if name.is_empty() || (file.is_none() && layers.is_empty()) {
+            continue;
+        }
+        let tuning = instrument
+            .number("pitchOffset")
+            .or_else(|| layer_elements.first().and_then(|layer| layer.number("pitch")))
+            .unwrap_or(0.0);
+        let choke = instrument.number("muteGroup").filter(|group| *group >= 0.0).map(|group| format!("mute{}", group));
+        manifest.insert(
+            label(name),
+            KitSample {
+                file,
+                gain: instrument.number("volume").unwrap_or(1.0),
+                tuning,
+                choke,
+                velocity: None,
+                layers,
+            },
+        );
+    }
+    Ok(manifest)
+}
+
+fn octal(field: &[u8]) -> usize {
+    let text: String = field.iter().take_while(|b| **b != 0 && **b != b' ').map(|b| *b as char).collect();
+    usize::from_str_radix(text.trim(), 8).unwrap_or(0)
+}
+
+fn tar_string(field: &[u8]) -> String {
+    String::from_utf8_lossy(&field[..field.iter().position(|b| *b == 0).unwrap_or(field.len())]).into_owned()
+}
+
+/// The regular files of an archive as (path, contents) pairs.
+type ArchiveFiles = Vec<(String, Vec<u8>)>;
+
+/// Unpacks the regular files of a .tar.gz.
+fn read_tar_gz(path: &Path) -> error::Result<ArchiveFiles> {
+    let mut archive = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| GzDecoder::new(file).read_to_end(&mut archive))
+        .map_err(|e| Error::io(path, e))?;
+    let mut files = Vec::new();
+    let mut long_name = None;
+    let mut pos = 0;
+    while pos + TAR_BLOCK <= archive.len() {
+        let header = &archive[pos..pos + TAR_BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = octal(&header[124..136]);
+        let data_start = pos + TAR_BLOCK;
+        let data = archive
+            .get(data_start..data_start + size)
+            .ok_or_else(|| Error::Archive { path: path.display().to_string(), reason: "truncated".to_string() })?;
+        let name = match (&header[257..262], tar_string(&header[345..500])) {
+            (b"ustar", prefix) if !prefix.is_empty() => format!("{}/{}", prefix, tar_string(&header[..100])),
+            _ => tar_string(&header[..100]),
+        };
+        match header[156] {
+            b'L' => long_name = Some(tar_string(data)), // GNU long name for the next entry
+            b'0' | 0 => files.push((long_name.take().unwrap_or(name), data.to_vec())),
+            _ => long_name = None,
+        }
+        pos = data_start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+    }
+    Ok(files)
+}
+
+/// Extracts a .h2drumkit archive into `into` and writes a kit.json from
+/// its drumkit.xml, returning the kit directory to add to `sounds.samples`.
+pub fn import(archive: &Path, into: &Path) -> error::Result<PathBuf> {
+    let archive_error = |reason: String| Error::Archive { path: archive.display().to_string(), reason };
+    let mut kit_dir = None;
+    for (name, contents) in read_tar_gz(archive)? {
+        let relative = Path::new(&name);
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(archive_error(format!("refusing to extract '{}' outside the target directory", name)));
+        }
+        let target = into.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        fs::write(&target, contents).map_err(|e| Error::io(&target, e))?;
+        if relative.file_name().is_some_and(|f| f == DRUMKIT_FILE) {
+            kit_dir = target.parent().map(Path::to_path_buf);
+        }
+    }
+    let kit_dir = kit_dir.ok_or_else(|| archive_error(format!("no {}", DRUMKIT_FILE)))?;
+    let drumkit = kit_dir.join(DRUMKIT_FILE);
+    let manifest = parse_drumkit(&drumkit, &fs::read_to_string(&drumkit).map_err(|e| Error::io(&drumkit, e))?)?;
+    let kit_file = kit_dir.join(KIT_FILE);
+    let json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| Error::Serialize { path: kit_file.display().to_string(), reason: e.to_string() })?;
+    fs::write(&kit_file, json).map_err(|e| Error::io(&kit_file, e))?;
+    log!("Imported {} instruments into {}", manifest.len(), kit_dir.display());
+    Ok(kit_dir)
+}
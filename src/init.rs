@@ -0,0 +1,61 @@
+use std::{fs, path::Path};
+
+
+use crate::cli::InitArgs;
+use crate::midi_io;
+
+const CONFIG_FILE: &str = "config.json";
+const PATTERNS_FILE: &str = "patterns.json";
+const SAMPLES_DIR: &str = "sounds/samples";
+const LOOPS_DIR: &str = "sounds/loops";
+
+/// Kick on every beat, hats on the off-beats, snare on two and four.
+const STARTER_PATTERNS: &str = r#"[
+    { "sound": "bd", "beats": [0.0, 1.0, 2.0, 3.0] },
+    { "sound": "hh", "beats": [0.5, 1.5, 2.5, 3.5], "velocity": 70.0 },
+    { "sound": "sd", "beats": [1.0, 3.0] }
+]
+"#;
+
+fn starter_config(midi_port: &str) -> String {
+    serde_json::to_string_pretty(&serde_json::json!({
+        "midi_port": midi_port,
+        "midi_track": {
+            "midi_file": "",
+            "track_name": "",
+            "start_beat": 0.0,
+            "end_beat": 0.0
+        },
+        "loop_beats": 4,
+        "sounds": {
+            "samples": SAMPLES_DIR,
+            "loops": LOOPS_DIR
+        }
+    }))
+    .unwrap_or_default()
+}
+
+/// Scaffolds a new project: config, starter patterns and sound directories.
+/// The config uses the first MIDI output found, if any.
+pub fn init(args: InitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(&args.dir);
+    let config_path = dir.join(CONFIG_FILE);
+    let patterns_path = dir.join(PATTERNS_FILE);
+    for path in [&config_path, &patterns_path] {
+        if path.exists() && !args.force {
+            return Err(format!("{} already exists; use --force to overwrite", path.display()).into());
+        }
+    }
+
+    let midi_port = midi_io::output_port_names().into_iter().next().unwrap_or_default();
+
+    fs::create_dir_all(dir.join(SAMPLES_DIR))?;
+    fs::create_dir_all(dir.join(LOOPS_DIR))?;
+    fs::write(&config_path, starter_config(&midi_port))?;
+    fs::write(&patterns_path, STARTER_PATTERNS)?;
+
+    println!("Created {} and {}", config_path.display(), patterns_path.display());
+    println!("Next: put bd.wav, hh.wav and sd.wav into {}", dir.join(SAMPLES_DIR).display());
+    println!("Then run: four_on_the_floor play 120");
+    Ok(())
+}
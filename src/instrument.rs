@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+
+use crate::audio::{self, Fader, OutputStreamHandle};
+use crate::meter::LevelMeter;
+use crate::midi_io::MidiOut;
+use crate::model::Pattern;
+use crate::{play_loop, play_midi_note, play_sound, release_midi_note, LoopBank, LoopPlay, SoundBank};
+
+/// Note played by patterns that don't set one, for instruments that are pitched.
+pub const DEFAULT_NOTE: u8 = 60;
+
+/// Where a triggered note ends up: the track's mixer settings and the tempo.
+#[derive(Clone)]
+pub struct Playback {
+    pub pan: f32,
+    pub meter: Arc<LevelMeter>,
+    pub bpm: u32,
+}
+
+/// Something a pattern plays. The scheduler only fires triggers, so a new
+/// engine (a synth, an SFZ player) is an implementation plus a case in
+/// `Rack::instrument`.
+pub trait Instrument: Send + Sync {
+    /// Plays a note at `velocity` (0-127) for `duration` beats, as set on
+    /// the pattern. Called from a scheduler worker thread.
+    fn trigger(&self, note: u8, velocity: f32, duration: f32, playback: &Playback);
+
+    /// Velocity the instrument always plays at, overriding the pattern's.
+    fn fixed_velocity(&self) -> Option<f32> {
+        None
+    }
+
+    /// Whether notes sound until `release`, sent `duration` beats after the
+    /// trigger, so ratchet repeats have to be shortened to fit in between.
+    fn holds_note(&self) -> bool {
+        false
+    }
+
+    /// Ends a note started by `trigger`, for instruments that hold notes.
+    fn release(&self, _note: u8) {}
+}
+
+/// A one-shot from the sound bank. Pitch comes from the kit, not the note.
+pub struct Sampler {
+    pub label: String,
+    pub sound_bank: Arc<SoundBank>,
+    pub stream_handle: Arc<OutputStreamHandle>,
+}
+
+impl Instrument for Sampler {
+    fn trigger(&self, _note: u8, velocity: f32, _duration: f32, playback: &Playback) {
+        play_sound(&self.label, velocity, playback.pan, Some(Arc::clone(&playback.meter)), &self.sound_bank, &self.stream_handle);
+    }
+
+    fn fixed_velocity(&self) -> Option<f32> {
+        self.sound_bank.fixed_velocity(&self.label)
+    }
+}
+
+/// A loop from the loop bank, stretched to the tempo and cut after `duration` beats.
+pub struct LoopPlayer {
+    pub name: String,
+    pub loop_bank: Arc<LoopBank>,
+    pub stream_handle: Arc<OutputStreamHandle>,
+    /// Shared by the loops of the scene playing, for crossfades.
+    pub fader: Arc<Fader>,
+}
+
+impl Instrument for LoopPlayer {
+    fn trigger(&self, _note: u8, velocity: f32, duration: f32, playback: &Playback) {
+        let play = LoopPlay {
+            duration,
+            velocity,
+            pan: playback.pan,
+            meter: Some(Arc::clone(&playback.meter)),
+            fader: Some(Arc::clone(&self.fader)),
+            bpm: playback.bpm,
+        };
+        play_loop(&self.name, play, &self.loop_bank, &self.stream_handle);
+    }
+}
+
+/// Notes sent to the MIDI output, held until the dispatcher releases them
+/// `duration` beats later.
+pub struct MidiInstrument {
+    pub midi_conn: Arc<Mutex<MidiOut>>,
+}
+
+impl Instrument for MidiInstrument {
+    fn trigger(&self, note: u8, velocity: f32, _duration: f32, playback: &Playback) {
+        // MIDI voices have no audio to tap, so meter the note velocity
+        playback.meter.record(velocity / 100.0, velocity / 100.0);
+        play_midi_note(note, velocity, &self.midi_conn);
+    }
+
+    fn holds_note(&self) -> bool {
+        true
+    }
+
+    fn release(&self, note: u8) {
+        release_midi_note(note, &self.midi_conn);
+    }
+}
+
+/// The outputs instruments are created on.
+pub struct Rack {
+    pub sound_bank: Arc<SoundBank>,
+    pub loop_bank: Arc<LoopBank>,
+    pub stream_handle: Arc<OutputStreamHandle>,
+    pub midi_conn: Arc<Mutex<MidiOut>>,
+    /// Ports picked by patterns' `output`, opened on first use.
+    pub midi_outputs: Mutex<HashMap<String, Arc<Mutex<MidiOut>>>>,
+    /// Fader of the loops started from now on.
+    pub loop_fader: ArcSwap<Fader>,
+}
+
+impl Rack {
+    /// The instrument a pattern plays: a MIDI note wins over a sound, which
+    /// wins over a loop.
+    pub fn instrument(&self, pattern: &Pattern) -> Option<Arc<dyn Instrument>> {
+        if pattern.midi_note.is_some() {
+            Some(Arc::new(MidiInstrument { midi_conn: self.midi_output(pattern.output.as_deref()) }))
+        } else if let Some(label) = &pattern.sound {
+            Some(Arc::new(Sampler {
+                label: label.clone(),
+                sound_bank: Arc::clone(&self.sound_bank),
+                stream_handle: Arc::clone(&self.stream_handle),
+            }))
+        } else {
+            pattern.loop_name.as_ref().map(|name| {
+                Arc::new(LoopPlayer {
+                    name: name.clone(),
+                    loop_bank: Arc::clone(&self.loop_bank),
+                    stream_handle: Arc::clone(&self.stream_handle),
+                    fader: self.loop_fader.load_full(),
+                }) as Arc<dyn Instrument>
+            })
+        }
+    }
+
+    /// The connection to the MIDI port `output`, or to the main port when
+    /// there is none or it can't be opened.
+    fn midi_output(&self, output: Option<&str>) -> Arc<Mutex<MidiOut>> {
+        let Some(port) = output else {
+            return Arc::clone(&self.midi_conn);
+        };
+        let mut outputs = self.midi_outputs.lock().unwrap();
+        let conn = outputs.entry(port.to_string()).or_insert_with(|| match MidiOut::open(port) {
+            Ok(conn) => Arc::new(Mutex::new(conn)),
+            Err(e) => {
+                log_error!("Could not open MIDI output '{}', using the main one: {}", port, e);
+                Arc::clone(&self.midi_conn)
+            }
+        });
+        Arc::clone(conn)
+    }
+
+    /// Connections to every MIDI port notes were sent to.
+    pub fn midi_connections(&self) -> Vec<Arc<Mutex<MidiOut>>> {
+        let mut connections = vec![Arc::clone(&self.midi_conn)];
+        connections.extend(self.midi_outputs.lock().unwrap().values().cloned());
+        connections
+    }
+
+    /// Fades the loops playing out over `duration` while the ones started
+    /// from now on fade in; a zero duration cuts the old loops off.
+    pub fn crossfade_loops(&self, duration: Duration) {
+        if duration.is_zero() {
+            self.loop_fader.swap(Arc::new(Fader::new(1.0))).set(0.0);
+            return;
+        }
+        let incoming = Arc::new(Fader::new(0.0));
+        let outgoing = self.loop_fader.swap(Arc::clone(&incoming));
+        thread::spawn(move || audio::crossfade(&outgoing, &incoming, duration));
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex};
+
+use eframe::egui;
+
+use crate::chord::note_name;
+use crate::midi_io::MidiOut;
+
+const WHITE_KEY_SIZE: egui::Vec2 = egui::vec2(22.0, 90.0);
+const BLACK_KEY_SIZE: egui::Vec2 = egui::vec2(14.0, 56.0);
+const OCTAVES: u8 = 2;
+/// Semitone offsets of the white keys within an octave.
+const WHITE_KEYS: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Semitone offsets of the black keys, with the white key they sit after.
+const BLACK_KEYS: [(u8, usize); 5] = [(1, 0), (3, 1), (6, 3), (8, 4), (10, 5)];
+
+/// Clickable piano for auditioning notes on the MIDI output.
+pub struct PianoKeyboard {
+    midi_conn: Arc<Mutex<MidiOut>>,
+    octave: u8,
+    velocity: u8,
+    held: Option<u8>,
+}
+
+impl PianoKeyboard {
+    pub fn new(midi_conn: Arc<Mutex<MidiOut>>) -> Self {
+        Self {
+            midi_conn,
+            octave: 4,
+            velocity: 100,
+            held: None,
+        }
+    }
+
+    fn send(&self, message: &[u8]) {
+        if let Ok(mut conn) = self.midi_conn.lock() {
+            if let Err(e) = conn.send(message) {
+                log_error!("Failed to send MIDI message: {}", e);
+            }
+        }
+    }
+
+    fn note_on(&mut self, note: u8) {
+        self.note_off();
+        self.send(&[0x90, note, self.velocity]);
+        self.held = Some(note);
+    }
+
+    /// Releases the held note, if any.
+    pub fn note_off(&mut self) {
+        if let Some(note) = self.held.take() {
+            self.send(&[0x80, note, 0]);
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Octave");
+            if ui.button("-").clicked() {
+                self.octave = self.octave.saturating_sub(1);
+            }
+            ui.label(self.octave.to_string());
+            if ui.button("+").clicked() {
+                self.octave = (self.octave + 1).min(7);
+            }
+            ui.separator();
+            ui.label("Velocity");
+            ui.add(egui::Slider::new(&mut self.velocity, 1..=127));
+            if let Some(note) = self.held {
+                ui.separator();
+                ui.label(format!("{} ({})", note_name(note), note));
+            }
+        });
+
+        let white_count = WHITE_KEYS.len() * OCTAVES as usize;
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(WHITE_KEY_SIZE.x * white_count as f32, WHITE_KEY_SIZE.y),
+            egui::Sense::click_and_drag(),
+        );
+        let base = (self.octave + 1) * 12;
+        let painter = ui.painter_at(rect);
+        let mut keys = Vec::new();
+
+        for index in 0..white_count {
+            let note = base + (index / 7) as u8 * 12 + WHITE_KEYS[index % 7];
+            let min = rect.min + egui::vec2(index as f32 * WHITE_KEY_SIZE.x, 0.0);
+            keys.push((note, egui::Rect::from_min_size(min, WHITE_KEY_SIZE), false));
+        }
+        for octave in 0..OCTAVES as usize {
+            for (offset, after) in BLACK_KEYS {
+                let note = base + octave as u8 * 12 + offset;
+                let x = (octave * 7 + after + 1) as f32 * WHITE_KEY_SIZE.x - BLACK_KEY_SIZE.x / 2.0;
+                let min = rect.min + egui::vec2(x, 0.0);
+                keys.push((note, egui::Rect::from_min_size(min, BLACK_KEY_SIZE), true));
+            }
+        }
+
+        for (note, key_rect, black) in keys.iter() {
+            let fill = match (self.held == Some(*note), *black) {
+                (true, _) => egui::Color32::LIGHT_BLUE,
+                (false, true) => egui::Color32::BLACK,
+                (false, false) => egui::Color32::WHITE,
+            };
+            painter.rect(*key_rect, 2.0, fill, egui::Stroke::new(1.0, egui::Color32::DARK_GRAY));
+        }
+
+        // Black keys are drawn last and sit on top, so they win the hit test
+        let pressed = response
+            .interact_pointer_pos()
+            .filter(|_| response.is_pointer_button_down_on())
+            .and_then(|pos| keys.iter().rev().find(|(_, key_rect, _)| key_rect.contains(pos)))
+            .map(|(note, _, _)| *note);
+        match pressed {
+            Some(note) if self.held != Some(note) => self.note_on(note),
+            None => self.note_off(),
+            _ => {}
+        }
+    }
+}
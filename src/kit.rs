@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Error};
+use crate::hydrogen;
+
+/// Manifest file that, when present in a samples directory, maps labels to files.
+pub const KIT_FILE: &str = "kit.json";
+
+/// Starts each setting in a sample file name such as `bd@gain=0.8@velocity=110.wav`.
+pub const SETTING_MARK: char = '@';
+
+/// Decoded sample data: (samples, channels, sample rate), as held by the SoundBank.
+pub type SampleData = Arc<(Vec<i16>, u16, u32)>;
+
+fn default_gain() -> f32 {
+    1.0
+}
+
+/// A velocity layer: the file played from `min_velocity` (0..127) up to the next layer.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct KitLayer {
+    pub file: String,
+    #[serde(default)]
+    pub min_velocity: f32,
+}
+
+/// One labelled sample of a kit.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct KitSample {
+    /// Sample file relative to the kit directory; optional when there are layers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default = "default_gain")]
+    pub gain: f32,
+    /// Retuning in semitones.
+    #[serde(default)]
+    pub tuning: f32,
+    /// Samples in the same choke group cut each other off, like open and closed hats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub choke: Option<String>,
+    /// Velocity every trigger plays at, whatever the pattern asks for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<KitLayer>,
+}
+
+impl KitSample {
+    fn from_file(file: &str) -> Self {
+        KitSample { file: Some(file.to_string()), gain: 1.0, tuning: 0.0, choke: None, velocity: None, layers: Vec::new() }
+    }
+}
+
+/// Label -> sample, as stored in `kit.json`.
+pub type KitManifest = BTreeMap<String, KitSample>;
+
+/// Reads the kit manifest of a samples directory, if it has one; an
+/// unpacked Hydrogen kit's drumkit.xml serves when there is no kit.json.
+pub fn read_kit(dir: &Path) -> error::Result<Option<KitManifest>> {
+    let path = dir.join(KIT_FILE);
+    if !path.exists() {
+        let drumkit = dir.join(hydrogen::DRUMKIT_FILE);
+        if drumkit.exists() {
+            let xml = fs::read_to_string(&drumkit).map_err(|source| Error::io(&drumkit, source))?;
+            return hydrogen::parse_drumkit(&drumkit, &xml).map(Some);
+        }
+        return Ok(None);
+    }
+    let json = fs::read_to_string(&path).map_err(|source| Error::io(&path, source))?;
+    let manifest = serde_json::from_str(&json).map_err(|e| Error::Manifest { path: path.display().to_string(), reason: e.to_string() })?;
+    Ok(Some(manifest))
+}
+
+/// Label and settings of a sample file named like `bd@gain=0.8@tuning=-2.wav`,
+/// for directories without a kit manifest; no settings when the name has
+/// none. The keys are those of `kit.json`: gain, tuning, choke and velocity.
+pub fn parse_file_name(file: &str) -> Result<(String, Option<KitSample>), String> {
+    let stem = Path::new(file).file_stem().and_then(|s| s.to_str()).ok_or("Invalid filename")?;
+    let mut parts = stem.split(SETTING_MARK);
+    let label = parts.next().unwrap_or_default().to_string();
+    let mut sample: Option<KitSample> = None;
+    for setting in parts {
+        let (key, value) = setting.split_once('=').ok_or(format!("setting '{}' has no value", setting))?;
+        let number = || value.parse::<f32>().map_err(|_| format!("{} '{}' is not a number", key, value));
+        let sample = sample.get_or_insert_with(|| KitSample::from_file(file));
+        match key {
+            "gain" => sample.gain = number()?,
+            "tuning" => sample.tuning = number()?,
+            "velocity" => sample.velocity = Some(number()?),
+            "choke" => sample.choke = Some(value.to_string()),
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+    }
+    Ok((label, sample))
+}
+
+/// Playback settings of a kit sample, kept by the SoundBank next to its data.
+pub struct KitVoice {
+    pub gain: f32,
+    pub tuning: f32,
+    pub choke: Option<String>,
+    pub velocity: Option<f32>,
+    /// Loaded layers sorted by minimum velocity.
+    pub layers: Vec<(f32, SampleData)>,
+}
+
+/// A sample picked for a trigger, with its kit settings.
+pub struct Voice {
+    pub sample: SampleData,
+    pub gain: f32,
+    /// Playback speed from the tuning.
+    pub speed: f32,
+    pub choke: Option<String>,
+}
+
+impl KitVoice {
+    pub fn new(sample: KitSample, layers: Vec<(f32, SampleData)>) -> Self {
+        KitVoice { gain: sample.gain, tuning: sample.tuning, choke: sample.choke, velocity: sample.velocity, layers }
+    }
+
+    /// Picks the layer for `velocity`, or the fixed velocity, falling back
+    /// to `base` without layers.
+    pub fn voice(&self, base: Option<SampleData>, velocity: f32) -> Option<Voice> {
+        let velocity = self.velocity.unwrap_or(velocity);
+        let sample = self
+            .layers
+            .iter()
+            .rev()
+            .find(|(min_velocity, _)| velocity >= *min_velocity)
+            .or(self.layers.first())
+            .map(|(_, sample)| Arc::clone(sample))
+            .or(base)?;
+        Some(Voice {
+            sample,
+            gain: self.gain,
+            speed: 2f32.powf(self.tuning / 12.0),
+            choke: self.choke.clone(),
+        })
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// What a bank does with a label that an earlier file already took.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateLabels {
+    /// The first file keeps the label and later ones are left out.
+    #[default]
+    First,
+    /// Later files are numbered: "kick-2", "kick-3", ...
+    Suffix,
+    /// Later files are prefixed with their directory's name, like "909/kick".
+    Directory,
+}
+
+/// The label a file from `directory` gets when `label` is taken, as
+/// `duplicates` says, or None when it is left out.
+pub fn disambiguate(label: &str, directory: &Path, duplicates: DuplicateLabels, taken: impl Fn(&str) -> bool) -> Option<String> {
+    let numbered = |base: &str| (2..).map(|n| format!("{}-{}", base, n)).find(|candidate| !taken(candidate));
+    match duplicates {
+        DuplicateLabels::First => None,
+        DuplicateLabels::Suffix => numbered(label),
+        DuplicateLabels::Directory => {
+            let name = directory.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let prefixed = format!("{}/{}", name, label);
+            if name.is_empty() || taken(&prefixed) { numbered(&prefixed) } else { Some(prefixed) }
+        }
+    }
+}
+
+/// Warns about a file whose label was taken.
+pub fn report(file: &Path, label: &str, renamed: Option<&str>) {
+    match renamed {
+        Some(renamed) => log_warn!("'{}' is taken, so {} is labelled '{}'", label, file.display(), renamed),
+        None => log_warn!("'{}' is taken, so {} is left out", label, file.display()),
+    }
+}
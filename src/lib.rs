@@ -0,0 +1,1542 @@
+//! Drum sequencer engine: sample and loop banks, the pattern scheduler and
+//! MIDI I/O, driven through [`Engine`]. The `four_on_the_floor` binary is a
+//! CLI, GUI and terminal frontend on top of it.
+
+use std::collections::{HashMap, HashSet};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use arc_swap::ArcSwap;
+use rayon::prelude::*;
+
+#[macro_use]
+pub mod logging;
+pub mod midi;
+pub mod model;
+pub mod chord;
+pub mod config;
+pub mod mixer;
+pub mod transport;
+pub mod session;
+pub mod song;
+pub mod scene;
+pub mod diagnostics;
+pub mod meter;
+pub mod render;
+pub mod formats;
+pub mod notation;
+mod scripting;
+pub mod osc;
+pub mod remote;
+pub mod validation;
+pub mod kit;
+pub mod hydrogen;
+pub mod loops;
+pub mod presets;
+pub mod audio;
+pub mod midi_io;
+pub mod instrument;
+pub mod error;
+pub mod dispatch;
+pub mod threads;
+pub mod sample_cache;
+pub mod labels;
+pub mod daemon;
+pub mod history;
+#[cfg(feature = "gui")]
+pub mod step_grid;
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub mod web;
+#[cfg(feature = "python")]
+pub mod python;
+
+use config::{Config, ShutdownConfig, ThreadConfig};
+use labels::DuplicateLabels;
+use model::{bank_names, Pattern, Ticks, PPQN};
+use mixer::Mixer;
+use transport::{Clock, Transport};
+use diagnostics::DIAGNOSTICS;
+use meter::LevelMeter;
+use audio::{Fader, OutputStream, OutputStreamHandle, Sink, VoiceParams};
+use midi_io::MidiOut;
+use instrument::{Instrument, Playback, Rack, DEFAULT_NOTE};
+use history::HistoryEntry;
+use dispatch::{Dispatcher, TriggerJob};
+use kit::{KitSample, KitVoice, SampleData, Voice};
+use loops::{LoopMeta, LoopSample};
+use sample_cache::Pcm;
+
+
+/// -------------------------------------------------------------------------
+/// 1) SoundBank
+/// -------------------------------------------------------------------------
+#[derive(Default)]
+pub struct SoundBank {
+    data: RwLock<HashMap<String, SampleData>>,
+    /// Settings of the labels that come from a kit manifest.
+    kit: RwLock<HashMap<String, KitVoice>>,
+    /// Files of the labels not decoded yet, in lazy banks.
+    pending: RwLock<HashMap<String, PathBuf>>,
+    /// Labels handed to the loader threads by `preload_in_background` and
+    /// not decoded yet.
+    preloading: std::sync::Mutex<HashSet<String>>,
+    /// Whether samples are decoded when first used rather than on load.
+    lazy: bool,
+    /// Directories the bank was loaded from, and the files found in them,
+    /// so files added later can be told apart.
+    directories: RwLock<String>,
+    files: RwLock<HashSet<PathBuf>>,
+    /// Voice currently sounding in each choke group.
+    chokes: std::sync::Mutex<HashMap<String, Sink>>,
+    /// Contents loaded by `stage`, waiting for `swap_staged`.
+    staged: std::sync::Mutex<Option<Box<SoundBank>>>,
+    /// Loader threads of this bank's loads and reloads.
+    threads: ThreadConfig,
+    /// How its loads label files whose label an earlier file took.
+    duplicates: DuplicateLabels,
+}
+
+/// A sample file to load: (directory order, label, velocity layer, file).
+type SampleJob = (usize, String, Option<usize>, PathBuf);
+
+/// The samples found in a bank's directories, every label given out once.
+#[derive(Default)]
+struct SampleScan {
+    jobs: Vec<SampleJob>,
+    kit_samples: HashMap<String, (usize, KitSample)>,
+    labels: HashSet<String>,
+    duplicates: DuplicateLabels,
+    /// Files whose label was taken: (file, label, label given instead).
+    collisions: Vec<(PathBuf, String, Option<String>)>,
+}
+
+impl SampleScan {
+    /// Scans `directories`, a PATH-style list, in order, so earlier
+    /// directories keep the labels they share with later ones, and the
+    /// files after them are labelled as `duplicates` says.
+    fn new(directories: &str, duplicates: DuplicateLabels) -> error::Result<Self> {
+        let mut scan = SampleScan { duplicates, ..Default::default() };
+        for (order, directory) in std::env::split_paths(directories).enumerate() {
+            scan.scan(&directory, "", order)?;
+        }
+        Ok(scan)
+    }
+
+    /// Collects the samples of `directory` and its subdirectories, labelling
+    /// those in subdirectories by their path, like "909/snare". A directory
+    /// with a `kit.json` loads the files it maps, otherwise every .wav is
+    /// labelled by its name, with kit settings taken from `@key=value` parts
+    /// of the name.
+    fn scan(&mut self, directory: &Path, prefix: &str, order: usize) -> error::Result<()> {
+        if let Some(manifest) = kit::read_kit(directory)? {
+            for (label, sample) in manifest {
+                let Some(label) = self.claim(format!("{}{}", prefix, label), directory, &directory.join(kit::KIT_FILE)) else {
+                    continue;
+                };
+                if let Some(file) = &sample.file {
+                    self.jobs.push((order, label.clone(), None, directory.join(file)));
+                }
+                for (index, layer) in sample.layers.iter().enumerate() {
+                    self.jobs.push((order, label.clone(), Some(index), directory.join(&layer.file)));
+                }
+                self.kit_samples.insert(label, (order, sample));
+            }
+            return Ok(());
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+            .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect())
+            .map_err(|source| error::Error::io(directory, source))?;
+        paths.sort();
+        for path in paths {
+            let Some(file) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if path.is_dir() {
+                if !file.starts_with('.') {
+                    self.scan(&path, &format!("{}{}/", prefix, file), order)?;
+                }
+            } else if path.extension().is_some_and(|extension| extension == "wav") {
+                let (label, settings) = kit::parse_file_name(file).unwrap_or_else(|e| {
+                    log_warn!("Ignoring the settings in {}: {}", path.display(), e);
+                    (file.split(kit::SETTING_MARK).next().unwrap_or_default().to_string(), None)
+                });
+                let Some(label) = self.claim(format!("{}{}", prefix, label), directory, &path) else {
+                    continue;
+                };
+                if let Some(sample) = settings {
+                    self.kit_samples.insert(label.clone(), (order, sample));
+                }
+                self.jobs.push((order, label, None, path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes `label` for `file`, or the label `labels::disambiguate` gives
+    /// instead when an earlier file has it; None leaves the file out.
+    fn claim(&mut self, label: String, directory: &Path, file: &Path) -> Option<String> {
+        if self.labels.insert(label.clone()) {
+            return Some(label);
+        }
+        let renamed = labels::disambiguate(&label, directory, self.duplicates, |candidate| self.labels.contains(candidate));
+        if let Some(renamed) = &renamed {
+            self.labels.insert(renamed.clone());
+        }
+        self.collisions.push((file.to_path_buf(), label, renamed.clone()));
+        renamed
+    }
+}
+
+/// Decodes a WAV file into interleaved 16-bit samples, whatever its bit
+/// depth, through the sample cache when one is configured.
+fn load_sample(path: &str) -> error::Result<Pcm> {
+    load_processed(path, "", |pcm| pcm)
+}
+
+/// Decodes a WAV file and runs `process` on it, through the sample cache
+/// when one is configured; `settings` names what `process` does, so
+/// differently processed entries stay apart. Entries are found by the
+/// file's path, size and modification time, and only when those changed
+/// by hashing its contents.
+fn load_processed(path: &str, settings: &str, process: impl FnOnce(Pcm) -> Pcm) -> error::Result<Pcm> {
+    let Some(dir) = sample_cache::dir() else {
+        return decode_wav(hound::WavReader::open(path), path).map(process);
+    };
+    let file_key = sample_cache::file_key(std::path::Path::new(path), settings);
+    let linked = file_key.as_deref().and_then(|file_key| sample_cache::resolve(&dir, file_key));
+    if let Some(entry) = linked.and_then(|key| sample_cache::read(&dir, &key)) {
+        return Ok(entry);
+    }
+    let bytes = fs::read(path).map_err(|source| error::Error::Io { path: path.to_string(), source })?;
+    let key = sample_cache::content_key(&bytes, settings);
+    let entry = match sample_cache::read(&dir, &key) {
+        Some(entry) => entry,
+        None => {
+            let entry = process(decode_wav(hound::WavReader::new(std::io::Cursor::new(bytes)), path)?);
+            if let Err(e) = sample_cache::write(&dir, &key, &entry) {
+                log_warn!("Could not cache {} in {}: {}", path, dir.display(), e);
+                return Ok(entry);
+            }
+            entry
+        }
+    };
+    if let Some(Err(e)) = file_key.map(|file_key| sample_cache::link(&dir, &file_key, &key)) {
+        log_warn!("Could not cache {} in {}: {}", path, dir.display(), e);
+    }
+    Ok(entry)
+}
+
+/// Decodes a WAV to 16-bit samples; `path` names it in errors.
+fn decode_wav<R: std::io::Read>(
+    reader: hound::Result<hound::WavReader<R>>,
+    path: &str,
+) -> error::Result<Pcm> {
+    let sample_error = |source| error::Error::Sample { path: path.to_string(), source };
+    let mut reader = reader.map_err(sample_error)?;
+    let spec = reader.spec();
+    let samples: Vec<i16> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| s.map(|v| (v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect::<Result<_, _>>()
+            .map_err(sample_error)?,
+        hound::SampleFormat::Int => {
+            let shift = spec.bits_per_sample as i32 - 16;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|v| (if shift >= 0 { v >> shift } else { v << -shift }) as i16))
+                .collect::<Result<_, _>>()
+                .map_err(sample_error)?
+        }
+    };
+    Ok((samples, spec.channels, spec.sample_rate))
+}
+
+impl SoundBank {
+    /// Loads the samples in `directories`, a PATH-style list, and their
+    /// subdirectories on the sample loader threads of `thread_config`; duplicate
+    /// labels are handled as `duplicates` says.
+    pub fn new(directories: &str, thread_config: &ThreadConfig, duplicates: DuplicateLabels) -> error::Result<Self> {
+        Self::load(directories, false, thread_config, duplicates)
+    }
+
+    /// Finds the samples in `directories` like `new`, but decodes them only
+    /// once used, by `preload`, `prefetch` or a lookup. Velocity layers of
+    /// kits are still decoded up front.
+    pub fn lazy(directories: &str, thread_config: &ThreadConfig, duplicates: DuplicateLabels) -> error::Result<Self> {
+        Self::load(directories, true, thread_config, duplicates)
+    }
+
+    fn load(directories: &str, lazy: bool, thread_config: &ThreadConfig, duplicates: DuplicateLabels) -> error::Result<Self> {
+        let mut data = HashMap::new();
+        let mut pending = HashMap::new();
+
+        let SampleScan { mut jobs, kit_samples, collisions, .. } = SampleScan::new(directories, duplicates)?;
+        for (file, label, renamed) in collisions {
+            labels::report(&file, &label, renamed.as_deref());
+        }
+
+        let files = jobs.iter().map(|(_, _, _, path)| path.clone()).collect();
+
+        // Lazy banks only note down the files, earlier directories last so they win
+        let mut winners = HashMap::new();
+        if lazy {
+            let (later, now) = jobs.into_iter().partition(|(_, _, layer, _)| layer.is_none());
+            jobs = now;
+            let mut later: Vec<SampleJob> = later;
+            later.sort_by_key(|(order, _, _, _)| std::cmp::Reverse(*order));
+            for (order, label, _, path) in later {
+                winners.insert(label.clone(), order);
+                pending.insert(label, path);
+            }
+        }
+
+        // Decode the files in parallel, then add them earlier directories last so they win
+        let progress = logging::Progress::new("samples", jobs.len());
+        let mut results: Vec<(usize, String, Option<usize>, Pcm)> =
+            threads::on_loaders(thread_config.sample_loaders(), || {
+                jobs.into_par_iter()
+                    .filter_map(|(order, label, layer, path)| {
+                        let entry = load_sample(&path.to_string_lossy());
+                        progress.advance();
+                        entry
+                            .map_err(|e| log_error!("Failed to load sample '{}': {}", path.display(), e))
+                            .ok()
+                            .map(|entry| (order, label, layer, entry))
+                    })
+                    .collect()
+            });
+        drop(progress);
+        results.sort_by_key(|(order, _, _, _)| std::cmp::Reverse(*order));
+        // Velocity layers by label: (directory order, layer index, sample)
+        let mut layers: HashMap<String, Vec<(usize, usize, SampleData)>> = HashMap::new();
+        for (order, label, layer, data_entry) in results.drain(..) {
+            match layer {
+                None => {
+                    winners.insert(label.clone(), order);
+                    data.insert(label, Arc::new(data_entry));
+                }
+                Some(index) => layers.entry(label).or_default().push((order, index, Arc::new(data_entry))),
+            }
+        }
+
+        // Kit settings apply only where the kit's directory provided the sample
+        let mut kit = HashMap::new();
+        for (label, (order, sample)) in kit_samples {
+            if winners.get(&label).is_some_and(|winner| *winner != order) {
+                continue;
+            }
+            let mut voice_layers: Vec<(f32, SampleData)> = layers
+                .remove(&label)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(layer_order, _, _)| *layer_order == order)
+                .map(|(_, index, entry)| (sample.layers[index].min_velocity, entry))
+                .collect();
+            voice_layers.sort_by(|a, b| a.0.total_cmp(&b.0));
+            if let Some((_, loudest)) = voice_layers.last().filter(|_| !pending.contains_key(&label)) {
+                data.entry(label.clone()).or_insert_with(|| Arc::clone(loudest));
+            }
+            kit.insert(label, KitVoice::new(sample, voice_layers));
+        }
+
+        Ok(SoundBank {
+            data: RwLock::new(data),
+            kit: RwLock::new(kit),
+            pending: RwLock::new(pending),
+            preloading: std::sync::Mutex::new(HashSet::new()),
+            lazy,
+            directories: RwLock::new(directories.to_string()),
+            files: RwLock::new(files),
+            chokes: std::sync::Mutex::new(HashMap::new()),
+            staged: std::sync::Mutex::new(None),
+            threads: thread_config.clone(),
+            duplicates,
+        })
+    }
+
+    /// The sample to play for a trigger at `velocity`, with its kit settings.
+    pub fn voice(&self, label: &str, velocity: f32) -> Option<Voice> {
+        let base = self.get(label);
+        match self.kit.read().unwrap().get(label) {
+            Some(kit_voice) => kit_voice.voice(base, velocity),
+            None => base.map(|sample| Voice { sample, gain: 1.0, speed: 1.0, choke: None }),
+        }
+    }
+
+    /// Velocity the kit fixes for `label`, if any.
+    pub fn fixed_velocity(&self, label: &str) -> Option<f32> {
+        self.kit.read().unwrap().get(label).and_then(|kit_voice| kit_voice.velocity)
+    }
+
+    /// Cuts off the voice sounding in the choke group, keeping `sink` as the new one.
+    pub fn choke(&self, group: &str, sink: Sink) {
+        if let Some(previous) = self.chokes.lock().unwrap().insert(group.to_string(), sink) {
+            previous.stop();
+        }
+    }
+
+    /// The sample of `label`, decoded now if the bank is lazy and it
+    /// wasn't yet.
+    pub fn get(&self, label: &str) -> Option<SampleData> {
+        if let Some(sample) = self.data.read().unwrap().get(label) {
+            return Some(Arc::clone(sample));
+        }
+        let path = self.pending.read().unwrap().get(label).cloned()?;
+        self.decode(vec![(label.to_string(), path)]);
+        self.data.read().unwrap().get(label).cloned()
+    }
+
+    /// Whether the bank has a sample for `label`, decoded or not.
+    pub fn contains(&self, label: &str) -> bool {
+        self.data.read().unwrap().contains_key(label) || self.pending.read().unwrap().contains_key(label)
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self.data.read().unwrap().keys().cloned().collect();
+        labels.extend(self.pending.read().unwrap().keys().cloned());
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+
+    /// Decodes the samples of `labels` that are still pending, on the
+    /// sample loader threads.
+    pub fn preload<'a>(&self, labels: impl IntoIterator<Item = &'a str>) {
+        let files: Vec<(String, PathBuf)> = {
+            let pending = self.pending.read().unwrap();
+            labels.into_iter().filter_map(|label| pending.get(label).map(|path| (label.to_string(), path.clone()))).collect()
+        };
+        if !files.is_empty() {
+            self.decode(files);
+        }
+    }
+
+    /// Hands the samples of `labels` that are still pending to the sample
+    /// loader threads without waiting for them to decode.
+    pub fn preload_in_background<'a>(self: &Arc<Self>, labels: impl IntoIterator<Item = &'a str>) {
+        let mut files = Vec::new();
+        {
+            let pending = self.pending.read().unwrap();
+            let mut preloading = self.preloading.lock().unwrap();
+            for label in labels {
+                if let Some(path) = pending.get(label) {
+                    if preloading.insert(label.to_string()) {
+                        files.push((label.to_string(), path.clone()));
+                    }
+                }
+            }
+        }
+        if files.is_empty() {
+            return;
+        }
+        let bank = Arc::clone(self);
+        threads::spawn_on_loaders(self.threads.sample_loaders(), move || {
+            let labels: Vec<String> = files.iter().map(|(label, _)| label.clone()).collect();
+            bank.decode(files);
+            let mut preloading = bank.preloading.lock().unwrap();
+            for label in labels {
+                preloading.remove(&label);
+            }
+        });
+    }
+
+    /// Decodes the pending samples one at a time until none are left, for a
+    /// background thread to warm a lazy bank up with.
+    pub fn prefetch(&self) {
+        loop {
+            let next = self.pending.read().unwrap().iter().next().map(|(label, path)| (label.clone(), path.clone()));
+            let Some(file) = next else {
+                break;
+            };
+            self.decode(vec![file]);
+        }
+    }
+
+    /// Decodes pending `files` into the bank, dropping the ones that fail to
+    /// load.
+    fn decode(&self, files: Vec<(String, PathBuf)>) {
+        let progress = logging::Progress::new("samples", files.len());
+        let results: Vec<(String, PathBuf, Option<Pcm>)> =
+            threads::on_loaders(self.threads.sample_loaders(), || {
+                files
+                    .into_par_iter()
+                    .map(|(label, path)| {
+                        let entry = load_sample(&path.to_string_lossy());
+                        progress.advance();
+                        if let Err(e) = &entry {
+                            log_error!("Failed to load sample '{}': {}", path.display(), e);
+                        }
+                        (label, path, entry.ok())
+                    })
+                    .collect()
+            });
+        drop(progress);
+        let mut data = self.data.write().unwrap();
+        let mut pending = self.pending.write().unwrap();
+        for (label, path, entry) in results {
+            // Skipped when another decode got there first or the bank was
+            // swapped while this one ran
+            if pending.get(&label) != Some(&path) {
+                continue;
+            }
+            pending.remove(&label);
+            if let Some(entry) = entry {
+                data.insert(label, Arc::new(entry));
+            }
+        }
+    }
+
+    /// Replaces the bank contents with the samples in `directories`.
+    pub fn reload(&self, directories: &str) -> error::Result<()> {
+        self.replace(SoundBank::load(directories, self.lazy, &self.threads, self.duplicates)?);
+        Ok(())
+    }
+
+    /// Loads the samples in `directories` next to the current ones, to be
+    /// swapped in by `swap_staged`. Lazy banks decode the labels already
+    /// played right away, so they don't have to wait for it after the swap.
+    pub fn stage(&self, directories: &str) -> error::Result<()> {
+        self.stage_bank(self.load_next(directories)?);
+        Ok(())
+    }
+
+    /// Loads the samples in `directories` the way this bank was loaded, for
+    /// `stage_bank`.
+    fn load_next(&self, directories: &str) -> error::Result<SoundBank> {
+        let fresh = SoundBank::load(directories, self.lazy, &self.threads, self.duplicates)?;
+        if self.lazy {
+            let used: Vec<String> = self.data.read().unwrap().keys().cloned().collect();
+            fresh.preload(used.iter().map(String::as_str));
+        }
+        Ok(fresh)
+    }
+
+    /// Stages the contents of a bank loaded earlier, e.g. ahead of a song change.
+    pub fn stage_bank(&self, fresh: SoundBank) {
+        *self.staged.lock().unwrap() = Some(Box::new(fresh));
+    }
+
+    /// Swaps in the staged contents, if any; returns whether it did.
+    pub fn swap_staged(&self) -> bool {
+        let staged = self.staged.lock().unwrap().take();
+        staged.map(|fresh| self.replace(*fresh)).is_some()
+    }
+
+    fn replace(&self, fresh: SoundBank) {
+        *self.data.write().unwrap() = fresh.data.into_inner().unwrap();
+        *self.kit.write().unwrap() = fresh.kit.into_inner().unwrap();
+        *self.pending.write().unwrap() = fresh.pending.into_inner().unwrap();
+        *self.directories.write().unwrap() = fresh.directories.into_inner().unwrap();
+        *self.files.write().unwrap() = fresh.files.into_inner().unwrap();
+    }
+
+    /// Loads the .wav files that appeared in the bank's directories since
+    /// it was loaded, returning their labels. Labels taken already keep
+    /// their sample, and files still being written wait for the next call.
+    pub fn load_new_files(&self) -> error::Result<Vec<String>> {
+        let directories = self.directories.read().unwrap().clone();
+        let SampleScan { jobs, mut kit_samples, .. } = SampleScan::new(&directories, self.duplicates)?;
+        let fresh: Vec<SampleJob> = {
+            let files = self.files.read().unwrap();
+            jobs.into_iter().filter(|(_, _, layer, path)| layer.is_none() && !files.contains(path) && is_settled(path)).collect()
+        };
+        let mut labels = Vec::new();
+        for (_, label, _, path) in fresh {
+            self.files.write().unwrap().insert(path.clone());
+            if self.contains(&label) {
+                log_warn!("Not adding {}: '{}' is loaded already", path.display(), label);
+                continue;
+            }
+            if let Some((_, sample)) = kit_samples.remove(&label) {
+                self.kit.write().unwrap().insert(label.clone(), KitVoice::new(sample, Vec::new()));
+            }
+            self.pending.write().unwrap().insert(label.clone(), path.clone());
+            if !self.lazy {
+                self.decode(vec![(label.clone(), path)]);
+            }
+            if self.contains(&label) {
+                labels.push(label);
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Adds a WAV held in memory under `label`, for builds that can't read
+    /// the sample directories, like the browser one.
+    pub fn insert_wav(&self, label: &str, bytes: &[u8]) -> error::Result<()> {
+        let entry = decode_wav(hound::WavReader::new(bytes), label)?;
+        self.data.write().unwrap().insert(label.to_string(), Arc::new(entry));
+        self.pending.write().unwrap().remove(label);
+        Ok(())
+    }
+
+    /// Loads a single file into the bank at runtime, returning its label.
+    pub fn load_file(&self, path: &str) -> error::Result<String> {
+        let entry = load_sample(path)?;
+        let name_error = |reason: String| error::Error::FileName { path: path.to_string(), reason };
+        let file = std::path::Path::new(path).file_name().and_then(|s| s.to_str()).ok_or_else(|| name_error("Invalid filename".to_string()))?;
+        let (label, settings) = kit::parse_file_name(file).map_err(name_error)?;
+        self.data.write().unwrap().insert(label.clone(), Arc::new(entry));
+        self.pending.write().unwrap().remove(&label);
+        match settings {
+            Some(sample) => {
+                self.kit.write().unwrap().insert(label.clone(), KitVoice::new(sample, Vec::new()));
+            }
+            None => {
+                self.kit.write().unwrap().remove(&label);
+            }
+        }
+        Ok(label)
+    }
+}
+
+
+#[derive(Default)]
+pub struct LoopBank {
+    data: RwLock<HashMap<String, Arc<LoopSample>>>,
+    /// Contents loaded by `stage`, waiting for `swap_staged`.
+    staged: std::sync::Mutex<Option<Box<LoopBank>>>,
+    /// Directories the bank was loaded from, and the files found in them.
+    directories: RwLock<String>,
+    files: RwLock<HashSet<PathBuf>>,
+    /// Loader threads of this bank's loads and reloads.
+    threads: ThreadConfig,
+    /// How its loads label files whose label an earlier file took.
+    duplicates: DuplicateLabels,
+}
+
+/// Whether a file is a loop: listed in its directory's loops.json or
+/// following the bpm_beats_name.wav convention.
+pub fn is_loop_filename(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    matches!(loops::sidecar_meta(path), Ok(Some(_))) || loops::parse_filename(path).is_some()
+}
+
+/// Decodes a loop, taking bpm, beats, key and label from its sidecar entry
+/// and falling back to the file name for what that leaves out. Loops with
+/// neither get their tempo detected, returned as a sidecar entry to cache.
+/// Loops in another key than the configured project key are shifted to it.
+fn load_loop(path: &str, sidecar: Option<LoopMeta>) -> error::Result<(String, LoopSample, Option<LoopMeta>)> {
+    let loop_error = |reason: String| error::Error::Loop { path: path.to_string(), reason };
+
+    let file_path = std::path::Path::new(path);
+    let mut meta = sidecar.unwrap_or_default().or(loops::parse_filename(file_path).unwrap_or_default());
+    let mut pitch = 1.0;
+    if let (Some(key), Some(project_key)) = (&meta.key, loops::project_key()) {
+        match loops::key_shift(key, &project_key) {
+            Ok(0) => {}
+            Ok(semitones) => {
+                pitch = 2f32.powf(semitones as f32 / 12.0);
+                log!("Shifting {} by {} semitones from {} to {}", path, semitones, key, project_key);
+            }
+            Err(e) => log_warn!("Not shifting {} to {}: {}", path, project_key, e),
+        }
+    }
+    let mut detected = None;
+    let mut unshifted = None;
+    if meta.bpm.is_none() && meta.beats.is_none() {
+        // Detected once, then read from the sidecar, so decoding twice here is rare
+        let pcm = load_sample(path)?;
+        let (bpm, beats) = loops::detect_tempo(&pcm.0, pcm.1, pcm.2)
+            .ok_or_else(|| loop_error(format!("No bpm for loop and none detected; add it to {}", loops::LOOPS_FILE)))?;
+        log!("Detected {:.1} bpm over {} beats in {}", bpm, beats, path);
+        meta = LoopMeta { bpm: Some(bpm), beats: Some(beats), detected: true, ..meta };
+        detected = Some(meta.clone());
+        unshifted = Some(pcm);
+    }
+    let label = match meta.label {
+        Some(label) => label,
+        None => file_path.file_stem().and_then(|s| s.to_str()).ok_or_else(|| loop_error("Invalid filename".to_string()))?.to_string(),
+    };
+    let (samples, channels, sample_rate) = match unshifted {
+        Some(pcm) if pitch == 1.0 => pcm,
+        _ if pitch == 1.0 => load_sample(path)?,
+        _ => load_processed(path, &format!("time_stretch {}", pitch), |(samples, channels, rate)| {
+            (loops::time_stretch(&samples, channels, pitch), channels, rate)
+        })?,
+    };
+    let mut sample = LoopSample { samples, channels, sample_rate, bpm: 0.0, beats: meta.beats, key: meta.key, pitch, stretch: meta.stretch.unwrap_or_default(), stretched: Default::default() };
+    sample.bpm = match (meta.bpm, meta.beats) {
+        (Some(bpm), _) => bpm,
+        // A beat count alone gives the tempo through the unshifted loop length
+        (None, Some(beats)) if beats > 0 => beats as f32 * 60.0 * pitch / sample.seconds(),
+        _ => return Err(loop_error(format!("No bpm for loop; add it to {} or name the file bpm_beats_name.wav", loops::LOOPS_FILE))),
+    };
+    Ok((label, sample, detected))
+}
+
+/// Writes detected loop tempos to the sidecar of `dir` so they are not
+/// detected again and can be corrected by hand.
+fn cache_detected(dir: &std::path::Path, detected: Vec<(String, LoopMeta)>) {
+    if detected.is_empty() {
+        return;
+    }
+    if let Err(e) = loops::cache_detected(dir, detected) {
+        log_warn!("Could not cache detected tempos in {}: {}", dir.join(loops::LOOPS_FILE).display(), e);
+    }
+}
+
+
+impl LoopBank {
+    /// Loads the loops in `directories`, a PATH-style list, on the loop
+    /// loader threads of `thread_config`; duplicate labels are handled as
+    /// `duplicates` says.
+    pub fn new(directories: &str, thread_config: &ThreadConfig, duplicates: DuplicateLabels) -> error::Result<Self> {
+        let mut data = HashMap::new();
+
+        let mut jobs = Vec::new();
+        let mut files = HashSet::new();
+        for (order, directory) in std::env::split_paths(directories).enumerate() {
+            let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+                .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect())
+                .map_err(|source| error::Error::io(&directory, source))?;
+            paths.sort();
+            let mut sidecar = loops::read_loops(&directory)?;
+            for path in paths {
+                if path.extension().is_some_and(|extension| extension == "wav") {
+                    files.insert(path.clone());
+                    let meta = path.file_name().and_then(|n| n.to_str()).and_then(|n| sidecar.remove(n));
+                    jobs.push((order, path, meta));
+                }
+            }
+        }
+
+        // Decode the files in parallel; the order is kept, so earlier directories keep their labels
+        let progress = logging::Progress::new("loops", jobs.len());
+        let results: Vec<(String, LoopSample, (PathBuf, Option<LoopMeta>))> =
+            threads::on_loaders(thread_config.loop_loaders(), || {
+                jobs.into_par_iter()
+                    .filter_map(|(_, path, meta)| {
+                        let entry = load_loop(&path.to_string_lossy(), meta);
+                        progress.advance();
+                        match entry {
+                            Ok((name, sample, detected)) => Some((name, sample, (path, detected))),
+                            Err(e) => {
+                                log_error!("Failed to load loop '{}': {}", path.display(), e);
+                                None
+                            }
+                        }
+                    })
+                    .collect()
+            });
+        drop(progress);
+        let mut detected: HashMap<PathBuf, Vec<(String, LoopMeta)>> = HashMap::new();
+        for (label, data_entry, (path, meta)) in results {
+            let label = if data.contains_key(&label) {
+                let directory = path.parent().unwrap_or(Path::new(""));
+                let renamed = labels::disambiguate(&label, directory, duplicates, |candidate| data.contains_key(candidate));
+                labels::report(&path, &label, renamed.as_deref());
+                match renamed {
+                    Some(renamed) => renamed,
+                    None => continue,
+                }
+            } else {
+                label
+            };
+            data.insert(label, Arc::new(data_entry));
+            if let (Some(meta), Some(dir), Some(name)) = (meta, path.parent(), path.file_name()) {
+                detected.entry(dir.to_path_buf()).or_default().push((name.to_string_lossy().into_owned(), meta));
+            }
+        }
+        for (dir, entries) in detected {
+            cache_detected(&dir, entries);
+        }
+
+        Ok(LoopBank {
+            data: RwLock::new(data),
+            staged: std::sync::Mutex::new(None),
+            directories: RwLock::new(directories.to_string()),
+            files: RwLock::new(files),
+            threads: thread_config.clone(),
+            duplicates,
+        })
+    }
+
+    pub fn get(&self, label: &str) -> Option<Arc<LoopSample>> {
+        self.data.read().unwrap().get(label).cloned()
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self.data.read().unwrap().keys().cloned().collect();
+        labels.sort();
+        labels
+    }
+
+    /// Replaces the bank contents with the loops in `directories`.
+    pub fn reload(&self, directories: &str) -> error::Result<()> {
+        self.replace(LoopBank::new(directories, &self.threads, self.duplicates)?);
+        Ok(())
+    }
+
+    /// Loads the loops in `directories` next to the current ones, to be
+    /// swapped in by `swap_staged`.
+    pub fn stage(&self, directories: &str) -> error::Result<()> {
+        self.stage_bank(LoopBank::new(directories, &self.threads, self.duplicates)?);
+        Ok(())
+    }
+
+    /// Stages the contents of a bank loaded earlier, e.g. ahead of a song change.
+    pub fn stage_bank(&self, fresh: LoopBank) {
+        *self.staged.lock().unwrap() = Some(Box::new(fresh));
+    }
+
+    /// Swaps in the staged contents, if any; returns whether it did.
+    pub fn swap_staged(&self) -> bool {
+        let staged = self.staged.lock().unwrap().take();
+        staged.map(|fresh| self.replace(*fresh)).is_some()
+    }
+
+    fn replace(&self, fresh: LoopBank) {
+        *self.data.write().unwrap() = fresh.data.into_inner().unwrap();
+        *self.directories.write().unwrap() = fresh.directories.into_inner().unwrap();
+        *self.files.write().unwrap() = fresh.files.into_inner().unwrap();
+    }
+
+    /// Loads the .wav files that appeared in the bank's directories since
+    /// it was loaded, returning their labels. Labels taken already keep
+    /// their loop, and files still being written wait for the next call.
+    pub fn load_new_files(&self) -> error::Result<Vec<String>> {
+        let directories = self.directories.read().unwrap().clone();
+        let mut labels = Vec::new();
+        for directory in std::env::split_paths(&directories) {
+            let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+                .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect())
+                .map_err(|source| error::Error::io(&directory, source))?;
+            paths.sort();
+            for path in paths {
+                if path.extension().is_none_or(|extension| extension != "wav")
+                    || self.files.read().unwrap().contains(&path)
+                    || !is_settled(&path)
+                {
+                    continue;
+                }
+                self.files.write().unwrap().insert(path.clone());
+                let path_str = path.to_str().ok_or_else(|| error::Error::FileName {
+                    path: path.display().to_string(),
+                    reason: "Invalid file path".to_string(),
+                })?;
+                let (label, sample, detected) = match load_loop(path_str, loops::sidecar_meta(&path)?) {
+                    Ok(loaded) => loaded,
+                    Err(e) => {
+                        log_error!("Failed to load loop '{}': {}", path_str, e);
+                        continue;
+                    }
+                };
+                if self.get(&label).is_some() {
+                    log_warn!("Not adding {}: '{}' is loaded already", path_str, label);
+                    continue;
+                }
+                if let (Some(meta), Some(name)) = (detected, path.file_name()) {
+                    cache_detected(&directory, vec![(name.to_string_lossy().into_owned(), meta)]);
+                }
+                self.data.write().unwrap().insert(label.clone(), Arc::new(sample));
+                labels.push(label);
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Loads a single loop file into the bank at runtime, returning its label.
+    pub fn load_file(&self, path: &str) -> error::Result<String> {
+        let file_path = std::path::Path::new(path);
+        let (label, sample, detected) = load_loop(path, loops::sidecar_meta(file_path)?)?;
+        if let (Some(meta), Some(dir), Some(name)) = (detected, file_path.parent(), file_path.file_name()) {
+            cache_detected(dir, vec![(name.to_string_lossy().into_owned(), meta)]);
+        }
+        self.data.write().unwrap().insert(label.clone(), Arc::new(sample));
+        Ok(label)
+    }
+}
+
+/// How long a new file has to stay unchanged before it is loaded, so files
+/// still being copied in are not read half-written.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+/// How often the bank directories are checked for new files.
+const WATCH_INTERVAL: Duration = Duration::from_secs(3);
+
+fn is_settled(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age >= SETTLE_TIME))
+}
+
+/// Checks the sample and loop directories for new files on a background
+/// thread while `alive` is set, adding them to the banks as they arrive.
+pub fn watch_banks(sound_bank: Arc<SoundBank>, loop_bank: Arc<LoopBank>, alive: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        while alive.load(Ordering::SeqCst) {
+            thread::sleep(WATCH_INTERVAL);
+            match sound_bank.load_new_files() {
+                Ok(labels) => labels.iter().for_each(|label| log!("New sample '{}'", label)),
+                Err(e) => log_error!("Failed to check for new samples: {}", e),
+            }
+            match loop_bank.load_new_files() {
+                Ok(labels) => labels.iter().for_each(|label| log!("New loop '{}'", label)),
+                Err(e) => log_error!("Failed to check for new loops: {}", e),
+            }
+        }
+    });
+}
+
+/// Loads other sample and/or loop directories on a background thread.
+/// Playback keeps the current banks until its next pass starts, where the
+/// scheduler swaps the new ones in.
+pub fn stage_banks(sound_bank: Arc<SoundBank>, loop_bank: Arc<LoopBank>, sample_dirs: Option<String>, loop_dirs: Option<String>) {
+    thread::spawn(move || {
+        if let Some(dirs) = sample_dirs {
+            match sound_bank.stage(&dirs) {
+                Ok(()) => log!("Samples from {} loaded, swapping them in at the next loop", dirs),
+                Err(e) => log_error!("Failed to load samples from {}: {}", dirs, e),
+            }
+        }
+        if let Some(dirs) = loop_dirs {
+            match loop_bank.stage(&dirs) {
+                Ok(()) => log!("Loops from {} loaded, swapping them in at the next loop", dirs),
+                Err(e) => log_error!("Failed to load loops from {}: {}", dirs, e),
+            }
+        }
+    });
+}
+
+pub fn beats_to_millis(beats: f32, bpm: u32) -> u64 {
+    let minutes = beats / bpm as f32;
+    let millis = minutes * 60.0 * 1000.0;
+    millis.round() as u64
+}
+
+/// How a loop is played: cut after `duration` beats at `bpm`, with an
+/// optional meter and crossfade fader.
+pub struct LoopPlay {
+    pub duration: f32,
+    pub velocity: f32,
+    pub pan: f32,
+    pub meter: Option<Arc<LevelMeter>>,
+    pub fader: Option<Arc<Fader>>,
+    pub bpm: u32,
+}
+
+pub fn play_loop(label: &str, play: LoopPlay, loop_bank: &LoopBank, stream_handle: &OutputStreamHandle) {
+    let LoopPlay { duration, velocity, pan, meter, fader, bpm: project_bpm } = play;
+    if let Some(entry) = loop_bank.get(label) {
+        let duration_millis = beats_to_millis(duration, project_bpm);
+        entry.with_playback(project_bpm, |samples, playback_speed| {
+            if let Some(meter) = &meter {
+                let seconds = samples.len() as f32 / entry.channels.max(1) as f32 / entry.sample_rate as f32;
+                let played = seconds.min(duration_millis as f32 / 1000.0) / playback_speed;
+                meter.start_loop(Duration::from_secs_f32(played));
+            }
+            let params = VoiceParams {
+                gain: velocity / 100.0,
+                speed: playback_speed,
+                pan: Some(pan),
+                limit: Some(Duration::from_millis(duration_millis)),
+                fader,
+            };
+            audio::start_voice(stream_handle, samples.to_vec(), entry.channels, entry.sample_rate, params, meter).detach();
+            log!(
+                "[Loop] Playing '{}' at project BPM {} for original {} with speed adjustment {:.2} ({:?})",
+                label, project_bpm, entry.bpm, playback_speed, entry.stretch
+            );
+        });
+    } else {
+        log_warn!("No loop label '{}' found in LoopBank", label);
+        DIAGNOSTICS.record_dropped();
+    }
+}
+
+
+
+
+/// Sends the note-on of a MIDI note; `release_midi_note` ends it.
+pub fn play_midi_note(note: u8, velocity: f32, midi_conn: &std::sync::Mutex<MidiOut>) {
+    let velocity = velocity.clamp(0.0, 127.0) as u8;
+    if let Ok(mut conn) = midi_conn.lock() {
+        let _ = conn.send(&[0x90, note, velocity]);
+        log!("[MIDI] Note On: {}, velocity: {}", note, velocity);
+    }
+}
+
+/// Sends the note-off of a MIDI note.
+pub fn release_midi_note(note: u8, midi_conn: &std::sync::Mutex<MidiOut>) {
+    if let Ok(mut conn) = midi_conn.lock() {
+        let _ = conn.send(&[0x80, note, 0]);
+        log!("[MIDI] Note Off: {}", note);
+    }
+}
+
+pub fn play_sound(
+    label: &str,
+    velocity: f32,
+    pan: f32,
+    meter: Option<Arc<LevelMeter>>,
+    sound_bank: &SoundBank,
+    stream_handle: &OutputStreamHandle,
+) {
+    if let Some(voice) = sound_bank.voice(label, velocity) {
+        let (samples, channels, sample_rate) = &*voice.sample;
+        let params = VoiceParams { gain: velocity / 100.0 * voice.gain, speed: voice.speed, pan: Some(pan), limit: None, fader: None };
+        let sink = audio::start_voice(stream_handle, samples.clone(), *channels, *sample_rate, params, meter);
+        match voice.choke {
+            Some(group) => sound_bank.choke(&group, sink),
+            None => sink.detach(),
+        }
+        log!("[Audio] Playing '{}' at velocity {:.1}", label, velocity);
+    } else {
+        log_warn!("No sound label '{}' found in SoundBank", label);
+        DIAGNOSTICS.record_dropped();
+    }
+}
+
+/// Decodes and plays a file straight from disk, bypassing the banks.
+pub fn play_file(path: &str, stream_handle: &OutputStreamHandle) {
+    match load_sample(path) {
+        Ok((samples, channels, sample_rate)) => {
+            let params = VoiceParams { gain: 1.0, speed: 1.0, pan: None, limit: None, fader: None };
+            audio::start_voice(stream_handle, samples, channels, sample_rate, params, None).detach();
+            log!("[Audio] Previewing '{}'", path);
+        }
+        Err(e) => log_error!("Failed to preview '{}': {}", path, e),
+    }
+}
+
+/// Length of a grid step in beats; ratchets subdivide this span.
+pub const STEP_BEATS: f32 = 0.25;
+/// Scheduler resolution in beats.
+pub const TICK_BEATS: f32 = 0.125;
+/// Scheduler resolution in position ticks, so the scheduler counts exactly.
+pub const TICK: Ticks = Ticks(PPQN / 8);
+
+/// What the scheduler plays on and reports to, the same for every pass.
+pub struct Scheduler<'a> {
+    pub current_beat: &'a RwLock<f32>,
+    pub rack: &'a Rack,
+    pub dispatcher: &'a Dispatcher,
+    pub mixer: &'a RwLock<Mixer>,
+    pub transport: &'a Transport,
+    pub events: &'a Events,
+    pub loop_beats: u32,
+    /// Once cleared the pass is cut short, at the end of the bar with
+    /// `finish_bar`, and the triggers still queued are waited for.
+    pub running: &'a AtomicBool,
+    pub finish_bar: bool,
+}
+
+/// Plays one pass of the loop, each tick at its time on `clock`.
+pub fn play_pattern_with_soundbank(patterns: Arc<Vec<Pattern>>, scheduler: &Scheduler, clock: &mut Clock) {
+    let Scheduler { current_beat, rack, dispatcher, mixer, transport, events, loop_beats, running, finish_bar } = *scheduler;
+    let bpm = transport.bpm();
+    let transpose = transport.transpose();
+    let variation = transport.variation();
+    let fill = transport.take_fill();
+    let pass = transport.next_pass();
+    let scene = transport.take_scene();
+    let bank = transport.advance_bank(&bank_names(&patterns));
+    let beat_duration = 60.0 / bpm as f32;
+    let eighth_beat_duration = beat_duration / 8.0;
+    let total_eighth_beats = loop_beats * 8;
+    clock.set_bpm(bpm);
+
+    // Banks loaded in the background change over between passes
+    if rack.sound_bank.swap_staged() {
+        log!("Swapped in the new samples");
+    }
+    if rack.loop_bank.swap_staged() {
+        log!("Swapped in the new loops");
+    }
+    if let Some(scene) = scene {
+        log!("Launching scene '{}'", scene.name);
+        mixer.write().unwrap().restore(&scene.mixer);
+        rack.crossfade_loops(Duration::from_secs_f32(scene.crossfade.max(0.0) * beat_duration));
+    }
+
+    // Lazy banks decode the samples the patterns use before they are due,
+    // on the loader threads so the pass doesn't wait for them
+    rack.sound_bank.preload_in_background(patterns.iter().filter_map(|pattern| pattern.sound.as_deref()));
+
+    // Instruments are set up once per pass, not per trigger
+    let instruments: Vec<_> = patterns.iter().map(|pattern| rack.instrument(pattern)).collect();
+
+    DIAGNOSTICS.reset_max_jitter();
+
+    for i in 0..total_eighth_beats {
+        if i % 32 == 0 && transport.start_bar() {
+            log!("Bar limit reached. Stopping loop...");
+            running.store(false, Ordering::SeqCst);
+        }
+        if !running.load(Ordering::SeqCst) && (!finish_bar || i % 32 == 0) {
+            dispatcher.drain();
+            return;
+        }
+        // A queued scene starts from its top at the next bar
+        if i > 0 && i % 32 == 0 && transport.scene_queued() {
+            return;
+        }
+        let position = Ticks(i as i32 * TICK.0);
+        let computed_current_beat = position.beats();
+        DIAGNOSTICS.record_tick(clock.lateness(), Duration::from_secs_f32(eighth_beat_duration));
+        {
+            let mut beat_lock = current_beat.write().unwrap();
+            *beat_lock = computed_current_beat;
+        }
+
+        if i % 8 == 0 {
+            events.publish(EngineEvent::Beat { beat: computed_current_beat, bar: i / 32 });
+        }
+
+        if i % 8 == 0 && transport.metronome() {
+            audio::play_click(i % 32 == 0, &rack.stream_handle);
+        }
+
+        for (pattern, instrument) in patterns.iter().zip(&instruments) {
+            let Some(instrument) = instrument else {
+                continue;
+            };
+            let enabled = pattern.is_enabled(&bank, variation, fill);
+            for (step, delay) in pattern.due_steps(pass, position, TICK, loop_beats).into_iter().filter(|_| enabled) {
+                let track = pattern.track_name().to_string();
+                let (gain, pan, meter) = {
+                    let mut mixer_lock = mixer.write().unwrap();
+                    (mixer_lock.gain(&track), (mixer_lock.pan(&track) + pattern.pan).clamp(-1.0, 1.0), mixer_lock.meter(&track))
+                };
+                if gain <= 0.0 {
+                    continue;
+                }
+
+                let offset_secs = delay * beat_duration;
+                let ratchet = step.ratchet.max(1);
+                let interval_beats = pattern.ratchet_beats(&step);
+                let interval_secs = interval_beats * beat_duration;
+
+                let note = pattern.note(&step, transpose).unwrap_or(DEFAULT_NOTE);
+                let velocity = instrument.fixed_velocity().unwrap_or_else(|| pattern.trigger_velocity(&step)) * gain;
+                let duration = pattern.note_beats(&step, bpm);
+                // Ratcheted notes have to end before the next repeat starts
+                let duration = if ratchet > 1 && instrument.holds_note() { duration.min(interval_beats * 0.9) } else { duration };
+                dispatcher.dispatch(TriggerJob {
+                    instrument: Arc::clone(instrument),
+                    track,
+                    note,
+                    velocity,
+                    duration,
+                    playback: Playback { pan, meter, bpm },
+                    offset_secs,
+                    ratchet,
+                    interval_secs,
+                });
+            }
+        }
+
+        clock.advance();
+        clock.sleep_until_due();
+    }
+}
+
+/// Lets the tails ring out under a fade, then silences the MIDI side.
+fn finish_playback(rack: &Rack, dispatcher: Dispatcher, events: &Events, shutdown: &ShutdownConfig) {
+    audio::fade_out(Duration::from_millis(shutdown.fade_ms));
+    for conn in rack.midi_connections() {
+        if let Err(e) = conn.lock().unwrap().all_notes_off() {
+            log_error!("Could not send MIDI note-offs: {}", e);
+        }
+    }
+    drop(dispatcher);
+    events.publish(EngineEvent::Stopped);
+}
+
+/// Edits a copy of the shared patterns and swaps it in, so playback keeps
+/// reading its snapshot without locking. When another edit got in first the
+/// edit is run again on its result, so concurrent edits are never lost.
+pub fn edit_patterns<R>(patterns: &ArcSwap<Vec<Pattern>>, mut edit: impl FnMut(&mut Vec<Pattern>) -> R) -> R {
+    let mut result = None;
+    patterns.rcu(|current| {
+        let mut edited = Vec::clone(current);
+        result = Some(edit(&mut edited));
+        edited
+    });
+    result.expect("rcu runs the edit at least once")
+}
+
+pub fn generate_combined_patterns(midi_pattern: Vec<Pattern>, json_patterns: Vec<Pattern>) -> Vec<Pattern> {
+    let mut combined_patterns = Vec::new();
+
+    combined_patterns.extend(json_patterns);
+
+    // combined_patterns.push(PatternBuilder::new()
+    //     .loop_name("dl-icarus")
+    //     .beats(vec![0.0, 8.0])
+    //     .duration(8.0)
+    //     .velocity(35.0)
+    //     .build()
+    // );
+
+    // combined_patterns.push(PatternBuilder::new()
+    //     .loop_name("dl-ethnic")
+    //     .beats(vec![0.0])
+    //     .repeat(4.0, 4)
+    //     .duration(2.0)
+    //     .build()
+    // );
+    // combined_patterns.push(PatternBuilder::new()
+    //     .loop_name("dl-ethnic")
+    //     .beats(vec![1.25])
+    //     .repeat(4.0, 4)
+    //     .duration(2.5)
+    //     .build()
+    // );
+
+    combined_patterns.extend(midi_pattern);
+
+    combined_patterns
+}
+
+pub fn load_and_combine_patterns(file_path: &str, midi_pattern: &[Pattern], loop_beats: u32) -> Vec<Pattern> {
+    if let Ok(file_content) = fs::read_to_string(file_path) {
+        load_and_combine_patterns_from_content(file_path, &file_content, midi_pattern, loop_beats)
+    } else {
+        log_error!("Failed to read {} during initial load.", file_path);
+        generate_combined_patterns(midi_pattern.to_vec(), Vec::new())
+    }
+}
+
+/// Helper function to load and combine patterns from file content; the
+/// format (JSON, TOML or YAML) follows the file extension
+pub fn load_and_combine_patterns_from_content(
+    file_path: &str,
+    file_content: &str,
+    midi_pattern: &[Pattern],
+    loop_beats: u32,
+) -> Vec<Pattern> {
+    match formats::parse_patterns(file_path, file_content, loop_beats) {
+        Ok(new_patterns) => generate_combined_patterns(midi_pattern.to_vec(), new_patterns),
+        Err(e) => {
+            log_error!("Failed to parse {}: {}", file_path, e);
+            generate_combined_patterns(midi_pattern.to_vec(), Vec::new())
+        }
+    }
+}
+
+/// -------------------------------------------------------------------------
+/// 2) Engine
+/// -------------------------------------------------------------------------
+/// Something that happened during playback.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineEvent {
+    /// The playhead reached a whole beat; `bar` counts 4-beat bars into the loop.
+    Beat { beat: f32, bar: u32 },
+    /// A track's voice was triggered `at`; `duration` in beats.
+    Trigger { track: String, note: u8, velocity: f32, duration: f32, pan: f32, at: Instant },
+    /// Playback ended.
+    Stopped,
+}
+
+/// Subscribers to the engine events; closed receivers are dropped on publish.
+#[derive(Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<EngineEvent>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> Receiver<EngineEvent> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// The sequencer without a frontend. Patterns, mixer and transport are
+/// shared state, so an embedding app can edit them while playing:
+///
+/// ```no_run
+/// use four_on_the_floor::{config, model::PatternBuilder, Engine, EngineEvent};
+///
+/// let config = config::read_config("config.json")?;
+/// let mut engine = Engine::new(&config, 120)?;
+/// engine.set_patterns(vec![PatternBuilder::new().sound("bd").beats(vec![0.0, 1.0, 2.0, 3.0]).build()]);
+/// let events = engine.subscribe();
+/// engine.play();
+/// while let Ok(event) = events.recv() {
+///     if let EngineEvent::Beat { bar: 1, .. } = event {
+///         break;
+///     }
+/// }
+/// engine.stop();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Engine {
+    pub sound_bank: Arc<SoundBank>,
+    pub loop_bank: Arc<LoopBank>,
+    /// Patterns played, picked up at the start of every loop pass.
+    pub patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    pub current_beat: Arc<RwLock<f32>>,
+    pub mixer: Arc<RwLock<Mixer>>,
+    pub transport: Arc<Transport>,
+    pub midi_conn: Arc<Mutex<MidiOut>>,
+    pub stream_handle: Arc<OutputStreamHandle>,
+    pub loop_beats: u32,
+    pub shutdown: ShutdownConfig,
+    pub threads: ThreadConfig,
+    events: Arc<Events>,
+    running: Arc<AtomicBool>,
+    playback: Option<thread::JoinHandle<()>>,
+    // Audio stops once the stream is dropped; none for MIDI-only engines
+    _stream: Option<OutputStream>,
+}
+
+impl Engine {
+    /// Opens the configured audio device and MIDI port and loads the
+    /// sample and loop banks.
+    pub fn new(config: &Config, bpm: u32) -> error::Result<Self> {
+        sample_cache::configure(config.sample_cache_dir());
+        loops::configure_key(config.loop_key());
+        let duplicates = config.sounds.duplicates;
+        let sound_bank = if config.sounds.lazy {
+            SoundBank::lazy(&config.sample_dirs(), &config.threads, duplicates)?
+        } else {
+            SoundBank::new(&config.sample_dirs(), &config.threads, duplicates)?
+        };
+        let loop_bank = LoopBank::new(&config.loop_dirs(), &config.threads, duplicates)?;
+        let engine = Self::with_banks(config, bpm, sound_bank, loop_bank, true)?;
+        if config.sounds.lazy {
+            let sound_bank = Arc::clone(&engine.sound_bank);
+            thread::spawn(move || sound_bank.prefetch());
+        }
+        Ok(engine)
+    }
+
+    /// Opens the MIDI port with empty banks, for rigs that only sequence MIDI
+    /// hardware: no audio device is opened and no samples or loops are
+    /// decoded, so patterns should stick to MIDI notes.
+    pub fn midi_only(config: &Config, bpm: u32) -> error::Result<Self> {
+        sample_cache::configure(config.sample_cache_dir());
+        loops::configure_key(config.loop_key());
+        // Empty, but set up for banks loaded into them later
+        let duplicates = config.sounds.duplicates;
+        let sound_bank = SoundBank { threads: config.threads.clone(), duplicates, ..Default::default() };
+        let loop_bank = LoopBank { threads: config.threads.clone(), duplicates, ..Default::default() };
+        Self::with_banks(config, bpm, sound_bank, loop_bank, false)
+    }
+
+    fn with_banks(
+        config: &Config,
+        bpm: u32,
+        sound_bank: SoundBank,
+        loop_bank: LoopBank,
+        audio_output: bool,
+    ) -> error::Result<Self> {
+        let (stream, stream_handle) = if audio_output {
+            let (stream, stream_handle) = audio::open_output_stream(config.audio_device.as_deref())
+                .map_err(|e| error::Error::Audio { reason: e.to_string() })?;
+            (Some(stream), stream_handle)
+        } else {
+            (None, OutputStreamHandle::silent())
+        };
+        let midi_conn = MidiOut::open(&config.midi_port)?;
+        let transport = Transport::new(bpm, config.song.clone(), config.scenes.clone());
+        transport.set_transpose(config.transpose);
+        transport.set_live_quantize(config.live_quantize);
+        Ok(Engine {
+            sound_bank: Arc::new(sound_bank),
+            loop_bank: Arc::new(loop_bank),
+            patterns: Arc::new(ArcSwap::from_pointee(Vec::new())),
+            current_beat: Arc::new(RwLock::new(0.0)),
+            mixer: Arc::new(RwLock::new(Mixer::new())),
+            transport: Arc::new(transport),
+            midi_conn: Arc::new(Mutex::new(midi_conn)),
+            stream_handle: Arc::new(stream_handle),
+            loop_beats: config.loop_beats,
+            shutdown: config.shutdown.clone(),
+            threads: config.threads.clone(),
+            events: Arc::new(Events::default()),
+            running: Arc::new(AtomicBool::new(true)),
+            playback: None,
+            _stream: stream,
+        })
+    }
+
+    /// Loads the samples and loops in the given PATH-style directory lists,
+    /// to replace the bank contents from the next pass. When either fails
+    /// to load, nothing is replaced.
+    pub fn load_banks(&self, sample_dirs: &str, loop_dirs: &str) -> error::Result<()> {
+        let sound_bank = self.sound_bank.load_next(sample_dirs)?;
+        let loop_bank = LoopBank::new(loop_dirs, &self.loop_bank.threads, self.loop_bank.duplicates)?;
+        self.sound_bank.stage_bank(sound_bank);
+        self.loop_bank.stage_bank(loop_bank);
+        Ok(())
+    }
+
+    /// Replaces the patterns from the next loop pass on.
+    pub fn set_patterns(&self, patterns: Vec<Pattern>) {
+        self.patterns.store(Arc::new(patterns));
+    }
+
+    /// Receives the playback events from now on.
+    pub fn subscribe(&self) -> Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+
+    /// Flag that stays set until the engine is stopped; clearing it, e.g.
+    /// from a Ctrl+C handler, ends playback as set in `shutdown`.
+    pub fn running(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.running)
+    }
+
+    /// Whether playback was started and has not been waited for.
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Starts looping the patterns in the background.
+    pub fn play(&mut self) {
+        self.transport.start();
+        self.play_on_start();
+    }
+
+    /// Starts playback in the background once the transport is started,
+    /// e.g. by a GUI after its first frame, a Play action or a countdown.
+    pub fn play_on_start(&mut self) {
+        if self.playback.is_some() {
+            return;
+        }
+        self.running.store(true, Ordering::SeqCst);
+        let running = Arc::clone(&self.running);
+        let patterns = Arc::clone(&self.patterns);
+        let current_beat = Arc::clone(&self.current_beat);
+        let rack = self.rack();
+        let mixer = Arc::clone(&self.mixer);
+        let transport = Arc::clone(&self.transport);
+        let events = Arc::clone(&self.events);
+        let loop_beats = self.loop_beats;
+        let shutdown = self.shutdown.clone();
+        let thread_config = self.threads.clone();
+        self.playback = Some(thread::spawn(move || {
+            if thread_config.realtime {
+                threads::raise_priority();
+            }
+            transport.wait_for_start(&running);
+            audio::set_master_gain(1.0);
+            let dispatcher =
+                Dispatcher::new(Arc::clone(&events), thread_config.audio_workers(), thread_config.midi_workers());
+            // One epoch for the whole run, so passes don't drift apart
+            let mut clock = Clock::new(transport.bpm(), Instant::now());
+            let scheduler = Scheduler {
+                current_beat: &current_beat,
+                rack: &rack,
+                dispatcher: &dispatcher,
+                mixer: &mixer,
+                transport: &transport,
+                events: &events,
+                loop_beats,
+                running: &running,
+                finish_bar: shutdown.finish_bar,
+            };
+            while running.load(Ordering::SeqCst) {
+                // Take a snapshot so edits apply from the next pass
+                let current_patterns = patterns.load_full();
+                log!("Starting playback");
+                play_pattern_with_soundbank(current_patterns, &scheduler, &mut clock);
+            }
+            finish_playback(&rack, dispatcher, &events, &shutdown);
+        }));
+    }
+
+    /// Re-performs a recorded history in the background, each trigger at
+    /// its logged time on the instrument of the pattern for its track.
+    pub fn replay(&mut self, history: Vec<HistoryEntry>) {
+        if self.playback.is_some() {
+            return;
+        }
+        self.running.store(true, Ordering::SeqCst);
+        let running = Arc::clone(&self.running);
+        let patterns = self.patterns.load_full();
+        let rack = self.rack();
+        let mixer = Arc::clone(&self.mixer);
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let thread_config = self.threads.clone();
+        self.playback = Some(thread::spawn(move || {
+            if thread_config.realtime {
+                threads::raise_priority();
+            }
+            audio::set_master_gain(1.0);
+            let dispatcher =
+                Dispatcher::new(Arc::clone(&events), thread_config.audio_workers(), thread_config.midi_workers());
+            let mut instruments: HashMap<String, Option<Arc<dyn Instrument>>> = HashMap::new();
+            log!("Replaying {} triggers", history.len());
+            let start = Instant::now();
+            for entry in history {
+                // Sleep in slices so a stop doesn't wait for a long pause
+                let due = start + Duration::from_secs_f64(entry.time);
+                while running.load(Ordering::SeqCst) && Instant::now() < due {
+                    thread::sleep((due - Instant::now()).min(Duration::from_millis(50)));
+                }
+                if !running.load(Ordering::SeqCst) {
+                    break;
+                }
+                let instrument = instruments.entry(entry.track.clone()).or_insert_with(|| {
+                    let instrument = patterns.iter().find(|p| p.track_name() == entry.track).and_then(|p| rack.instrument(p));
+                    if instrument.is_none() {
+                        log_warn!("No pattern plays '{}', skipping its triggers", entry.track);
+                    }
+                    instrument
+                });
+                let Some(instrument) = instrument else {
+                    continue;
+                };
+                let meter = mixer.write().unwrap().meter(&entry.track);
+                dispatcher.dispatch(TriggerJob {
+                    instrument: Arc::clone(instrument),
+                    track: entry.track,
+                    note: entry.note,
+                    velocity: entry.velocity,
+                    duration: entry.duration,
+                    playback: Playback { pan: entry.pan, meter, bpm: entry.bpm },
+                    offset_secs: 0.0,
+                    ratchet: 1,
+                    interval_secs: 0.0,
+                });
+            }
+            dispatcher.drain();
+            finish_playback(&rack, dispatcher, &events, &shutdown);
+        }));
+    }
+
+    fn rack(&self) -> Arc<Rack> {
+        Arc::new(Rack {
+            sound_bank: Arc::clone(&self.sound_bank),
+            loop_bank: Arc::clone(&self.loop_bank),
+            stream_handle: Arc::clone(&self.stream_handle),
+            midi_conn: Arc::clone(&self.midi_conn),
+            midi_outputs: Mutex::new(HashMap::new()),
+            loop_fader: ArcSwap::from_pointee(Fader::new(1.0)),
+        })
+    }
+
+    /// Stops playback as set in `shutdown` and waits for it to end.
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.wait();
+    }
+
+    /// Waits for playback to end, once the `running` flag was cleared.
+    pub fn wait(&mut self) {
+        if let Some(handle) = self.playback.take() {
+            match handle.join() {
+                Ok(_) => log!("Playback finished"),
+                Err(e) => log!("Playback encountered an error: {:?}", e),
+            }
+        }
+    }
+}
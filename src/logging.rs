@@ -0,0 +1,127 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// When set, console output is suppressed (e.g. while the terminal UI owns the screen).
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Undelivered notifications are capped so a headless run doesn't grow them forever.
+const MAX_PENDING: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// Warnings and errors waiting to be shown by the GUI.
+static NOTIFICATIONS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+pub fn notify(level: Level, message: String) {
+    let mut pending = NOTIFICATIONS.lock().unwrap();
+    if pending.len() >= MAX_PENDING {
+        pending.remove(0);
+    }
+    pending.push((level, message));
+}
+
+/// Takes the notifications raised since the last call.
+pub fn take_notifications() -> Vec<(Level, String)> {
+    std::mem::take(&mut *NOTIFICATIONS.lock().unwrap())
+}
+
+/// How far the running bank load got, for the GUI status bar.
+static PROGRESS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Loads with fewer files finish without reporting each step.
+const REPORTED_STEPS_FROM: usize = 10;
+
+pub fn progress() -> Option<String> {
+    PROGRESS.lock().unwrap().clone()
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::SeqCst)
+}
+
+/// `println!` that respects quiet mode.
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if !$crate::logging::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `println!` that respects quiet mode and raises a GUI notification.
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if !$crate::logging::is_quiet() {
+            println!("Warning: {}", message);
+        }
+        $crate::logging::notify($crate::logging::Level::Warning, message);
+    }};
+}
+
+/// `eprintln!` that respects quiet mode and raises a GUI notification.
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        if !$crate::logging::is_quiet() {
+            eprintln!("{}", message);
+        }
+        $crate::logging::notify($crate::logging::Level::Error, message);
+    }};
+}
+
+/// Counts the files of a bank load, printing every tenth of it with the
+/// time taken so far and showing the count in the GUI until dropped.
+pub struct Progress {
+    what: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    started: Instant,
+}
+
+impl Progress {
+    pub fn new(what: &'static str, total: usize) -> Self {
+        Progress { what, total, done: AtomicUsize::new(0), started: Instant::now() }
+    }
+
+    /// Counts one more file done; safe to call from the loader threads.
+    pub fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.total < REPORTED_STEPS_FROM {
+            return;
+        }
+        let status = format!("Loading {} {}/{}", self.what, done, self.total);
+        if done * 10 / self.total != (done - 1) * 10 / self.total {
+            log!("{} ({:.1}s)", status, self.started.elapsed().as_secs_f32());
+        }
+        *PROGRESS.lock().unwrap() = Some(status);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.total >= REPORTED_STEPS_FROM {
+            *PROGRESS.lock().unwrap() = None;
+        }
+        if self.total > 1 {
+            let done = self.done.load(Ordering::SeqCst);
+            log!("Loaded {} {} in {:.1}s", done, self.what, self.started.elapsed().as_secs_f32());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Error};
+
+/// Sidecar file that, when present in a loops directory, describes its loops.
+pub const LOOPS_FILE: &str = "loops.json";
+
+/// Key loops are pitch-shifted to as they load; None leaves them as recorded.
+static PROJECT_KEY: RwLock<Option<String>> = RwLock::new(None);
+
+/// Pitch-shifts the loops of the bank loads that follow from their own key
+/// to `key`, or stops with None.
+pub fn configure_key(key: Option<String>) {
+    *PROJECT_KEY.write().unwrap() = key;
+}
+
+pub fn project_key() -> Option<String> {
+    PROJECT_KEY.read().unwrap().clone()
+}
+
+/// How a loop is fitted to the project tempo.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stretch {
+    /// Sped up or slowed down like tape, so the pitch follows the tempo.
+    #[default]
+    Repitch,
+    /// Time-stretched to the tempo, keeping its pitch.
+    Stretch,
+    /// Played at its own tempo, for risers, vocals and other one-shots.
+    Off,
+}
+
+/// What is known about a loop file, from the sidecar or its name.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct LoopMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bpm: Option<f32>,
+    /// Length of the loop in beats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beats: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stretch: Option<Stretch>,
+    /// Set on entries estimated by tempo detection; edit the values to override them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub detected: bool,
+}
+
+impl LoopMeta {
+    /// Fills the fields this one lacks from `other`.
+    pub fn or(self, other: LoopMeta) -> LoopMeta {
+        LoopMeta {
+            bpm: self.bpm.or(other.bpm),
+            beats: self.beats.or(other.beats),
+            key: self.key.or(other.key),
+            label: self.label.or(other.label),
+            stretch: self.stretch.or(other.stretch),
+            detected: self.detected,
+        }
+    }
+}
+
+/// File name -> metadata, as stored in `loops.json`.
+pub type LoopsManifest = BTreeMap<String, LoopMeta>;
+
+/// Reads the loops sidecar of a directory, empty when there is none.
+pub fn read_loops(dir: &Path) -> error::Result<LoopsManifest> {
+    let path = dir.join(LOOPS_FILE);
+    if !path.exists() {
+        return Ok(LoopsManifest::new());
+    }
+    let json = fs::read_to_string(&path).map_err(|source| Error::io(&path, source))?;
+    let manifest = serde_json::from_str(&json).map_err(|e| Error::Manifest { path: path.display().to_string(), reason: e.to_string() })?;
+    Ok(manifest)
+}
+
+/// Adds detected entries to a directory's sidecar, keeping the ones already there.
+pub fn cache_detected(dir: &Path, detected: Vec<(String, LoopMeta)>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut manifest = read_loops(dir)?;
+    for (name, meta) in detected {
+        manifest.entry(name).or_insert(meta);
+    }
+    fs::write(dir.join(LOOPS_FILE), serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
+
+/// Sidecar entry for a loop file, looked up in its own directory.
+pub fn sidecar_meta(path: &Path) -> error::Result<Option<LoopMeta>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Ok(None);
+    };
+    Ok(read_loops(dir)?.remove(name))
+}
+
+/// Metadata from the `bpm_beats_name.wav` naming convention, optionally
+/// followed by the key, as in `120_8_bass_Am.wav`.
+pub fn parse_filename(path: &Path) -> Option<LoopMeta> {
+    let stem = path.file_stem()?.to_str()?;
+    let parts: Vec<&str> = stem.split('_').collect();
+    let key = match parts.len() {
+        3 => None,
+        4 => Some(parts[3].to_string()).filter(|key| parse_key(key).is_ok()),
+        _ => return None,
+    };
+    Some(LoopMeta {
+        bpm: Some(parts[0].parse::<u32>().ok()? as f32),
+        beats: parts[1].parse().ok(),
+        key,
+        label: Some(parts[2].to_string()),
+        stretch: None,
+        detected: false,
+    })
+}
+
+/// Tonic pitch class (C = 0) and whether the key is minor, from names like
+/// "C", "F#m", "Bb minor" or "E maj".
+pub fn parse_key(key: &str) -> Result<(u8, bool), String> {
+    let key = key.trim();
+    let mut chars = key.chars();
+    let natural = match chars.next().map(|letter| letter.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(format!("'{}' does not start with a note", key)),
+    };
+    let rest = chars.as_str();
+    let (accidental, quality) = match rest.chars().next() {
+        Some('#') => (1, &rest[1..]),
+        Some('b') => (11, &rest[1..]),
+        _ => (0, rest),
+    };
+    let minor = match quality.trim() {
+        "" | "maj" | "major" => false,
+        "m" | "min" | "minor" => true,
+        other => return Err(format!("Unknown key quality '{}' in '{}'", other, key)),
+    };
+    Ok(((natural + accidental) % 12, minor))
+}
+
+/// Semitones that take a loop in key `from` to key `to`, the shorter way
+/// round. A minor key matches its relative major, so "Am" to "C" is 0.
+pub fn key_shift(from: &str, to: &str) -> Result<i8, String> {
+    let relative_major = |key: &str| parse_key(key).map(|(tonic, minor)| if minor { (tonic + 3) % 12 } else { tonic });
+    let shift = (relative_major(to)? as i8 - relative_major(from)? as i8).rem_euclid(12);
+    Ok(if shift > 6 { shift - 12 } else { shift })
+}
+
+/// Frames per grain of `time_stretch`.
+const GRAIN: usize = 2048;
+
+/// Lengthens interleaved audio by `factor` without changing its pitch, by
+/// overlap-adding Hann-windowed grains read at 1/`factor` of the speed
+/// they are written at.
+pub fn time_stretch(samples: &[i16], channels: u16, factor: f32) -> Vec<i16> {
+    let channels = channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let out_frames = (frames as f32 * factor).round() as usize;
+    let hop = GRAIN / 2;
+    let window: Vec<f32> = (0..GRAIN).map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / GRAIN as f32).cos()).collect();
+    let mut out = vec![0.0f32; out_frames * channels];
+    let mut weights = vec![0.0f32; out_frames];
+    for grain in 0..=out_frames / hop {
+        let (from, to) = (((grain * hop) as f32 / factor) as usize, grain * hop);
+        for (i, weight) in window.iter().enumerate() {
+            let (source, target) = (from + i, to + i);
+            if source >= frames || target >= out_frames {
+                break;
+            }
+            for channel in 0..channels {
+                out[target * channels + channel] += samples[source * channels + channel] as f32 * weight;
+            }
+            weights[target] += weight;
+        }
+    }
+    out.iter()
+        .enumerate()
+        .map(|(index, sample)| (sample / weights[index / channels].max(1e-3)).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+        .collect()
+}
+
+/// A decoded loop with the tempo it was recorded at.
+pub struct LoopSample {
+    pub samples: Vec<i16>,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bpm: f32,
+    pub beats: Option<u32>,
+    pub key: Option<String>,
+    /// Speed factor that pitch-shifts the loop to the project key; the
+    /// samples are stretched by it so the tempo is kept.
+    pub pitch: f32,
+    pub stretch: Stretch,
+    /// Time-stretched samples for the last tempo played at with `Stretch::Stretch`.
+    pub stretched: Mutex<Option<(u32, Arc<Vec<i16>>)>>,
+}
+
+impl LoopSample {
+    pub fn seconds(&self) -> f32 {
+        self.samples.len() as f32 / self.channels.max(1) as f32 / self.sample_rate as f32
+    }
+
+    /// Playback speed at `project_bpm`. With a beat count the loop is
+    /// stretched to span exactly that many beats, otherwise it is sped up
+    /// by the tempo ratio; either way on top of the key's pitch shift.
+    pub fn speed(&self, project_bpm: u32) -> f32 {
+        match self.beats.filter(|beats| *beats > 0) {
+            Some(beats) => self.seconds() * project_bpm as f32 / (beats as f32 * 60.0),
+            None => project_bpm as f32 / self.bpm * self.pitch,
+        }
+    }
+
+    /// Calls `play` with the samples and speed that play the loop at
+    /// `project_bpm` as its stretch policy says.
+    pub fn with_playback<R>(&self, project_bpm: u32, play: impl FnOnce(&[i16], f32) -> R) -> R {
+        match self.stretch {
+            Stretch::Repitch => play(&self.samples, self.speed(project_bpm)),
+            Stretch::Off => play(&self.samples, self.pitch),
+            Stretch::Stretch => {
+                let samples = {
+                    let mut stretched = self.stretched.lock().unwrap();
+                    match &*stretched {
+                        Some((bpm, samples)) if *bpm == project_bpm => Arc::clone(samples),
+                        _ => {
+                            let factor = self.pitch / self.speed(project_bpm);
+                            let samples = Arc::new(time_stretch(&self.samples, self.channels, factor));
+                            *stretched = Some((project_bpm, Arc::clone(&samples)));
+                            samples
+                        }
+                    }
+                };
+                play(&samples, self.pitch)
+            }
+        }
+    }
+}
+
+/// Samples per analysis frame of the onset envelope.
+const HOP: usize = 512;
+/// Tempo range searched by detection.
+const MIN_BPM: f32 = 70.0;
+const MAX_BPM: f32 = 180.0;
+
+/// Estimates the bpm and length in beats of a loop. Onsets are taken from
+/// rises in frame energy, the beat period from their autocorrelation, and
+/// the bpm is then fitted to a whole number of beats over the loop, which
+/// is how loops are cut.
+pub fn detect_tempo(samples: &[i16], channels: u16, sample_rate: u32) -> Option<(f32, u32)> {
+    let channels = channels.max(1) as usize;
+    let energies: Vec<f32> = samples
+        .chunks(HOP * channels)
+        .map(|frame| frame.iter().map(|s| (*s as f32 / 32768.0).powi(2)).sum())
+        .collect();
+    let onsets: Vec<f32> = energies.windows(2).map(|pair| (pair[1] - pair[0]).max(0.0)).collect();
+
+    let hop_secs = HOP as f32 / sample_rate as f32;
+    let shortest = (60.0 / MAX_BPM / hop_secs).floor() as usize;
+    let longest = (60.0 / MIN_BPM / hop_secs).ceil() as usize;
+    if shortest == 0 || onsets.len() < longest * 2 {
+        return None;
+    }
+    let correlation = |lag: usize| {
+        let sum: f32 = onsets.iter().zip(&onsets[lag..]).map(|(a, b)| a * b).sum();
+        sum / (onsets.len() - lag) as f32
+    };
+    let period = (shortest..=longest).max_by(|a, b| correlation(*a).total_cmp(&correlation(*b)))?;
+    if correlation(period) <= 0.0 {
+        return None;
+    }
+
+    let seconds = samples.len() as f32 / channels as f32 / sample_rate as f32;
+    let beats = (seconds / (period as f32 * hop_secs)).round().max(1.0) as u32;
+    Some((beats as f32 * 60.0 / seconds, beats))
+}
@@ -1,573 +1,744 @@
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
 use std::{
+    collections::BTreeMap,
     fs,
-    sync::{Arc, RwLock, atomic::{AtomicBool, Ordering}},
+    io::{IsTerminal, Write},
+    path::Path,
+    sync::{Arc, RwLock, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver}},
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
-use std::env;
-use midir::{MidiOutput, MidiOutputConnection};
+use clap::Parser;
 
-use ctrlc;
-mod midi;
-mod model;
-mod config;
+#[macro_use]
+extern crate four_on_the_floor;
+#[cfg(feature = "gui")]
 mod grid;
-
-use model::{Pattern, PatternBuilder};
-use grid::PatternVisualizerApp;
+#[cfg(feature = "gui")]
+mod browser;
+#[cfg(feature = "gui")]
+mod selection;
+mod tui;
+mod repl;
+#[cfg(feature = "gui")]
+mod keyboard;
+#[cfg(feature = "gui")]
+mod recorder;
+mod settings;
+#[cfg(feature = "gui")]
+mod toasts;
+mod cli;
+mod init;
+mod setlist;
+
+use four_on_the_floor::{
+    audio, config, daemon, edit_patterns, error, formats, history, hydrogen, load_and_combine_patterns,
+    load_and_combine_patterns_from_content, logging, loops, midi, midi_io, mixer, model, osc, remote, render, sample_cache, session,
+    stage_banks, transport, validation, watch_banks, Engine, EngineEvent, LoopBank, SoundBank,
+};
+#[cfg(feature = "gui")]
+use four_on_the_floor::{
+    chord, diagnostics, is_loop_filename, meter, notation, play_file, play_loop, play_sound, presets, scene, song, step_grid, LoopPlay,
+};
+#[cfg(feature = "gui")]
+use grid::{GridSetup, PatternVisualizerApp, INITIAL_WINDOW_SIZE};
+#[cfg(feature = "gui")]
+use browser::SampleBrowser;
+use transport::Transport;
+use session::{Session, Snapshot};
+use mixer::Mixer;
+use tui::TerminalUi;
+use repl::Repl;
+use setlist::Setlist;
+#[cfg(feature = "gui")]
+use keyboard::PianoKeyboard;
+#[cfg(feature = "gui")]
+use recorder::Recorder;
+#[cfg(feature = "gui")]
+use settings::SettingsDialog;
+use settings::Subsystems;
+use osc::{OscControl, OscSender};
+use remote::RemoteControl;
+use cli::{Cli, Command, ImportKitArgs, Paths, PlayArgs, RenderArgs, ReplayArgs};
 
 
 /// -------------------------------------------------------------------------
-/// 1) SoundBank
+/// 3) Main
 /// -------------------------------------------------------------------------
-struct SoundBank {
-    data: HashMap<String, (Vec<i16>, u16, u32)>,
-}
-
-fn load_sample(path: &str) -> Result<(Vec<i16>, u16, u32), Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let decoder = Decoder::new(BufReader::new(file))?;
-    // We need the Source trait in scope for channels() & sample_rate().
-    let channels = decoder.channels();
-    let sample_rate = decoder.sample_rate();
-    let samples: Vec<i16> = decoder.convert_samples().collect();
-    Ok((samples, channels, sample_rate))
-}
-
-impl SoundBank {
-    fn new(directory: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut data = HashMap::new();
-
-        // Read all files in the given directory using a thread pool
-        let paths = fs::read_dir(directory)?;
-        let pool = ThreadPool::new(4);
-        let results = Arc::new(std::sync::Mutex::new(Vec::new()));
-
-        for path in paths {
-            let path = path?.path();
-            if let Some(extension) = path.extension() {
-                if extension == "wav" {
-                    let path_str = path.to_str().ok_or("Invalid file path")?.to_string();
-                    let results_clone = Arc::clone(&results);
-
-                    pool.execute(move || {
-                        println!("Loading {}", path_str);
-                        match load_sample(&path_str) {
-                            Ok((samples, channels, rate)) => {
-                                let label = std::path::Path::new(&path_str)
-                                    .file_stem()
-                                    .and_then(|s| s.to_str())
-                                    .unwrap_or_default()
-                                    .to_string();
-                                results_clone.lock().unwrap().push((label, (samples, channels, rate)));
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to load sample '{}': {}", path_str, e);
-                            }
-                        }
-                    });
-                }
-            }
-        }
-
-        // Wait for all threads to finish
-        pool.join();
-
-        // Collect results into the data map
-        for (label, data_entry) in results.lock().unwrap().drain(..) {
-            data.insert(label, data_entry);
-        }
-
-        Ok(SoundBank { data })
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let paths = cli.paths.resolve_project();
+    match cli.command {
+        Command::Play(args) => play(args, paths),
+        Command::Render(args) => render(args, &paths),
+        Command::Replay(args) => replay(args, &paths),
+        Command::Ports => list_ports(),
+        Command::Init(args) => init::init(args),
+        Command::ImportKit(args) => import_kit(args),
+        Command::Validate => validate(&paths),
+        Command::ListSamples => list_samples(&paths.config),
     }
+}
 
-    fn get(&self, label: &str) -> Option<&(Vec<i16>, u16, u32)> {
-        self.data.get(label)
+fn play(mut args: PlayArgs, mut paths: Paths) -> Result<(), Box<dyn std::error::Error>> {
+    // A setlist starts with its first song
+    let default_config = paths.config.clone();
+    let setlist_songs = args.setlist.as_deref().map(setlist::read).transpose()?;
+    if let Some((songs, dir)) = &setlist_songs {
+        (paths.config, paths.patterns) = songs[0].paths(dir, &default_config)?;
+        args.bpm = songs[0].bpm.unwrap_or(args.bpm);
+        log!("Setlist of {} songs, starting with '{}'", songs.len(), songs[0].title());
     }
-}
 
+    // Read config
+    let config = config::read_config(&paths.config)?;
 
-struct LoopBank {
-    data: HashMap<String, (Vec<i16>, u16, u32, u32)>, // (samples, channels, sample_rate, beats)
-}
+    // Open audio and MIDI output and load the banks, which headless rigs skip
+    let headless = args.headless || cfg!(feature = "headless");
+    let mut engine = if headless { Engine::midi_only(&config, args.bpm)? } else { Engine::new(&config, args.bpm)? };
+    let sound_bank = Arc::clone(&engine.sound_bank);
+    let loop_bank = Arc::clone(&engine.loop_bank);
+    let stream_handle = Arc::clone(&engine.stream_handle);
+    let midi_conn = Arc::clone(&engine.midi_conn);
 
-fn load_loop(path: &str) -> Result<(Vec<i16>, u16, u32, u32, String), Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let decoder = Decoder::new(BufReader::new(file))?;
-    let channels = decoder.channels();
-    let sample_rate = decoder.sample_rate();
-    let samples: Vec<i16> = decoder.convert_samples().collect();
-
-    // Extract bpm and beats from filename
-    let filename = std::path::Path::new(path)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .ok_or("Invalid filename")?;
-
-    let parts: Vec<&str> = filename.split('_').collect();
-    if parts.len() != 3 {
-        return Err("Invalid loop filename format. Expected: bpm_beats_name.wav".into());
-    }
+    let bpm = args.bpm;
+    let show_tui = args.tui;
+    let show_gui = cfg!(feature = "gui") && !show_tui && !args.no_gui && !args.daemon && !args.repl && !headless;
 
-    let bpm: u32 = parts[0].parse()?;
-    let name: &str = parts[2];
+    let loop_beats = config.loop_beats;
+    let midi_pattern = read_midi_pattern(&config, bpm);
+    log!("Midi pattern {:?}", midi_pattern);
+    // Shared so the settings dialog can re-import the MIDI track
+    let midi_pattern = Arc::new(RwLock::new(midi_pattern));
+    
+    // Playback runs while `running` is set, the background threads while `alive` is
+    let running = engine.running();
+    let alive = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    let a = Arc::clone(&alive);
 
-    Ok((samples, channels, sample_rate, bpm, name.to_string()))
-}
+    // Set up Ctrl+C handler
+    ctrlc::set_handler(move || {
+        log!("Ctrl+C detected. Stopping loop...");
+        r.store(false, Ordering::SeqCst);
+        a.store(false, Ordering::SeqCst);
+    })?;
+    log!("Press Ctrl+C to stop the loop.");
+
+    // Unattended runs stop by themselves, at the end of a bar
+    engine.transport.set_bar_limit(args.bars);
+    if let Some(duration) = args.duration {
+        let r = running.clone();
+        thread::spawn(move || {
+            thread::sleep(duration);
+            log!("Run time of {:?} reached. Stopping loop...", duration);
+            r.store(false, Ordering::SeqCst);
+        });
+    }
 
+    // Shared state for the patterns
+    let patterns = Arc::clone(&engine.patterns);
+    // Rows and step edits made in the GUI, kept across reloads of the patterns file
+    let session = Arc::new(RwLock::new(Session::new()));
+
+    let mixer = Arc::clone(&engine.mixer); // Per-track gain staging
+    let transport = Arc::clone(&engine.transport); // Tempo and performance controls
+    let user_presets = Arc::new(RwLock::new(BTreeMap::new())); // Presets of the patterns file, for the GUI
+
+    let initial_tracks = {
+        let mut initial_patterns = load_and_combine_patterns(&paths.patterns, &midi_pattern.read().unwrap(), loop_beats);
+        if headless {
+            initial_patterns.retain(|p| p.midi_note.is_some());
+        }
+        let content = fs::read_to_string(&paths.patterns).unwrap_or_default();
+        for problem in validation::validate_patterns(&paths.patterns, &content, &initial_patterns, &sound_bank, &loop_bank, loop_beats) {
+            log_warn!("{}", problem);
+        }
+        let tracks = formats::parse_tracks(&paths.patterns, &content).unwrap_or_default();
+        mixer.write().unwrap().apply_tracks(&tracks);
+        *user_presets.write().unwrap() = formats::parse_presets(&paths.patterns, &content).unwrap_or_default();
+
+        // Offer the state saved by the last run, which may have crashed mid-set
+        let snapshot_path = session::snapshot_path(&paths.patterns);
+        match Snapshot::read(&snapshot_path) {
+            Ok(Some(snapshot))
+                if args.restore_session
+                    || confirm(&format!("Restore the session saved in {}?", snapshot_path.display())) =>
+            {
+                snapshot.restore(&transport, &mut mixer.write().unwrap(), &mut session.write().unwrap());
+                log!("Session restored from {}", snapshot_path.display());
+            }
+            Ok(_) => {}
+            Err(e) => log_error!("Could not read the saved session: {}", e),
+        }
+        session.read().unwrap().apply(&mut initial_patterns);
+        engine.set_patterns(initial_patterns);
+        tracks
+    };
 
-impl LoopBank {
-    fn new(directory: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut data = HashMap::new();
-
-        // Read all files in the given directory using a thread pool
-        let paths = fs::read_dir(directory)?;
-        let pool = ThreadPool::new(16);
-        let results = Arc::new(std::sync::Mutex::new(Vec::new()));
-
-        for path in paths {
-            let path = path?.path();
-            if let Some(extension) = path.extension() {
-                if extension == "wav" {
-                    let path_str = path.to_str().ok_or("Invalid file path")?.to_string();
-                    let results_clone = Arc::clone(&results);
-
-                    pool.execute(move || {
-                        println!("Loading {}", path_str);
-                        match load_loop(&path_str) {
-                            Ok((samples, channels, rate, total_beats, name)) => {
-                                results_clone.lock().unwrap().push((name, (samples, channels, rate, total_beats)));
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to load loop '{}': {}", path_str, e);
-                            }
+    // Start a background thread to watch for changes
+    let patterns_clone = Arc::clone(&patterns);
+    let running_clone = Arc::clone(&alive);
+    let session_clone = Arc::clone(&session);
+    let patterns_path = Arc::new(RwLock::new(paths.patterns.clone())); // Switchable over OSC
+    let watcher_patterns_path = Arc::clone(&patterns_path);
+    let midi_pattern_clone = Arc::clone(&midi_pattern); // Share MIDI patterns with the thread
+    let watcher_sound_bank = Arc::clone(&sound_bank);
+    let watcher_loop_bank = Arc::clone(&loop_bank);
+    let watcher_mixer = Arc::clone(&mixer);
+    let watcher_presets = Arc::clone(&user_presets);
+    thread::spawn(move || {
+        let mut reported = Vec::new(); // Only report problems again once they change
+        let mut applied_tracks = initial_tracks; // Track settings are applied once, so live mixer moves stick
+        loop {
+            if running_clone.load(Ordering::SeqCst) {
+                let patterns_path = watcher_patterns_path.read().unwrap().clone();
+                if let Ok(file_content) = fs::read_to_string(&patterns_path) {
+                    let mut combined_patterns = load_and_combine_patterns_from_content(
+                        &patterns_path,
+                        &file_content,
+                        &midi_pattern_clone.read().unwrap(),
+                        loop_beats,
+                    );
+                    if headless {
+                        combined_patterns.retain(|p| p.midi_note.is_some());
+                    }
+                    let problems = validation::validate_patterns(
+                        &patterns_path,
+                        &file_content,
+                        &combined_patterns,
+                        &watcher_sound_bank,
+                        &watcher_loop_bank,
+                        loop_beats,
+                    );
+                    let tracks = formats::parse_tracks(&patterns_path, &file_content).unwrap_or_default();
+                    if tracks != applied_tracks {
+                        watcher_mixer.write().unwrap().apply_tracks(&tracks);
+                        applied_tracks = tracks;
+                    }
+                    if let Ok(presets) = formats::parse_presets(&patterns_path, &file_content) {
+                        *watcher_presets.write().unwrap() = presets;
+                    }
+                    if problems != reported {
+                        for problem in problems.iter() {
+                            log_warn!("{}", problem);
                         }
-                    });
+                        reported = problems;
+                    }
+                    session_clone.read().unwrap().apply(&mut combined_patterns);
+                    // Only swap in a new snapshot when a pattern changed, matched by name
+                    let changes = model::diff_patterns(&patterns_clone.load(), &combined_patterns);
+                    if !changes.is_empty() {
+                        patterns_clone.store(Arc::new(combined_patterns));
+                        log!("Patterns updated: {}", changes);
+                    }
+                } else {
+                    log_error!("Failed to read {}", patterns_path);
                 }
+            } else {
+                break;
             }
+            thread::sleep(Duration::from_secs(3));
         }
+    });
 
-        // Wait for all threads to finish
-        pool.join();
-
-        // Collect results into the data map
-        for (label, data_entry) in results.lock().unwrap().drain(..) {
-            data.insert(label, data_entry);
-        }
-
-        Ok(LoopBank { data })
+    // Sounds dropped into the bank directories become playable without a restart
+    if !headless {
+        watch_banks(Arc::clone(&sound_bank), Arc::clone(&loop_bank), Arc::clone(&alive));
     }
 
-    fn get(&self, label: &str) -> Option<&(Vec<i16>, u16, u32, u32)> {
-        self.data.get(label)
+    // Keep a snapshot of the live state for the next launch
+    let snapshot_path = session::snapshot_path(&paths.patterns);
+    {
+        let running = Arc::clone(&alive);
+        let transport = Arc::clone(&transport);
+        let mixer = Arc::clone(&mixer);
+        let session = Arc::clone(&session);
+        let snapshot_path = snapshot_path.clone();
+        thread::spawn(move || {
+            let mut written = String::new();
+            while running.load(Ordering::SeqCst) {
+                thread::sleep(SNAPSHOT_INTERVAL);
+                save_snapshot(&snapshot_path, &transport, &mixer, &session, &mut written);
+            }
+        });
     }
-}
 
-fn beats_to_millis(beats: f32, bpm: u32) -> u64 {
-    let minutes = beats / bpm as f32;
-    let millis = minutes * 60.0 * 1000.0;
-    millis.round() as u64
-}
-
-fn play_loop(
-    label: &str,
-    duration: f32,
-    velocity: f32,
-    loop_bank: &LoopBank,
-    stream_handle: &OutputStreamHandle,
-    project_bpm: u32,
-) {
-    if let Some((samples, channels, sample_rate, loop_bpm_beats)) = loop_bank.get(label) {
-        let original_bpm = *loop_bpm_beats;
-        let playback_speed = project_bpm as f32 / original_bpm as f32;
-        let duration_millis = beats_to_millis(duration, project_bpm);
-
-        let source = rodio::buffer::SamplesBuffer::new(*channels, *sample_rate, samples.to_vec())
-            .buffered()
-            .amplify(velocity / 100.0)
-            // .reverb(Duration::from_millis(delay as u64), 0.8) // Add delay for reverb effect
-            .take_duration(Duration::from_millis(duration_millis))
-            .speed(playback_speed); // Adjust speed for BPM
-        let sink = Sink::try_new(stream_handle).unwrap();
-        sink.append(source);
-        sink.detach();
-        println!(
-            "[Loop] Playing '{}' at project BPM {} for original {} with speed adjustment {:.2}",
-            label, project_bpm, original_bpm, playback_speed
-        );
-    } else {
-        println!("Warning: No loop label '{}' found in LoopBank", label);
+    let gui_transport = Arc::clone(&transport);
+    let gui_mixer = Arc::clone(&mixer);
+    let current_beat = Arc::clone(&engine.current_beat); // Shared state for the current beat
+    let gui_current_beat = Arc::clone(&current_beat);
+    let gui_patterns = Arc::clone(&patterns);
+    if let Some(port) = config.osc_port {
+        osc::spawn_server(
+            &config.osc_bind,
+            port,
+            OscControl {
+                transport: Arc::clone(&transport),
+                mixer: Arc::clone(&mixer),
+                patterns: Arc::clone(&patterns),
+                sound_bank: Arc::clone(&sound_bank),
+                stream_handle: Arc::clone(&stream_handle),
+                patterns_path: Arc::clone(&patterns_path),
+                project_dir: config.base_dir.clone(),
+                loop_beats: config.loop_beats,
+            },
+        )?;
     }
-}
-
-
-
-
-/// Plays a MIDI note using the provided MIDI connection.
-fn play_midi_note(
-    note: u8,
-    velocity: f32,
-    duration: f32,
-    midi_conn: Arc<std::sync::Mutex<MidiOutputConnection>>,
-) {
-    let velocity = (velocity.max(0.0).min(127.0)) as u8;
-
-    // MIDI Note On message
-    if let Ok(mut conn) = midi_conn.lock() {
-        let _ = conn.send(&[0x90, note, velocity]);
-        println!("[MIDI] Note On: {}, velocity: {}, duration: {:.2}s", note, velocity, duration);
+    if let Some(port) = config.remote_port {
+        remote::spawn_server(
+            &config.remote_bind,
+            port,
+            RemoteControl {
+                patterns: Arc::clone(&patterns),
+                current_beat: Arc::clone(&current_beat),
+                transport: Arc::clone(&transport),
+                mixer: Arc::clone(&mixer),
+                session: Arc::clone(&session),
+                patterns_path: Arc::clone(&patterns_path),
+                project_dir: config.base_dir.clone(),
+                loop_beats: config.loop_beats,
+            },
+        )?;
+    }
+    if let Some(target) = &config.osc_out {
+        OscSender::new(target)?.forward(engine.subscribe());
     }
+    if let Some(path) = &args.history {
+        history::record(Path::new(path), engine.subscribe(), Arc::clone(&transport))?;
+    }
+    let subsystems = Subsystems {
+        config: Arc::new(RwLock::new(config.clone())),
+        midi_conn: Arc::clone(&midi_conn),
+        sound_bank: Arc::clone(&sound_bank),
+        loop_bank: Arc::clone(&loop_bank),
+        midi_pattern: Arc::clone(&midi_pattern),
+        patterns: Arc::clone(&patterns),
+        mixer: Arc::clone(&mixer),
+    };
+    settings::watch_config(paths.config.clone(), subsystems.clone(), Arc::clone(&transport), Arc::clone(&alive));
+    let setlist = setlist_songs.map(|(songs, dir)| {
+        Arc::new(Setlist::new(
+            songs,
+            dir,
+            &default_config,
+            loop_beats,
+            headless,
+            subsystems.clone(),
+            Arc::clone(&transport),
+            Arc::clone(&mixer),
+            Arc::clone(&session),
+            Arc::clone(&patterns_path),
+        ))
+    });
 
-    thread::sleep(Duration::from_secs_f32(duration));
+    let tui_running = Arc::clone(&running);
 
-    // MIDI Note Off message
-    if let Ok(mut conn) = midi_conn.lock() {
-        let _ = conn.send(&[0x80, note, 0]);
-        println!("[MIDI] Note Off: {}", note);
+    // Playback waits for a Play action or the countdown when asked to,
+    // otherwise for the window's first frame or not at all
+    let hold = args.wait || args.countdown.is_some();
+    if let Some(secs) = args.countdown {
+        spawn_countdown(Arc::clone(&transport), Arc::clone(&running), secs);
     }
-}
 
-fn play_sound(
-    label: &str,
-    velocity: f32,
-    sound_bank: &SoundBank,
-    stream_handle: &OutputStreamHandle,
-) {
-    if let Some((samples, channels, sample_rate)) = sound_bank.get(label) {
-        let sink = Sink::try_new(stream_handle).unwrap();
-        let source =
-            rodio::buffer::SamplesBuffer::new(*channels, *sample_rate, samples.clone())
-            .amplify(velocity / 100.0);
-        sink.append(source);
-        sink.detach();
-        println!("[Audio] Playing '{}' at velocity {:.1}", label, velocity);
+    if args.daemon {
+        let (requests, incoming) = mpsc::channel();
+        daemon::spawn_server(&args.socket, requests)?;
+        run_daemon(&mut engine, incoming, &patterns_path, setlist.as_deref(), &alive);
+    } else if show_gui {
+        engine.play_on_start();
+        #[cfg(feature = "gui")]
+        run_gui(&engine, Arc::clone(&session), user_presets, !hold, &paths.config, subsystems, config);
+    } else if args.repl {
+        let events = engine.subscribe();
+        if hold {
+            engine.play_on_start();
+        } else {
+            engine.play();
+        }
+        Repl::new(
+            Arc::clone(&patterns),
+            Arc::clone(&mixer),
+            Arc::clone(&transport),
+            Arc::clone(&session),
+            Arc::clone(&sound_bank),
+            Arc::clone(&loop_bank),
+            Arc::clone(&running),
+        )
+        .with_setlist(setlist)
+        .run(events)?;
+    } else if show_tui {
+        if hold {
+            engine.play_on_start();
+        } else {
+            engine.play();
+        }
+        logging::set_quiet(true);
+        let tui = TerminalUi::new(
+            Arc::clone(&gui_patterns),
+            Arc::clone(&gui_current_beat),
+            Arc::clone(&gui_mixer),
+            Arc::clone(&gui_transport),
+            tui_running,
+            loop_beats,
+        );
+        let result = tui.run();
+        logging::set_quiet(false);
+        result?;
+    } else if hold {
+        if args.wait {
+            log!("Press Enter to start playback.");
+            let transport = Arc::clone(&transport);
+            thread::spawn(move || {
+                let _ = std::io::stdin().read_line(&mut String::new());
+                transport.start();
+            });
+        }
+        engine.play_on_start();
     } else {
-        println!("Warning: No sound label '{}' found in SoundBank", label);
+        engine.play();
     }
-}
-
-use threadpool::ThreadPool;
-
-fn play_pattern_with_soundbank(
-    patterns: Arc<Vec<Pattern>>,
-    current_beat: Arc<RwLock<f32>>,
-    sound_bank: Arc<SoundBank>,
-    loop_bank: Arc<LoopBank>,
-    stream_handle: Arc<OutputStreamHandle>,
-    midi_conn: Arc<std::sync::Mutex<MidiOutputConnection>>,
-    bpm: u32,
-    loop_beats: u32,
-) {
-    let beat_duration = 60.0 / bpm as f32;
-    let eighth_beat_duration = beat_duration / 8.0;
-    let total_eighth_beats = loop_beats * 8;
-
-    let start_time = Instant::now();
-    let pool = ThreadPool::new(4); // Create a thread pool with 4 workers
-
-    for i in 0..total_eighth_beats {
-        let computed_current_beat = i as f32 / 8.0;
-        {
-            let mut beat_lock = current_beat.write().unwrap();
-            *beat_lock = computed_current_beat;
-        }
 
-        for pattern in patterns.iter() {
-            if pattern.beats.contains(&computed_current_beat) {
-                let sb_clone = Arc::clone(&sound_bank);
-                let sh_clone = Arc::clone(&stream_handle);
-                let midi_conn_clone = Arc::clone(&midi_conn);
-                let sound = pattern.sound.clone();
-                let loop_name = pattern.loop_name.clone();
-                let midi_note = pattern.midi_note;
-                let velocity = pattern.velocity;
-                let duration = pattern.duration;
-
-                if let Some(note) = midi_note {
-                    pool.execute(move || {
-                        play_midi_note(note, velocity, duration, midi_conn_clone);
-                    });
-                }
+    engine.wait();
+    alive.store(false, Ordering::SeqCst);
+    save_snapshot(&snapshot_path, &transport, &mixer, &session, &mut String::new());
 
-                else if let Some(label) = sound {
-                    pool.execute(move || {
-                        play_sound(&label,  velocity, &sb_clone, &sh_clone);
-                    });
-                }
+    Ok(())
+}
 
-                else if let Some(loop_name) = loop_name {
-                    let lb_clone = Arc::clone(&loop_bank);
-                    pool.execute(move || {
-                        play_loop(&loop_name, duration, velocity, &lb_clone, &sh_clone, bpm);
-                    });
-                }
+/// Starts playback after counting down the given seconds in the log.
+fn spawn_countdown(transport: Arc<Transport>, running: Arc<AtomicBool>, secs: u32) {
+    thread::spawn(move || {
+        for left in (1..=secs).rev() {
+            if !running.load(Ordering::SeqCst) || transport.started() {
+                return;
             }
+            log!("Starting in {}...", left);
+            thread::sleep(Duration::from_secs(1));
         }
-
-        let elapsed = start_time.elapsed().as_secs_f32();
-        let target_time = (i + 1) as f32 * eighth_beat_duration;
-        let remaining = target_time - elapsed;
-        if remaining > 0.0 {
-            thread::sleep(Duration::from_secs_f32(remaining));
-        }
-    }
+        transport.start();
+    });
 }
 
-fn generate_shape_patterns() -> Vec<Pattern> {
-    let mut patterns = Vec::new();
-
-    fn add_chord_pattern(
-        patterns: &mut Vec<Pattern>,
-        chord_notes: &[u8],
-        chord_beats: &[f32],
-        velocity: f32,
-        duration: f32,
-    ) {
-        for &note in chord_notes {
-            for &beat in chord_beats {
-                patterns.push(Pattern {
-                    sound: None,
-                    loop_name: None,
-                    midi_note: Some(note),
-                    beats: vec![beat],
-                    velocity,
-                    duration,
-                });
+/// Serves daemon commands until `quit` or Ctrl+C; playback waits for `play`.
+fn run_daemon(
+    engine: &mut Engine,
+    requests: Receiver<daemon::Request>,
+    patterns_path: &RwLock<String>,
+    setlist: Option<&Setlist>,
+    alive: &AtomicBool,
+) {
+    use daemon::DaemonCommand;
+
+    while alive.load(Ordering::SeqCst) {
+        let Ok((command, reply)) = requests.recv_timeout(Duration::from_millis(200)) else {
+            continue;
+        };
+        let result = match command {
+            DaemonCommand::Play => {
+                engine.play();
+                Ok(String::new())
             }
-        }
+            DaemonCommand::Stop => {
+                engine.stop();
+                Ok(String::new())
+            }
+            DaemonCommand::Bpm(bpm) => {
+                engine.transport.set_bpm(bpm.clamp(20, 300));
+                Ok(String::new())
+            }
+            DaemonCommand::Mute(name) => {
+                let track = model::track_of(&engine.patterns.load(), &name);
+                let mut mixer = engine.mixer.write().unwrap();
+                match mixer.channel_index(&track) {
+                    Some(index) => {
+                        mixer.toggle_mute(index);
+                        Ok(String::new())
+                    }
+                    None => Err(format!("Unknown track '{}'", track)),
+                }
+            }
+            DaemonCommand::Load(path) if Path::new(&path).exists() => {
+                *patterns_path.write().unwrap() = path.clone();
+                log!("Patterns file switched to {}", path);
+                Ok(String::new())
+            }
+            DaemonCommand::Load(path) => Err(format!("{} not found", path)),
+            DaemonCommand::Samples(dirs) => switch_banks(&engine.sound_bank, &engine.loop_bank, Some(dirs), None),
+            DaemonCommand::Loops(dirs) => switch_banks(&engine.sound_bank, &engine.loop_bank, None, Some(dirs)),
+            DaemonCommand::Next => {
+                setlist.ok_or("No setlist given".to_string()).and_then(|setlist| setlist.next().map_err(|e| e.to_string()))
+            }
+            DaemonCommand::Song(number) => setlist
+                .ok_or("No setlist given".to_string())
+                .and_then(|setlist| setlist.select(number.saturating_sub(1)).map_err(|e| e.to_string())),
+            DaemonCommand::Status => Ok(format!(
+                "bpm {} {} {}{}",
+                engine.transport.bpm(),
+                if engine.is_playing() { "playing" } else { "stopped" },
+                patterns_path.read().unwrap(),
+                setlist.map(|setlist| format!(" song {}", setlist.current())).unwrap_or_default()
+            )),
+            DaemonCommand::Quit => {
+                alive.store(false, Ordering::SeqCst);
+                Ok(String::new())
+            }
+        };
+        let _ = reply.send(result);
     }
-
-    // Define chords and their beats
-    let c_sharp_m = [61, 64, 68]; // C#4, E4, G#4
-    let f_sharp_m = [66, 69, 73]; // F#4, A4, C#5
-    let a_maj = [69, 73, 76];     // A4, C#5, E5
-    let b_maj = [71, 75, 78];     // B4, D#5, F#5
-
-    let c_sharp_beats = [0.25, 1.25, 1.75];
-    let f_sharp_beats = [2.25, 3.25, 3.75];
-    let a_beats       = [4.25, 5.25, 5.75];
-    let b_beats       = [6.25, 6.50, 7.25, 7.75];
-
-    add_chord_pattern(&mut patterns, &c_sharp_m, &c_sharp_beats, 100.0, 0.1);
-    add_chord_pattern(&mut patterns, &f_sharp_m, &f_sharp_beats, 100.0, 0.1);
-    add_chord_pattern(&mut patterns, &a_maj, &a_beats, 100.0, 0.1);
-    add_chord_pattern(&mut patterns, &b_maj, &b_beats, 100.0, 0.1);
-
-    patterns
+    engine.stop();
 }
 
+/// Starts loading other bank directories, checking first that they exist.
+fn switch_banks(
+    sound_bank: &Arc<SoundBank>,
+    loop_bank: &Arc<LoopBank>,
+    sample_dirs: Option<String>,
+    loop_dirs: Option<String>,
+) -> Result<String, String> {
+    let dirs = sample_dirs.iter().chain(&loop_dirs);
+    if let Some(missing) = dirs.flat_map(std::env::split_paths).find(|dir| !dir.is_dir()) {
+        return Err(format!("{} is not a directory", missing.display()));
+    }
+    stage_banks(Arc::clone(sound_bank), Arc::clone(loop_bank), sample_dirs, loop_dirs);
+    Ok("loading".to_string())
+}
 
-fn generate_combined_patterns(midi_pattern: Vec<Pattern>, json_patterns: Vec<Pattern>) -> Vec<Pattern> {
-    let mut combined_patterns = Vec::new();
-
-    combined_patterns.extend(json_patterns);
-
-    // combined_patterns.push(PatternBuilder::new()
-    //     .loop_name("dl-icarus")
-    //     .beats(vec![0.0, 8.0])
-    //     .duration(8.0)
-    //     .velocity(35.0)
-    //     .build()
-    // );
-
-    // combined_patterns.push(PatternBuilder::new()
-    //     .loop_name("dl-ethnic")
-    //     .beats(vec![0.0, 4.0, 8.0, 12.0])
-    //     .duration(2.0)
-    //     .build()
-    // );
-    // combined_patterns.push(PatternBuilder::new()
-    //     .loop_name("dl-ethnic")
-    //     .beats(vec![1.25, 5.25, 9.25, 13.25])
-    //     .duration(2.5)
-    //     .build()
-    // );
-
-    // // Add chord patterns
-    // combined_patterns.extend(generate_shape_patterns());
-
-    combined_patterns.extend(midi_pattern);
-
-    combined_patterns
+/// How often the live state is written to the session snapshot.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Writes the live state to the snapshot file when it differs from `written`.
+fn save_snapshot(path: &Path, transport: &Transport, mixer: &RwLock<Mixer>, session: &RwLock<Session>, written: &mut String) {
+    let snapshot = Snapshot::capture(transport, &mixer.read().unwrap(), &session.read().unwrap());
+    match snapshot.to_json() {
+        Ok(json) if json != *written => match fs::write(path, &json) {
+            Ok(()) => *written = json,
+            Err(e) => log_error!("Could not save the session to {}: {}", path.display(), e),
+        },
+        Ok(_) => {}
+        Err(e) => log_error!("Could not save the session: {}", e),
+    }
 }
 
-fn load_and_combine_patterns(file_path: &str, midi_pattern: &Vec<Pattern>) -> Vec<Pattern> {
-    if let Ok(file_content) = fs::read_to_string(file_path) {
-        load_and_combine_patterns_from_content(&file_content, midi_pattern)
-    } else {
-        eprintln!("Failed to read {} during initial load.", file_path);
-        generate_combined_patterns(midi_pattern.clone(), Vec::new())
+/// Asks a yes/no question on the terminal; no when there is none to ask on.
+fn confirm(question: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        return false;
     }
+    print!("{} [y/N] ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
 }
 
-/// Helper function to load and combine patterns from file content
-fn load_and_combine_patterns_from_content(
-    file_content: &str,
-    midi_pattern: &Vec<Pattern>,
-) -> Vec<Pattern> {
-    match serde_json::from_str::<Vec<Pattern>>(file_content) {
-        Ok(new_patterns) => generate_combined_patterns(midi_pattern.clone(), new_patterns),
-        Err(e) => {
-            eprintln!("Failed to parse JSON: {}", e);
-            generate_combined_patterns(midi_pattern.clone(), Vec::new())
-        }
+/// Runs the pattern grid window until it is closed.
+#[cfg(feature = "gui")]
+fn run_gui(
+    engine: &Engine,
+    session: Arc<RwLock<Session>>,
+    user_presets: Arc<RwLock<BTreeMap<String, presets::Preset>>>,
+    start_on_first_frame: bool,
+    config_path: &str,
+    subsystems: Subsystems,
+    config: config::Config,
+) {
+    let browser = SampleBrowser::new(
+        Arc::clone(&engine.sound_bank),
+        Arc::clone(&engine.loop_bank),
+        Arc::clone(&engine.stream_handle),
+        engine.transport.bpm(),
+        ".",
+    );
+    let app = PatternVisualizerApp::new(GridSetup {
+        patterns: Arc::clone(&engine.patterns),
+        current_beat: Arc::clone(&engine.current_beat),
+        start_on_first_frame,
+        running: engine.running(),
+        session,
+        user_presets,
+        mixer: Arc::clone(&engine.mixer),
+        transport: Arc::clone(&engine.transport),
+        browser,
+        keyboard: PianoKeyboard::new(Arc::clone(&engine.midi_conn)),
+        recorder: Recorder::new(config.midi_input_port.as_deref()),
+        settings: SettingsDialog::new(config_path, subsystems),
+        loop_beats: engine.loop_beats,
+        gui_config: config.gui,
+    });
+    let options = eframe::NativeOptions {
+        viewport: eframe::egui::ViewportBuilder::default().with_inner_size(INITIAL_WINDOW_SIZE),
+        ..Default::default()
+    };
+
+    // Run the GUI
+    let result = eframe::run_native(
+        "Pattern Visualizer",
+        options,
+        Box::new(move |cc| {
+            app.apply_theme(&cc.egui_ctx);
+            Box::new(app)
+        }),
+    );
+    // Also stop when the window went away without a close request, e.g. on a backend error
+    engine.running().store(false, Ordering::SeqCst);
+    log!("All done. Exiting now... {}", result.is_err());
+}
+
+/// Renders the loop offline to a WAV file.
+fn render(args: RenderArgs, paths: &Paths) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::read_config(&paths.config)?;
+    sample_cache::configure(config.sample_cache_dir());
+    loops::configure_key(config.loop_key());
+    let sound_bank = SoundBank::new(&config.sample_dirs(), &config.threads, config.sounds.duplicates)?;
+    let loop_bank = LoopBank::new(&config.loop_dirs(), &config.threads, config.sounds.duplicates)?;
+    let patterns = load_and_combine_patterns(&paths.patterns, &Vec::new(), config.loop_beats);
+    let transport = Transport::new(args.bpm, config.song, config.scenes);
+    // Track gains and mutes from the pattern file, as they'd start out live
+    let mut mixer = Mixer::new();
+    if let Ok(content) = fs::read_to_string(&paths.patterns) {
+        mixer.apply_tracks(&formats::parse_tracks(&paths.patterns, &content).unwrap_or_default());
     }
+    let setup = render::RenderSetup {
+        sound_bank: &sound_bank,
+        loop_bank: &loop_bank,
+        transport: &transport,
+        mixer: &mixer,
+        loop_beats: config.loop_beats,
+    };
+    let buffer = render::render_patterns(&patterns, &setup, args.loops);
+    render::write_wav(&args.output, &buffer)?;
+    log!("Rendered {} pass(es) at {} BPM to {}", args.loops, args.bpm, args.output);
+    Ok(())
 }
 
-/// -------------------------------------------------------------------------
-/// 3) Main
-/// -------------------------------------------------------------------------
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Read config
-    let config = config::read_config("config.json")?;
-
-    // Set up rodio
-    let (_stream, stream_handle) = OutputStream::try_default()?;
-
-    // Set up MIDI output
-    let midi_out = MidiOutput::new("MIDI Output")?;
-    let ports = midi_out.ports();
-    let port = ports
-        .iter()
-        .find(|p| midi_out.port_name(p).map_or(false, |name| name == config.midi_port))
-        .ok_or(format!("Could not find {} port", config.midi_port))?;
-    let conn = midi_out.connect(port, &config.midi_port)?;
-    let midi_conn = Arc::new(std::sync::Mutex::new(conn));
-
-    // Wrap in Arc
-    let sound_bank: Arc<SoundBank> = Arc::new(SoundBank::new(&config.sounds.samples)?);
-    let stream_handle = Arc::new(stream_handle);
-    let loop_bank = Arc::new(LoopBank::new(&config.sounds.loops)?);
-
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <BPM> [--no-gui]", args[0]);
-        std::process::exit(1);
+/// Plays a recorded history back, or renders it with `--output`. Tracks
+/// are looked up in the patterns file, so it should be the one recorded with.
+fn replay(args: ReplayArgs, paths: &Paths) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::read_config(&paths.config)?;
+    let entries = history::read(Path::new(&args.log))?;
+    let bpm = entries.first().map_or(120, |entry| entry.bpm);
+
+    if let Some(output) = &args.output {
+        sample_cache::configure(config.sample_cache_dir());
+        loops::configure_key(config.loop_key());
+        let sound_bank = SoundBank::new(&config.sample_dirs(), &config.threads, config.sounds.duplicates)?;
+        let loop_bank = LoopBank::new(&config.loop_dirs(), &config.threads, config.sounds.duplicates)?;
+        let patterns = load_and_combine_patterns(&paths.patterns, &Vec::new(), config.loop_beats);
+        let buffer = render::render_history(&entries, &patterns, &sound_bank, &loop_bank);
+        render::write_wav(output, &buffer)?;
+        log!("Rendered {} triggers from {} to {}", entries.len(), args.log, output);
+        return Ok(());
     }
-    let bpm: u32 = args[1].parse()?;
-    let show_gui = !args.contains(&"--no-gui".to_string());
 
-    let loop_beats = config.loop_beats;
-    let midi_pattern = midi::read_midi_and_extract_pattern(
-        &config.midi_track.midi_file,
+    let mut engine = Engine::new(&config, bpm)?;
+    let midi_pattern = read_midi_pattern(&config, bpm);
+    engine.set_patterns(load_and_combine_patterns(&paths.patterns, &midi_pattern, config.loop_beats));
+    let running = engine.running();
+    ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))?;
+    engine.replay(entries);
+    engine.wait();
+    Ok(())
+}
+
+/// Imports the configured MIDI track; empty when it can't be read, so the
+/// patterns file plays alone.
+fn read_midi_pattern(config: &config::Config, bpm: u32) -> Vec<model::Pattern> {
+    midi::read_midi_and_extract_pattern(
+        &config.midi_file(),
         &config.midi_track.track_name,
         bpm,
         config.midi_track.start_beat,
         config.midi_track.end_beat,
-    );
-
-    println!("Midi pattern {:?}", midi_pattern);
-    
-    // Atomic flag for stopping threads
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-
-    // Set up Ctrl+C handler
-    ctrlc::set_handler(move || {
-        println!("Ctrl+C detected. Stopping loop...");
-        r.store(false, Ordering::SeqCst);
-    })?;
-    println!("Press Ctrl+C to stop the loop.");
+    )
+    .unwrap_or_else(|e| {
+        log_error!("Skipping the MIDI track: {}", e);
+        Vec::new()
+    })
+}
 
-    // Shared state for the patterns
-    let patterns = Arc::new(RwLock::new(Vec::new()));
+fn import_kit(args: ImportKitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let kit_dir = hydrogen::import(std::path::Path::new(&args.archive), std::path::Path::new(&args.into))?;
+    println!("Add {} to sounds.samples to play the kit", kit_dir.display());
+    Ok(())
+}
 
-    {
-        let initial_patterns = load_and_combine_patterns("patterns.json", &midi_pattern);
-        let mut patterns_write = patterns.write().unwrap();
-        *patterns_write = initial_patterns;
+fn list_ports() -> Result<(), Box<dyn std::error::Error>> {
+    println!("MIDI outputs:");
+    for port in midi_io::output_port_names() {
+        println!("  {}", port);
     }
+    println!("MIDI inputs:");
+    for port in midi_io::input_port_names() {
+        println!("  {}", port);
+    }
+    println!("Audio outputs:");
+    for device in audio::output_device_names() {
+        println!("  {}", device);
+    }
+    Ok(())
+}
 
-    // Start a background thread to watch for changes
-    let patterns_clone = Arc::clone(&patterns);
-    let running_clone = Arc::clone(&running);
-    let midi_pattern_clone = midi_pattern.clone(); // Clone MIDI patterns for the thread
-    thread::spawn(move || {
-        loop {
-            if running_clone.load(Ordering::SeqCst) {
-                if let Ok(file_content) = fs::read_to_string("patterns.json") {
-                    let combined_patterns = load_and_combine_patterns_from_content(
-                        &file_content,
-                        &midi_pattern_clone,
-                    );
-                    let mut patterns_write = patterns_clone.write().unwrap(); // Write lock
-                    *patterns_write = combined_patterns;
-                    println!("Patterns updated from JSON and MIDI patterns combined.");
-                } else {
-                    eprintln!("Failed to read patterns.json");
-                }
-            } else {
-                break;
-            }
-            thread::sleep(Duration::from_secs(3));
-        }
-    });
-
-    let current_beat = Arc::new(RwLock::new(0.0)); // Shared state for the current beat
-    let gui_current_beat = Arc::clone(&current_beat);
-    let gui_patterns = Arc::clone(&patterns);
-    let gui_ready = Arc::new(AtomicBool::new(false)); // Flag to signal when GUI is ready
-    let playback_gui_ready = Arc::clone(&gui_ready);
-
-    let playback_handle = std::thread::spawn(move || {
-        while running.load(Ordering::SeqCst) {
-            // Load the current patterns
-            let current_patterns = {
-                let patterns_lock = patterns.read().unwrap();
-                patterns_lock.clone()
-            };
-
-            while !playback_gui_ready.load(Ordering::SeqCst) {
-                std::thread::sleep(std::time::Duration::from_millis(100));
-            }
-
-            println!("Starting playback");
-
-            // Play the pattern with the sound bank
-            play_pattern_with_soundbank(
-                Arc::new(current_patterns),
-                Arc::clone(&current_beat),
-                Arc::clone(&sound_bank),
-                Arc::clone(&loop_bank),
-                Arc::clone(&stream_handle),
-                Arc::clone(&midi_conn),
-                bpm,
-                loop_beats,
-            );
-        }
-    });
+/// Checks that the config, patterns and MIDI import load, and that every
+/// pattern refers to a sample or loop that exists.
+fn validate(paths: &Paths) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::read_config(&paths.config)?;
+    sample_cache::configure(config.sample_cache_dir());
+    loops::configure_key(config.loop_key());
+    let mut problems = Vec::new();
+
+    let content = fs::read_to_string(&paths.patterns);
+    let patterns = content
+        .as_ref()
+        .map_err(|e| e.to_string())
+        .and_then(|content| formats::parse_patterns(&paths.patterns, content, config.loop_beats).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            problems.push(format!("{}: {}", paths.patterns, e));
+            Vec::new()
+        });
+    let content = content.unwrap_or_default();
+
+    let sound_bank = SoundBank::new(&config.sample_dirs(), &config.threads, config.sounds.duplicates)?;
+    let loop_bank = LoopBank::new(&config.loop_dirs(), &config.threads, config.sounds.duplicates)?;
+    problems.extend(validation::validate_patterns(
+        &paths.patterns,
+        &content,
+        &patterns,
+        &sound_bank,
+        &loop_bank,
+        config.loop_beats,
+    ));
+
+    let track = &config.midi_track;
+    if let Err(e) = midi::read_midi_and_extract_pattern(&config.midi_file(), &track.track_name, 120, track.start_beat, track.end_beat) {
+        problems.push(e.to_string());
+    }
 
-    if show_gui {
-        // Create the GUI app
-        let app = PatternVisualizerApp::new(
-            Arc::clone(&gui_patterns), 
-            Arc::clone(&gui_current_beat), 
-            Arc::clone(&gui_ready),
-            bpm,
-        );
-        let options = eframe::NativeOptions::default();
-
-        // Run the GUI
-        let result = eframe::run_native(
-            "Pattern Visualizer", 
-            options, 
-            Box::new(move |_cc| {
-                Box::new(app)
-            }
-        ));
-        println!("All done. Exiting now... {}", result.is_err());
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{} and {} are valid", paths.config, paths.patterns);
+        Ok(())
     } else {
-        gui_ready.store(true, Ordering::SeqCst);
+        Err(format!("{} problem(s) found", problems.len()).into())
     }
+}
 
-    match playback_handle.join() {
-        Ok(_) => println!("Playback finished"),
-        Err(e) => println!("Playback encountered an error: {:?}", e),
+fn list_samples(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::read_config(config_path)?;
+    sample_cache::configure(config.sample_cache_dir());
+    loops::configure_key(config.loop_key());
+    logging::set_quiet(true); // Keep per-file loading messages out of the listing
+    println!("Samples ({}):", config.sample_dirs());
+    for label in SoundBank::new(&config.sample_dirs(), &config.threads, config.sounds.duplicates)?.labels() {
+        println!("  {}", label);
+    }
+    println!("Loops ({}):", config.loop_dirs());
+    let loop_bank = LoopBank::new(&config.loop_dirs(), &config.threads, config.sounds.duplicates)?;
+    for label in loop_bank.labels() {
+        let Some(sample) = loop_bank.get(&label) else { continue };
+        let beats = sample.beats.map(|beats| format!(", {} beats", beats)).unwrap_or_default();
+        let key = sample.key.as_ref().map(|key| format!(", {}", key)).unwrap_or_default();
+        println!("  {} ({} bpm{}{})", label, sample.bpm, beats, key);
     }
-
     Ok(())
 }
@@ -0,0 +1,130 @@
+#[cfg(feature = "audio")]
+use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "audio")]
+use rodio::Source;
+
+/// Samples per metering block.
+#[cfg(feature = "audio")]
+const BLOCK_SIZE: usize = 512;
+
+/// Peak and RMS collected from the audio thread, read out by the GUI.
+///
+/// Levels are non-negative floats stored as bits, so `fetch_max` on the
+/// bits keeps the loudest value seen since the last `take`.
+#[derive(Default)]
+pub struct LevelMeter {
+    peak: AtomicU32,
+    rms: AtomicU32,
+    /// When the loop last started on the track, and how long it plays.
+    playing_loop: Mutex<Option<(Instant, Duration)>>,
+}
+
+impl LevelMeter {
+    pub fn record(&self, peak: f32, rms: f32) {
+        self.peak.fetch_max(peak.max(0.0).to_bits(), Ordering::Relaxed);
+        self.rms.fetch_max(rms.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns the (peak, rms) seen since the previous call and resets them.
+    pub fn take(&self) -> (f32, f32) {
+        (
+            f32::from_bits(self.peak.swap(0, Ordering::Relaxed)),
+            f32::from_bits(self.rms.swap(0, Ordering::Relaxed)),
+        )
+    }
+
+    /// Notes that a loop playing for `length` started on the track.
+    pub fn start_loop(&self, length: Duration) {
+        *self.playing_loop.lock().unwrap() = Some((Instant::now(), length));
+    }
+
+    /// How far into the playing loop the track is, and how long it plays;
+    /// None when no loop is playing.
+    pub fn loop_position(&self) -> Option<(Duration, Duration)> {
+        let (started, length) = (*self.playing_loop.lock().unwrap())?;
+        Some((started.elapsed(), length)).filter(|(elapsed, length)| elapsed < length)
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Metering tap in the mixing path: passes samples through unchanged while
+/// reporting block peak/RMS to a `LevelMeter`.
+pub struct MeterTap<S> {
+    inner: S,
+    meter: Arc<LevelMeter>,
+    count: usize,
+    peak: f32,
+    sum_squares: f32,
+}
+
+#[cfg(feature = "audio")]
+impl<S> MeterTap<S> {
+    pub fn new(inner: S, meter: Arc<LevelMeter>) -> Self {
+        Self {
+            inner,
+            meter,
+            count: 0,
+            peak: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let rms = (self.sum_squares / self.count as f32).sqrt();
+            self.meter.record(self.peak, rms);
+        }
+        self.count = 0;
+        self.peak = 0.0;
+        self.sum_squares = 0.0;
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: Source<Item = i16>> Iterator for MeterTap<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.inner.next();
+        match sample {
+            Some(value) => {
+                let level = (value as f32 / i16::MAX as f32).abs();
+                self.peak = self.peak.max(level);
+                self.sum_squares += level * level;
+                self.count += 1;
+                if self.count >= BLOCK_SIZE {
+                    self.flush();
+                }
+            }
+            None => self.flush(),
+        }
+        sample
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<S: Source<Item = i16>> Source for MeterTap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
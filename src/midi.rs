@@ -2,29 +2,37 @@ use midly::{Smf, TrackEventKind, MidiMessage};
 use std::fs::File;
 use std::io::Read;
 
-use crate::model::Pattern;
+use crate::error::{Error, Result};
+use crate::model::{Pattern, PatternBuilder, Step};
 
 use std::collections::HashMap;
 
+/// Imports the notes of one track as patterns; an empty `file_path` means
+/// no MIDI import. A missing track is an error so a typo in its name shows.
 pub fn read_midi_and_extract_pattern(
     file_path: &str,
     track_name: &str,
     bpm: u32,
     start_beat: f32,
     end_beat: f32,
-) -> Vec<Pattern> {
+) -> Result<Vec<Pattern>> {
+    if file_path.is_empty() {
+        return Ok(Vec::new());
+    }
+
     // Read the MIDI file into memory
-    let mut file = File::open(file_path).expect("Failed to open MIDI file");
+    let io_error = |source| Error::Io { path: file_path.to_string(), source };
+    let mut file = File::open(file_path).map_err(io_error)?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Failed to read MIDI file");
+    file.read_to_end(&mut buffer).map_err(io_error)?;
 
     // Parse the MIDI file
-    let smf = Smf::parse(&buffer).expect("Failed to parse MIDI file");
+    let smf = Smf::parse(&buffer).map_err(|source| Error::Midi { path: file_path.to_string(), source })?;
 
     // Time conversion constants
     let ticks_per_beat = match smf.header.timing {
         midly::Timing::Metrical(tpb) => tpb.as_int() as f32,
-        _ => panic!("Unsupported MIDI timing format"),
+        _ => return Err(Error::MidiTiming { path: file_path.to_string() }),
     };
     let seconds_per_tick = 60.0 / (bpm as f32 * ticks_per_beat);
     let increment = 0.25; // Round to nearest 0.25
@@ -45,27 +53,27 @@ pub fn read_midi_and_extract_pattern(
             // Filter patterns within the specified beat range
             if rounded_beat_start >= start_beat && rounded_beat_start < end_beat {
                 patterns.push(Pattern {
-                    sound: None,
-                    loop_name: None,
                     midi_note: Some(key),
-                    beats: vec![rounded_beat_start - start_beat],
                     velocity: velocity / 127.0 * 100.0,
-                    duration,
+                    duration: duration * bpm as f32 / 60.0,
+                    steps: vec![Step::new(rounded_beat_start - start_beat)],
+                    ..PatternBuilder::new().build()
                 });
             }
         }
     };
 
     // Process each track
+    let mut track_found = false;
     for track in smf.tracks.iter() {
         let mut found_name = false;
 
         // Check if this is the desired track
         for event in track.iter() {
             if let TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) = &event.kind {
-                let track_name_bytes: Vec<u8> = name.iter().cloned().collect();
+                let track_name_bytes: Vec<u8> = name.to_vec();
                 if let Ok(name_str) = String::from_utf8(track_name_bytes) {
-                    println!("Track {}", name_str);
+                    log!("Track {}", name_str);
                     if name_str == track_name {
                         found_name = true;
                         break;
@@ -77,6 +85,7 @@ pub fn read_midi_and_extract_pattern(
         if !found_name {
             continue;
         }
+        track_found = true;
 
         // Process events in the track
         let mut current_time: u32 = 0;
@@ -113,6 +122,8 @@ pub fn read_midi_and_extract_pattern(
         }
     }
 
-    patterns
+    if !track_found {
+        return Err(Error::MidiTrackNotFound { path: file_path.to_string(), track: track_name.to_string() });
+    }
+    Ok(patterns)
 }
-
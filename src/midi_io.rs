@@ -0,0 +1,144 @@
+//! MIDI ports. Without the `midi` feature there are no ports and the
+//! output swallows what is sent to it, so patterns with notes stay silent.
+
+#[cfg(feature = "midi")]
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+use crate::error::{self, Error};
+
+/// Name of the virtual MIDI port opened when the configured one is missing.
+#[cfg(feature = "midi")]
+const VIRTUAL_MIDI_PORT: &str = "four_on_the_floor";
+
+/// Connection to a MIDI output port.
+pub struct MidiOut {
+    #[cfg(feature = "midi")]
+    conn: MidiOutputConnection,
+}
+
+/// Open MIDI input, listening until dropped.
+pub struct MidiIn {
+    #[cfg(feature = "midi")]
+    _conn: MidiInputConnection<()>,
+}
+
+/// Names of the MIDI output ports.
+pub fn output_port_names() -> Vec<String> {
+    #[cfg(feature = "midi")]
+    if let Ok(out) = MidiOutput::new("MIDI Output") {
+        return out.ports().iter().filter_map(|p| out.port_name(p).ok()).collect();
+    }
+    Vec::new()
+}
+
+/// Names of the MIDI input ports.
+pub fn input_port_names() -> Vec<String> {
+    #[cfg(feature = "midi")]
+    if let Ok(input) = MidiInput::new("MIDI Input") {
+        return input.ports().iter().filter_map(|p| input.port_name(p).ok()).collect();
+    }
+    Vec::new()
+}
+
+impl MidiOut {
+    /// Connects to the named output port.
+    #[cfg(feature = "midi")]
+    pub fn connect(port_name: &str) -> error::Result<Self> {
+        let port_error = |reason: String| Error::MidiPort { port: port_name.to_string(), reason };
+        let midi_out = MidiOutput::new("MIDI Output").map_err(|e| port_error(e.to_string()))?;
+        let port = midi_out
+            .ports()
+            .into_iter()
+            .find(|p| midi_out.port_name(p).is_ok_and(|name| name == port_name))
+            .ok_or_else(|| port_error("not found".to_string()))?;
+        let conn = midi_out.connect(&port, port_name).map_err(|e| port_error(e.to_string()))?;
+        Ok(Self { conn })
+    }
+
+    #[cfg(not(feature = "midi"))]
+    pub fn connect(port_name: &str) -> error::Result<Self> {
+        Err(Error::MidiPort { port: port_name.to_string(), reason: "built without MIDI support".to_string() })
+    }
+
+    /// Connects to the named output port, or opens a virtual one other apps
+    /// can listen on when it is missing (or none is set, as after `init`).
+    #[cfg(feature = "midi")]
+    pub fn open(port_name: &str) -> error::Result<Self> {
+        match Self::connect(port_name) {
+            Ok(out) => Ok(out),
+            Err(e) => Self::open_virtual(port_name, e),
+        }
+    }
+
+    #[cfg(not(feature = "midi"))]
+    pub fn open(port_name: &str) -> error::Result<Self> {
+        if !port_name.is_empty() {
+            log_warn!("Built without MIDI support, notes for {} are not sent", port_name);
+        }
+        Ok(Self {})
+    }
+
+    #[cfg(all(feature = "midi", unix))]
+    fn open_virtual(port_name: &str, _error: Error) -> error::Result<Self> {
+        use midir::os::unix::VirtualOutput;
+        if !port_name.is_empty() {
+            log_warn!("Could not find {} port, opening virtual port {}", port_name, VIRTUAL_MIDI_PORT);
+        }
+        let port_error = |reason: String| Error::MidiPort { port: VIRTUAL_MIDI_PORT.to_string(), reason };
+        let conn = MidiOutput::new("MIDI Output")
+            .map_err(|e| port_error(e.to_string()))?
+            .create_virtual(VIRTUAL_MIDI_PORT)
+            .map_err(|e| port_error(e.to_string()))?;
+        Ok(Self { conn })
+    }
+
+    #[cfg(all(feature = "midi", not(unix)))]
+    fn open_virtual(_port_name: &str, error: Error) -> error::Result<Self> {
+        Err(error)
+    }
+
+    /// Silences whatever is still sounding: All Notes Off on every channel.
+    pub fn all_notes_off(&mut self) -> error::Result<()> {
+        for channel in 0..16u8 {
+            self.send(&[0xB0 | channel, 123, 0])?;
+        }
+        Ok(())
+    }
+
+    pub fn send(&mut self, message: &[u8]) -> error::Result<()> {
+        #[cfg(feature = "midi")]
+        self.conn.send(message).map_err(|e| Error::MidiSend { reason: e.to_string() })?;
+        #[cfg(not(feature = "midi"))]
+        let _ = message;
+        Ok(())
+    }
+}
+
+impl MidiIn {
+    /// Opens the named input port, passing every message to `on_message`.
+    #[cfg(feature = "midi")]
+    pub fn listen(
+        port_name: &str,
+        mut on_message: impl FnMut(&[u8]) + Send + 'static,
+    ) -> error::Result<Self> {
+        let port_error = |reason: String| Error::MidiPort { port: port_name.to_string(), reason };
+        let midi_in = MidiInput::new("MIDI Input").map_err(|e| port_error(e.to_string()))?;
+        let port = midi_in
+            .ports()
+            .into_iter()
+            .find(|p| midi_in.port_name(p).is_ok_and(|name| name == port_name))
+            .ok_or_else(|| port_error("not found".to_string()))?;
+        let conn = midi_in
+            .connect(&port, port_name, move |_, message, _| on_message(message), ())
+            .map_err(|e| port_error(e.to_string()))?;
+        Ok(Self { _conn: conn })
+    }
+
+    #[cfg(not(feature = "midi"))]
+    pub fn listen(
+        port_name: &str,
+        _on_message: impl FnMut(&[u8]) + Send + 'static,
+    ) -> error::Result<Self> {
+        Err(Error::MidiPort { port: port_name.to_string(), reason: "built without MIDI support".to_string() })
+    }
+}
@@ -0,0 +1,212 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio;
+use crate::meter::LevelMeter;
+use crate::model::Track;
+
+/// Fraction of the displayed level kept per GUI frame.
+const METER_FALLOFF: f32 = 0.9;
+
+#[derive(Clone)]
+pub struct ChannelStrip {
+    pub gain: f32,
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+    /// Hex color set by the track in the pattern file.
+    pub color: Option<String>,
+    meter: Arc<LevelMeter>,
+    peak: f32,
+    rms: f32,
+}
+
+impl Default for ChannelStrip {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+            color: None,
+            meter: Arc::new(LevelMeter::default()),
+            peak: 0.0,
+            rms: 0.0,
+        }
+    }
+}
+
+impl ChannelStrip {
+    /// Displayed peak level, with falloff.
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    /// Displayed RMS level, with falloff.
+    pub fn rms(&self) -> f32 {
+        self.rms
+    }
+
+    /// Pulls the latest levels from the audio thread; call once per GUI frame.
+    fn update_meter(&mut self) {
+        let (peak, rms) = self.meter.take();
+        self.peak = peak.max(self.peak * METER_FALLOFF);
+        self.rms = rms.max(self.rms * METER_FALLOFF);
+    }
+}
+
+/// The settings of a strip that are saved in session snapshots.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct StripSettings {
+    pub gain: f32,
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+/// Per-track gain staging shared between the scheduler and the GUI.
+#[derive(Default)]
+pub struct Mixer {
+    channels: BTreeMap<String, ChannelStrip>,
+    /// Tracks declared in the pattern file, without their patterns.
+    tracks: Vec<Track>,
+    /// Displayed master (peak, rms), with falloff.
+    master: (f32, f32),
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes sure a strip exists for every given track name.
+    pub fn ensure_channels<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        for name in names {
+            self.channels.entry(name.to_string()).or_default();
+        }
+    }
+
+    /// Takes gain, mute and color from the tracks declared in the pattern file.
+    pub fn apply_tracks(&mut self, tracks: &[Track]) {
+        self.tracks = tracks.iter().map(|track| Track { patterns: Vec::new(), ..track.clone() }).collect();
+        for track in tracks {
+            let strip = self.channels.entry(track.name.clone()).or_default();
+            strip.gain = track.gain;
+            strip.mute = track.mute;
+            strip.color = track.color.clone();
+        }
+    }
+
+    /// The declared tracks with their current gain, mute and color and no
+    /// patterns, for saving.
+    pub fn tracks(&self) -> Vec<Track> {
+        self.tracks
+            .iter()
+            .map(|track| match self.channels.get(&track.name) {
+                Some(strip) => Track { gain: strip.gain, mute: strip.mute, color: strip.color.clone(), ..track.clone() },
+                None => track.clone(),
+            })
+            .collect()
+    }
+
+    pub fn color(&self, name: &str) -> Option<&str> {
+        self.channels.get(name).and_then(|c| c.color.as_deref())
+    }
+
+    pub fn channels_mut(&mut self) -> impl Iterator<Item = (&String, &mut ChannelStrip)> {
+        self.channels.iter_mut()
+    }
+
+    /// Mute state of every track.
+    pub fn mutes(&self) -> impl Iterator<Item = (&String, bool)> {
+        self.channels.iter().map(|(name, strip)| (name, strip.mute))
+    }
+
+    /// Gain, pan, mute and solo of every track.
+    pub fn settings(&self) -> BTreeMap<String, StripSettings> {
+        self.channels
+            .iter()
+            .map(|(name, c)| (name.clone(), StripSettings { gain: c.gain, pan: c.pan, mute: c.mute, solo: c.solo }))
+            .collect()
+    }
+
+    /// Sets strips back to saved settings, creating the missing ones.
+    pub fn restore(&mut self, settings: &BTreeMap<String, StripSettings>) {
+        for (name, saved) in settings {
+            let strip = self.channels.entry(name.clone()).or_default();
+            strip.gain = saved.gain;
+            strip.pan = saved.pan;
+            strip.mute = saved.mute;
+            strip.solo = saved.solo;
+        }
+    }
+
+    /// Position of the track in the mixer, as used by the 1-9 shortcuts.
+    pub fn channel_index(&self, name: &str) -> Option<usize> {
+        self.channels.keys().position(|k| k == name)
+    }
+
+    pub fn toggle_mute(&mut self, index: usize) {
+        if let Some(strip) = self.channels.values_mut().nth(index) {
+            strip.mute = !strip.mute;
+        }
+    }
+
+    pub fn set_mute(&mut self, index: usize, mute: bool) {
+        if let Some(strip) = self.channels.values_mut().nth(index) {
+            strip.mute = mute;
+        }
+    }
+
+    pub fn toggle_solo(&mut self, index: usize) {
+        if let Some(strip) = self.channels.values_mut().nth(index) {
+            strip.solo = !strip.solo;
+        }
+    }
+
+    fn any_solo(&self) -> bool {
+        self.channels.values().any(|c| c.solo)
+    }
+
+    /// Effective gain for a track, taking mute and solo into account.
+    pub fn gain(&self, name: &str) -> f32 {
+        match self.channels.get(name) {
+            Some(c) if c.mute || (self.any_solo() && !c.solo) => 0.0,
+            Some(c) => c.gain,
+            None if self.any_solo() => 0.0,
+            None => 1.0,
+        }
+    }
+
+    pub fn pan(&self, name: &str) -> f32 {
+        self.channels.get(name).map_or(0.0, |c| c.pan)
+    }
+
+    /// Position and length of the loop playing on a track, if any.
+    pub fn loop_position(&self, name: &str) -> Option<(Duration, Duration)> {
+        self.channels.get(name)?.meter.loop_position()
+    }
+
+    /// Meter that the audio engine feeds for the given track.
+    pub fn meter(&mut self, name: &str) -> Arc<LevelMeter> {
+        Arc::clone(&self.channels.entry(name.to_string()).or_default().meter)
+    }
+
+    /// Refreshes every strip's displayed levels and returns the master
+    /// (peak, rms), read from the summed output after the master gain.
+    pub fn update_meters(&mut self) -> (f32, f32) {
+        for strip in self.channels.values_mut() {
+            strip.update_meter();
+        }
+        let (peak, rms) = audio::take_master_levels();
+        self.master = (peak.max(self.master.0 * METER_FALLOFF), rms.max(self.master.1 * METER_FALLOFF));
+        self.master
+    }
+}
+
+/// Converts a pan position in -1..1 into left/right channel volumes.
+pub fn pan_volumes(pan: f32) -> Vec<f32> {
+    let pan = pan.clamp(-1.0, 1.0);
+    vec![(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)]
+}
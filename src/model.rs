@@ -1,36 +1,798 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Deserialize, Clone)]
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::chord::{note_name, Chord, Voicing};
+use crate::STEP_BEATS;
+
+const BAR_BEATS: f32 = 4.0;
+
+fn default_velocity() -> f32 {
+    100.0
+}
+
+fn default_duration() -> f32 {
+    0.25
+}
+
+fn default_time_scale() -> f32 {
+    1.0
+}
+
+fn is_default_time_scale(time_scale: &f32) -> bool {
+    *time_scale == default_time_scale()
+}
+
+fn default_probability() -> f32 {
+    1.0
+}
+
+fn default_ratchet() -> u32 {
+    1
+}
+
+fn default_every() -> u32 {
+    1
+}
+
+fn is_zero(pitch: &i8) -> bool {
+    *pitch == 0
+}
+
+fn is_centered(pan: &f32) -> bool {
+    *pan == 0.0
+}
+
+fn default_accent_boost() -> f32 {
+    20.0
+}
+
+fn is_default_accent_boost(boost: &f32) -> bool {
+    *boost == default_accent_boost()
+}
+
+fn no_jitter(jitter: &f32) -> bool {
+    *jitter == 0.0
+}
+
+fn is_default_probability(probability: &f32) -> bool {
+    *probability == default_probability()
+}
+
+fn is_default_ratchet(ratchet: &u32) -> bool {
+    *ratchet == default_ratchet()
+}
+
+fn is_default_every(every: &u32) -> bool {
+    *every == default_every()
+}
+
+fn is_no_offset(offset: &f32) -> bool {
+    *offset == 0.0
+}
+
+/// Ticks per beat of step positions.
+pub const PPQN: i32 = 960;
+
+/// A position in the loop in whole ticks of 1/`PPQN` beat, so positions
+/// compare exactly: 0.1 + 0.2 is 0.3 and triplets land on a tick. Read and
+/// written as beats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ticks(pub i32);
+
+impl Ticks {
+    /// The tick nearest to `beats`.
+    pub fn from_beats(beats: f32) -> Self {
+        Ticks((beats as f64 * PPQN as f64).round() as i32)
+    }
+
+    pub fn beats(self) -> f32 {
+        (self.0 as f64 / PPQN as f64) as f32
+    }
+}
+
+impl fmt::Display for Ticks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.beats())
+    }
+}
+
+impl Serialize for Ticks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.beats())
+    }
+}
+
+impl<'de> Deserialize<'de> for Ticks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f32::deserialize(deserializer).map(Ticks::from_beats)
+    }
+}
+
+/// A step that plays: where it sits in the loop, and anything it plays
+/// differently from the rest of the pattern. Unset values fall back to the
+/// pattern's.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Step {
+    /// Position within the loop; `beat` in older pattern files.
+    #[serde(alias = "beat")]
+    pub position: Ticks,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f32>,
+    /// Chance (0..1) that the step plays on a given pass.
+    #[serde(default = "default_probability", skip_serializing_if = "is_default_probability")]
+    pub probability: f32,
+    /// Number of evenly spaced repeats within the step.
+    #[serde(default = "default_ratchet", skip_serializing_if = "is_default_ratchet")]
+    pub ratchet: u32,
+    /// Semitones added to the pattern's MIDI note.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub pitch: i8,
+    /// Micro-timing delay in beats.
+    #[serde(default, skip_serializing_if = "is_no_offset")]
+    pub offset: f32,
+    /// Condition: only plays on every Nth pass of the loop.
+    #[serde(default = "default_every", skip_serializing_if = "is_default_every")]
+    pub every: u32,
+}
+
+impl Step {
+    /// Whether the step may be skipped: below full probability or conditional on the pass.
+    pub fn is_conditional(&self) -> bool {
+        self.probability < 1.0 || self.every > 1
+    }
+
+    /// Whether the step plays just like the rest of its pattern.
+    pub fn is_plain(&self) -> bool {
+        *self == Step::new(self.beat())
+    }
+
+    /// Position within the loop in beats.
+    pub fn beat(&self) -> f32 {
+        self.position.beats()
+    }
+
+    /// A plain step at `beat`, rounded to the nearest tick.
+    pub fn new(beat: f32) -> Self {
+        Self {
+            position: Ticks::from_beats(beat),
+            velocity: None,
+            duration: None,
+            probability: default_probability(),
+            ratchet: default_ratchet(),
+            pitch: 0,
+            offset: 0.0,
+            every: default_every(),
+        }
+    }
+}
+
+/// Unit of a pattern's durations.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationUnit {
+    #[default]
+    Beats,
+    /// Seconds, as MIDI note lengths used to be given.
+    Seconds,
+}
+
+impl DurationUnit {
+    fn is_beats(&self) -> bool {
+        *self == DurationUnit::Beats
+    }
+}
+
+/// Velocities for the steps of a pattern in order, cycled when there are
+/// fewer than steps: levels such as `[100, 60, 80, 60]`, or accent markers
+/// such as `"X.x."` where `X` or `>` accents the step.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Velocities {
+    Levels(Vec<f32>),
+    Accents(String),
+}
+
+/// Steps written as their positions, spelled out only where they differ
+/// from the pattern: `[0, 1, {"position": 2.5, "velocity": 80}]`.
+mod step_list {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Step, Ticks};
+
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Written<'a> {
+        Position(Ticks),
+        Step(&'a Step),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Read {
+        Position(Ticks),
+        Step(Step),
+    }
+
+    pub fn serialize<S: Serializer>(steps: &[Step], serializer: S) -> Result<S::Ok, S::Error> {
+        let written = steps.iter().map(|step| if step.is_plain() { Written::Position(step.position) } else { Written::Step(step) });
+        serializer.collect_seq(written)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Step>, D::Error> {
+        let read = Vec::<Read>::deserialize(deserializer)?;
+        let step = |read| match read {
+            Read::Position(position) => Step { position, ..Step::new(0.0) },
+            Read::Step(step) => step,
+        };
+        // Hand-written lists may be in any order; the pattern keeps them sorted
+        let mut steps: Vec<Step> = read.into_iter().map(step).collect();
+        steps.sort_by_key(|step| step.position);
+        Ok(steps)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(remote = "Self")]
 pub struct Pattern {
+    /// Name such as "hats-main" that logs, commands and reloads refer to
+    /// the pattern by; unnamed patterns go by their track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Track the pattern belongs to; patterns without one are their own track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sound: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub loop_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub midi_note: Option<u8>,
-    pub beats: Vec<f32>,
+    /// The steps that play, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "step_list")]
+    pub steps: Vec<Step>,
+    #[serde(default = "default_velocity")]
     pub velocity: f32,
+    /// Dynamics within the pattern, applied to the steps on load; steps
+    /// setting their own velocity keep it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocities: Option<Velocities>,
+    /// Random amount, up to this much either way, added to the velocity
+    /// of every trigger, for hats and ghost notes that don't sound
+    /// machine-gunned.
+    #[serde(default, skip_serializing_if = "no_jitter")]
+    pub velocity_jitter: f32,
+    /// Beats played `accent_boost` louder, such as `[0.0, 2.0]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accents: Vec<f32>,
+    /// Velocity added on the accented beats.
+    #[serde(default = "default_accent_boost", skip_serializing_if = "is_default_accent_boost")]
+    pub accent_boost: f32,
+    /// Length in beats for every kind of event; 0.25 (a 16th) when omitted.
+    #[serde(default = "default_duration")]
     pub duration: f32,
+    /// "seconds" keeps older files, with MIDI note lengths in seconds,
+    /// playing as they did.
+    #[serde(default, skip_serializing_if = "DurationUnit::is_beats")]
+    pub duration_unit: DurationUnit,
+    /// Stereo position of samples and loops from -1 (left) to 1 (right),
+    /// added to the track's pan.
+    #[serde(default, skip_serializing_if = "is_centered")]
+    pub pan: f32,
+    /// MIDI port the notes go to instead of the configured one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Semitones added to `midi_note` when the notes play, on top of the
+    /// global transpose.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub transpose: i8,
+    /// Only play while this variation is selected; None plays in all variations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variation: Option<u32>,
+    /// Only play during a loop pass where a fill was triggered.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fill: bool,
+    /// Left in the file but silent, for parts toggled while live-coding.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub muted: bool,
+    /// Pattern bank (scene) this pattern belongs to; None plays in every bank.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank: Option<String>,
+    /// Mini-notation such as "bd ~ sn [hh hh]", expanded into one pattern per sound on load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notation: Option<String>,
+    /// Drum-machine step string such as "x...x...X...x...", one 16th per character.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<String>,
+    /// Rhythm preset such as "backbeat", or "backbeat:sd" for one of its parts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Playback speed against the loop: 0.5 is half-time, with one pass of
+    /// the pattern spanning two loops, and 2.0 double-time.
+    #[serde(default = "default_time_scale", skip_serializing_if = "is_default_time_scale")]
+    pub time_scale: f32,
+    /// Part of the loop, as `[start_beat, end_beat)`, the pattern sounds
+    /// in, such as `[8, 16]` for hats in the second half only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_range: Option<[f32; 2]>,
+    /// Problems fixed up while reading the steps, for validation to report.
+    #[serde(skip)]
+    pub load_warnings: Vec<String>,
+}
+
+/// A pattern row as read from a file. Older files list the step positions
+/// in `beats`, with `steps` only overriding some of them.
+#[derive(Deserialize)]
+struct PatternRow {
+    #[serde(default)]
+    beats: Option<Vec<f32>>,
+    #[serde(flatten, with = "Pattern")]
+    pattern: Pattern,
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let PatternRow { beats, mut pattern } = PatternRow::deserialize(deserializer)?;
+        pattern.merge_duplicate_steps();
+        if let Some(beats) = beats {
+            let overrides = std::mem::take(&mut pattern.steps);
+            for beat in beats {
+                pattern.set_beat(beat, true);
+            }
+            for step in overrides {
+                if pattern.has_step(step.beat()) {
+                    pattern.set_step(step);
+                } else {
+                    pattern.load_warnings.push(format!("settings for beat {} which is not in beats", step.position));
+                }
+            }
+        }
+        Ok(pattern)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Pattern::serialize(self, serializer)
+    }
+}
+
+impl Pattern {
+    /// Keeps one step per position of the sorted steps: one with settings
+    /// wins over a plain one, and of two with settings the later one.
+    fn merge_duplicate_steps(&mut self) {
+        let warnings = &mut self.load_warnings;
+        self.steps.dedup_by(|later, earlier| {
+            if later.position != earlier.position {
+                return false;
+            }
+            warnings.push(format!("beat {} appears twice", later.position));
+            if !later.is_plain() {
+                std::mem::swap(later, earlier);
+            }
+            true
+        });
+    }
+
+    /// Positions of the steps in beats, in order.
+    pub fn beats(&self) -> impl Iterator<Item = f32> + '_ {
+        self.steps.iter().map(Step::beat)
+    }
+
+    pub fn has_step(&self, beat: f32) -> bool {
+        let position = Ticks::from_beats(beat);
+        self.steps.iter().any(|step| step.position == position)
+    }
+
+    /// The step at `beat`, or a plain one when the step is off.
+    pub fn step_at(&self, beat: f32) -> Step {
+        let position = Ticks::from_beats(beat);
+        self.steps
+            .iter()
+            .find(|step| step.position == position)
+            .cloned()
+            .unwrap_or_else(|| Step::new(beat))
+    }
+
+    /// Switches the step at `beat` on or off.
+    pub fn set_beat(&mut self, beat: f32, on: bool) {
+        let position = Ticks::from_beats(beat);
+        let index = self.steps.partition_point(|step| step.position < position);
+        let present = self.steps.get(index).is_some_and(|step| step.position == position);
+        if on && !present {
+            self.steps.insert(index, Step::new(beat));
+        } else if !on && present {
+            self.steps.remove(index);
+        }
+    }
+
+    /// Replaces the step at the same position; steps that are off stay off.
+    pub fn set_step(&mut self, step: Step) {
+        if let Some(existing) = self.steps.iter_mut().find(|s| s.position == step.position) {
+            *existing = step;
+        }
+    }
+
+    /// Puts `step` at its position, replacing the step there.
+    pub fn insert_step(&mut self, step: Step) {
+        let index = self.steps.partition_point(|s| s.position < step.position);
+        match self.steps.get_mut(index) {
+            Some(existing) if existing.position == step.position => *existing = step,
+            _ => self.steps.insert(index, step),
+        }
+    }
+
+    /// Takes out the step at `beat`, if it is on.
+    pub fn remove_step(&mut self, beat: f32) -> Option<Step> {
+        let position = Ticks::from_beats(beat);
+        let index = self.steps.iter().position(|step| step.position == position)?;
+        Some(self.steps.remove(index))
+    }
+
+    /// Switches on exactly the steps at `beats`, keeping the settings of
+    /// those already on.
+    pub fn set_beats(&mut self, beats: &[f32]) {
+        let positions: Vec<Ticks> = beats.iter().map(|beat| Ticks::from_beats(*beat)).collect();
+        self.steps.retain(|step| positions.contains(&step.position));
+        for beat in beats {
+            self.set_beat(*beat, true);
+        }
+    }
+
+    /// Whether loop position `position` is within the active range.
+    pub fn is_active_at(&self, position: f32) -> bool {
+        self.active_range.is_none_or(|[start, end]| position >= start && position < end)
+    }
+
+    /// Steps due in the scheduler tick of `tick` starting at loop position
+    /// `position` on pass `pass` that pass their "every Nth" condition and
+    /// probability roll, each with its delay into the tick in loop beats,
+    /// the step's offset included, so steps between ticks such as triplets
+    /// still play. Time-scaled patterns run on their own timeline,
+    /// continuing across passes; none are due outside the active range.
+    pub fn due_steps(&self, pass: u32, position: Ticks, tick: Ticks, loop_beats: u32) -> Vec<(Step, f32)> {
+        let loop_ticks = loop_beats as i64 * PPQN as i64;
+        if !self.is_active_at(position.beats()) || loop_ticks == 0 {
+            return Vec::new();
+        }
+        let speed = self.speed() as f64;
+        let elapsed = pass as i64 * loop_ticks + position.0 as i64;
+        let local = ((elapsed as f64 * speed).round() as i64).rem_euclid(loop_ticks);
+        let window = (tick.0 as f64 * speed).round() as i64;
+        self.steps
+            .iter()
+            .filter(|step| (0..loop_ticks).contains(&(step.position.0 as i64)))
+            .filter_map(|step| {
+                let ahead = (step.position.0 as i64 - local).rem_euclid(loop_ticks);
+                (ahead < window).then(|| (step, (ahead as f64 / speed / PPQN as f64) as f32))
+            })
+            .filter(|(step, _)| pass.is_multiple_of(step.every.max(1)))
+            .filter(|(step, _)| step.probability >= 1.0 || rand::thread_rng().gen::<f32>() < step.probability)
+            .map(|(step, delay)| (step.clone(), delay + step.offset.max(0.0) / self.speed()))
+            .collect()
+    }
+
+    /// Beats between the repeats of a ratcheted step.
+    pub fn ratchet_beats(&self, step: &Step) -> f32 {
+        STEP_BEATS / self.speed() / step.ratchet.max(1) as f32
+    }
+
+    /// Length in beats of the step's note at `bpm`.
+    pub fn note_beats(&self, step: &Step, bpm: u32) -> f32 {
+        let duration = step.duration.unwrap_or(self.duration);
+        match self.duration_unit {
+            DurationUnit::Beats => duration,
+            DurationUnit::Seconds => duration * bpm as f32 / 60.0,
+        }
+    }
+
+    /// Whether the step at `position` is in the accent list.
+    pub fn is_accented(&self, position: Ticks) -> bool {
+        self.accents.iter().any(|beat| Ticks::from_beats(*beat) == position)
+    }
+
+    /// Velocity of one trigger of a step, with the accent and jitter applied.
+    pub fn trigger_velocity(&self, step: &Step) -> f32 {
+        let mut velocity = step.velocity.unwrap_or(self.velocity);
+        if self.is_accented(step.position) {
+            velocity = (velocity + self.accent_boost).min(127.0);
+        }
+        if self.velocity_jitter <= 0.0 {
+            return velocity;
+        }
+        (velocity + rand::thread_rng().gen_range(-self.velocity_jitter..=self.velocity_jitter)).clamp(0.0, 127.0)
+    }
+
+    /// MIDI note a step plays, shifted by its pitch, the pattern's transpose
+    /// and the global `transpose`; None for patterns without a MIDI note.
+    pub fn note(&self, step: &Step, transpose: i8) -> Option<u8> {
+        self.midi_note.map(|note| {
+            (note as i32 + step.pitch as i32 + self.transpose as i32 + transpose as i32).clamp(0, 127) as u8
+        })
+    }
+
+    /// The time scale, with nonsensical values treated as normal speed.
+    pub fn speed(&self) -> f32 {
+        if self.time_scale > 0.0 { self.time_scale } else { 1.0 }
+    }
+
+    /// Whether the pattern should sound for the given bank, variation and
+    /// fill state; muted patterns never do.
+    pub fn is_enabled(&self, bank: &str, variation: u32, fill: bool) -> bool {
+        !self.muted
+            && self.bank.as_deref().is_none_or(|b| b == bank)
+            && self.variation.is_none_or(|v| v == variation)
+            && (!self.fill || fill)
+    }
+
+    /// The pattern's name, or its track's when it has none.
+    pub fn id(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| self.track_name())
+    }
+
+    /// Name of the track this pattern plays on, which is also its mixer channel.
+    pub fn track_name(&self) -> &str {
+        self.track
+            .as_deref()
+            .or(self.sound.as_deref())
+            .or(self.loop_name.as_deref())
+            .unwrap_or("midi")
+    }
+}
+
+fn default_gain() -> f32 {
+    1.0
+}
+
+/// What a track plays: a sample, a loop or a MIDI note.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Instrument {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midi_note: Option<u8>,
+}
+
+/// A named track owning several patterns (variations, fills, banks) that
+/// share its instrument, color and mixer settings.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Track {
+    pub name: String,
+    #[serde(default)]
+    pub instrument: Instrument,
+    /// Hex color such as "#ff8800" for the track's rows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default = "default_gain")]
+    pub gain: f32,
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+}
+
+impl Track {
+    /// The track's patterns tagged with its name, playing its instrument
+    /// unless a pattern picks its own.
+    pub fn flatten(&self) -> Vec<Pattern> {
+        self.patterns
+            .iter()
+            .map(|pattern| {
+                let mut pattern = pattern.clone();
+                pattern.track = Some(self.name.clone());
+                if pattern.sound.is_none() && pattern.loop_name.is_none() && pattern.midi_note.is_none() {
+                    pattern.sound = self.instrument.sound.clone();
+                    pattern.loop_name = self.instrument.loop_name.clone();
+                    pattern.midi_note = self.instrument.midi_note;
+                }
+                pattern
+            })
+            .collect()
+    }
+
+    /// Undoes `flatten`: takes the patterns on this track out of `patterns`,
+    /// dropping what they get from the track.
+    pub fn gather(&mut self, patterns: &mut Vec<Pattern>) {
+        let (mine, others) = std::mem::take(patterns).into_iter().partition(|p| p.track.as_deref() == Some(&self.name));
+        *patterns = others;
+        self.patterns = mine
+            .into_iter()
+            .map(|mut pattern: Pattern| {
+                pattern.track = None;
+                let instrument = Instrument {
+                    sound: pattern.sound.clone(),
+                    loop_name: pattern.loop_name.clone(),
+                    midi_note: pattern.midi_note,
+                };
+                if instrument == self.instrument {
+                    pattern.sound = None;
+                    pattern.loop_name = None;
+                    pattern.midi_note = None;
+                }
+                pattern
+            })
+            .collect();
+    }
 }
 
 pub struct PatternBuilder {
+    name: Option<String>,
     sound: Option<String>,
     loop_name: Option<String>,
     beats: Vec<f32>,
     midi_note: Option<u8>,
     velocity: f32,
     duration: f32,
+    pan: f32,
+    output: Option<String>,
+    variation: Option<u32>,
+    fill: bool,
+    bank: Option<String>,
+    chord: Option<(String, i8)>,
+    voicing: Voicing,
+}
+
+/// Position of the pattern called `name`, or else of the first one on the track `name`.
+pub fn pattern_index(patterns: &[Pattern], name: &str) -> Option<usize> {
+    let named = patterns.iter().position(|p| p.name.as_deref() == Some(name));
+    named.or_else(|| patterns.iter().position(|p| p.track_name() == name))
+}
+
+/// Track of the pattern called `name`, for mixer commands that take a
+/// pattern or a track; `name` itself when no pattern goes by it.
+pub fn track_of(patterns: &[Pattern], name: &str) -> String {
+    pattern_index(patterns, name).map_or(name, |index| patterns[index].track_name()).to_string()
+}
+
+/// Patterns added, removed and changed between two loads, matched by id;
+/// patterns sharing an id are matched in order.
+#[derive(Debug, Default, PartialEq)]
+pub struct PatternChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Changed patterns with what changed in each, like "added step at 3.5".
+    pub changed: Vec<(String, Vec<String>)>,
+}
+
+impl PatternChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for PatternChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let groups = [("added", &self.added), ("removed", &self.removed)];
+        let parts: Vec<String> = groups
+            .iter()
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(what, ids)| format!("{} {}", what, ids.join(", ")))
+            .chain(self.changed.iter().map(|(id, details)| format!("{}: {}", id, details.join(", "))))
+            .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// What differs between two versions of a pattern: steps added, removed
+/// or edited by position, and settings by name, like "velocity 100.0 -> 80.0".
+fn describe_changes(old: &Pattern, new: &Pattern) -> Vec<String> {
+    let mut details = Vec::new();
+    for step in new.steps.iter() {
+        match old.steps.iter().find(|old_step| old_step.position == step.position) {
+            None => details.push(format!("added step at {}", step.position)),
+            Some(old_step) if old_step != step => details.push(format!("changed step at {}", step.position)),
+            Some(_) => {}
+        }
+    }
+    for step in old.steps.iter().filter(|step| !new.steps.iter().any(|new_step| new_step.position == step.position)) {
+        details.push(format!("removed step at {}", step.position));
+    }
+    let settings = |pattern: &Pattern| match serde_json::to_value(pattern) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.remove("steps");
+            fields
+        }
+        _ => serde_json::Map::new(),
+    };
+    let (old_settings, new_settings) = (settings(old), settings(new));
+    for (name, value) in new_settings.iter() {
+        match old_settings.get(name) {
+            Some(old_value) if old_value == value => {}
+            Some(old_value) => details.push(format!("{} {} -> {}", name, old_value, value)),
+            None => details.push(format!("{} set to {}", name, value)),
+        }
+    }
+    for name in old_settings.keys().filter(|name| !new_settings.contains_key(*name)) {
+        details.push(format!("{} unset", name));
+    }
+    if details.is_empty() {
+        // Differences in what the files don't store, such as a step order
+        details.push("changed".to_string());
+    }
+    details
+}
+
+/// Keys patterns by id, numbering repeated ids: "midi", "midi#2", ...
+fn keyed(patterns: &[Pattern]) -> Vec<(String, &Pattern)> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    patterns
+        .iter()
+        .map(|pattern| {
+            let count = seen.entry(pattern.id()).or_default();
+            *count += 1;
+            let key = if *count == 1 { pattern.id().to_string() } else { format!("{}#{}", pattern.id(), count) };
+            (key, pattern)
+        })
+        .collect()
+}
+
+/// Compares two loads of the patterns by identity, ignoring their order.
+pub fn diff_patterns(old: &[Pattern], new: &[Pattern]) -> PatternChanges {
+    let old = keyed(old);
+    let new = keyed(new);
+    let mut changes = PatternChanges::default();
+    for (key, pattern) in new.iter() {
+        match old.iter().find(|(old_key, _)| old_key == key) {
+            Some((_, old_pattern)) if old_pattern != pattern => changes.changed.push((key.clone(), describe_changes(old_pattern, pattern))),
+            Some(_) => {}
+            None => changes.added.push(key.clone()),
+        }
+    }
+    changes.removed = old.iter().filter(|(key, _)| !new.iter().any(|(new_key, _)| new_key == key)).map(|(key, _)| key.clone()).collect();
+    changes
+}
+
+/// Copies of `beats` every `size` beats, `times` times:
+/// `repeat(&[0.0, 0.75], 2.0, 4)` gives `[0, 0.75, 2, 2.75, 4, 4.75, 6, 6.75]`.
+pub fn repeat(beats: &[f32], size: f32, times: u32) -> Vec<f32> {
+    (0..times).flat_map(|i| beats.iter().map(move |beat| beat + size * i as f32)).collect()
+}
+
+/// Sorted, de-duplicated bank names used by the given patterns.
+pub fn bank_names(patterns: &[Pattern]) -> Vec<String> {
+    let mut names: Vec<String> = patterns.iter().filter_map(|p| p.bank.clone()).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl Default for PatternBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PatternBuilder {
     pub fn new() -> Self {
         Self {
+            name: None,
             sound: None,
             loop_name: None,
             beats: vec![],
             midi_note: None,
-            velocity: 100.0,
-            duration: 0.25,
+            velocity: default_velocity(),
+            duration: default_duration(),
+            pan: 0.0,
+            output: None,
+            variation: None,
+            fill: false,
+            bank: None,
+            chord: None,
+            voicing: Voicing::default(),
         }
     }
 
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
     pub fn sound(mut self, sound: &str) -> Self {
         self.sound = Some(sound.to_string());
         self
@@ -46,6 +808,56 @@ impl PatternBuilder {
         self
     }
 
+    /// Repeats the beats so far every `bar_len` beats, `times` times in all.
+    pub fn repeat(mut self, bar_len: f32, times: u32) -> Self {
+        self.beats = repeat(&self.beats, bar_len, times);
+        self.tidy()
+    }
+
+    /// Rewrites every `n`th bar of the beats so far, such as the last bar
+    /// of each four with `every(4, |bar| bar.rotate(2))`. The transform
+    /// sees the bar's beats from 0.
+    pub fn every(mut self, n: u32, transform: impl Fn(PatternBuilder) -> PatternBuilder) -> Self {
+        let n = n.max(1);
+        let bars = (self.span() / BAR_BEATS) as u32;
+        for bar in (n - 1..bars).step_by(n as usize) {
+            let start = bar as f32 * BAR_BEATS;
+            let in_bar = |beat: &f32| *beat >= start && *beat < start + BAR_BEATS;
+            let beats = self.beats.iter().filter(|b| in_bar(b)).map(|b| b - start).collect();
+            let rewritten = transform(PatternBuilder::new().beats(beats)).beats;
+            self.beats.retain(|b| !in_bar(b));
+            self.beats.extend(rewritten.into_iter().filter(|b| *b >= 0.0 && *b < BAR_BEATS).map(|b| b + start));
+        }
+        self.tidy()
+    }
+
+    /// Shifts the beats by `steps` 16ths, wrapping around the bars they span.
+    pub fn rotate(mut self, steps: i32) -> Self {
+        let span = self.span();
+        let shift = steps as f32 * STEP_BEATS;
+        self.beats = self.beats.iter().map(|beat| (beat + shift).rem_euclid(span)).collect();
+        self.tidy()
+    }
+
+    /// Moves the beats later by `beats`, or earlier when negative, dropping
+    /// those moved before the start.
+    pub fn offset(mut self, beats: f32) -> Self {
+        self.beats = self.beats.iter().map(|beat| beat + beats).filter(|beat| *beat >= 0.0).collect();
+        self.tidy()
+    }
+
+    /// Whole bars covering the beats, at least one.
+    fn span(&self) -> f32 {
+        let last = self.beats.iter().copied().fold(0.0, f32::max);
+        ((last / BAR_BEATS).floor() + 1.0) * BAR_BEATS
+    }
+
+    fn tidy(mut self) -> Self {
+        self.beats.sort_by(f32::total_cmp);
+        self.beats.dedup();
+        self
+    }
+
     pub fn midi_note(mut self, note: u8) -> Self {
         self.midi_note = Some(note);
         self
@@ -61,14 +873,96 @@ impl PatternBuilder {
         self
     }
 
+    pub fn pan(mut self, pan: f32) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    pub fn output(mut self, output: &str) -> Self {
+        self.output = Some(output.to_string());
+        self
+    }
+
+    pub fn variation(mut self, variation: u32) -> Self {
+        self.variation = Some(variation);
+        self
+    }
+
+    pub fn fill(mut self, fill: bool) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    pub fn bank(mut self, bank: &str) -> Self {
+        self.bank = Some(bank.to_string());
+        self
+    }
+
+    /// Plays a chord such as "C#m" in `octave` instead of a single note;
+    /// see [`PatternBuilder::build_chord`].
+    pub fn chord(mut self, chord: &str, octave: i8) -> Self {
+        self.chord = Some((chord.to_string(), octave));
+        self
+    }
+
+    pub fn inversion(mut self, inversion: u32) -> Self {
+        self.voicing.inversion = inversion;
+        self
+    }
+
+    pub fn spread(mut self, spread: bool) -> Self {
+        self.voicing.spread = spread;
+        self
+    }
+
+    /// One pattern per note of the chord, sharing the beats and the other
+    /// settings; a name gets the note appended, as in "stabs.C#4".
+    pub fn build_chord(mut self) -> Result<Vec<Pattern>, String> {
+        let (name, octave) = self.chord.take().ok_or("No chord to build")?;
+        let notes = Chord::parse(&name, octave)?.notes(self.voicing);
+        let pattern = self.build();
+        Ok(notes
+            .into_iter()
+            .map(|note| Pattern {
+                name: pattern.name.as_ref().map(|name| format!("{}.{}", name, note_name(note))),
+                midi_note: Some(note),
+                ..pattern.clone()
+            })
+            .collect())
+    }
+
     pub fn build(self) -> Pattern {
+        // Steps are kept sorted by position with one step per position
+        let mut steps: Vec<Step> = self.beats.into_iter().map(Step::new).collect();
+        steps.sort_by_key(|step| step.position);
+        steps.dedup_by_key(|step| step.position);
         Pattern {
+            name: self.name,
+            track: None,
             sound: self.sound,
             loop_name: self.loop_name,
-            beats: self.beats,
             midi_note: self.midi_note,
             velocity: self.velocity,
+            velocities: None,
+            velocity_jitter: 0.0,
+            accents: Vec::new(),
+            accent_boost: default_accent_boost(),
             duration: self.duration,
+            duration_unit: DurationUnit::Beats,
+            pan: self.pan,
+            output: self.output,
+            transpose: 0,
+            variation: self.variation,
+            fill: self.fill,
+            muted: false,
+            bank: self.bank,
+            steps,
+            notation: None,
+            sequence: None,
+            preset: None,
+            time_scale: default_time_scale(),
+            active_range: None,
+            load_warnings: Vec::new(),
         }
     }
 }
@@ -0,0 +1,227 @@
+use crate::model::{Pattern, Step, Ticks, Velocities};
+
+/// Beats covered by one cycle of mini-notation: a bar in 4/4.
+const CYCLE_BEATS: f32 = 4.0;
+/// Beats per character of a step string.
+const STEP_BEATS: f32 = 0.25;
+/// Velocity boost for accented (`X`) steps.
+const ACCENT_GAIN: f32 = 1.3;
+
+/// Parsed mini-notation term.
+#[derive(Clone, Debug)]
+enum Node {
+    Rest,
+    Sound(String),
+    /// Sub-sequence squeezed into a single slot.
+    Group(Vec<Node>),
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            self.chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    /// Parses terms up to `closing` (or the end of input when None).
+    fn sequence(&mut self, closing: Option<char>) -> Result<Vec<Node>, String> {
+        let mut nodes: Vec<Node> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let atom = match self.chars.peek().copied() {
+                None if closing.is_none() => return Ok(nodes),
+                None => return Err("Unclosed '['".to_string()),
+                Some(c) if Some(c) == closing => {
+                    self.chars.next();
+                    return Ok(nodes);
+                }
+                Some('[') => {
+                    self.chars.next();
+                    Node::Group(self.sequence(Some(']'))?)
+                }
+                Some('~') => {
+                    self.chars.next();
+                    Node::Rest
+                }
+                Some('!') => {
+                    // A bare '!' repeats the previous term
+                    self.chars.next();
+                    nodes.last().cloned().ok_or("'!' with nothing to repeat")?
+                }
+                Some(c) if c.is_alphanumeric() || "_-:#./".contains(c) => {
+                    let mut name = String::new();
+                    while let Some(c) = self.chars.peek().filter(|c| c.is_alphanumeric() || "_-:#./".contains(**c)) {
+                        name.push(*c);
+                        self.chars.next();
+                    }
+                    Node::Sound(name)
+                }
+                Some(c) => return Err(format!("Unexpected '{}'", c)),
+            };
+
+            // Postfix operators: `*n` plays the term n times in its slot, `!n` takes n slots
+            match self.chars.peek() {
+                Some('*') => {
+                    self.chars.next();
+                    let times = self.number().ok_or("Expected a number after '*'")?;
+                    nodes.push(Node::Group(vec![atom; times.max(1)]));
+                }
+                Some('!') => {
+                    self.chars.next();
+                    match self.number() {
+                        Some(times) => nodes.extend(std::iter::repeat_n(atom, times.max(1))),
+                        None => nodes.extend([atom.clone(), atom]),
+                    }
+                }
+                _ => nodes.push(atom),
+            }
+        }
+    }
+}
+
+fn layout(nodes: &[Node], start: f32, span: f32, events: &mut Vec<(String, f32)>) {
+    let slot = span / nodes.len().max(1) as f32;
+    for (index, node) in nodes.iter().enumerate() {
+        let beat = start + index as f32 * slot;
+        match node {
+            Node::Rest => {}
+            Node::Sound(name) => events.push((name.clone(), beat)),
+            Node::Group(children) => layout(children, beat, slot, events),
+        }
+    }
+}
+
+/// Parses Tidal-style mini-notation such as `"bd ~ ~ bd sn ~ [hh hh] ~"` into
+/// (sound, beat) events over one cycle. Supports rests `~`, groups `[..]`,
+/// `*n` to repeat within a step and `!`/`!n` to replicate a step.
+pub fn parse(notation: &str) -> Result<Vec<(String, f32)>, String> {
+    let mut parser = Parser { chars: notation.chars().peekable() };
+    let nodes = parser.sequence(None)?;
+    let mut events = Vec::new();
+    layout(&nodes, 0.0, CYCLE_BEATS, &mut events);
+    Ok(events)
+}
+
+/// Parses a drum-machine step string into (beat, accented) hits: `x` plays,
+/// `X` plays accented, `.` or `-` rests; spaces and `|` are only separators.
+pub fn parse_sequence(sequence: &str) -> Result<Vec<(f32, bool)>, String> {
+    let mut hits = Vec::new();
+    for (step, c) in sequence.chars().filter(|c| !c.is_whitespace() && *c != '|').enumerate() {
+        match c {
+            'x' => hits.push((step as f32 * STEP_BEATS, false)),
+            'X' => hits.push((step as f32 * STEP_BEATS, true)),
+            '.' | '-' => {}
+            _ => return Err(format!("Unexpected '{}' in step string", c)),
+        }
+    }
+    Ok(hits)
+}
+
+/// Fills in the beats of a pattern given as a step string, repeating it
+/// across the loop; accents become per-step velocity overrides.
+fn expand_sequence(mut pattern: Pattern, sequence: &str, loop_beats: u32) -> Result<Pattern, String> {
+    let hits = parse_sequence(sequence).map_err(|e| format!("In \"{}\": {}", sequence, e))?;
+    let length = sequence.chars().filter(|c| !c.is_whitespace() && *c != '|').count() as f32 * STEP_BEATS;
+    let accent = (pattern.velocity * ACCENT_GAIN).min(127.0);
+    let mut offset = 0.0;
+    while length > 0.0 && offset < loop_beats as f32 {
+        for (beat, accented) in hits.iter() {
+            let beat = offset + beat;
+            if beat >= loop_beats as f32 {
+                break;
+            }
+            pattern.set_beat(beat, true);
+            if *accented {
+                let mut step = pattern.step_at(beat);
+                step.velocity = Some(accent);
+                pattern.set_step(step);
+            }
+        }
+        offset += length;
+    }
+    pattern.sequence = None;
+    Ok(pattern)
+}
+
+/// Sets step velocities from the pattern's velocity list or accent markers.
+fn apply_velocities(mut pattern: Pattern) -> Pattern {
+    let Some(velocities) = pattern.velocities.take() else {
+        return pattern;
+    };
+    let levels: Vec<Option<f32>> = match velocities {
+        Velocities::Levels(levels) => levels.into_iter().map(|level| Some(level.clamp(0.0, 127.0))).collect(),
+        Velocities::Accents(accents) => accents
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '|')
+            .map(|c| matches!(c, 'X' | '>').then_some((pattern.velocity * ACCENT_GAIN).min(127.0)))
+            .collect(),
+    };
+    if levels.is_empty() {
+        return pattern;
+    }
+    for (step, level) in pattern.steps.iter_mut().zip(levels.iter().cycle()) {
+        if step.velocity.is_none() {
+            step.velocity = *level;
+        }
+    }
+    pattern
+}
+
+/// Expands the compact row notations: step strings fill in the pattern's
+/// beats, and mini-notation becomes one pattern per sound with its cycle
+/// repeated across the loop. Other fields are inherited. Velocity lists
+/// are then spread over each pattern's steps.
+pub fn expand(patterns: Vec<Pattern>, loop_beats: u32) -> Result<Vec<Pattern>, String> {
+    let mut expanded = Vec::new();
+    for pattern in patterns {
+        let pattern = match pattern.sequence.clone() {
+            Some(sequence) => expand_sequence(pattern, &sequence, loop_beats)?,
+            None => pattern,
+        };
+        let Some(notation) = pattern.notation.clone() else {
+            expanded.push(pattern);
+            continue;
+        };
+        let events = parse(&notation).map_err(|e| format!("In \"{}\": {}", notation, e))?;
+        let mut sounds: Vec<&String> = events.iter().map(|(sound, _)| sound).collect();
+        sounds.sort();
+        sounds.dedup();
+        let named_parts = sounds.len() > 1;
+        for sound in sounds {
+            let mut beats: Vec<f32> = Vec::new();
+            let mut cycle_start = 0.0;
+            while cycle_start < loop_beats as f32 {
+                beats.extend(
+                    events
+                        .iter()
+                        .filter(|(name, beat)| name == sound && cycle_start + beat < loop_beats as f32)
+                        .map(|(_, beat)| cycle_start + beat),
+                );
+                cycle_start += CYCLE_BEATS;
+            }
+            beats.sort_by(|a, b| a.total_cmp(b));
+            beats.dedup_by_key(|beat| Ticks::from_beats(*beat));
+            expanded.push(Pattern {
+                name: pattern.name.as_ref().map(|name| if named_parts { format!("{}.{}", name, sound) } else { name.clone() }),
+                sound: Some(sound.clone()),
+                steps: beats.into_iter().map(Step::new).collect(),
+                notation: None,
+                ..pattern.clone()
+            });
+        }
+    }
+    Ok(expanded.into_iter().map(apply_velocities).collect())
+}
@@ -0,0 +1,177 @@
+use std::{
+    net::UdpSocket,
+    path::PathBuf,
+    sync::{mpsc::Receiver, Arc, RwLock},
+    thread,
+};
+
+use arc_swap::ArcSwap;
+use rosc::{decoder, encoder, OscMessage, OscPacket, OscType};
+
+use crate::audio::OutputStreamHandle;
+use crate::error::{self, Error};
+use crate::mixer::Mixer;
+use crate::model::{track_of, Pattern};
+use crate::remote;
+use crate::transport::Transport;
+use crate::{play_sound, EngineEvent, SoundBank};
+
+/// Largest OSC packet accepted, plenty for control messages.
+const MAX_PACKET: usize = 1536;
+
+fn as_f32(arg: &OscType) -> Option<f32> {
+    match arg {
+        OscType::Int(i) => Some(*i as f32),
+        OscType::Long(i) => Some(*i as f32),
+        OscType::Float(f) => Some(*f),
+        OscType::Double(d) => Some(*d as f32),
+        OscType::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        OscType::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_str(arg: &OscType) -> Option<&str> {
+    match arg {
+        OscType::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// The messages of a packet, flattening bundles; timetags are ignored and
+/// bundled messages run immediately.
+fn messages(packet: OscPacket) -> Vec<OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(messages).collect(),
+    }
+}
+
+/// Sends beat ticks and triggered events to an external OSC target, such
+/// as a visualizer: `/beat <beat> <bar>` on every beat and
+/// `/trigger/<track> <velocity>` when a step plays.
+pub struct OscSender {
+    socket: UdpSocket,
+}
+
+impl OscSender {
+    /// Connects to a `host:port` target.
+    pub fn new(target: &str) -> error::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))
+            .and_then(|socket| socket.connect(target).map(|_| socket))
+            .map_err(|source| Error::Network { address: target.to_string(), source })?;
+        log!("Sending OSC events to {}", target);
+        Ok(Self { socket })
+    }
+
+    fn send(&self, addr: String, args: Vec<OscType>) {
+        match encoder::encode(&OscPacket::Message(OscMessage { addr, args })) {
+            // Nobody listening is fine; events are fire and forget
+            Ok(packet) => {
+                let _ = self.socket.send(&packet);
+            }
+            Err(e) => log_warn!("Could not encode an OSC event: {}", e),
+        }
+    }
+
+    pub fn beat(&self, beat: f32, bar: u32) {
+        self.send("/beat".to_string(), vec![OscType::Float(beat), OscType::Int(bar as i32)]);
+    }
+
+    pub fn trigger(&self, track: &str, velocity: f32) {
+        self.send(format!("/trigger/{}", track), vec![OscType::Float(velocity)]);
+    }
+
+    /// Sends the beats and triggers of the engine events in the background.
+    pub fn forward(self, events: Receiver<EngineEvent>) {
+        thread::spawn(move || {
+            for event in events {
+                match event {
+                    EngineEvent::Beat { beat, bar } => self.beat(beat, bar),
+                    EngineEvent::Trigger { track, velocity, .. } => self.trigger(&track, velocity),
+                    EngineEvent::Stopped => {}
+                }
+            }
+        });
+    }
+}
+
+/// Engine parts the OSC server controls.
+pub struct OscControl {
+    pub transport: Arc<Transport>,
+    pub mixer: Arc<RwLock<Mixer>>,
+    /// Looked up to mute patterns by name.
+    pub patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    pub sound_bank: Arc<SoundBank>,
+    pub stream_handle: Arc<OutputStreamHandle>,
+    /// Patterns file the watcher thread reloads from.
+    pub patterns_path: Arc<RwLock<String>>,
+    /// Directory `/pattern/load` is limited to, the one holding the config.
+    pub project_dir: PathBuf,
+    /// Loop length `/pattern/load` checks new pattern files against.
+    pub loop_beats: u32,
+}
+
+impl OscControl {
+    /// Handles one message. Supported addresses:
+    /// `/bpm <n>`, `/mute/<track or pattern name> [0|1]` (toggles without an argument),
+    /// `/trigger/<sound> [velocity]` and `/pattern/load <path>`.
+    fn handle(&self, address: &str, args: &[OscType]) -> Result<(), String> {
+        let parts: Vec<&str> = address.trim_start_matches('/').splitn(2, '/').collect();
+        match parts.as_slice() {
+            ["bpm"] => {
+                let bpm = args.first().and_then(as_f32).ok_or("/bpm expects a number")?;
+                self.transport.set_bpm(bpm.round().clamp(20.0, 300.0) as u32);
+            }
+            ["mute", name] => {
+                let track = track_of(&self.patterns.load(), name);
+                let mut mixer = self.mixer.write().unwrap();
+                let index = mixer.channel_index(&track).ok_or(format!("Unknown track '{}'", track))?;
+                match args.first().and_then(as_f32) {
+                    Some(value) => mixer.set_mute(index, value != 0.0),
+                    None => mixer.toggle_mute(index),
+                }
+            }
+            ["trigger", sound] => {
+                let velocity = args.first().and_then(as_f32).unwrap_or(100.0);
+                let (pan, meter) = {
+                    let mut mixer = self.mixer.write().unwrap();
+                    (mixer.pan(sound), mixer.meter(sound))
+                };
+                play_sound(sound, velocity, pan, Some(meter), &self.sound_bank, &self.stream_handle);
+            }
+            ["pattern", "load"] => {
+                let path = args.first().and_then(as_str).ok_or("/pattern/load expects a path")?;
+                remote::check_pattern_file(&self.project_dir, path, self.loop_beats).map_err(|e| e.to_string())?;
+                *self.patterns_path.write().unwrap() = path.to_string();
+                log!("Patterns file switched to {}", path);
+            }
+            _ => return Err(format!("Unknown OSC address {}", address)),
+        }
+        Ok(())
+    }
+}
+
+/// Listens for OSC control messages on the given address and UDP port in
+/// the background.
+pub fn spawn_server(host: &str, port: u16, control: OscControl) -> error::Result<()> {
+    let socket =
+        UdpSocket::bind((host, port)).map_err(|source| Error::Network { address: format!("{}:{}", host, port), source })?;
+    log!("Listening for OSC on {}:{}", host, port);
+    thread::spawn(move || {
+        let mut buf = [0u8; MAX_PACKET];
+        while let Ok((len, _)) = socket.recv_from(&mut buf) {
+            match decoder::decode_udp(&buf[..len]) {
+                Ok((_, packet)) => {
+                    for message in messages(packet) {
+                        if let Err(e) = control.handle(&message.addr, &message.args) {
+                            log_warn!("{}", e);
+                        }
+                    }
+                }
+                Err(e) => log_warn!("Bad OSC packet: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
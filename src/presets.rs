@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+use crate::model::{Pattern, PatternBuilder};
+
+/// A named rhythm: one step string per part, keyed by the sound label the
+/// part plays unless the pattern picks another.
+pub type Preset = BTreeMap<String, String>;
+
+/// Presets shipped with the sequencer, as (name, [(part, steps)]).
+const BUILTIN: &[(&str, &[(&str, &str)])] = &[
+    ("four_on_the_floor", &[("bd", "x..."), ("hh", "..x.")]),
+    ("backbeat", &[("bd", "x......."), ("sd", "....x..."), ("hh", "x.x.")]),
+    ("breakbeat", &[("bd", "x.x.......x....."), ("sd", "....x..x.x..x..x"), ("hh", "x.x.")]),
+    ("tresillo", &[("bd", "x..x..x.")]),
+];
+
+/// Built-in presets merged with user-defined ones, which win on equal names.
+pub fn library(user: &BTreeMap<String, Preset>) -> BTreeMap<String, Preset> {
+    let mut library: BTreeMap<String, Preset> = BUILTIN
+        .iter()
+        .map(|(name, parts)| (name.to_string(), parts.iter().map(|(part, steps)| (part.to_string(), steps.to_string())).collect()))
+        .collect();
+    library.extend(user.iter().map(|(name, preset)| (name.clone(), preset.clone())));
+    library
+}
+
+/// Starting-point patterns for a preset, one per part, as step strings.
+pub fn patterns(preset: &Preset) -> Vec<Pattern> {
+    preset
+        .iter()
+        .map(|(part, steps)| Pattern { sequence: Some(steps.clone()), ..PatternBuilder::new().sound(part).build() })
+        .collect()
+}
+
+/// Replaces patterns referencing a preset with its parts as step strings,
+/// to be filled in by the notation expansion. Other fields are inherited;
+/// a pattern that sets its own sound, loop or note must pick one part.
+pub fn expand(patterns: Vec<Pattern>, user: &BTreeMap<String, Preset>) -> Result<Vec<Pattern>, String> {
+    let library = library(user);
+    let mut expanded = Vec::new();
+    for pattern in patterns {
+        let Some(reference) = pattern.preset.clone() else {
+            expanded.push(pattern);
+            continue;
+        };
+        let (name, part) = match reference.split_once(':') {
+            Some((name, part)) => (name, Some(part)),
+            None => (reference.as_str(), None),
+        };
+        let preset = library.get(name).ok_or(format!("Unknown preset '{}'", name))?;
+        let parts: Vec<(&String, &String)> = match part {
+            Some(part) => vec![preset.get_key_value(part).ok_or(format!("Preset '{}' has no part '{}'", name, part))?],
+            None => preset.iter().collect(),
+        };
+        let has_instrument = pattern.sound.is_some() || pattern.loop_name.is_some() || pattern.midi_note.is_some();
+        if has_instrument && parts.len() > 1 {
+            return Err(format!("Preset '{}' has several parts; pick one as '{}:<part>'", name, name));
+        }
+        let named_parts = parts.len() > 1;
+        for (part, steps) in parts {
+            expanded.push(Pattern {
+                name: pattern.name.as_ref().map(|name| if named_parts { format!("{}.{}", name, part) } else { name.clone() }),
+                sound: if has_instrument { pattern.sound.clone() } else { Some(part.clone()) },
+                sequence: Some(steps.clone()),
+                preset: None,
+                ..pattern.clone()
+            });
+        }
+    }
+    Ok(expanded)
+}
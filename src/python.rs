@@ -0,0 +1,420 @@
+//! Python module for scripting the sequencer from notebooks while it plays:
+//! build patterns with the beat generators, hand them to a running engine
+//! and steer the transport and mixer. Build and install it into the active
+//! virtualenv with `maturin develop`.
+//!
+//! ```python
+//! import four_on_the_floor as fotf
+//!
+//! engine = fotf.Engine("config.json", bpm=124)
+//! engine.set_patterns([
+//!     fotf.Pattern("bd", fotf.repeat([0.0, 2.0, 2.75], 4, 2)),
+//!     fotf.Pattern("hh", fotf.euclid(5, 16), velocity=40.0),
+//! ])
+//! engine.play()
+//! ```
+
+// The pymethods expansion trips this on every PyResult return
+#![allow(clippy::useless_conversion)]
+
+use std::collections::BTreeMap;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::config::{self, Config};
+use crate::model::{self, track_of, Pattern, PatternBuilder};
+use crate::scene::Scene;
+use crate::{formats, notation, presets, Engine, STEP_BEATS};
+
+fn runtime_error(e: impl ToString) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// One row of the pattern file; notation, step strings and presets are
+/// expanded when the patterns are handed to the engine.
+#[pyclass(name = "Pattern", module = "four_on_the_floor")]
+#[derive(Clone)]
+pub struct PyPattern {
+    inner: Pattern,
+}
+
+#[pymethods]
+impl PyPattern {
+    #[new]
+    #[pyo3(signature = (
+        sound=None, beats=Vec::new(), *, name=None, midi_note=None, loop_name=None, velocity=100.0, velocity_jitter=0.0,
+        accents=Vec::new(), accent_boost=20.0, duration=0.25, pan=0.0, output=None, transpose=0, track=None, bank=None,
+        variation=None, fill=false, notation=None, sequence=None, preset=None, time_scale=1.0
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        sound: Option<String>,
+        beats: Vec<f32>,
+        name: Option<String>,
+        midi_note: Option<u8>,
+        loop_name: Option<String>,
+        velocity: f32,
+        velocity_jitter: f32,
+        accents: Vec<f32>,
+        accent_boost: f32,
+        duration: f32,
+        pan: f32,
+        output: Option<String>,
+        transpose: i8,
+        track: Option<String>,
+        bank: Option<String>,
+        variation: Option<u32>,
+        fill: bool,
+        notation: Option<String>,
+        sequence: Option<String>,
+        preset: Option<String>,
+        time_scale: f32,
+    ) -> Self {
+        let mut beats = beats;
+        beats.sort_by(f32::total_cmp);
+        beats.dedup();
+        let pattern = PatternBuilder::new().beats(beats).velocity(velocity).duration(duration).pan(pan).fill(fill).build();
+        PyPattern {
+            inner: Pattern {
+                name,
+                velocity_jitter,
+                accents,
+                accent_boost,
+                output,
+                transpose,
+                track,
+                sound,
+                loop_name,
+                midi_note,
+                variation,
+                bank,
+                notation,
+                sequence,
+                preset,
+                time_scale,
+                ..pattern
+            },
+        }
+    }
+
+    #[getter]
+    fn sound(&self) -> Option<String> {
+        self.inner.sound.clone()
+    }
+
+    #[setter]
+    fn set_sound(&mut self, sound: Option<String>) {
+        self.inner.sound = sound;
+    }
+
+    #[getter]
+    fn beats(&self) -> Vec<f32> {
+        self.inner.beats().collect()
+    }
+
+    #[setter]
+    fn set_beats(&mut self, beats: Vec<f32>) {
+        self.inner.steps.clear();
+        for beat in beats {
+            self.inner.set_beat(beat, true);
+        }
+    }
+
+    #[getter]
+    fn midi_note(&self) -> Option<u8> {
+        self.inner.midi_note
+    }
+
+    #[setter]
+    fn set_midi_note(&mut self, midi_note: Option<u8>) {
+        self.inner.midi_note = midi_note;
+    }
+
+    #[getter]
+    fn transpose(&self) -> i8 {
+        self.inner.transpose
+    }
+
+    #[setter]
+    fn set_transpose(&mut self, transpose: i8) {
+        self.inner.transpose = transpose;
+    }
+
+    #[getter]
+    fn velocity(&self) -> f32 {
+        self.inner.velocity
+    }
+
+    #[setter]
+    fn set_velocity(&mut self, velocity: f32) {
+        self.inner.velocity = velocity;
+    }
+
+    #[getter]
+    fn velocity_jitter(&self) -> f32 {
+        self.inner.velocity_jitter
+    }
+
+    #[setter]
+    fn set_velocity_jitter(&mut self, velocity_jitter: f32) {
+        self.inner.velocity_jitter = velocity_jitter;
+    }
+
+    #[getter]
+    fn accents(&self) -> Vec<f32> {
+        self.inner.accents.clone()
+    }
+
+    #[setter]
+    fn set_accents(&mut self, accents: Vec<f32>) {
+        self.inner.accents = accents;
+    }
+
+    #[getter]
+    fn duration(&self) -> f32 {
+        self.inner.duration
+    }
+
+    #[setter]
+    fn set_duration(&mut self, duration: f32) {
+        self.inner.duration = duration;
+    }
+
+    #[getter]
+    fn name(&self) -> Option<String> {
+        self.inner.name.clone()
+    }
+
+    #[setter]
+    fn set_name(&mut self, name: Option<String>) {
+        self.inner.name = name;
+    }
+
+    #[getter]
+    fn track(&self) -> String {
+        self.inner.track_name().to_string()
+    }
+
+    #[setter]
+    fn set_track(&mut self, track: Option<String>) {
+        self.inner.track = track;
+    }
+
+    /// Switches the step at `beat` on or off.
+    #[pyo3(signature = (beat, on=true))]
+    fn set_beat(&mut self, beat: f32, on: bool) {
+        self.inner.set_beat(beat, on);
+    }
+
+    /// The pattern as a row of the JSON pattern file.
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner).map_err(runtime_error)
+    }
+
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        let inner = serde_json::from_str(json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyPattern { inner })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Pattern({})", serde_json::to_string(&self.inner).unwrap_or_default())
+    }
+}
+
+/// Replicates `beats` every `size` beats, `times` times:
+/// `repeat([0, 0.75], 2, 4)` gives `[0, 0.75, 2, 2.75, 4, 4.75, 6, 6.75]`.
+#[pyfunction]
+#[pyo3(signature = (beats, size=4.0, times=2))]
+fn repeat(beats: Vec<f32>, size: f32, times: u32) -> Vec<f32> {
+    model::repeat(&beats, size, times)
+}
+
+/// Spreads `hits` as evenly as possible over `steps` 16ths, rotated by `rotate` steps.
+#[pyfunction]
+#[pyo3(signature = (hits, steps, rotate=0))]
+fn euclid(hits: u32, steps: u32, rotate: u32) -> Vec<f32> {
+    if steps == 0 {
+        return Vec::new();
+    }
+    let hits = hits.min(steps);
+    (0..steps)
+        .filter(|step| ((step + rotate) * hits) % steps < hits)
+        .map(|step| step as f32 * STEP_BEATS)
+        .collect()
+}
+
+/// Beats of a drum-machine step string such as `"x...x..x"`, one 16th per character.
+#[pyfunction]
+fn steps(sequence: &str) -> PyResult<Vec<f32>> {
+    let hits = notation::parse_sequence(sequence).map_err(PyValueError::new_err)?;
+    Ok(hits.into_iter().map(|(beat, _)| beat).collect())
+}
+
+/// Reads the patterns of a pattern file, in any of its formats.
+#[pyfunction]
+#[pyo3(signature = (path, loop_beats=8))]
+fn load_patterns(path: &str, loop_beats: u32) -> PyResult<Vec<PyPattern>> {
+    let content = std::fs::read_to_string(path).map_err(runtime_error)?;
+    let patterns = formats::parse_patterns(path, &content, loop_beats).map_err(runtime_error)?;
+    Ok(patterns.into_iter().map(|inner| PyPattern { inner }).collect())
+}
+
+/// The sequencer engine on the configured audio device and MIDI port.
+/// Playback runs on its own threads, so the interpreter stays free to
+/// change patterns between loop passes.
+#[pyclass(name = "Engine", module = "four_on_the_floor", unsendable)]
+pub struct PyEngine {
+    engine: Engine,
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    #[pyo3(signature = (config="config.json", bpm=120, midi_only=false))]
+    fn new(config: &str, bpm: u32, midi_only: bool) -> PyResult<Self> {
+        let config: Config = config::read_config(config).map_err(runtime_error)?;
+        let engine = if midi_only { Engine::midi_only(&config, bpm) } else { Engine::new(&config, bpm) };
+        Ok(PyEngine { engine: engine.map_err(runtime_error)? })
+    }
+
+    /// Starts looping the patterns in the background.
+    fn play(&mut self) {
+        self.engine.play();
+    }
+
+    /// Stops playback and waits for it to end.
+    fn stop(&mut self) {
+        self.engine.stop();
+    }
+
+    #[getter]
+    fn is_playing(&self) -> bool {
+        self.engine.is_playing()
+    }
+
+    /// Replaces the patterns from the next loop pass on.
+    fn set_patterns(&self, patterns: Vec<PyPattern>) -> PyResult<()> {
+        let patterns = patterns.into_iter().map(|p| p.inner).collect();
+        let patterns = presets::expand(patterns, &BTreeMap::new()).map_err(PyValueError::new_err)?;
+        let patterns = notation::expand(patterns, self.engine.loop_beats).map_err(PyValueError::new_err)?;
+        self.engine.set_patterns(patterns);
+        Ok(())
+    }
+
+    /// The patterns playing, as expanded.
+    fn patterns(&self) -> Vec<PyPattern> {
+        self.engine.patterns.load().iter().cloned().map(|inner| PyPattern { inner }).collect()
+    }
+
+    #[getter]
+    fn bpm(&self) -> u32 {
+        self.engine.transport.bpm()
+    }
+
+    #[setter]
+    fn set_bpm(&self, bpm: u32) {
+        self.engine.transport.set_bpm(bpm);
+    }
+
+    #[getter]
+    fn transpose(&self) -> i8 {
+        self.engine.transport.transpose()
+    }
+
+    #[setter]
+    fn set_transpose(&self, semitones: i8) {
+        self.engine.transport.set_transpose(semitones);
+    }
+
+    #[getter]
+    fn loop_beats(&self) -> u32 {
+        self.engine.loop_beats
+    }
+
+    /// Beat within the loop the scheduler is at.
+    #[getter]
+    fn current_beat(&self) -> f32 {
+        *self.engine.current_beat.read().unwrap()
+    }
+
+    fn set_variation(&self, variation: u32) {
+        self.engine.transport.set_variation(variation);
+    }
+
+    /// Plays the fill patterns on the next pass.
+    fn queue_fill(&self) {
+        self.engine.transport.queue_fill();
+    }
+
+    /// Switches to the pattern bank at the next pass.
+    fn queue_bank(&self, bank: &str) {
+        self.engine.transport.queue_bank(bank);
+    }
+
+    /// Launches the named scene at the next bar.
+    fn launch_scene(&self, name: &str) -> PyResult<()> {
+        self.engine.transport.queue_scene(name).map_err(PyValueError::new_err)
+    }
+
+    /// Saves the bank and mixer as they are as a scene, crossfading its
+    /// loops over `crossfade` beats when launched.
+    #[pyo3(signature = (name, crossfade=0.0))]
+    fn capture_scene(&self, name: &str, crossfade: f32) {
+        let mixer = self.engine.mixer.read().unwrap();
+        let scene = Scene { crossfade, ..Scene::capture(name, &self.engine.transport.active_bank(), &mixer) };
+        let mut scenes = self.engine.transport.scenes.write().unwrap();
+        scenes.retain(|s| s.name != name);
+        scenes.push(scene);
+    }
+
+    fn scenes(&self) -> Vec<String> {
+        self.engine.transport.scenes.read().unwrap().iter().map(|s| s.name.clone()).collect()
+    }
+
+    /// Mutes a track, given by its name or the name of one of its patterns.
+    #[pyo3(signature = (track, mute=true))]
+    fn mute(&self, track: &str, mute: bool) -> PyResult<()> {
+        let track = track_of(&self.engine.patterns.load(), track);
+        let track = track.as_str();
+        let mut mixer = self.engine.mixer.write().unwrap();
+        mixer.ensure_channels([track]);
+        let index = mixer.channel_index(track).ok_or_else(|| PyValueError::new_err(format!("No track '{}'", track)))?;
+        mixer.set_mute(index, mute);
+        Ok(())
+    }
+
+    /// Loads the samples and loops in the given PATH-style directory lists;
+    /// they replace the bank contents from the next pass. Raises, replacing
+    /// nothing, when either fails to load.
+    fn load_banks(&self, sample_dirs: &str, loop_dirs: &str) -> PyResult<()> {
+        self.engine.load_banks(sample_dirs, loop_dirs).map_err(runtime_error)
+    }
+
+    fn sounds(&self) -> Vec<String> {
+        self.engine.sound_bank.labels()
+    }
+
+    fn loops(&self) -> Vec<String> {
+        self.engine.loop_bank.labels()
+    }
+}
+
+impl Drop for PyEngine {
+    // The playback thread would outlive a collected engine otherwise
+    fn drop(&mut self) {
+        self.engine.stop();
+    }
+}
+
+#[pymodule]
+fn four_on_the_floor(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPattern>()?;
+    m.add_class::<PyEngine>()?;
+    m.add_function(wrap_pyfunction!(repeat, m)?)?;
+    m.add_function(wrap_pyfunction!(euclid, m)?)?;
+    m.add_function(wrap_pyfunction!(steps, m)?)?;
+    m.add_function(wrap_pyfunction!(load_patterns, m)?)?;
+    Ok(())
+}
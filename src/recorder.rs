@@ -0,0 +1,60 @@
+use std::sync::{Arc, Mutex};
+
+use crate::error;
+use crate::midi_io::MidiIn;
+
+/// MIDI note of the first pad; following notes map to the following rows.
+const PAD_BASE_NOTE: u8 = 36;
+
+/// A pad hit: grid row and MIDI-style velocity.
+pub type PadHit = (usize, f32);
+
+/// Collects pad hits from the MIDI input and tracks whether record is armed.
+pub struct Recorder {
+    armed: bool,
+    midi_hits: Arc<Mutex<Vec<PadHit>>>,
+    _midi_in: Option<MidiIn>,
+}
+
+impl Recorder {
+    pub fn new(midi_input_port: Option<&str>) -> Self {
+        let midi_hits = Arc::new(Mutex::new(Vec::new()));
+        let midi_in = midi_input_port.and_then(|port| match connect(port, Arc::clone(&midi_hits)) {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                log_error!("Failed to open MIDI input: {}", e);
+                None
+            }
+        });
+        Self {
+            armed: false,
+            midi_hits,
+            _midi_in: midi_in,
+        }
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn set_armed(&mut self, armed: bool) {
+        self.armed = armed;
+    }
+
+    /// Drains the hits received on the MIDI input since the last call.
+    pub fn take_midi_hits(&self) -> Vec<PadHit> {
+        std::mem::take(&mut *self.midi_hits.lock().unwrap())
+    }
+}
+
+fn connect(port_name: &str, hits: Arc<Mutex<Vec<PadHit>>>) -> error::Result<MidiIn> {
+    MidiIn::listen(port_name, move |message| {
+        // Note On with non-zero velocity, any channel
+        if let [status, note, velocity] = message {
+            if status & 0xF0 == 0x90 && *velocity > 0 && *note >= PAD_BASE_NOTE {
+                let row = (note - PAD_BASE_NOTE) as usize;
+                hits.lock().unwrap().push((row, *velocity as f32));
+            }
+        }
+    })
+}
@@ -0,0 +1,329 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+use crate::error::{self, Error};
+use crate::formats;
+use crate::mixer::Mixer;
+use crate::model::{pattern_index, track_of, Pattern};
+use crate::session::Session;
+use crate::transport::Transport;
+use crate::edit_patterns;
+
+/// How often WebSocket clients are sent the current state.
+const PUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// Largest request body or WebSocket message accepted; commands are tiny.
+const MAX_MESSAGE: u64 = 64 * 1024;
+/// Fixed key suffix from RFC 6455 used in the handshake.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Close status for messages over `MAX_MESSAGE` (RFC 6455 7.4.1).
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// Checks a patterns file a client asked to switch to: it has to be inside
+/// `project_dir` and readable as patterns by the watcher. Every failure
+/// gives the same error, so clients can't probe or read other files.
+pub fn check_pattern_file(project_dir: &Path, path: &str, loop_beats: u32) -> error::Result<()> {
+    let rejected = || Error::PatternFileRejected { path: path.to_string() };
+    let dir = if project_dir.as_os_str().is_empty() { Path::new(".") } else { project_dir };
+    let dir = dir.canonicalize().map_err(|_| rejected())?;
+    let file = Path::new(path).canonicalize().map_err(|_| rejected())?;
+    if !file.starts_with(&dir) {
+        return Err(rejected());
+    }
+    let content = fs::read_to_string(&file).map_err(|_| rejected())?;
+    formats::parse_patterns(path, &content, loop_beats).map_err(|_| rejected())?;
+    Ok(())
+}
+
+/// Commands accepted as JSON, e.g. `{"cmd": "bpm", "value": 128}`, posted
+/// to `/command` or sent as WebSocket text messages.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum RemoteCommand {
+    Bpm { value: u32 },
+    ToggleStep { track: String, beat: f32 },
+    Mute { track: String },
+    Load { path: String },
+}
+
+/// Engine state the remote API reads and controls.
+pub struct RemoteControl {
+    pub patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    pub current_beat: Arc<RwLock<f32>>,
+    pub transport: Arc<Transport>,
+    pub mixer: Arc<RwLock<Mixer>>,
+    pub session: Arc<RwLock<Session>>,
+    pub patterns_path: Arc<RwLock<String>>,
+    /// Directory `load` is limited to, the one holding the config.
+    pub project_dir: PathBuf,
+    /// Loop length `load` checks new pattern files against.
+    pub loop_beats: u32,
+}
+
+impl RemoteControl {
+    /// Current patterns, beat, tempo and mutes as JSON.
+    fn state(&self) -> String {
+        let patterns: Vec<_> = self
+            .patterns
+            .load()
+            .iter()
+            .map(|p| json!({ "name": p.name, "track": p.track_name(), "beats": p.beats().collect::<Vec<_>>() }))
+            .collect();
+        let mutes: serde_json::Map<String, serde_json::Value> =
+            self.mixer.read().unwrap().mutes().map(|(name, mute)| (name.clone(), json!(mute))).collect();
+        json!({
+            "bpm": self.transport.bpm(),
+            "beat": *self.current_beat.read().unwrap(),
+            "patterns_file": *self.patterns_path.read().unwrap(),
+            "patterns": patterns,
+            "mutes": mutes,
+        })
+        .to_string()
+    }
+
+    fn execute(&self, body: &str) -> Result<(), String> {
+        match serde_json::from_str(body).map_err(|e| e.to_string())? {
+            RemoteCommand::Bpm { value } => self.transport.set_bpm(value.clamp(20, 300)),
+            RemoteCommand::ToggleStep { track, beat } => {
+                let (track, on) = edit_patterns(&self.patterns, |patterns| {
+                    let index = pattern_index(patterns, &track).ok_or(format!("Unknown track '{}'", track))?;
+                    let pattern = &mut patterns[index];
+                    let on = !pattern.has_step(beat);
+                    pattern.set_beat(beat, on);
+                    Ok::<_, String>((pattern.track_name().to_string(), on))
+                })?;
+                self.session.write().unwrap().record_beat_edit(&track, beat, on);
+            }
+            RemoteCommand::Mute { track } => {
+                let track = track_of(&self.patterns.load(), &track);
+                let mut mixer = self.mixer.write().unwrap();
+                let index = mixer.channel_index(&track).ok_or(format!("Unknown track '{}'", track))?;
+                mixer.toggle_mute(index);
+            }
+            RemoteCommand::Load { path } => {
+                check_pattern_file(&self.project_dir, &path, self.loop_beats).map_err(|e| e.to_string())?;
+                *self.patterns_path.write().unwrap() = path.clone();
+                log!("Patterns file switched to {}", path);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length: u64 = 0;
+        let mut websocket_key = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => content_length = value.trim().parse()?,
+                    "sec-websocket-key" => websocket_key = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+        let mut stream = stream;
+        if content_length > MAX_MESSAGE {
+            respond(&mut stream, "413 Payload Too Large", "Request body too large")?;
+            return Ok(());
+        }
+        let mut body = vec![0; content_length as usize];
+        reader.read_exact(&mut body)?;
+        let body = String::from_utf8(body)?;
+
+        let mut parts = request_line.split_whitespace();
+        match (parts.next().unwrap_or(""), parts.next().unwrap_or("")) {
+            ("GET", "/ws") => match websocket_key {
+                Some(key) => self.serve_websocket(stream, reader, &key)?,
+                None => respond(&mut stream, "400 Bad Request", "Expected a WebSocket upgrade")?,
+            },
+            ("GET", "/state") => respond(&mut stream, "200 OK", &self.state())?,
+            ("POST", "/command") => match self.execute(&body) {
+                Ok(()) => respond(&mut stream, "200 OK", &self.state())?,
+                Err(e) => respond(&mut stream, "400 Bad Request", &json!({ "error": e }).to_string())?,
+            },
+            _ => respond(&mut stream, "404 Not Found", "Not found")?,
+        }
+        Ok(())
+    }
+
+    /// Pushes the state to the client while a second thread reads its commands.
+    fn serve_websocket(
+        &self,
+        mut stream: TcpStream,
+        mut reader: BufReader<TcpStream>,
+        key: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let accept = base64(&Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        )?;
+
+        let (commands_tx, commands_rx) = std::sync::mpsc::channel();
+        let mut closer = stream.try_clone()?;
+        thread::spawn(move || loop {
+            match read_frame(&mut reader) {
+                Ok(Some(text)) => {
+                    if commands_tx.send(text).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    if e.kind() == ErrorKind::InvalidData {
+                        let _ = write_close(&mut closer, CLOSE_TOO_BIG);
+                    }
+                    break;
+                }
+            }
+        });
+        loop {
+            loop {
+                match commands_rx.try_recv() {
+                    Ok(text) => {
+                        if let Err(e) = self.execute(&text) {
+                            write_frame(&mut stream, &json!({ "error": e }).to_string())?;
+                        }
+                    }
+                    Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                    // The reader stops when the client closes the connection
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            write_frame(&mut stream, &self.state())?;
+            thread::sleep(PUSH_INTERVAL);
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let content_type = if body.starts_with('{') { "application/json" } else { "text/plain" };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Reads client frames until a text message arrives; None once the client
+/// closes. Messages over `MAX_MESSAGE` fail with `InvalidData`.
+fn read_frame(reader: &mut impl Read) -> std::io::Result<Option<String>> {
+    loop {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+        let opcode = header[0] & 0x0F;
+        let mut len = (header[1] & 0x7F) as u64;
+        if len == 126 {
+            let mut ext = [0u8; 2];
+            reader.read_exact(&mut ext)?;
+            len = u16::from_be_bytes(ext) as u64;
+        } else if len == 127 {
+            let mut ext = [0u8; 8];
+            reader.read_exact(&mut ext)?;
+            len = u64::from_be_bytes(ext);
+        }
+        if len > MAX_MESSAGE {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("{} byte message is too large", len)));
+        }
+        // Client frames are always masked
+        let mut mask = [0u8; 4];
+        if header[1] & 0x80 != 0 {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        match opcode {
+            0x1 => return Ok(Some(String::from_utf8_lossy(&payload).into_owned())),
+            0x8 => return Ok(None),
+            _ => {} // Pings and binary frames are ignored
+        }
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, text: &str) -> std::io::Result<()> {
+    let mut frame = vec![0x81]; // FIN + text
+    let len = text.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(text.as_bytes());
+    stream.write_all(&frame)
+}
+
+/// Closes the connection with the given status code.
+fn write_close(stream: &mut TcpStream, code: u16) -> std::io::Result<()> {
+    let mut frame = vec![0x88, 2]; // FIN + close, two byte status
+    frame.extend_from_slice(&code.to_be_bytes());
+    stream.write_all(&frame)
+}
+
+/// Serves the remote control API on the given address and TCP port in the
+/// background: `GET /state`, `POST /command` and a WebSocket at `/ws` that
+/// streams the state and accepts the same commands. There is no
+/// authentication, so bind anything but localhost only on trusted networks.
+pub fn spawn_server(host: &str, port: u16, control: RemoteControl) -> error::Result<()> {
+    let listener =
+        TcpListener::bind((host, port)).map_err(|source| Error::Network { address: format!("{}:{}", host, port), source })?;
+    log!("Remote control listening on http://{}:{}", host, port);
+    let control = Arc::new(control);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let control = Arc::clone(&control);
+            thread::spawn(move || {
+                if let Err(e) = control.handle_connection(stream) {
+                    log_warn!("Remote connection failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
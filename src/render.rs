@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::history::HistoryEntry;
+use crate::mixer::{pan_volumes, Mixer};
+use crate::model::{bank_names, Pattern, Ticks};
+use crate::transport::Transport;
+use crate::{beats_to_millis, LoopBank, SoundBank, TICK};
+
+const RENDER_RATE: u32 = 44100;
+const RENDER_CHANNELS: u16 = 2;
+
+/// What an offline render plays on: the banks, the tempo and pass state,
+/// and the track gains and pans.
+pub struct RenderSetup<'a> {
+    pub sound_bank: &'a SoundBank,
+    pub loop_bank: &'a LoopBank,
+    pub transport: &'a Transport,
+    pub mixer: &'a Mixer,
+    pub loop_beats: u32,
+}
+
+/// Where and how loud a voice goes into the mix. `limit` caps the source
+/// time played, as `take_duration` does before the speed change.
+struct Mix {
+    start_secs: f32,
+    gain: f32,
+    speed: f32,
+    pan: f32,
+    limit: Option<f32>,
+}
+
+/// Mixes `loops` passes of the sample and loop patterns offline into
+/// interleaved stereo, with the gain and pan they get live. MIDI rows have
+/// no audio and are skipped.
+pub fn render_patterns(patterns: &[Pattern], setup: &RenderSetup, loops: u32) -> Vec<f32> {
+    let RenderSetup { sound_bank, loop_bank, transport, mixer, loop_beats } = *setup;
+    let bpm = transport.bpm();
+    let beat_secs = 60.0 / bpm as f32;
+    let loop_secs = loop_beats as f32 * beat_secs;
+    let mut buffer = vec![0.0; (loops as f32 * loop_secs * RENDER_RATE as f32) as usize * 2];
+
+    for loop_index in 0..loops {
+        let pass_start = loop_index as f32 * loop_secs;
+        let variation = transport.variation();
+        let fill = transport.take_fill();
+        let pass = transport.next_pass();
+        let bank = transport.advance_bank(&bank_names(patterns));
+
+        for pattern in patterns.iter().filter(|p| p.is_enabled(&bank, variation, fill)) {
+            let track = pattern.track_name();
+            let track_gain = mixer.gain(track);
+            let pan = (mixer.pan(track) + pattern.pan).clamp(-1.0, 1.0);
+            if track_gain <= 0.0 {
+                continue;
+            }
+            for tick in 0..loop_beats * 8 {
+                let position = Ticks(tick as i32 * TICK.0);
+                for (step, delay) in pattern.due_steps(pass, position, TICK, loop_beats) {
+                    let fixed = pattern.sound.as_ref().and_then(|label| sound_bank.fixed_velocity(label));
+                    let gain = fixed.unwrap_or_else(|| pattern.trigger_velocity(&step)) * track_gain / 100.0;
+                    let duration = pattern.note_beats(&step, bpm);
+                    let ratchet = step.ratchet.max(1);
+                    let interval = pattern.ratchet_beats(&step) * beat_secs;
+                    for repeat in 0..ratchet {
+                        let start = pass_start + (position.beats() + delay) * beat_secs + repeat as f32 * interval;
+                        let velocity = gain * 100.0;
+                        if let Some(voice) = pattern.sound.as_ref().and_then(|label| sound_bank.voice(label, velocity)) {
+                            let (samples, channels, rate) = &*voice.sample;
+                            let mix = Mix { start_secs: start, gain: gain * voice.gain, speed: voice.speed, pan, limit: None };
+                            mix_voice(&mut buffer, samples, *channels, *rate, mix);
+                        } else if let Some(entry) = pattern.loop_name.as_ref().and_then(|label| loop_bank.get(label)) {
+                            let limit = beats_to_millis(duration, bpm) as f32 / 1000.0;
+                            entry.with_playback(bpm, |samples, speed| {
+                                let mix = Mix { start_secs: start, gain, speed, pan, limit: Some(limit) };
+                                mix_voice(&mut buffer, samples, entry.channels, entry.sample_rate, mix)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    buffer
+}
+
+/// Mixes a recorded history offline, each trigger at its logged time on
+/// the sample or loop of the pattern for its track with its logged pan;
+/// MIDI is skipped.
+pub fn render_history(history: &[HistoryEntry], patterns: &[Pattern], sound_bank: &SoundBank, loop_bank: &LoopBank) -> Vec<f32> {
+    let mut buffer = Vec::new();
+    for entry in history {
+        let Some(pattern) = patterns.iter().find(|p| p.track_name() == entry.track && p.midi_note.is_none()) else {
+            continue;
+        };
+        let gain = entry.velocity / 100.0;
+        let start = entry.time as f32;
+        if let Some(voice) = pattern.sound.as_ref().and_then(|label| sound_bank.voice(label, entry.velocity)) {
+            let (samples, channels, rate) = &*voice.sample;
+            let mix = Mix { start_secs: start, gain: gain * voice.gain, speed: voice.speed, pan: entry.pan, limit: None };
+            mix_voice(&mut buffer, samples, *channels, *rate, mix);
+        } else if let Some(loop_entry) = pattern.loop_name.as_ref().and_then(|label| loop_bank.get(label)) {
+            let limit = beats_to_millis(entry.duration, entry.bpm) as f32 / 1000.0;
+            loop_entry.with_playback(entry.bpm, |samples, speed| {
+                let mix = Mix { start_secs: start, gain, speed, pan: entry.pan, limit: Some(limit) };
+                mix_voice(&mut buffer, samples, loop_entry.channels, loop_entry.sample_rate, mix)
+            });
+        }
+    }
+    buffer
+}
+
+/// Adds one voice to the buffer, resampling by nearest frame and panning
+/// as the live output does.
+fn mix_voice(buffer: &mut Vec<f32>, samples: &[i16], channels: u16, rate: u32, mix: Mix) {
+    let Mix { start_secs, gain, speed, pan, limit } = mix;
+    let volumes = pan_volumes(pan);
+    let channels = channels.max(1) as usize;
+    let source_frames = samples.len() / channels;
+    let source_frames = match limit {
+        Some(secs) => source_frames.min((secs * rate as f32) as usize),
+        None => source_frames,
+    };
+    let step = speed * rate as f32 / RENDER_RATE as f32;
+    let out_frames = (source_frames as f32 / step) as usize;
+    let start_frame = (start_secs * RENDER_RATE as f32) as usize;
+    let needed = (start_frame + out_frames) * 2;
+    if buffer.len() < needed {
+        buffer.resize(needed, 0.0);
+    }
+    for frame in 0..out_frames {
+        let source = (frame as f32 * step) as usize * channels;
+        let left = samples[source] as f32 / i16::MAX as f32;
+        let right = if channels > 1 { samples[source + 1] as f32 / i16::MAX as f32 } else { left };
+        let out = (start_frame + frame) * 2;
+        buffer[out] += left * gain * volumes[0];
+        buffer[out + 1] += right * gain * volumes[1];
+    }
+}
+
+/// Writes interleaved stereo as a 16-bit PCM WAV file.
+pub fn write_wav(path: &str, buffer: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let data_len = (buffer.len() * 2) as u32;
+    let block_align = RENDER_CHANNELS * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&RENDER_CHANNELS.to_le_bytes())?;
+    out.write_all(&RENDER_RATE.to_le_bytes())?;
+    out.write_all(&(RENDER_RATE * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in buffer {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::{
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+
+use crate::mixer::Mixer;
+use crate::model::{pattern_index, track_of, Pattern};
+use crate::session::Session;
+use crate::setlist::Setlist;
+use crate::transport::{LiveQuantize, Transport};
+use crate::{edit_patterns, switch_banks, EngineEvent, LoopBank, SoundBank};
+
+const HELP: &str = "\
+bd.beats(0, 1, 2, 3)  set the steps of a track
+bd.velocity(90)       set a track's velocity
+play                  start playback held by --wait
+mute hats             toggle a track's mute
+bpm 126               set the tempo
+transpose -2          shift the MIDI patterns by semitones
+quantize 16th         hold live pad hits back to off, 16th, 8th or beat
+samples kits/808      switch the sample directories
+loops loops/house     switch the loop directories
+scene chorus          launch a scene at the next bar
+next                  switch to the next song of the setlist
+songs                 list the setlist
+tracks                list the tracks
+quit                  stop and exit
+Changes apply from the next loop pass.";
+
+enum ReplCommand {
+    Beats { track: String, beats: Vec<f32> },
+    Velocity { track: String, velocity: f32 },
+    Play,
+    Mute(String),
+    Bpm(u32),
+    Transpose(i8),
+    Quantize(LiveQuantize),
+    Samples(String),
+    Loops(String),
+    Scene(String),
+    Next,
+    Songs,
+    Tracks,
+    Help,
+    Quit,
+}
+
+fn parse_numbers(args: &str) -> Result<Vec<f32>, String> {
+    args.split(',')
+        .map(str::trim)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| arg.parse().map_err(|_| format!("'{}' is not a number", arg)))
+        .collect()
+}
+
+/// Parses `track.method(args)` calls and `word [argument]` commands.
+fn parse(line: &str) -> Result<ReplCommand, String> {
+    let line = line.trim();
+    if let Some((track, call)) = line.split_once('.').filter(|_| line.ends_with(')')) {
+        let (method, args) = call.trim_end_matches(')').split_once('(').ok_or(format!("Expected {}.method(...)", track))?;
+        let track = track.trim().to_string();
+        let mut numbers = parse_numbers(args)?;
+        return match method.trim() {
+            "beats" => Ok(ReplCommand::Beats { track, beats: numbers }),
+            "velocity" if numbers.len() == 1 => Ok(ReplCommand::Velocity { track, velocity: numbers.remove(0) }),
+            "velocity" => Err("velocity takes one value".to_string()),
+            other => Err(format!("Unknown method '{}'; try beats or velocity", other)),
+        };
+    }
+    let (command, argument) = line.split_once(' ').map_or((line, ""), |(c, a)| (c, a.trim()));
+    match command {
+        "mute" if !argument.is_empty() => Ok(ReplCommand::Mute(argument.to_string())),
+        "samples" if !argument.is_empty() => Ok(ReplCommand::Samples(argument.to_string())),
+        "loops" if !argument.is_empty() => Ok(ReplCommand::Loops(argument.to_string())),
+        "scene" if !argument.is_empty() => Ok(ReplCommand::Scene(argument.to_string())),
+        "bpm" => argument.parse().map(ReplCommand::Bpm).map_err(|_| format!("Invalid bpm '{}'", argument)),
+        "transpose" => argument
+            .parse()
+            .map(ReplCommand::Transpose)
+            .map_err(|_| format!("Invalid transpose '{}'", argument)),
+        "quantize" => LiveQuantize::parse(argument).map(ReplCommand::Quantize),
+        "play" => Ok(ReplCommand::Play),
+        "next" => Ok(ReplCommand::Next),
+        "songs" => Ok(ReplCommand::Songs),
+        "tracks" => Ok(ReplCommand::Tracks),
+        "help" => Ok(ReplCommand::Help),
+        "quit" | "exit" => Ok(ReplCommand::Quit),
+        _ => Err(format!("Unknown command '{}'; type help", line)),
+    }
+}
+
+/// Live-coding prompt on the terminal. Pattern and tempo edits are picked
+/// up by the scheduler at the next loop pass; mutes wait for it too.
+pub struct Repl {
+    patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    mixer: Arc<RwLock<Mixer>>,
+    transport: Arc<Transport>,
+    session: Arc<RwLock<Session>>,
+    sound_bank: Arc<SoundBank>,
+    loop_bank: Arc<LoopBank>,
+    running: Arc<AtomicBool>,
+    /// Tracks whose mute toggles when the next pass starts.
+    queued_mutes: Arc<Mutex<Vec<String>>>,
+    setlist: Option<Arc<Setlist>>,
+}
+
+impl Repl {
+    pub fn new(
+        patterns: Arc<ArcSwap<Vec<Pattern>>>,
+        mixer: Arc<RwLock<Mixer>>,
+        transport: Arc<Transport>,
+        session: Arc<RwLock<Session>>,
+        sound_bank: Arc<SoundBank>,
+        loop_bank: Arc<LoopBank>,
+        running: Arc<AtomicBool>,
+    ) -> Self {
+        let queued_mutes = Arc::new(Mutex::new(Vec::new()));
+        Self { patterns, mixer, transport, session, sound_bank, loop_bank, running, queued_mutes, setlist: None }
+    }
+
+    /// Enables the `next` and `songs` commands.
+    pub fn with_setlist(mut self, setlist: Option<Arc<Setlist>>) -> Self {
+        self.setlist = setlist;
+        self
+    }
+
+    fn setlist(&self) -> Result<&Setlist, String> {
+        self.setlist.as_deref().ok_or("No setlist given; play with --setlist".to_string())
+    }
+
+    /// Reads commands until `quit`, end of input or the engine stopping.
+    pub fn run(self, events: Receiver<EngineEvent>) -> io::Result<()> {
+        self.apply_mutes_at_loop_start(events);
+
+        // Read stdin on its own thread so Ctrl+C isn't stuck behind a read
+        let (lines, input) = channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                if line.is_err() || lines.send(line.unwrap_or_default()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("Type help for the commands.");
+        prompt()?;
+        while self.running.load(Ordering::SeqCst) {
+            let line = match input.recv_timeout(Duration::from_millis(100)) {
+                Ok(line) => line,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            if !line.trim().is_empty() {
+                match parse(&line) {
+                    Ok(ReplCommand::Quit) => break,
+                    Ok(command) => {
+                        if let Err(e) = self.execute(command) {
+                            println!("{}", e);
+                        }
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            prompt()?;
+        }
+        self.running.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn execute(&self, command: ReplCommand) -> Result<(), String> {
+        match command {
+            ReplCommand::Beats { track, mut beats } => {
+                beats.sort_by(f32::total_cmp);
+                let (track, old) = edit_patterns(&self.patterns, |patterns| {
+                    let index = pattern_index(patterns, &track).ok_or(format!("Unknown track '{}'", track))?;
+                    let pattern = &mut patterns[index];
+                    let old: Vec<f32> = pattern.beats().collect();
+                    pattern.set_beats(&beats);
+                    Ok::<_, String>((pattern.track_name().to_string(), old))
+                })?;
+                // Recorded so the edit survives reloads of the patterns file
+                let mut session = self.session.write().unwrap();
+                for beat in old.iter().filter(|beat| !beats.contains(beat)) {
+                    session.record_beat_edit(&track, *beat, false);
+                }
+                for beat in beats.iter().filter(|beat| !old.contains(beat)) {
+                    session.record_beat_edit(&track, *beat, true);
+                }
+            }
+            ReplCommand::Velocity { track, velocity } => {
+                let velocity = velocity.clamp(0.0, 127.0);
+                edit_patterns(&self.patterns, |patterns| {
+                    let index = pattern_index(patterns, &track).ok_or(format!("Unknown track '{}'", track))?;
+                    patterns[index].velocity = velocity;
+                    Ok::<_, String>(())
+                })?;
+                // Recorded so the edit survives reloads of the patterns file
+                self.session.write().unwrap().record_velocity_edit(&track, velocity);
+            }
+            ReplCommand::Mute(name) => {
+                let patterns = self.patterns.load();
+                if pattern_index(&patterns, &name).is_none() {
+                    return Err(format!("Unknown track '{}'", name));
+                }
+                self.queued_mutes.lock().unwrap().push(track_of(&patterns, &name));
+            }
+            ReplCommand::Bpm(bpm) => self.transport.set_bpm(bpm.clamp(20, 300)),
+            ReplCommand::Transpose(semitones) => self.transport.set_transpose(semitones),
+            ReplCommand::Quantize(quantize) => self.transport.set_live_quantize(quantize),
+            ReplCommand::Samples(dirs) => {
+                switch_banks(&self.sound_bank, &self.loop_bank, Some(dirs), None)?;
+            }
+            ReplCommand::Loops(dirs) => {
+                switch_banks(&self.sound_bank, &self.loop_bank, None, Some(dirs))?;
+            }
+            ReplCommand::Scene(name) => self.transport.queue_scene(&name)?,
+            ReplCommand::Play => self.transport.start(),
+            ReplCommand::Next => println!("{}", self.setlist()?.next().map_err(|e| e.to_string())?),
+            ReplCommand::Songs => {
+                let setlist = self.setlist()?;
+                println!("Playing {}", setlist.current());
+                for (index, title) in setlist.titles().iter().enumerate() {
+                    println!("{:>3} {}", index + 1, title);
+                }
+            }
+            ReplCommand::Tracks => {
+                let mixer = self.mixer.read().unwrap();
+                let mutes: Vec<(&String, bool)> = mixer.mutes().collect();
+                // MIDI tracks have a pattern per note; list their beats together
+                let mut tracks: Vec<(String, Vec<f32>)> = Vec::new();
+                for pattern in self.patterns.load().iter() {
+                    match tracks.iter_mut().find(|(name, _)| *name == pattern.track_name()) {
+                        Some((_, beats)) => beats.extend(pattern.beats()),
+                        None => tracks.push((pattern.track_name().to_string(), pattern.beats().collect())),
+                    }
+                }
+                for (track, mut beats) in tracks {
+                    beats.sort_by(f32::total_cmp);
+                    beats.dedup();
+                    let muted = mutes.iter().any(|(name, mute)| **name == track && *mute);
+                    println!("{}{} {:?}", track, if muted { " (muted)" } else { "" }, beats);
+                }
+            }
+            ReplCommand::Help => println!("{}", HELP),
+            ReplCommand::Quit => {}
+        }
+        Ok(())
+    }
+
+    /// Toggles the queued mutes on the first beat of every pass.
+    fn apply_mutes_at_loop_start(&self, events: Receiver<EngineEvent>) {
+        let mixer = Arc::clone(&self.mixer);
+        let queued = Arc::clone(&self.queued_mutes);
+        thread::spawn(move || {
+            for event in events {
+                let EngineEvent::Beat { beat, .. } = event else { continue };
+                if beat != 0.0 {
+                    continue;
+                }
+                let mut mixer = mixer.write().unwrap();
+                for track in queued.lock().unwrap().drain(..) {
+                    mixer.ensure_channels([track.as_str()]);
+                    if let Some(index) = mixer.channel_index(&track) {
+                        mixer.toggle_mute(index);
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn prompt() -> io::Result<()> {
+    print!("> ");
+    io::stdout().flush()
+}
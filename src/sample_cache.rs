@@ -0,0 +1,107 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::UNIX_EPOCH,
+};
+
+use sha1::{Digest, Sha1};
+
+/// Start of every cache file, bumped when the layout changes.
+const MAGIC: &[u8; 8] = b"FOTFPCM1";
+
+/// Directory decoded samples are cached in; None leaves caching off.
+static CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Decoded sample data: (interleaved samples, channels, sample rate).
+pub type Pcm = (Vec<i16>, u16, u32);
+
+/// Caches the samples decoded by the bank loads that follow in `dir`, or
+/// stops caching with None.
+pub fn configure(dir: Option<PathBuf>) {
+    *CACHE_DIR.write().unwrap() = dir;
+}
+
+pub fn dir() -> Option<PathBuf> {
+    CACHE_DIR.read().unwrap().clone()
+}
+
+/// Cache key of a file as it is on disk: its path, size and modification
+/// time with the `settings` it was processed with. Found without reading
+/// the file, but missed once it is moved or touched.
+pub fn file_key(file: &Path, settings: &str) -> Option<String> {
+    let meta = fs::metadata(file).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let file = fs::canonicalize(file).ok()?;
+    let id = format!("{}\0{}\0{}\0{}", file.display(), meta.len(), modified.as_nanos(), settings);
+    Some(hex(Sha1::digest(id.as_bytes()).as_slice()))
+}
+
+/// Cache key of a file's contents with the `settings` they were processed
+/// with, so renamed or moved files still hit and edited ones miss.
+pub fn content_key(bytes: &[u8], settings: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(bytes);
+    hasher.update(settings.as_bytes());
+    hex(hasher.finalize().as_slice())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.pcm", key))
+}
+
+fn link_path(dir: &Path, file_key: &str) -> PathBuf {
+    dir.join(format!("{}.ref", file_key))
+}
+
+/// The content key the file with `file_key` was last cached under.
+pub fn resolve(dir: &Path, file_key: &str) -> Option<String> {
+    let key = fs::read_to_string(link_path(dir, file_key)).ok()?;
+    Some(key.trim().to_string()).filter(|key| !key.is_empty())
+}
+
+/// Points `file_key` at the entry stored under `content_key`.
+pub fn link(dir: &Path, file_key: &str, content_key: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(link_path(dir, file_key), content_key)
+}
+
+/// The cached samples stored under `key`, if there is a readable entry.
+pub fn read(dir: &Path, key: &str) -> Option<Pcm> {
+    let mut file = io::BufReader::new(fs::File::open(path(dir, key)).ok()?);
+    let mut header = [0u8; 14];
+    file.read_exact(&mut header).ok()?;
+    if &header[..8] != MAGIC {
+        return None;
+    }
+    let channels = u16::from_le_bytes([header[8], header[9]]);
+    let sample_rate = u32::from_le_bytes([header[10], header[11], header[12], header[13]]);
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    let samples = bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+    Some((samples, channels, sample_rate))
+}
+
+/// Stores samples under `key`, through a temporary file so a crash never
+/// leaves a partial entry behind.
+pub fn write(dir: &Path, key: &str, (samples, channels, sample_rate): &Pcm) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let target = path(dir, key);
+    let temp = target.with_extension(format!("{}.tmp", std::process::id()));
+    {
+        let mut file = io::BufWriter::new(fs::File::create(&temp)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        for sample in samples {
+            file.write_all(&sample.to_le_bytes())?;
+        }
+        file.flush()?;
+    }
+    fs::rename(temp, target)
+}
@@ -0,0 +1,27 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mixer::{Mixer, StripSettings};
+
+/// A pattern bank with the mixer state it plays under, launched as one,
+/// like a row of clips in a session view.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub name: String,
+    pub bank: String,
+    /// Strips set on launch; tracks not listed keep their settings.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mixer: BTreeMap<String, StripSettings>,
+    /// Beats over which the loops playing fade out as the scene's loops
+    /// fade in; 0 cuts them at the launch.
+    #[serde(default)]
+    pub crossfade: f32,
+}
+
+impl Scene {
+    /// The bank and mixer as they are now, under a new name.
+    pub fn capture(name: &str, bank: &str, mixer: &Mixer) -> Self {
+        Scene { name: name.to_string(), bank: bank.to_string(), mixer: mixer.settings(), crossfade: 0.0 }
+    }
+}
@@ -0,0 +1,36 @@
+use rand::Rng;
+use rhai::{Dynamic, Engine, Scope};
+
+use crate::chord::{Chord, Voicing};
+use crate::error::{self, Error};
+use crate::model::Pattern;
+
+/// Runs a patterns script. The script evaluates to an array of maps with the
+/// same fields as the JSON pattern file, and can use `rand()`,
+/// `rand_int(lo, hi)`, `chord(name, octave[, inversion])` for the notes of
+/// a chord such as "C#m", and the `LOOP_BEATS` constant.
+pub fn eval_patterns(script: &str, loop_beats: u32) -> error::Result<Vec<Pattern>> {
+    let mut engine = Engine::new();
+    engine.register_fn("rand", rand::random::<f64>);
+    engine.register_fn("rand_int", |lo: i64, hi: i64| {
+        if hi > lo { rand::thread_rng().gen_range(lo..hi) } else { lo }
+    });
+
+    engine.register_fn("chord", |name: &str, octave: i64| chord_notes(name, octave, 0));
+    engine.register_fn("chord", chord_notes);
+
+    let mut scope = Scope::new();
+    scope.push_constant("LOOP_BEATS", loop_beats as i64);
+    let script_error = |reason: String| Error::Script { reason };
+    let result: Dynamic = engine.eval_with_scope(&mut scope, script).map_err(|e| script_error(e.to_string()))?;
+
+    // Go through JSON values so script integers and floats fit any numeric field
+    let value: serde_json::Value = rhai::serde::from_dynamic(&result).map_err(|e| script_error(e.to_string()))?;
+    serde_json::from_value(value).map_err(|e| script_error(e.to_string()))
+}
+
+fn chord_notes(name: &str, octave: i64, inversion: i64) -> Result<rhai::Array, Box<rhai::EvalAltResult>> {
+    let voicing = Voicing { inversion: inversion.max(0) as u32, spread: false };
+    let chord = Chord::parse(name, octave.clamp(-1, 9) as i8)?;
+    Ok(chord.notes(voicing).into_iter().map(|note| Dynamic::from(note as i64)).collect())
+}
@@ -0,0 +1,68 @@
+use crate::model::Step;
+
+/// Rectangular selection of grid cells, as (row, column) corners in visible-row space.
+#[derive(Clone, Copy)]
+pub struct Selection {
+    pub anchor: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Selection {
+    pub fn new(cell: (usize, usize)) -> Self {
+        Self { anchor: cell, end: cell }
+    }
+
+    pub fn rows(&self) -> std::ops::RangeInclusive<usize> {
+        self.anchor.0.min(self.end.0)..=self.anchor.0.max(self.end.0)
+    }
+
+    pub fn cols(&self) -> std::ops::RangeInclusive<usize> {
+        self.anchor.1.min(self.end.1)..=self.anchor.1.max(self.end.1)
+    }
+
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        self.rows().contains(&row) && self.cols().contains(&col)
+    }
+
+    /// Top-left corner of the selection.
+    pub fn origin(&self) -> (usize, usize) {
+        (*self.rows().start(), *self.cols().start())
+    }
+
+    /// Moves the selection by `delta` columns, clamped to the grid.
+    pub fn shifted(&self, delta: isize, total_cols: usize) -> Self {
+        let shift = |col: usize| (col as isize + delta).clamp(0, total_cols as isize - 1) as usize;
+        Self {
+            anchor: (self.anchor.0, shift(self.anchor.1)),
+            end: (self.end.0, shift(self.end.1)),
+        }
+    }
+}
+
+/// Copied block of cells; `cells` are the active cells relative to the
+/// block origin with their steps, settings and all.
+#[derive(Clone, Default)]
+pub struct Clipboard {
+    pub rows: usize,
+    pub cols: usize,
+    pub cells: Vec<(usize, usize, Step)>,
+}
+
+impl Clipboard {
+    /// The step copied from cell (`row`, `col`) of the block, if it was on.
+    pub fn step(&self, row: usize, col: usize) -> Option<&Step> {
+        self.cells.iter().find(|(r, c, _)| (*r, *c) == (row, col)).map(|(_, _, step)| step)
+    }
+
+    /// Renders the block as drum-machine style step strings, one row per line.
+    pub fn to_text(&self) -> String {
+        (0..self.rows)
+            .map(|row| {
+                (0..self.cols)
+                    .map(|col| if self.step(row, col).is_some() { 'x' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
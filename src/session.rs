@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mixer::{Mixer, StripSettings};
+use crate::model::{pattern_index, Pattern, Step, Ticks};
+use crate::transport::Transport;
+
+/// Edits made from the GUI during this run, re-applied whenever patterns are reloaded.
+#[derive(Default, Clone, Deserialize, Serialize)]
+pub struct Session {
+    patterns: Vec<Pattern>,
+    step_edits: Vec<(String, Step)>,
+    beat_edits: Vec<(String, f32, bool)>,
+    #[serde(default)]
+    velocity_edits: Vec<(String, f32)>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_pattern(&mut self, pattern: Pattern) {
+        self.patterns.push(pattern);
+    }
+
+    pub fn record_step_edit(&mut self, track: &str, step: Step) {
+        self.step_edits
+            .retain(|(t, s)| !(t == track && s.position == step.position));
+        self.step_edits.push((track.to_string(), step));
+    }
+
+    /// Records a step being switched on or off for a track.
+    pub fn record_beat_edit(&mut self, track: &str, beat: f32, on: bool) {
+        let position = Ticks::from_beats(beat);
+        self.beat_edits.retain(|(t, b, _)| !(t == track && Ticks::from_beats(*b) == position));
+        self.beat_edits.push((track.to_string(), beat, on));
+    }
+
+    /// Records a new velocity for the pattern or track called `name`.
+    pub fn record_velocity_edit(&mut self, name: &str, velocity: f32) {
+        self.velocity_edits.retain(|(n, _)| n != name);
+        self.velocity_edits.push((name.to_string(), velocity));
+    }
+
+    /// Adds the session rows to freshly loaded patterns and replays step edits on them.
+    pub fn apply(&self, patterns: &mut Vec<Pattern>) {
+        patterns.extend(self.patterns.iter().cloned());
+        for (track, beat, on) in self.beat_edits.iter() {
+            if let Some(pattern) = patterns.iter_mut().find(|p| p.track_name() == track) {
+                pattern.set_beat(*beat, *on);
+            }
+        }
+        for (name, velocity) in self.velocity_edits.iter() {
+            if let Some(index) = pattern_index(patterns, name) {
+                patterns[index].velocity = *velocity;
+            }
+        }
+        for (track, step) in self.step_edits.iter() {
+            if let Some(pattern) = patterns
+                .iter_mut()
+                .find(|p| p.track_name() == track && p.has_step(step.beat()))
+            {
+                pattern.set_step(step.clone());
+            }
+        }
+    }
+}
+
+/// Runtime state written to disk while playing, so a crash doesn't lose
+/// live tweaks: tempo, variation, bank, mixer and the session edits.
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub bpm: u32,
+    pub variation: u32,
+    pub bank: String,
+    pub mixer: BTreeMap<String, StripSettings>,
+    pub session: Session,
+}
+
+impl Snapshot {
+    pub fn capture(transport: &Transport, mixer: &Mixer, session: &Session) -> Self {
+        Snapshot {
+            bpm: transport.bpm(),
+            variation: transport.variation(),
+            bank: transport.active_bank(),
+            mixer: mixer.settings(),
+            session: session.clone(),
+        }
+    }
+
+    /// Puts the state back; the bank switches at the next loop boundary.
+    pub fn restore(self, transport: &Transport, mixer: &mut Mixer, session: &mut Session) {
+        transport.set_bpm(self.bpm);
+        transport.set_variation(self.variation);
+        if !self.bank.is_empty() {
+            transport.queue_bank(&self.bank);
+        }
+        mixer.restore(&self.mixer);
+        *session = self.session;
+    }
+
+    /// Reads a snapshot, None when there is none.
+    pub fn read(path: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let snapshot = serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Some(snapshot))
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Snapshot file kept next to a patterns file.
+pub fn snapshot_path(patterns_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.session.json", patterns_path))
+}
@@ -0,0 +1,253 @@
+//! Setlists: several songs played one after the other at a gig. While a song
+//! plays the next one is loaded in the background, and on `next` it is
+//! swapped in at the following loop pass.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use serde::Deserialize;
+
+use crate::config::{self, Config};
+use crate::error::{self, Error};
+use crate::formats::{self, Format};
+use crate::midi_io::MidiOut;
+use crate::mixer::Mixer;
+use crate::model::{Pattern, Track};
+use crate::session::Session;
+use crate::settings::Subsystems;
+use crate::transport::Transport;
+use crate::{load_and_combine_patterns_from_content, read_midi_pattern, LoopBank, SoundBank};
+
+/// A song of the setlist: a project file, or a patterns file played with
+/// its own config or the one given on the command line.
+#[derive(Deserialize, Clone)]
+pub struct SetlistSong {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub patterns: Option<String>,
+    #[serde(default)]
+    pub config: Option<String>,
+    /// Tempo to switch to; the current one is kept when unset.
+    #[serde(default)]
+    pub bpm: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SetlistFile {
+    songs: Vec<SetlistSong>,
+}
+
+impl SetlistSong {
+    pub fn title(&self) -> String {
+        let file = self.project.as_ref().or(self.patterns.as_ref());
+        let stem = file.and_then(|f| Path::new(f).file_stem()).map(|s| s.to_string_lossy().into_owned());
+        self.name.clone().or(stem).unwrap_or_default()
+    }
+
+    /// Config and patterns paths, relative ones resolved against `dir`.
+    pub fn paths(&self, dir: &Path, default_config: &str) -> error::Result<(String, String)> {
+        let resolve = |path: &str| dir.join(path).to_string_lossy().into_owned();
+        match (&self.project, &self.patterns) {
+            (Some(project), _) => Ok((resolve(project), resolve(project))),
+            (None, Some(patterns)) => {
+                let config = self.config.as_deref().map_or(default_config.to_string(), resolve);
+                Ok((config, resolve(patterns)))
+            }
+            (None, None) => {
+                Err(Error::Setlist { reason: format!("song '{}' needs a project or patterns file", self.title()) })
+            }
+        }
+    }
+}
+
+/// Reads a setlist in JSON, TOML or YAML, with the directory its paths are relative to.
+pub fn read(path: &str) -> error::Result<(Vec<SetlistSong>, PathBuf)> {
+    let content = fs::read_to_string(path).map_err(|e| Error::io(Path::new(path), e))?;
+    let file: SetlistFile = Format::from_path(path).parse(path, &content)?;
+    if file.songs.is_empty() {
+        return Err(Error::parse(path, "lists no songs"));
+    }
+    let dir = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok((file.songs, dir))
+}
+
+/// A song read and decoded, ready to swap in.
+struct LoadedSong {
+    index: usize,
+    config: Config,
+    patterns_path: String,
+    patterns: Vec<Pattern>,
+    midi_pattern: Vec<Pattern>,
+    tracks: Vec<Track>,
+    sound_bank: SoundBank,
+    loop_bank: LoopBank,
+}
+
+/// A song loading in the background: its index in the setlist and the loader thread.
+type Preload = (usize, JoinHandle<error::Result<LoadedSong>>);
+
+/// The setlist being played and the engine parts a song change touches.
+pub struct Setlist {
+    songs: Vec<SetlistSong>,
+    dir: PathBuf,
+    default_config: String,
+    loop_beats: u32,
+    headless: bool,
+    position: Mutex<usize>,
+    preload: Mutex<Option<Preload>>,
+    subsystems: Subsystems,
+    transport: Arc<Transport>,
+    mixer: Arc<RwLock<Mixer>>,
+    session: Arc<RwLock<Session>>,
+    patterns_path: Arc<RwLock<String>>,
+}
+
+impl Setlist {
+    /// Takes over from the first song, already playing, and starts loading the second.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        songs: Vec<SetlistSong>,
+        dir: PathBuf,
+        default_config: &str,
+        loop_beats: u32,
+        headless: bool,
+        subsystems: Subsystems,
+        transport: Arc<Transport>,
+        mixer: Arc<RwLock<Mixer>>,
+        session: Arc<RwLock<Session>>,
+        patterns_path: Arc<RwLock<String>>,
+    ) -> Self {
+        let setlist = Setlist {
+            songs,
+            dir,
+            default_config: default_config.to_string(),
+            loop_beats,
+            headless,
+            position: Mutex::new(0),
+            preload: Mutex::new(None),
+            subsystems,
+            transport,
+            mixer,
+            session,
+            patterns_path,
+        };
+        setlist.preload(1);
+        setlist
+    }
+
+    /// Starts loading the song at `index` in the background, if there is one.
+    fn preload(&self, index: usize) {
+        let Some(song) = self.songs.get(index).cloned() else {
+            return;
+        };
+        let dir = self.dir.clone();
+        let default_config = self.default_config.clone();
+        let (loop_beats, headless) = (self.loop_beats, self.headless);
+        let bpm = song.bpm.unwrap_or_else(|| self.transport.bpm());
+        log!("Preloading '{}'", song.title());
+        let handle = thread::spawn(move || load(index, &song, &dir, &default_config, loop_beats, headless, bpm));
+        *self.preload.lock().unwrap() = Some((index, handle));
+    }
+
+    /// Switches to the next song; its banks and patterns start with the next loop pass.
+    pub fn next(&self) -> error::Result<String> {
+        let index = *self.position.lock().unwrap() + 1;
+        self.select(index)
+    }
+
+    /// Switches to the song at `index`, loading it now unless it was preloaded.
+    pub fn select(&self, index: usize) -> error::Result<String> {
+        let song = self.songs.get(index).ok_or_else(|| Error::Setlist {
+            reason: format!("no song {} in the setlist of {}", index + 1, self.songs.len()),
+        })?;
+        let preloaded = self.preload.lock().unwrap().take();
+        let loaded = match preloaded {
+            Some((preloaded, handle)) if preloaded == index => handle
+                .join()
+                .map_err(|_| Error::Setlist { reason: format!("loading '{}' failed", song.title()) })?,
+            _ => {
+                let bpm = song.bpm.unwrap_or_else(|| self.transport.bpm());
+                load(index, song, &self.dir, &self.default_config, self.loop_beats, self.headless, bpm)
+            }
+        }?;
+        self.apply(loaded, song.bpm)?;
+        *self.position.lock().unwrap() = index;
+        self.preload(index + 1);
+        Ok(format!("{}/{} {}", index + 1, self.songs.len(), song.title()))
+    }
+
+    /// The song playing, as `position/count title`.
+    pub fn current(&self) -> String {
+        let index = *self.position.lock().unwrap();
+        format!("{}/{} {}", index + 1, self.songs.len(), self.songs[index].title())
+    }
+
+    /// Titles of the songs, in order.
+    pub fn titles(&self) -> Vec<String> {
+        self.songs.iter().map(SetlistSong::title).collect()
+    }
+
+    fn apply(&self, song: LoadedSong, bpm: Option<u32>) -> error::Result<()> {
+        let subsystems = &self.subsystems;
+        if song.config.midi_port != subsystems.config.read().unwrap().midi_port {
+            let conn = MidiOut::connect(&song.config.midi_port)?;
+            *subsystems.midi_conn.lock().unwrap() = conn;
+        }
+        // Everything below lands together at the start of the next pass
+        subsystems.sound_bank.stage_bank(song.sound_bank);
+        subsystems.loop_bank.stage_bank(song.loop_bank);
+        *subsystems.midi_pattern.write().unwrap() = song.midi_pattern;
+        *subsystems.config.write().unwrap() = song.config;
+        // Edits made to the last song don't carry over
+        *self.session.write().unwrap() = Session::new();
+        self.mixer.write().unwrap().apply_tracks(&song.tracks);
+        *self.patterns_path.write().unwrap() = song.patterns_path;
+        subsystems.patterns.store(Arc::new(song.patterns));
+        if let Some(bpm) = bpm {
+            self.transport.set_bpm(bpm);
+        }
+        log!("Song {} of the setlist queued for the next pass", song.index + 1);
+        Ok(())
+    }
+}
+
+/// Reads a song's config and patterns and decodes its banks; headless rigs
+/// only keep the MIDI patterns and skip the banks.
+fn load(
+    index: usize,
+    song: &SetlistSong,
+    dir: &Path,
+    default_config: &str,
+    loop_beats: u32,
+    headless: bool,
+    bpm: u32,
+) -> error::Result<LoadedSong> {
+    let (config_path, patterns_path) = song.paths(dir, default_config)?;
+    let in_song = |e: Error| Error::Song { title: song.title(), source: Box::new(e) };
+    let config = config::read_config(&config_path).map_err(in_song)?;
+    if config.loop_beats != loop_beats {
+        log_warn!("'{}' has {}-beat loops, it plays in {}", song.title(), config.loop_beats, loop_beats);
+    }
+    let content = fs::read_to_string(&patterns_path).map_err(|e| in_song(Error::io(Path::new(&patterns_path), e)))?;
+    let midi_pattern = read_midi_pattern(&config, bpm);
+    let mut patterns = load_and_combine_patterns_from_content(&patterns_path, &content, &midi_pattern, loop_beats);
+    let tracks = formats::parse_tracks(&patterns_path, &content).unwrap_or_default();
+    let (sound_bank, loop_bank) = if headless {
+        patterns.retain(|p| p.midi_note.is_some());
+        (SoundBank::default(), LoopBank::default())
+    } else {
+        (
+            SoundBank::new(&config.sample_dirs(), &config.threads, config.sounds.duplicates).map_err(in_song)?,
+            LoopBank::new(&config.loop_dirs(), &config.threads, config.sounds.duplicates).map_err(in_song)?,
+        )
+    };
+    log!("'{}' is loaded", song.title());
+    Ok(LoadedSong { index, config, patterns_path, patterns, midi_pattern, tracks, sound_bank, loop_bank })
+}
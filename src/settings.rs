@@ -0,0 +1,276 @@
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+#[cfg(feature = "gui")]
+use eframe::egui;
+#[cfg(feature = "gui")]
+use std::path::Path;
+
+use crate::config::{self, Config};
+use crate::midi;
+use crate::midi_io::MidiOut;
+use crate::mixer::Mixer;
+use crate::model::Pattern;
+use crate::transport::Transport;
+use crate::{stage_banks, LoopBank, SoundBank};
+#[cfg(feature = "gui")]
+use crate::{audio, midi_io};
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+
+/// Engine parts re-initialized when their settings change, from the
+/// settings dialog or an edit of the config file.
+#[derive(Clone)]
+pub struct Subsystems {
+    /// Settings currently in effect.
+    pub config: Arc<RwLock<Config>>,
+    pub midi_conn: Arc<Mutex<MidiOut>>,
+    pub sound_bank: Arc<SoundBank>,
+    pub loop_bank: Arc<LoopBank>,
+    pub midi_pattern: Arc<RwLock<Vec<Pattern>>>,
+    /// Saved with the project from the settings dialog.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    /// Mixer holding the declared tracks, saved along with their patterns.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub mixer: Arc<RwLock<Mixer>>,
+}
+
+/// Preferences window editing the config file.
+#[cfg(feature = "gui")]
+pub struct SettingsDialog {
+    pub open: bool,
+    config_path: String,
+    /// Where "Save project" writes the config and patterns.
+    project_path: String,
+    /// Settings being edited.
+    draft: Config,
+    subsystems: Subsystems,
+    midi_ports: Vec<String>,
+    audio_devices: Vec<String>,
+    status: Option<String>,
+}
+
+#[cfg(feature = "gui")]
+impl SettingsDialog {
+    pub fn new(config_path: &str, subsystems: Subsystems) -> Self {
+        let draft = subsystems.config.read().unwrap().clone();
+        Self {
+            open: false,
+            config_path: config_path.to_string(),
+            project_path: Path::new(config_path)
+                .with_extension(config::PROJECT_EXTENSION)
+                .to_string_lossy()
+                .into_owned(),
+            draft,
+            subsystems,
+            midi_ports: Vec::new(),
+            audio_devices: Vec::new(),
+            status: None,
+        }
+    }
+
+    /// Opens the dialog with the device lists refreshed.
+    pub fn show_dialog(&mut self) {
+        self.midi_ports = midi_io::output_port_names();
+        self.audio_devices = audio::output_device_names();
+        self.draft = self.subsystems.config.read().unwrap().clone();
+        self.status = None;
+        self.open = true;
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, bpm: u32) {
+        let mut open = self.open;
+        let mut apply = false;
+        let mut save_project = false;
+        egui::Window::new("Settings").open(&mut open).show(ctx, |ui| {
+            egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                ui.label("MIDI output");
+                egui::ComboBox::from_id_source("midi_port")
+                    .selected_text(self.draft.midi_port.clone())
+                    .show_ui(ui, |ui| {
+                        for port in self.midi_ports.iter() {
+                            ui.selectable_value(&mut self.draft.midi_port, port.clone(), port);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Audio device (applies on restart)");
+                let device_text = self.draft.audio_device.clone().unwrap_or("System default".to_string());
+                egui::ComboBox::from_id_source("audio_device")
+                    .selected_text(device_text)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.draft.audio_device, None, "System default");
+                        for device in self.audio_devices.iter() {
+                            ui.selectable_value(&mut self.draft.audio_device, Some(device.clone()), device);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Samples");
+                ui.text_edit_singleline(&mut self.draft.sounds.samples);
+                ui.end_row();
+                ui.label("Loops");
+                ui.text_edit_singleline(&mut self.draft.sounds.loops);
+                ui.end_row();
+
+                ui.label("MIDI file");
+                ui.text_edit_singleline(&mut self.draft.midi_track.midi_file);
+                ui.end_row();
+                ui.label("MIDI track");
+                ui.text_edit_singleline(&mut self.draft.midi_track.track_name);
+                ui.end_row();
+                ui.label("Import beats");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.draft.midi_track.start_beat).speed(0.25));
+                    ui.label("to");
+                    ui.add(egui::DragValue::new(&mut self.draft.midi_track.end_beat).speed(0.25));
+                });
+                ui.end_row();
+            });
+
+            ui.horizontal(|ui| {
+                apply = ui.button("Apply").clicked();
+                if ui.button("Revert").clicked() {
+                    self.draft = self.subsystems.config.read().unwrap().clone();
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Project");
+                ui.text_edit_singleline(&mut self.project_path);
+                save_project = ui.button("Save project").clicked();
+            });
+            if let Some(status) = &self.status {
+                ui.label(status);
+            }
+        });
+        self.open = open;
+
+        if apply {
+            self.status = Some(match self.apply(bpm) {
+                Ok(()) => format!("Saved to {}", self.config_path),
+                Err(e) => format!("Error: {}", e),
+            });
+        }
+        if save_project {
+            self.status = Some(match self.save_project() {
+                Ok(()) => format!("Project saved to {}", self.project_path),
+                Err(e) => format!("Error: {}", e),
+            });
+        }
+    }
+
+    /// Writes the applied settings and the current patterns, without the
+    /// rows imported from the MIDI file, to the project file. Patterns on a
+    /// declared track are saved under it, with the track's mixer settings.
+    fn save_project(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let midi_pattern = self.subsystems.midi_pattern.read().unwrap();
+        let mut patterns: Vec<Pattern> = self
+            .subsystems
+            .patterns
+            .load()
+            .iter()
+            .filter(|p| !midi_pattern.contains(p))
+            .cloned()
+            .collect();
+        let mut tracks = self.subsystems.mixer.read().unwrap().tracks();
+        for track in tracks.iter_mut() {
+            track.gather(&mut patterns);
+        }
+        config::write_project(&self.project_path, &self.subsystems.config.read().unwrap(), &patterns, &tracks)?;
+        Ok(())
+    }
+
+    /// Re-initializes the subsystems whose settings changed, then saves the config.
+    fn apply(&mut self, bpm: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.subsystems.reconfigure(&self.draft, bpm)?;
+        config::write_config(&self.config_path, &self.draft)?;
+        Ok(())
+    }
+}
+
+impl Subsystems {
+    /// Brings the subsystems in line with `target`, reloading only what
+    /// changed. Each part is swapped in whole under its lock, so playback
+    /// picks it up at its next trigger; new banks load in the background
+    /// and change over at the next loop. Settings needing a restart are
+    /// recorded and reported.
+    pub fn reconfigure(&self, target: &Config, bpm: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let applied = self.config.read().unwrap().clone();
+        if target.midi_port != applied.midi_port {
+            let conn = MidiOut::connect(&target.midi_port)?;
+            *self.midi_conn.lock().unwrap() = conn;
+            self.config.write().unwrap().midi_port = target.midi_port.clone();
+            log!("MIDI output switched to {}", target.midi_port);
+        }
+        let sample_dirs = Some(target.sample_dirs()).filter(|dirs| *dirs != applied.sample_dirs());
+        let loop_dirs = Some(target.loop_dirs()).filter(|dirs| *dirs != applied.loop_dirs());
+        if sample_dirs.is_some() || loop_dirs.is_some() {
+            stage_banks(Arc::clone(&self.sound_bank), Arc::clone(&self.loop_bank), sample_dirs, loop_dirs);
+        }
+        self.config.write().unwrap().sounds = target.sounds.clone();
+        let track = &target.midi_track;
+        let applied_track = &applied.midi_track;
+        if target.midi_file() != applied.midi_file()
+            || track.track_name != applied_track.track_name
+            || track.start_beat != applied_track.start_beat
+            || track.end_beat != applied_track.end_beat
+        {
+            let pattern = midi::read_midi_and_extract_pattern(
+                &target.midi_file(),
+                &track.track_name,
+                bpm,
+                track.start_beat,
+                track.end_beat,
+            )?;
+            *self.midi_pattern.write().unwrap() = pattern;
+            log!("MIDI track {} re-imported", track.track_name);
+        }
+        if target.audio_device != applied.audio_device
+            || target.midi_input_port != applied.midi_input_port
+            || target.osc_port != applied.osc_port
+            || target.osc_bind != applied.osc_bind
+            || target.osc_out != applied.osc_out
+            || target.remote_port != applied.remote_port
+            || target.remote_bind != applied.remote_bind
+        {
+            log!("Device and network settings change on the next start");
+        }
+        *self.config.write().unwrap() = target.clone();
+        Ok(())
+    }
+}
+
+/// Watches the config file and applies edits to it while playing.
+pub fn watch_config(path: String, subsystems: Subsystems, transport: Arc<Transport>, running: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last_modified = modified(&path);
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(CONFIG_POLL);
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+            match config::read_config(&path) {
+                Ok(config) => {
+                    if let Err(e) = subsystems.reconfigure(&config, transport.bpm()) {
+                        log_error!("Failed to apply {}: {}", path, e);
+                    }
+                }
+                Err(e) => log_error!("Failed to read {}: {}", path, e),
+            }
+        }
+    });
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+fn default_repeats() -> u32 {
+    1
+}
+
+/// One entry of the arrangement: a bank played for a number of loop passes.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SongSection {
+    pub bank: String,
+    #[serde(default = "default_repeats")]
+    pub repeats: u32,
+}
+
+/// Song mode playback order over pattern banks.
+#[derive(Default)]
+pub struct Song {
+    pub sections: Vec<SongSection>,
+    pub enabled: bool,
+    position: usize,
+    pass: u32,
+}
+
+impl Song {
+    pub fn new(sections: Vec<SongSection>) -> Self {
+        Self {
+            enabled: !sections.is_empty(),
+            sections,
+            ..Self::default()
+        }
+    }
+
+    /// Index of the section currently playing.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Called at each loop boundary; returns the bank to play for the coming pass.
+    pub fn advance(&mut self) -> Option<String> {
+        if !self.enabled || self.sections.is_empty() {
+            return None;
+        }
+        if self.position >= self.sections.len() {
+            self.position = 0;
+            self.pass = 0;
+        }
+        if self.pass >= self.sections[self.position].repeats.max(1) {
+            self.position = (self.position + 1) % self.sections.len();
+            self.pass = 0;
+        }
+        self.pass += 1;
+        Some(self.sections[self.position].bank.clone())
+    }
+
+    /// Moves a section to a new place in the order.
+    pub fn move_section(&mut self, from: usize, to: usize) {
+        if from < self.sections.len() && to < self.sections.len() && from != to {
+            let section = self.sections.remove(from);
+            self.sections.insert(to, section);
+        }
+    }
+}
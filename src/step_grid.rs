@@ -0,0 +1,119 @@
+//! Drawing of the step grid shared by the desktop window and the browser
+//! build: the cells of a pattern row and what is painted over them.
+
+use eframe::egui;
+
+use crate::model::{Pattern, Step};
+
+/// Beats per grid column.
+pub const RESOLUTION: f32 = 0.25;
+const BEATS_PER_BAR: f32 = 4.0;
+
+/// Fill of the cell at `beat`: the row color, dimmed for quiet steps and
+/// steps outside the active range, or highlighted under the playhead.
+pub fn cell_color(pattern: &Pattern, beat: f32, current_beat: Option<f32>, track_color: egui::Color32, empty_color: egui::Color32) -> egui::Color32 {
+    let is_active = pattern.has_step(beat);
+    let is_playing = current_beat.is_some_and(|current| current >= beat && current < beat + RESOLUTION);
+    if is_playing && is_active {
+        egui::Color32::YELLOW
+    } else if is_active && !pattern.is_active_at(beat) {
+        track_color.gamma_multiply(0.25)
+    } else if is_active {
+        // Dim steps whose velocity was set below the row default
+        match pattern.step_at(beat).velocity {
+            Some(velocity) => track_color.gamma_multiply((0.3 + 0.7 * velocity / 127.0).min(1.0)),
+            None => track_color,
+        }
+    } else {
+        empty_color
+    }
+}
+
+/// Draws the `size` square cell of `pattern` at `beat` and returns its rect.
+pub fn step_cell(ui: &mut egui::Ui, pattern: &Pattern, beat: f32, fill: egui::Color32, stroke: egui::Stroke, size: f32, empty_color: egui::Color32) -> egui::Rect {
+    let rect = egui::Frame::default()
+        .fill(fill)
+        .stroke(stroke)
+        .show(ui, |ui| {
+            ui.allocate_space(egui::vec2(size, size));
+        })
+        .response
+        .rect;
+    let step = pattern.step_at(beat);
+    if pattern.has_step(beat) && step.is_conditional() {
+        draw_condition_badge(ui, rect.shrink(1.0), &step, empty_color);
+    }
+    rect
+}
+
+/// Marks a stochastic or conditional step: a diagonal split for probability
+/// and a corner badge with the pass count for "every Nth" conditions.
+fn draw_condition_badge(ui: &egui::Ui, rect: egui::Rect, step: &Step, empty_color: egui::Color32) {
+    let painter = ui.painter();
+    if step.probability < 1.0 {
+        let split = vec![rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+        painter.add(egui::Shape::convex_polygon(split, empty_color.gamma_multiply(0.8), egui::Stroke::NONE));
+    }
+    if step.every > 1 {
+        let radius = (rect.width() / 4.0).max(2.0);
+        let center = rect.right_top() + egui::vec2(-radius, radius);
+        painter.circle_filled(center, radius, ui.visuals().strong_text_color());
+        if rect.width() >= 20.0 {
+            painter.text(
+                center,
+                egui::Align2::CENTER_CENTER,
+                step.every.to_string(),
+                egui::FontId::proportional(radius * 1.6),
+                ui.visuals().extreme_bg_color,
+            );
+        }
+    }
+}
+
+/// Distance between the left edges of neighbouring cells in `cells`, a
+/// rect spanning `columns` cells.
+pub fn cell_pitch(ui: &egui::Ui, cells: egui::Rect, columns: usize) -> f32 {
+    (cells.width() + ui.spacing().item_spacing.x) / columns.max(1) as f32
+}
+
+/// Note and loop lengths as bars across a row's cells, wrapping tails past the loop end.
+pub fn paint_lengths(ui: &egui::Ui, pattern: &Pattern, row: egui::Rect, pitch: f32, loop_beats: u32, color: egui::Color32) {
+    let spacing_x = ui.spacing().item_spacing.x;
+    let loop_end = loop_beats as f32;
+    let bar_height = row.height() / 3.0;
+    let y = row.center().y;
+    for step in pattern.steps.iter() {
+        let duration = step.duration.unwrap_or(pattern.duration);
+        let start = step.beat();
+        let end = start + duration.max(RESOLUTION);
+        let mut spans = vec![(start, end.min(loop_end))];
+        if end > loop_end {
+            spans.push((0.0, end - loop_end));
+        }
+        for (from, to) in spans {
+            let x0 = row.left() + (from / RESOLUTION) * pitch;
+            let x1 = row.left() + (to / RESOLUTION) * pitch - spacing_x;
+            let bar = egui::Rect::from_x_y_ranges(x0..=x1.max(x0), (y - bar_height / 2.0)..=(y + bar_height / 2.0));
+            ui.painter().rect_filled(bar, 2.0, color);
+        }
+    }
+}
+
+/// Bar separators every 4 beats, centered in the gap between cells.
+pub fn paint_bar_lines(ui: &egui::Ui, cells: egui::Rect, pitch: f32, loop_beats: u32) {
+    let spacing_x = ui.spacing().item_spacing.x;
+    let mut bar = BEATS_PER_BAR;
+    while bar < loop_beats as f32 {
+        let x = cells.left() + (bar / RESOLUTION) * pitch - spacing_x / 2.0;
+        ui.painter().vline(x, cells.y_range(), egui::Stroke::new(1.0, egui::Color32::GRAY));
+        bar += BEATS_PER_BAR;
+    }
+}
+
+/// Continuously moving playhead, positioned between cells by the
+/// interpolated beat; returns its x.
+pub fn paint_playhead(ui: &egui::Ui, cells: egui::Rect, pitch: f32, beat: f32) -> f32 {
+    let x = cells.left() + (beat / RESOLUTION) * pitch;
+    ui.painter().vline(x, cells.y_range(), egui::Stroke::new(2.0, egui::Color32::BLUE));
+    x
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// Loader pools by thread count, built on first use and kept for later loads.
+static LOADER_POOLS: Mutex<BTreeMap<usize, Arc<rayon::ThreadPool>>> = Mutex::new(BTreeMap::new());
+
+pub fn cpu_count() -> usize {
+    std::thread::available_parallelism().map_or(4, |count| count.get())
+}
+
+/// The pool of `count` threads decoding the files of bank loads.
+pub fn loader_pool(count: usize) -> Result<Arc<rayon::ThreadPool>, rayon::ThreadPoolBuildError> {
+    let mut pools = LOADER_POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&count) {
+        return Ok(Arc::clone(pool));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(count)
+        .thread_name(|index| format!("loader-{}", index))
+        .build()?;
+    let pool = Arc::new(pool);
+    pools.insert(count, Arc::clone(&pool));
+    Ok(pool)
+}
+
+/// Runs the parallel decoding of a bank load on `count` loader threads, or
+/// on rayon's global pool when they can't be started.
+pub fn on_loaders<R: Send>(count: usize, work: impl FnOnce() -> R + Send) -> R {
+    match loader_pool(count) {
+        Ok(pool) => pool.install(work),
+        Err(e) => {
+            log_warn!("Could not start {} loader threads, using the shared pool: {}", count, e);
+            work()
+        }
+    }
+}
+
+/// Starts `work` on `count` loader threads and returns right away, for
+/// loads the caller shouldn't wait on.
+pub fn spawn_on_loaders(count: usize, work: impl FnOnce() + Send + 'static) {
+    match loader_pool(count) {
+        Ok(pool) => pool.spawn(work),
+        Err(e) => {
+            log_warn!("Could not start {} loader threads, using the shared pool: {}", count, e);
+            rayon::spawn(work)
+        }
+    }
+}
+
+/// Moves the calling thread to real-time (FIFO) scheduling so ticks aren't
+/// delayed by other processes. Usually needs rtprio rights on Linux; when
+/// the OS refuses, the thread keeps its normal priority.
+#[cfg(unix)]
+pub fn raise_priority() {
+    // SAFETY: sched_param is plain data and the call only affects this thread
+    let result = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        let (min, max) = (libc::sched_get_priority_min(libc::SCHED_FIFO), libc::sched_get_priority_max(libc::SCHED_FIFO));
+        param.sched_priority = min + (max - min) / 2;
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+    };
+    match result {
+        0 => log!("Scheduler running at real-time priority"),
+        error => log_warn!(
+            "Could not raise the scheduler to real-time priority: {}",
+            std::io::Error::from_raw_os_error(error)
+        ),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_priority() {
+    log_warn!("Real-time priority is not supported on this platform");
+}
@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+use crate::logging::{self, Level};
+
+/// How long a toast stays up after its last occurrence.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+const MAX_TOASTS: usize = 5;
+
+struct Toast {
+    level: Level,
+    message: String,
+    count: usize,
+    raised: Instant,
+}
+
+/// Toast notifications for warnings and errors raised through the log macros.
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<Toast>,
+    latest: Option<(Level, String)>,
+}
+
+impl Toasts {
+    /// Collects new notifications, folding repeats of a visible one into a count.
+    pub fn update(&mut self) {
+        for (level, message) in logging::take_notifications() {
+            match self.toasts.iter_mut().find(|t| t.message == message) {
+                Some(toast) => {
+                    toast.count += 1;
+                    toast.raised = Instant::now();
+                }
+                None => self.toasts.push(Toast {
+                    level,
+                    message: message.clone(),
+                    count: 1,
+                    raised: Instant::now(),
+                }),
+            }
+            self.latest = Some((level, message));
+        }
+        self.toasts.retain(|t| t.raised.elapsed() < TOAST_DURATION);
+        if self.toasts.len() > MAX_TOASTS {
+            self.toasts.drain(..self.toasts.len() - MAX_TOASTS);
+        }
+    }
+
+    /// Most recent message, for the status bar.
+    pub fn latest(&self) -> Option<&(Level, String)> {
+        self.latest.as_ref()
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if self.toasts.is_empty() {
+            return;
+        }
+        let mut dismissed = None;
+        egui::Area::new("toasts")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (index, toast) in self.toasts.iter().enumerate() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(level_color(toast.level), level_text(toast.level));
+                            let text = if toast.count > 1 {
+                                format!("{} (x{})", toast.message, toast.count)
+                            } else {
+                                toast.message.clone()
+                            };
+                            ui.label(text);
+                            if ui.small_button("x").clicked() {
+                                dismissed = Some(index);
+                            }
+                        });
+                    });
+                }
+            });
+        if let Some(index) = dismissed {
+            self.toasts.remove(index);
+        }
+    }
+}
+
+pub fn level_color(level: Level) -> egui::Color32 {
+    match level {
+        Level::Warning => egui::Color32::from_rgb(242, 177, 52),
+        Level::Error => egui::Color32::from_rgb(232, 85, 60),
+    }
+}
+
+fn level_text(level: Level) -> &'static str {
+    match level {
+        Level::Warning => "Warning",
+        Level::Error => "Error",
+    }
+}
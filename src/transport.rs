@@ -0,0 +1,365 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI8, AtomicU32, Ordering},
+        Condvar, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scene::Scene;
+use crate::song::{Song, SongSection};
+
+/// Scheduler ticks per beat.
+pub const TICKS_PER_BEAT: u64 = 8;
+/// Beats in a bar, the longest stall the clock catches up on.
+const BAR_BEATS: u64 = 4;
+
+/// Taps further apart than this start a new tap tempo measurement.
+const TAP_RESET_SECS: f32 = 2.0;
+
+/// Time reference of the scheduler. Every tick is due a whole number of
+/// ticks after the epoch, so bar 500 starts where it should instead of
+/// where the rounding of 499 bars left it. A tempo change moves the epoch
+/// to the tick it takes effect on, and so does a stall of over a bar.
+pub struct Clock {
+    epoch: Instant,
+    bpm: u32,
+    /// Ticks played since the epoch.
+    ticks: u64,
+}
+
+impl Clock {
+    pub fn new(bpm: u32, epoch: Instant) -> Self {
+        Self { epoch, bpm: bpm.max(1), ticks: 0 }
+    }
+
+    /// Plays at this tempo from the next tick on.
+    pub fn set_bpm(&mut self, bpm: u32) {
+        let bpm = bpm.max(1);
+        if bpm != self.bpm {
+            self.epoch = self.next_due();
+            self.ticks = 0;
+            self.bpm = bpm;
+        }
+    }
+
+    /// When the next tick is due.
+    pub fn next_due(&self) -> Instant {
+        let nanos = self.ticks as u128 * 60_000_000_000 / (self.bpm as u128 * TICKS_PER_BEAT as u128);
+        self.epoch + Duration::from_nanos(nanos as u64)
+    }
+
+    /// How far behind its due time the next tick is.
+    pub fn lateness(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.next_due())
+    }
+
+    /// Moves on to the next tick.
+    pub fn advance(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Sleeps until the next tick is due. After a stall of more than a
+    /// bar the epoch moves to the next tick and it plays now, dropping
+    /// the missed ticks instead of firing them back-to-back.
+    pub fn sleep_until_due(&mut self) {
+        let lateness = self.lateness();
+        if lateness > Duration::from_nanos(BAR_BEATS * 60_000_000_000 / self.bpm as u64) {
+            log_warn!("The scheduler stalled for {} ms; skipping the missed ticks", lateness.as_millis());
+            self.epoch = Instant::now();
+            self.ticks = 0;
+        }
+        let remaining = self.next_due().saturating_duration_since(Instant::now());
+        if !remaining.is_zero() {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+/// Grid that live pad hits are held back to, so finger drumming lands in
+/// time with the sequence.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveQuantize {
+    #[default]
+    Off,
+    #[serde(rename = "16th")]
+    Sixteenth,
+    #[serde(rename = "8th")]
+    Eighth,
+    Beat,
+}
+
+impl LiveQuantize {
+    pub const ALL: [LiveQuantize; 4] = [LiveQuantize::Off, LiveQuantize::Sixteenth, LiveQuantize::Eighth, LiveQuantize::Beat];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LiveQuantize::Off => "off",
+            LiveQuantize::Sixteenth => "16th",
+            LiveQuantize::Eighth => "8th",
+            LiveQuantize::Beat => "beat",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|quantize| quantize.name() == name)
+            .ok_or(format!("Unknown quantize '{}'; try off, 16th, 8th or beat", name))
+    }
+
+    fn grid(self) -> Option<f32> {
+        match self {
+            LiveQuantize::Off => None,
+            LiveQuantize::Sixteenth => Some(0.25),
+            LiveQuantize::Eighth => Some(0.5),
+            LiveQuantize::Beat => Some(1.0),
+        }
+    }
+
+    /// Beats a hit at `beat` waits for the next grid point. Hits up to a
+    /// quarter of the grid late play right away, as they were meant for the
+    /// point just passed.
+    pub fn delay(self, beat: f32) -> f32 {
+        let Some(grid) = self.grid() else {
+            return 0.0;
+        };
+        let past = beat.rem_euclid(grid);
+        if past <= grid / 4.0 {
+            0.0
+        } else {
+            grid - past
+        }
+    }
+}
+
+/// Performance controls shared between the GUI and the scheduler.
+pub struct Transport {
+    bpm: AtomicU32,
+    transpose: AtomicI8,
+    live_quantize: RwLock<LiveQuantize>,
+    metronome: AtomicBool,
+    variation: AtomicU32,
+    fill_queued: AtomicBool,
+    pass: AtomicU32,
+    bars_left: RwLock<Option<u32>>,
+    /// When playback was let begin; never cleared.
+    started: Mutex<Option<Instant>>,
+    start_signal: Condvar,
+    active_bank: RwLock<String>,
+    queued_bank: RwLock<Option<String>>,
+    queued_scene: RwLock<Option<Scene>>,
+    pub song: RwLock<Song>,
+    /// Scenes to launch, from the config and captured while playing.
+    pub scenes: RwLock<Vec<Scene>>,
+}
+
+impl Transport {
+    pub fn new(bpm: u32, song: Vec<SongSection>, scenes: Vec<Scene>) -> Self {
+        Self {
+            bpm: AtomicU32::new(bpm),
+            transpose: AtomicI8::new(0),
+            live_quantize: RwLock::new(LiveQuantize::Off),
+            metronome: AtomicBool::new(false),
+            variation: AtomicU32::new(0),
+            fill_queued: AtomicBool::new(false),
+            pass: AtomicU32::new(0),
+            bars_left: RwLock::new(None),
+            started: Mutex::new(None),
+            start_signal: Condvar::new(),
+            active_bank: RwLock::new(String::new()),
+            queued_bank: RwLock::new(None),
+            queued_scene: RwLock::new(None),
+            song: RwLock::new(Song::new(song)),
+            scenes: RwLock::new(scenes),
+        }
+    }
+
+    pub fn bpm(&self) -> u32 {
+        self.bpm.load(Ordering::SeqCst)
+    }
+
+    pub fn set_bpm(&self, bpm: u32) {
+        self.bpm.store(bpm.clamp(20, 300), Ordering::SeqCst);
+    }
+
+    /// Semitones every MIDI pattern is shifted by.
+    pub fn transpose(&self) -> i8 {
+        self.transpose.load(Ordering::SeqCst)
+    }
+
+    pub fn set_transpose(&self, semitones: i8) {
+        self.transpose.store(semitones.clamp(-48, 48), Ordering::SeqCst);
+    }
+
+    pub fn live_quantize(&self) -> LiveQuantize {
+        *self.live_quantize.read().unwrap()
+    }
+
+    pub fn set_live_quantize(&self, quantize: LiveQuantize) {
+        *self.live_quantize.write().unwrap() = quantize;
+    }
+
+    pub fn metronome(&self) -> bool {
+        self.metronome.load(Ordering::SeqCst)
+    }
+
+    pub fn toggle_metronome(&self) {
+        self.metronome.fetch_xor(true, Ordering::SeqCst);
+    }
+
+    pub fn variation(&self) -> u32 {
+        self.variation.load(Ordering::SeqCst)
+    }
+
+    pub fn set_variation(&self, variation: u32) {
+        self.variation.store(variation, Ordering::SeqCst);
+    }
+
+    /// Called at the start of each loop pass; returns the number of the pass starting.
+    pub fn next_pass(&self) -> u32 {
+        self.pass.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Lets playback begin: the GUI's first frame, a Play action or the end
+    /// of a countdown. Later calls do nothing.
+    pub fn start(&self) {
+        let mut started = self.started.lock().unwrap();
+        if started.is_none() {
+            *started = Some(Instant::now());
+            self.start_signal.notify_all();
+        }
+    }
+
+    pub fn started(&self) -> bool {
+        self.started.lock().unwrap().is_some()
+    }
+
+    /// When `start` was first called.
+    pub fn started_at(&self) -> Option<Instant> {
+        *self.started.lock().unwrap()
+    }
+
+    /// Blocks until `start` is called or `running` is cleared.
+    pub fn wait_for_start(&self, running: &AtomicBool) {
+        let mut started = self.started.lock().unwrap();
+        while started.is_none() && running.load(Ordering::SeqCst) {
+            // Woken right away by `start`; the timeout only notices a stop
+            started = self.start_signal.wait_timeout(started, Duration::from_millis(200)).unwrap().0;
+        }
+    }
+
+    /// Stops playback after this many more bars; `None` plays until stopped.
+    pub fn set_bar_limit(&self, bars: Option<u32>) {
+        *self.bars_left.write().unwrap() = bars;
+    }
+
+    /// Called at the start of each bar; returns true once the bar limit is used up.
+    pub fn start_bar(&self) -> bool {
+        match self.bars_left.write().unwrap().as_mut() {
+            Some(0) => true,
+            Some(left) => {
+                *left -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Queues a fill for the next loop pass.
+    pub fn queue_fill(&self) {
+        self.fill_queued.store(true, Ordering::SeqCst);
+    }
+
+    pub fn active_bank(&self) -> String {
+        self.active_bank.read().unwrap().clone()
+    }
+
+    pub fn queued_bank(&self) -> Option<String> {
+        self.queued_bank.read().unwrap().clone()
+    }
+
+    /// Queues a bank to start at the next loop boundary.
+    pub fn queue_bank(&self, bank: &str) {
+        *self.queued_bank.write().unwrap() = Some(bank.to_string());
+    }
+
+    /// Name of the scene waiting for the next bar.
+    pub fn queued_scene(&self) -> Option<String> {
+        self.queued_scene.read().unwrap().as_ref().map(|scene| scene.name.clone())
+    }
+
+    /// Queues the named scene to start at the next bar.
+    pub fn queue_scene(&self, name: &str) -> Result<(), String> {
+        let scene = self.scenes.read().unwrap().iter().find(|s| s.name == name).cloned();
+        let scene = scene.ok_or_else(|| format!("No scene named '{}'", name))?;
+        log!("Queued scene '{}'", name);
+        *self.queued_scene.write().unwrap() = Some(scene);
+        Ok(())
+    }
+
+    /// Whether a scene is waiting to launch; the scheduler ends the pass at
+    /// the next bar when one is.
+    pub fn scene_queued(&self) -> bool {
+        self.queued_scene.read().unwrap().is_some()
+    }
+
+    /// Consumes the queued scene as its first pass starts, queueing its bank.
+    pub fn take_scene(&self) -> Option<Scene> {
+        let scene = self.queued_scene.write().unwrap().take()?;
+        self.queue_bank(&scene.bank);
+        Some(scene)
+    }
+
+    /// Called at the loop boundary: follows the song arrangement when song mode is on,
+    /// otherwise switches to the queued bank, falling back to the first available bank
+    /// when the active one no longer exists.
+    pub fn advance_bank(&self, banks: &[String]) -> String {
+        let mut active = self.active_bank.write().unwrap();
+        if let Some(section_bank) = self.song.write().unwrap().advance() {
+            *active = section_bank;
+            self.queued_bank.write().unwrap().take();
+        } else if let Some(queued) = self.queued_bank.write().unwrap().take() {
+            log!("Switching to bank '{}'", queued);
+            *active = queued;
+        }
+        if !banks.is_empty() && !banks.contains(&active) {
+            *active = banks[0].clone();
+        }
+        active.clone()
+    }
+
+    /// Consumes a queued fill, returning whether the coming loop pass should play it.
+    pub fn take_fill(&self) -> bool {
+        self.fill_queued.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Collects taps and derives a tempo from their average interval.
+#[derive(Default)]
+pub struct TapTempo {
+    taps: Vec<Instant>,
+}
+
+impl TapTempo {
+    pub fn tap(&mut self) -> Option<u32> {
+        let now = Instant::now();
+        if let Some(last) = self.taps.last() {
+            if now.duration_since(*last).as_secs_f32() > TAP_RESET_SECS {
+                self.taps.clear();
+            }
+        }
+        self.taps.push(now);
+        if self.taps.len() < 2 {
+            return None;
+        }
+
+        let span = now.duration_since(self.taps[0]).as_secs_f32();
+        let interval = span / (self.taps.len() - 1) as f32;
+        Some((60.0 / interval).round() as u32)
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    DefaultTerminal,
+};
+
+use crate::mixer::Mixer;
+use crate::model::Pattern;
+use crate::transport::{TapTempo, Transport};
+
+const RESOLUTION: f32 = 0.25;
+const POLL_INTERVAL: Duration = Duration::from_millis(30);
+
+/// Terminal front end sharing the engine state with the egui app.
+pub struct TerminalUi {
+    patterns: Arc<ArcSwap<Vec<Pattern>>>,
+    current_beat: Arc<RwLock<f32>>,
+    mixer: Arc<RwLock<Mixer>>,
+    transport: Arc<Transport>,
+    running: Arc<AtomicBool>,
+    tap_tempo: TapTempo,
+    loop_beats: u32,
+}
+
+impl TerminalUi {
+    pub fn new(
+        patterns: Arc<ArcSwap<Vec<Pattern>>>,
+        current_beat: Arc<RwLock<f32>>,
+        mixer: Arc<RwLock<Mixer>>,
+        transport: Arc<Transport>,
+        running: Arc<AtomicBool>,
+        loop_beats: u32,
+    ) -> Self {
+        Self {
+            patterns,
+            current_beat,
+            mixer,
+            transport,
+            running,
+            tap_tempo: TapTempo::default(),
+            loop_beats,
+        }
+    }
+
+    pub fn run(mut self) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while self.running.load(Ordering::SeqCst) {
+            terminal.draw(|frame| {
+                let paragraph = Paragraph::new(self.lines())
+                    .block(Block::default().borders(Borders::ALL).title("Rust 4x4 Groovebox"));
+                frame.render_widget(paragraph, frame.area());
+            })?;
+
+            if event::poll(POLL_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key.code);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 1-9 mute tracks, m metronome, t tap tempo, v variation, f fill,
+    /// p starts a held playback, +/- nudge the tempo, q quits.
+    fn handle_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c @ '1'..='9') => {
+                let index = c as usize - '1' as usize;
+                self.mixer.write().unwrap().toggle_mute(index);
+            }
+            KeyCode::Char('m') => self.transport.toggle_metronome(),
+            KeyCode::Char('t') => {
+                if let Some(bpm) = self.tap_tempo.tap() {
+                    self.transport.set_bpm(bpm);
+                }
+            }
+            KeyCode::Char('v') => {
+                self.transport.set_variation((self.transport.variation() + 1) % 4);
+            }
+            KeyCode::Char('f') => self.transport.queue_fill(),
+            KeyCode::Char('p') => self.transport.start(),
+            KeyCode::Char('+') => self.transport.set_bpm(self.transport.bpm() + 1),
+            KeyCode::Char('-') => self.transport.set_bpm(self.transport.bpm().saturating_sub(1)),
+            KeyCode::Char('q') | KeyCode::Esc => self.running.store(false, Ordering::SeqCst),
+            _ => {}
+        }
+    }
+
+    fn lines(&self) -> Vec<Line<'static>> {
+        let current_beat = *self.current_beat.read().unwrap();
+        let total_cols = (self.loop_beats as f32 / RESOLUTION) as usize;
+        let playing_col = (current_beat / RESOLUTION) as usize;
+        let active_bank = self.transport.active_bank();
+
+        let mut lines = vec![
+            Line::from(format!(
+                "{} BPM | bank {} | variation {} | metronome {} | beat {:.2}",
+                self.transport.bpm(),
+                if active_bank.is_empty() { "-" } else { active_bank.as_str() },
+                self.transport.variation() + 1,
+                if self.transport.metronome() { "on" } else { "off" },
+                current_beat,
+            )),
+            Line::from(""),
+        ];
+
+        let patterns = self.patterns.load();
+        let mixer = self.mixer.read().unwrap();
+        for pattern in patterns
+            .iter()
+            .filter(|p| p.sound.is_some() || p.loop_name.is_some())
+            .filter(|p| p.bank.as_ref().is_none_or(|b| *b == active_bank))
+        {
+            let track = pattern.track_name();
+            let key = mixer
+                .channel_index(track)
+                .filter(|i| *i < 9)
+                .map_or(" ".to_string(), |i| (i + 1).to_string());
+            let muted = mixer.gain(track) <= 0.0;
+
+            let mut spans = vec![Span::styled(
+                format!("{} {:<8} {} ", key, track, if muted { "M" } else { " " }),
+                if muted { Style::default().fg(Color::DarkGray) } else { Style::default() },
+            )];
+            for col in 0..total_cols {
+                let beat = col as f32 * RESOLUTION;
+                let symbol = if pattern.has_step(beat) { "■" } else { "·" };
+                let mut style = if muted { Style::default().fg(Color::DarkGray) } else { Style::default() };
+                if col == playing_col {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                if col % 16 == 0 && col > 0 {
+                    spans.push(Span::raw("|"));
+                }
+                spans.push(Span::styled(symbol, style));
+            }
+            lines.push(Line::from(spans));
+        }
+
+        lines.push(Line::from(""));
+        lines.push(Line::from("1-9 mute  m metronome  t tap  v variation  f fill  p play  +/- bpm  q quit"));
+        lines
+    }
+}
@@ -0,0 +1,94 @@
+use crate::model::Pattern;
+use crate::{LoopBank, SoundBank};
+
+/// Highest note number MIDI can carry.
+const MAX_MIDI_NOTE: u8 = 127;
+
+/// Line (1-based) of the first mention of `label` as a quoted value, to
+/// point at the pattern in the source file.
+fn line_of(content: &str, label: &str) -> Option<usize> {
+    let quoted = format!("\"{}\"", label);
+    let single = format!("'{}'", label);
+    content
+        .lines()
+        .position(|line| line.contains(&quoted) || line.contains(&single) || line.trim_end().ends_with(&format!(": {}", label)))
+        .map(|index| index + 1)
+}
+
+/// Checks loaded patterns against the banks and the loop length, returning
+/// one message per problem with file, line and field context. `content` is
+/// the source text of `path`, used to find line numbers.
+pub fn validate_patterns(
+    path: &str,
+    content: &str,
+    patterns: &[Pattern],
+    sound_bank: &SoundBank,
+    loop_bank: &LoopBank,
+    loop_beats: u32,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (index, pattern) in patterns.iter().enumerate() {
+        let track = pattern.track_name();
+        let (label, context) = match &pattern.name {
+            Some(name) => (name.as_str(), format!("pattern '{}'", name)),
+            None => (track, format!("pattern {} ({})", index + 1, track)),
+        };
+        let context = match line_of(content, label) {
+            Some(line) => format!("{}:{}: {}", path, line, context),
+            None => format!("{}: {}", path, context),
+        };
+        let mut report = |field: &str, message: String| problems.push(format!("{}: {}: {}", context, field, message));
+
+        if let Some(label) = &pattern.sound {
+            if !sound_bank.contains(label) {
+                report("sound", format!("unknown sound '{}'", label));
+            }
+        }
+        if let Some(label) = &pattern.loop_name {
+            if loop_bank.get(label).is_none() {
+                report("loop_name", format!("unknown loop '{}'", label));
+            }
+        }
+        if pattern.sound.is_none() && pattern.loop_name.is_none() && pattern.midi_note.is_none() {
+            report("sound", "no sound, loop_name or midi_note set".to_string());
+        }
+        if let Some(note) = pattern.midi_note.filter(|n| *n > MAX_MIDI_NOTE) {
+            report("midi_note", format!("{} is outside 0..={}", note, MAX_MIDI_NOTE));
+        }
+        if pattern.time_scale <= 0.0 {
+            report("time_scale", format!("{} is not a positive speed", pattern.time_scale));
+        }
+        if !(-1.0..=1.0).contains(&pattern.pan) {
+            report("pan", format!("{} is outside -1..=1", pattern.pan));
+        }
+        if pattern.output.is_some() && pattern.midi_note.is_none() {
+            report("output", "only MIDI notes can be routed to another port".to_string());
+        }
+        if let Some([start, end]) = pattern.active_range {
+            if start >= end || start < 0.0 || start >= loop_beats as f32 {
+                report("active_range", format!("[{}, {}] leaves nothing of the {}-beat loop", start, end, loop_beats));
+            }
+        }
+        for beat in pattern.accents.iter().filter(|beat| !pattern.has_step(**beat)) {
+            report("accents", format!("no step at beat {} to accent", beat));
+        }
+        if pattern.velocity_jitter < 0.0 {
+            report("velocity_jitter", format!("negative jitter {}", pattern.velocity_jitter));
+        }
+        if pattern.duration < 0.0 {
+            report("duration", format!("negative duration {}", pattern.duration));
+        }
+        for warning in &pattern.load_warnings {
+            report("steps", warning.clone());
+        }
+        for step in pattern.steps.iter() {
+            if step.beat() < 0.0 || step.beat() >= loop_beats as f32 {
+                report("steps", format!("beat {} is outside the {}-beat loop", step.position, loop_beats));
+            }
+            if let Some(duration) = step.duration.filter(|d| *d < 0.0) {
+                report("steps", format!("negative duration {} at beat {}", duration, step.position));
+            }
+        }
+    }
+    problems
+}
@@ -0,0 +1,323 @@
+//! Browser build: the demo kit and patterns on the desktop's step grid,
+//! played through Web Audio and WebMIDI. Build and serve it with
+//! `trunk serve web/index.html`.
+//!
+//! Browsers give wasm no threads to sleep on, so instead of the engine's
+//! scheduler thread the grid advances the loop every frame and queues the
+//! steps the patterns report due, as the engine does; triggers are as tight
+//! as the frame rate.
+
+use std::collections::HashSet;
+
+use eframe::egui;
+
+use crate::audio::{self, OutputStream, OutputStreamHandle};
+use crate::config::Config;
+use crate::logging;
+use crate::midi_io::{self, MidiOut};
+use crate::model::{Pattern, Ticks};
+use crate::step_grid::{self, RESOLUTION};
+use crate::{formats, play_sound, SoundBank, TICK, TICK_BEATS};
+
+const CONFIG: &str = include_str!("../config.json");
+const PATTERNS: &str = include_str!("../patterns.json");
+/// Warnings and errors kept on screen, as the page has no console output.
+const MAX_MESSAGES: usize = 3;
+const CELL_SIZE: f32 = 18.0;
+/// Width reserved for the mute checkboxes in front of the cells.
+const LABEL_WIDTH: f32 = 80.0;
+/// The demo kit, compiled in as the page can't read the sample directories.
+const SAMPLES: [(&str, &[u8]); 7] = [
+    ("909ch", include_bytes!("../sounds/samples/909ch.wav")),
+    ("bd", include_bytes!("../sounds/samples/bd.wav")),
+    ("claps", include_bytes!("../sounds/samples/claps.wav")),
+    ("devs", include_bytes!("../sounds/samples/devs.wav")),
+    ("hh", include_bytes!("../sounds/samples/hh.wav")),
+    ("hho", include_bytes!("../sounds/samples/hho.wav")),
+    ("sd", include_bytes!("../sounds/samples/sd.wav")),
+];
+
+/// Starts the grid on the canvas with id `canvas_id`.
+pub fn start(canvas_id: &'static str) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let runner = eframe::WebRunner::new();
+        let app: eframe::AppCreator = Box::new(|_cc| Box::new(WebApp::new()));
+        if let Err(e) = runner.start(canvas_id, eframe::WebOptions::default(), app).await {
+            log_error!("Failed to start the grid: {:?}", e);
+        }
+    });
+}
+
+/// Something to play once its time has come.
+enum Action {
+    Sound { label: String, velocity: f32 },
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+}
+
+struct WebApp {
+    patterns: Vec<Pattern>,
+    loop_beats: u32,
+    bpm: u32,
+    /// Semitones the MIDI patterns are shifted by.
+    transpose: i8,
+    muted: HashSet<String>,
+    sound_bank: SoundBank,
+    /// Opened on the first Play, as browsers only start audio after a click.
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    midi_out: Option<MidiOut>,
+    midi_port: String,
+    /// Beats played and the frame time they were counted at; None when stopped.
+    clock: Option<(f64, f64)>,
+    next_tick: u64,
+    /// Actions with the frame time they are due at.
+    pending: Vec<(f64, Action)>,
+    messages: Vec<String>,
+}
+
+impl WebApp {
+    fn new() -> Self {
+        let config = serde_json::from_str::<Config>(CONFIG).ok();
+        let loop_beats = config.as_ref().map_or(8, |config| config.loop_beats);
+        let patterns = formats::parse_patterns("patterns.json", PATTERNS, loop_beats).unwrap_or_else(|e| {
+            log_error!("Failed to parse the demo patterns: {}", e);
+            Vec::new()
+        });
+        let sound_bank = SoundBank::default();
+        for (label, bytes) in SAMPLES {
+            if let Err(e) = sound_bank.insert_wav(label, bytes) {
+                log_error!("Failed to load {}: {}", label, e);
+            }
+        }
+        WebApp {
+            patterns,
+            loop_beats,
+            bpm: 120,
+            transpose: config.map_or(0, |config| config.transpose),
+            muted: HashSet::new(),
+            sound_bank,
+            output: None,
+            midi_out: None,
+            midi_port: String::new(),
+            clock: None,
+            next_tick: 0,
+            pending: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    fn play(&mut self, now: f64) {
+        if self.output.is_none() {
+            match audio::open_output_stream(None) {
+                Ok(output) => self.output = Some(output),
+                Err(e) => log_error!("No audio output: {}", e),
+            }
+        }
+        self.clock = Some((0.0, now));
+        self.next_tick = 0;
+    }
+
+    fn stop(&mut self) {
+        self.clock = None;
+        // Release held notes, drop the rest
+        for (_, action) in std::mem::take(&mut self.pending) {
+            if let Action::NoteOff { .. } = action {
+                self.perform(action);
+            }
+        }
+    }
+
+    /// Moves the clock to `now` and queues the steps of every tick passed.
+    fn advance(&mut self, now: f64) {
+        let Some((beats, last)) = self.clock else {
+            return;
+        };
+        let beat_secs = 60.0 / self.bpm as f64;
+        let beats = beats + (now - last) / beat_secs;
+        self.clock = Some((beats, now));
+        let ticks_per_loop = (self.loop_beats as f32 / TICK_BEATS) as u64;
+        while self.next_tick as f64 * TICK_BEATS as f64 <= beats {
+            let tick = self.next_tick;
+            self.next_tick += 1;
+            // Where the tick fell, at most a frame ago
+            let tick_time = now - (beats - tick as f64 * TICK_BEATS as f64) * beat_secs;
+            let pass = (tick / ticks_per_loop) as u32;
+            let position = Ticks((tick % ticks_per_loop) as i32 * TICK.0);
+            self.schedule(pass, position, tick_time, beat_secs);
+        }
+    }
+
+    /// Queues the steps due at `position`, as the engine's scheduler does.
+    fn schedule(&mut self, pass: u32, position: Ticks, tick_time: f64, beat_secs: f64) {
+        for pattern in self.patterns.iter() {
+            if self.muted.contains(pattern.track_name()) || !pattern.is_enabled("", 0, false) {
+                continue;
+            }
+            for (step, delay) in pattern.due_steps(pass, position, TICK, self.loop_beats) {
+                let velocity = pattern.trigger_velocity(&step);
+                let start = tick_time + delay as f64 * beat_secs;
+                let ratchet = step.ratchet.max(1);
+                let interval = pattern.ratchet_beats(&step) as f64 * beat_secs;
+                for repeat in 0..ratchet {
+                    let at = start + repeat as f64 * interval;
+                    if let Some(note) = pattern.note(&step, self.transpose) {
+                        let duration = pattern.note_beats(&step, self.bpm) as f64 * beat_secs;
+                        let duration = if ratchet > 1 { duration.min(interval * 0.9) } else { duration };
+                        let velocity = velocity.clamp(0.0, 127.0) as u8;
+                        self.pending.push((at, Action::NoteOn { note, velocity }));
+                        self.pending.push((at + duration, Action::NoteOff { note }));
+                    } else if let Some(label) = &pattern.sound {
+                        self.pending.push((at, Action::Sound { label: label.clone(), velocity }));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Plays the queued actions due by `now`, in time order.
+    fn fire_due(&mut self, now: f64) {
+        let (mut due, later): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending).into_iter().partition(|(at, _)| *at <= now);
+        self.pending = later;
+        due.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, action) in due {
+            self.perform(action);
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::Sound { label, velocity } => {
+                if let Some((_, stream_handle)) = &self.output {
+                    play_sound(&label, velocity, 0.0, None, &self.sound_bank, stream_handle);
+                }
+            }
+            Action::NoteOn { note, velocity } => self.send_midi(&[0x90, note, velocity]),
+            Action::NoteOff { note } => self.send_midi(&[0x80, note, 0]),
+        }
+    }
+
+    fn send_midi(&mut self, message: &[u8]) {
+        if let Some(midi_out) = &mut self.midi_out {
+            if let Err(e) = midi_out.send(message) {
+                log_error!("MIDI send failed: {}", e);
+            }
+        }
+    }
+
+    fn transport_bar(&mut self, ui: &mut egui::Ui, now: f64) {
+        ui.horizontal(|ui| {
+            let playing = self.clock.is_some();
+            if ui.button(if playing { "Stop" } else { "Play" }).clicked() {
+                if playing {
+                    self.stop();
+                } else {
+                    self.play(now);
+                }
+            }
+            ui.add(egui::DragValue::new(&mut self.bpm).clamp_range(20..=300).suffix(" BPM"));
+            ui.add(egui::DragValue::new(&mut self.transpose).clamp_range(-48..=48).prefix("Transpose "));
+
+            // Ports show up once the browser granted MIDI access
+            let selected = if self.midi_port.is_empty() { "None" } else { self.midi_port.as_str() };
+            egui::ComboBox::from_label("MIDI out").selected_text(selected.to_string()).show_ui(ui, |ui| {
+                for port in midi_io::output_port_names() {
+                    if ui.selectable_label(port == self.midi_port, &port).clicked() {
+                        match MidiOut::connect(&port) {
+                            Ok(midi_out) => {
+                                self.midi_out = Some(midi_out);
+                                self.midi_port = port;
+                            }
+                            Err(e) => log_error!("Could not open {}: {}", port, e),
+                        }
+                    }
+                }
+            });
+
+            if ui.button("Copy patterns").on_hover_text("Copy the edited patterns as JSON").clicked() {
+                match formats::patterns_to_string("patterns.json", &self.patterns) {
+                    Ok(json) => ui.ctx().output_mut(|output| output.copied_text = json),
+                    Err(e) => log_error!("Could not copy the patterns: {}", e),
+                }
+            }
+        });
+    }
+
+    fn grid(&mut self, ui: &mut egui::Ui) {
+        let columns = (self.loop_beats as f32 / RESOLUTION) as usize;
+        let loop_beats = self.loop_beats;
+        let current_beat = self.clock.map(|(beats, _)| (beats % loop_beats as f64) as f32);
+        let track_color = ui.visuals().selection.bg_fill;
+        let empty_color = ui.visuals().widgets.inactive.bg_fill;
+        let stroke = egui::Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color);
+        let muted = &mut self.muted;
+        let patterns = &mut self.patterns;
+        egui::ScrollArea::both().show(ui, |ui| {
+            let mut rows = Vec::new();
+            for (index, pattern) in patterns.iter_mut().enumerate() {
+                let mut row: Option<egui::Rect> = None;
+                ui.horizontal(|ui| {
+                    let track = pattern.track_name().to_string();
+                    let mut is_muted = muted.contains(&track);
+                    let checkbox = egui::Checkbox::new(&mut is_muted, &track);
+                    if ui.add_sized(egui::vec2(LABEL_WIDTH, CELL_SIZE), checkbox).on_hover_text("Mute").changed() {
+                        if is_muted {
+                            muted.insert(track);
+                        } else {
+                            muted.remove(&track);
+                        }
+                    }
+                    for column in 0..columns {
+                        let beat = column as f32 * RESOLUTION;
+                        let fill = step_grid::cell_color(pattern, beat, current_beat, track_color, empty_color);
+                        let rect = step_grid::step_cell(ui, pattern, beat, fill, stroke, CELL_SIZE, empty_color);
+                        if ui.interact(rect, ui.id().with((index, column)), egui::Sense::click()).clicked() {
+                            let on = pattern.has_step(beat);
+                            pattern.set_beat(beat, !on);
+                        }
+                        row = Some(row.map_or(rect, |r| r.union(rect)));
+                    }
+                });
+                if let Some(row) = row {
+                    rows.push((index, row));
+                }
+            }
+
+            let Some(cells) = rows.iter().map(|(_, row)| *row).reduce(|a, b| a.union(b)) else {
+                return;
+            };
+            let pitch = step_grid::cell_pitch(ui, cells, columns);
+            for (index, row) in &rows {
+                step_grid::paint_lengths(ui, &patterns[*index], *row, pitch, loop_beats, track_color.gamma_multiply(0.6));
+            }
+            step_grid::paint_bar_lines(ui, cells, pitch, loop_beats);
+            if let Some(beat) = current_beat {
+                step_grid::paint_playhead(ui, cells, pitch, beat);
+            }
+        });
+    }
+}
+
+impl eframe::App for WebApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let now = ctx.input(|input| input.time);
+        self.advance(now);
+        self.fire_due(now);
+
+        self.messages.extend(logging::take_notifications().into_iter().map(|(_, message)| message));
+        let excess = self.messages.len().saturating_sub(MAX_MESSAGES);
+        self.messages.drain(..excess);
+
+        egui::TopBottomPanel::top("transport").show(ctx, |ui| self.transport_bar(ui, now));
+        if !self.messages.is_empty() {
+            egui::TopBottomPanel::bottom("messages").show(ctx, |ui| {
+                for message in &self.messages {
+                    ui.label(message);
+                }
+            });
+        }
+        egui::CentralPanel::default().show(ctx, |ui| self.grid(ui));
+        if self.clock.is_some() || !self.pending.is_empty() {
+            ctx.request_repaint();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use four_on_the_floor::chord::{ChordProgression, Voicing};
+use four_on_the_floor::model::{Pattern, PatternBuilder};
+
+fn beats(pattern: Pattern) -> Vec<f32> {
+    pattern.beats().collect()
+}
+
+#[test]
+fn repeat_copies_the_beats_every_bar() {
+    let pattern = PatternBuilder::new().beats(vec![0.0, 0.75]).repeat(2.0, 4).build();
+    assert_eq!(beats(pattern), vec![0.0, 0.75, 2.0, 2.75, 4.0, 4.75, 6.0, 6.75]);
+}
+
+#[test]
+fn every_rewrites_only_the_nth_bars() {
+    let pattern = PatternBuilder::new()
+        .beats(vec![0.0, 2.0])
+        .repeat(4.0, 4)
+        .every(2, |bar| bar.offset(1.0))
+        .build();
+    assert_eq!(beats(pattern), vec![0.0, 2.0, 5.0, 7.0, 8.0, 10.0, 13.0, 15.0]);
+}
+
+#[test]
+fn rotate_wraps_around_the_bar() {
+    let pattern = PatternBuilder::new().beats(vec![0.0, 3.5]).rotate(2).build();
+    assert_eq!(beats(pattern), vec![0.0, 0.5]);
+    let pattern = PatternBuilder::new().beats(vec![0.0, 1.0]).rotate(-1).build();
+    assert_eq!(beats(pattern), vec![0.75, 3.75]);
+}
+
+#[test]
+fn offset_drops_beats_moved_before_the_start() {
+    let pattern = PatternBuilder::new().beats(vec![0.0, 1.0, 2.5]).offset(-1.0).build();
+    assert_eq!(beats(pattern), vec![0.0, 1.5]);
+}
+
+#[test]
+fn chords_give_a_pattern_per_note() {
+    let notes = |patterns: Vec<Pattern>| patterns.iter().map(|p| p.midi_note.unwrap()).collect::<Vec<_>>();
+    let chord = PatternBuilder::new().name("stabs").beats(vec![0.25]).chord("C#m", 4).build_chord().unwrap();
+    assert_eq!(chord[0].name.as_deref(), Some("stabs.C#4"));
+    assert_eq!(notes(chord), vec![61, 64, 68]);
+    let inverted = PatternBuilder::new().chord("Am7", 3).inversion(1).spread(true).build_chord().unwrap();
+    assert_eq!(notes(inverted), vec![60, 67, 76, 81]);
+    assert!(PatternBuilder::new().chord("H", 4).build_chord().is_err());
+
+    let progression = ChordProgression::new(4)
+        .chord("A", vec![4.25])
+        .chord("Bb", vec![6.25])
+        .voicing(Voicing { inversion: 2, spread: false })
+        .build()
+        .unwrap();
+    assert_eq!(notes(progression), vec![76, 81, 85, 77, 82, 86]);
+}
+
+#[test]
+fn build_sorts_and_dedups_the_steps() {
+    let pattern = PatternBuilder::new().sound("hh").beats(vec![2.0, 0.5, 2.0, 1.0]).build();
+    assert_eq!(beats(pattern.clone()), vec![0.5, 1.0, 2.0]);
+    assert!(pattern.has_step(2.0));
+}
@@ -0,0 +1,81 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use four_on_the_floor::{
+    dispatch::{Dispatcher, TriggerJob},
+    instrument::{Instrument, Playback},
+    meter::LevelMeter,
+    Events,
+};
+
+/// Records when its notes start and end instead of sending them.
+#[derive(Default)]
+struct HeldNotes {
+    on: Mutex<Vec<Instant>>,
+    off: Mutex<Vec<Instant>>,
+}
+
+impl Instrument for HeldNotes {
+    fn trigger(&self, _note: u8, _velocity: f32, _duration: f32, _playback: &Playback) {
+        self.on.lock().unwrap().push(Instant::now());
+    }
+
+    fn holds_note(&self) -> bool {
+        true
+    }
+
+    fn release(&self, _note: u8) {
+        self.off.lock().unwrap().push(Instant::now());
+    }
+}
+
+fn job(instrument: &Arc<HeldNotes>, offset_secs: f32, ratchet: u32) -> TriggerJob {
+    TriggerJob {
+        instrument: Arc::clone(instrument) as Arc<dyn Instrument>,
+        track: "keys".to_string(),
+        note: 60,
+        velocity: 100.0,
+        // Half a second at 120 BPM
+        duration: 1.0,
+        playback: Playback { pan: 0.0, meter: Arc::new(LevelMeter::default()), bpm: 120 },
+        offset_secs,
+        ratchet,
+        interval_secs: 0.1,
+    }
+}
+
+#[test]
+fn held_notes_do_not_keep_workers_busy() {
+    let dispatcher = Dispatcher::new(Arc::new(Events::default()), 1, 1);
+    let notes = Arc::new(HeldNotes::default());
+    let start = Instant::now();
+    for _ in 0..4 {
+        dispatcher.dispatch(job(&notes, 0.0, 1));
+    }
+    dispatcher.drain();
+
+    let on = notes.on.lock().unwrap();
+    let off = notes.off.lock().unwrap();
+    assert_eq!((on.len(), off.len()), (4, 4));
+    assert!(on.iter().all(|at| *at - start < Duration::from_millis(250)), "every note starts on the single worker right away");
+    assert!(off.iter().all(|at| *at - start >= Duration::from_millis(500)), "notes end after their length");
+}
+
+#[test]
+fn offsets_and_ratchets_are_timed_without_a_worker() {
+    let dispatcher = Dispatcher::new(Arc::new(Events::default()), 1, 1);
+    let late = Arc::new(HeldNotes::default());
+    let now = Arc::new(HeldNotes::default());
+    let start = Instant::now();
+    dispatcher.dispatch(job(&late, 0.2, 3));
+    dispatcher.dispatch(job(&now, 0.0, 1));
+    dispatcher.drain();
+
+    assert!(*now.on.lock().unwrap().first().unwrap() - start < Duration::from_millis(150), "the offset step doesn't hold up the next one");
+    let repeats = late.on.lock().unwrap();
+    assert_eq!(repeats.len(), 3);
+    assert!(repeats[0] - start >= Duration::from_millis(200));
+    assert!(repeats[2] - repeats[0] >= Duration::from_millis(150), "repeats are an interval apart");
+}
@@ -0,0 +1,25 @@
+use four_on_the_floor::kit;
+
+#[test]
+fn plain_file_names_are_labels() {
+    let (label, settings) = kit::parse_file_name("bd.wav").unwrap();
+    assert_eq!(label, "bd");
+    assert!(settings.is_none());
+}
+
+#[test]
+fn file_names_carry_kit_settings() {
+    let (label, settings) = kit::parse_file_name("ohh@gain=0.8@tuning=-2@choke=hats@velocity=110.wav").unwrap();
+    let sample = settings.unwrap();
+    assert_eq!(label, "ohh");
+    assert_eq!(sample.file.as_deref(), Some("ohh@gain=0.8@tuning=-2@choke=hats@velocity=110.wav"));
+    assert_eq!((sample.gain, sample.tuning, sample.velocity), (0.8, -2.0, Some(110.0)));
+    assert_eq!(sample.choke.as_deref(), Some("hats"));
+}
+
+#[test]
+fn bad_file_settings_are_errors() {
+    assert!(kit::parse_file_name("bd@gain.wav").is_err());
+    assert!(kit::parse_file_name("bd@gain=loud.wav").is_err());
+    assert!(kit::parse_file_name("bd@pan=1.wav").is_err());
+}
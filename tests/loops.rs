@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use four_on_the_floor::loops::{self, key_shift, parse_key, LoopSample, Stretch};
+
+#[test]
+fn keys_parse_with_accidentals_and_qualities() {
+    assert_eq!(parse_key("C"), Ok((0, false)));
+    assert_eq!(parse_key("F#m"), Ok((6, true)));
+    assert_eq!(parse_key("Bb minor"), Ok((10, true)));
+    assert_eq!(parse_key("cb"), Ok((11, false)));
+    assert!(parse_key("H").is_err() && parse_key("Cdorian").is_err());
+}
+
+#[test]
+fn loops_shift_the_shorter_way_to_the_project_key() {
+    assert_eq!(key_shift("C", "D"), Ok(2));
+    assert_eq!(key_shift("C", "A"), Ok(-3));
+    assert_eq!(key_shift("Am", "C"), Ok(0));
+    assert_eq!(key_shift("Em", "Am"), Ok(5));
+}
+
+#[test]
+fn file_names_may_end_in_a_key() {
+    let meta = loops::parse_filename(Path::new("120_8_bass_Am.wav")).unwrap();
+    assert_eq!((meta.bpm, meta.key.as_deref(), meta.label.as_deref()), (Some(120.0), Some("Am"), Some("bass")));
+    assert_eq!(loops::parse_filename(Path::new("120_8_bass.wav")).unwrap().key, None);
+}
+
+#[test]
+fn stretching_keeps_the_level_and_scales_the_length() {
+    let samples: Vec<i16> = (0..44100).map(|i| ((i as f32 * 0.05).sin() * 10000.0) as i16).flat_map(|s| [s, s]).collect();
+    let stretched = loops::time_stretch(&samples, 2, 1.5);
+    assert_eq!(stretched.len(), 44100 * 3);
+    let peak = |samples: &[i16]| samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+    assert!(peak(&stretched) <= 10000 && peak(&stretched) > 8000);
+}
+
+#[test]
+fn stretch_policies_pick_samples_and_speed() {
+    let play = |stretch| {
+        let sample = LoopSample {
+            samples: vec![0; 44100],
+            channels: 1,
+            sample_rate: 44100,
+            bpm: 120.0,
+            beats: None,
+            key: None,
+            pitch: 1.0,
+            stretch,
+            stretched: Default::default(),
+        };
+        sample.with_playback(90, |samples, speed| (samples.len(), speed))
+    };
+    assert_eq!(play(Stretch::Repitch), (44100, 0.75));
+    assert_eq!(play(Stretch::Off), (44100, 1.0));
+    let (length, speed) = play(Stretch::Stretch);
+    assert_eq!(speed, 1.0);
+    assert!((length as i32 - 58800).abs() <= 1);
+}
@@ -0,0 +1,349 @@
+use std::{sync::Arc, thread};
+
+use arc_swap::ArcSwap;
+use four_on_the_floor::edit_patterns;
+use four_on_the_floor::error::Error;
+use four_on_the_floor::config;
+use four_on_the_floor::formats::{self, Format};
+use four_on_the_floor::mixer::Mixer;
+use four_on_the_floor::model::{self, Instrument, Pattern, PatternBuilder, Step, Ticks, Track};
+use four_on_the_floor::remote;
+use four_on_the_floor::TICK;
+use four_on_the_floor::session::Session;
+
+const HAND_WRITTEN: &str = r#"[
+    {
+        "sound": "bd",
+        "steps": [
+            0.0,
+            2.0,
+            {
+                "position": 2.75,
+                "velocity": 40.0,
+                "probability": 0.5
+            },
+            3.25
+        ],
+        "velocity": 70.0,
+        "duration": 0.25
+    },
+    {
+        "name": "bass",
+        "track": "synth",
+        "midi_note": 36,
+        "steps": [
+            1.5,
+            {
+                "position": 3.5,
+                "ratchet": 2,
+                "pitch": -12,
+                "offset": 0.05,
+                "every": 2
+            }
+        ],
+        "velocity": 100.0,
+        "duration": 0.5,
+        "variation": 1,
+        "fill": true,
+        "bank": "B",
+        "time_scale": 0.5
+    }
+]"#;
+
+fn patterns() -> Vec<Pattern> {
+    let mut bd = PatternBuilder::new().sound("bd").beats(vec![0.0, 1.0, 2.5]).velocity(90.0).build();
+    bd.set_step(Step { velocity: Some(60.0), duration: Some(0.125), ..Step::new(1.0) });
+    let mut lead = PatternBuilder::new().name("lead").midi_note(60).beats(vec![0.0, 3.75]).variation(2).bank("A").build();
+    lead.set_step(Step { probability: 0.25, ratchet: 3, pitch: 7, offset: 0.1, every: 4, ..Step::new(3.75) });
+    lead.track = Some("keys".to_string());
+    lead.time_scale = 2.0;
+    let fill = PatternBuilder::new().loop_name("break").beats(vec![4.0]).fill(true).build();
+    vec![bd, lead, fill]
+}
+
+fn round_trip(path: &str) {
+    let written = formats::patterns_to_string(path, &patterns()).unwrap();
+    let read = formats::parse_patterns(path, &written, 16).unwrap();
+    assert_eq!(read, patterns(), "{} read back differently:\n{}", path, written);
+}
+
+#[test]
+fn json_round_trip() {
+    round_trip("patterns.json");
+}
+
+#[test]
+fn toml_round_trip() {
+    round_trip("patterns.toml");
+}
+
+#[test]
+fn yaml_round_trip() {
+    round_trip("patterns.yaml");
+}
+
+#[test]
+fn hand_written_file_is_written_back_unchanged() {
+    let patterns = formats::parse_patterns("patterns.json", HAND_WRITTEN, 8).unwrap();
+    assert_eq!(formats::patterns_to_string("patterns.json", &patterns).unwrap(), HAND_WRITTEN);
+}
+
+#[test]
+fn plain_steps_are_written_as_positions() {
+    let pattern = PatternBuilder::new().sound("hh").beats(vec![0.0, 0.5]).build();
+    let json = serde_json::to_string(&pattern).unwrap();
+    assert_eq!(json, r#"{"sound":"hh","steps":[0.0,0.5],"velocity":100.0,"duration":0.25}"#);
+}
+
+#[test]
+fn older_files_read_into_steps() {
+    let old = r#"[{"sound": "sd", "beats": [1.0, 3.0], "steps": [{"beat": 3.0, "velocity": 50.0}, {"beat": 2.0, "ratchet": 2}]}]"#;
+    let mut patterns: Vec<Pattern> = Format::Json.parse("patterns.json", old).unwrap();
+    let expected = vec![Step::new(1.0), Step { velocity: Some(50.0), ..Step::new(3.0) }];
+    assert_eq!(patterns[0].steps, expected);
+    // The override off the beats is dropped, but validation still hears of it
+    assert_eq!(patterns[0].load_warnings, vec!["settings for beat 2 which is not in beats".to_string()]);
+    patterns[0].load_warnings.clear();
+
+    let written = formats::patterns_to_string("patterns.json", &patterns).unwrap();
+    assert!(!written.contains("beats"));
+    assert_eq!(formats::parse_patterns("patterns.json", &written, 8).unwrap(), patterns);
+}
+
+#[test]
+fn velocity_lists_spread_over_the_steps() {
+    let file = r#"[
+        {"sound": "hh", "steps": [0, 0.5, 1, 1.5, {"position": 2, "velocity": 20}, 2.5], "velocities": [100, 60, 80]},
+        {"sound": "sd", "sequence": "x.x.x.x.", "velocity": 50, "velocities": "X..."}
+    ]"#;
+    let patterns = formats::parse_patterns("patterns.json", file, 8).unwrap();
+    let velocities = |pattern: &Pattern| pattern.steps.iter().map(|step| step.velocity).collect::<Vec<_>>();
+    assert_eq!(velocities(&patterns[0]), vec![Some(100.0), Some(60.0), Some(80.0), Some(100.0), Some(20.0), Some(80.0)]);
+    assert_eq!(velocities(&patterns[1])[..5], [Some(65.0), None, None, None, Some(65.0)]);
+    assert_eq!(patterns[1].velocities, None);
+}
+
+#[test]
+fn transpose_shifts_midi_notes() {
+    let mut bass = PatternBuilder::new().midi_note(36).beats(vec![0.0, 2.0]).build();
+    bass.transpose = -2;
+    bass.set_step(Step { pitch: 12, ..Step::new(2.0) });
+    assert_eq!(bass.note(&bass.step_at(0.0), 0), Some(34));
+    assert_eq!(bass.note(&bass.step_at(2.0), 5), Some(51));
+    assert_eq!(bass.note(&bass.step_at(0.0), -48), Some(0));
+    assert_eq!(PatternBuilder::new().sound("bd").build().note(&Step::new(0.0), 3), None);
+}
+
+#[test]
+fn velocity_jitter_stays_within_range() {
+    let mut hats = PatternBuilder::new().sound("hh").beats(vec![0.0]).velocity(60.0).build();
+    assert_eq!(hats.trigger_velocity(&Step::new(0.0)), 60.0);
+    hats.velocity_jitter = 15.0;
+    let velocities: Vec<f32> = (0..200).map(|_| hats.trigger_velocity(&Step::new(0.0))).collect();
+    assert!(velocities.iter().all(|v| (45.0..=75.0).contains(v)));
+    assert!(velocities.iter().any(|v| *v != 60.0));
+}
+
+#[test]
+fn accents_boost_their_beats() {
+    let file = r#"[{"sound": "hh", "steps": [0, 1, {"position": 2, "velocity": 40}, 3], "velocity": 80, "accents": [0, 2], "accent_boost": 30}]"#;
+    let patterns = formats::parse_patterns("patterns.json", file, 4).unwrap();
+    let velocities: Vec<f32> = patterns[0].steps.iter().map(|step| patterns[0].trigger_velocity(step)).collect();
+    assert_eq!(velocities, vec![110.0, 80.0, 70.0, 80.0]);
+    assert_eq!(formats::patterns_to_string("patterns.json", &patterns).unwrap().matches("accent").count(), 2);
+}
+
+#[test]
+fn positions_compare_exactly() {
+    let mut pattern = PatternBuilder::new().sound("bd").build();
+    pattern.set_beat(0.1 + 0.2, true);
+    assert!(pattern.has_step(0.3));
+    pattern.set_beat(0.3, false);
+    assert!(pattern.steps.is_empty());
+}
+
+#[test]
+fn triplets_are_due_between_ticks() {
+    let file = r#"[{"sound": "hh", "notation": "[hh hh hh] ~ ~ ~"}]"#;
+    let pattern = &formats::parse_patterns("patterns.json", file, 4).unwrap()[0];
+    let due: Vec<(Step, f32)> = (0..8).flat_map(|tick| pattern.due_steps(0, Ticks(tick * TICK.0), TICK, 4)).collect();
+    assert_eq!(due.len(), 3);
+    let (step, delay) = &due[1];
+    assert!((step.beat() - 1.0 / 3.0).abs() < 1e-6 && (*delay - (1.0 / 3.0 - 0.25)).abs() < 1e-6);
+    assert_eq!(formats::parse_patterns("patterns.json", &formats::patterns_to_string("patterns.json", std::slice::from_ref(pattern)).unwrap(), 4).unwrap()[0], *pattern);
+}
+
+#[test]
+fn due_steps_follow_every_and_offset() {
+    let mut pattern = PatternBuilder::new().sound("bd").build();
+    let mut step = Step::new(0.0);
+    step.every = 2;
+    step.offset = 0.0625;
+    pattern.steps.push(step);
+    assert!(pattern.due_steps(1, Ticks(0), TICK, 4).is_empty());
+    let due = pattern.due_steps(2, Ticks(0), TICK, 4);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].1, 0.0625);
+}
+
+#[test]
+fn notation_names_namespaced_sounds() {
+    let file = r#"[{"notation": "909/snare ~ bd ~"}]"#;
+    let patterns = formats::parse_patterns("patterns.json", file, 4).unwrap();
+    let sounds: Vec<_> = patterns.iter().filter_map(|pattern| pattern.sound.as_deref()).collect();
+    assert_eq!(sounds, vec!["909/snare", "bd"]);
+}
+
+#[test]
+fn reload_diffs_say_what_changed() {
+    let old = vec![
+        PatternBuilder::new().sound("hh").beats(vec![0.0, 2.0]).build(),
+        PatternBuilder::new().sound("cp").beats(vec![1.0]).build(),
+    ];
+    let mut hats = PatternBuilder::new().sound("hh").beats(vec![0.0, 3.5]).velocity(80.0).build();
+    hats.set_step(Step { ratchet: 2, ..Step::new(0.0) });
+    let new = vec![hats, PatternBuilder::new().sound("bd").beats(vec![0.0]).build()];
+    let changes = model::diff_patterns(&old, &new);
+    assert_eq!(
+        changes.to_string(),
+        "added bd; removed cp; hh: changed step at 0, added step at 3.5, removed step at 2, velocity 100.0 -> 80.0"
+    );
+    assert!(model::diff_patterns(&new, &new).is_empty());
+}
+
+#[test]
+fn concurrent_edits_are_not_lost() {
+    let patterns = Arc::new(ArcSwap::from_pointee(Vec::new()));
+    let editors: Vec<_> = (0..8)
+        .map(|_| {
+            let patterns = Arc::clone(&patterns);
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    edit_patterns(&patterns, |patterns| patterns.push(PatternBuilder::new().sound("bd").build()));
+                }
+            })
+        })
+        .collect();
+    for editor in editors {
+        editor.join().unwrap();
+    }
+    assert_eq!(patterns.load().len(), 400);
+}
+
+#[test]
+fn velocity_edits_survive_reloads() {
+    let mut session = Session::new();
+    session.record_velocity_edit("bd", 64.0);
+    let mut reloaded = vec![PatternBuilder::new().sound("bd").velocity(100.0).build()];
+    session.apply(&mut reloaded);
+    assert_eq!(reloaded[0].velocity, 64.0);
+}
+
+#[test]
+fn unsorted_steps_are_sorted_and_merged_on_read() {
+    let content = r#"[{"sound": "bd", "steps": [3, 1, {"position": 1, "velocity": 50}, 1]}]"#;
+    let mut patterns: Vec<Pattern> = Format::Json.parse("patterns.json", content).unwrap();
+    let pattern = &mut patterns[0];
+    assert_eq!(pattern.beats().collect::<Vec<_>>(), vec![1.0, 3.0]);
+    assert_eq!(pattern.step_at(1.0).velocity, Some(50.0), "settings win over a plain duplicate");
+    assert_eq!(pattern.load_warnings.len(), 2);
+
+    pattern.set_beat(2.0, true);
+    pattern.set_beat(3.0, false);
+    assert_eq!(pattern.beats().collect::<Vec<_>>(), vec![1.0, 2.0]);
+}
+
+
+#[test]
+fn saved_projects_keep_their_tracks() {
+    let declared = Track {
+        name: "drums".to_string(),
+        instrument: Instrument { sound: Some("bd".to_string()), ..Default::default() },
+        color: Some("#ff8800".to_string()),
+        mute: false,
+        gain: 0.8,
+        patterns: vec![PatternBuilder::new().beats(vec![0.0, 2.0]).build()],
+    };
+    let loose = PatternBuilder::new().sound("hh").beats(vec![0.5]).build();
+    let mut playing = vec![loose.clone()];
+    playing.extend(declared.flatten());
+
+    // Saving regroups what plays by the declared tracks, with the live mixer settings
+    let mut mixer = Mixer::new();
+    mixer.apply_tracks(std::slice::from_ref(&declared));
+    mixer.channels_mut().for_each(|(_, strip)| strip.mute = true);
+    let mut tracks = mixer.tracks();
+    let mut patterns = playing.clone();
+    for track in tracks.iter_mut() {
+        track.gather(&mut patterns);
+    }
+    let path = std::env::temp_dir().join(format!("four_on_the_floor-tracks-{}.fotf", std::process::id()));
+    let path = path.to_str().unwrap();
+    let config = config::read_config("config.json").unwrap();
+    config::write_project(path, &config, &patterns, &tracks).unwrap();
+
+    let content = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let loaded = formats::parse_tracks(path, &content).unwrap();
+    assert_eq!(loaded, vec![Track { mute: true, ..declared }]);
+    assert_eq!(formats::parse_patterns(path, &content, 8).unwrap(), playing);
+}
+
+#[test]
+fn presets_declared_in_a_pattern_file_are_listed() {
+    let content = r#"{"presets": {"shuffle": {"bd": "x..x..x.", "sn": "....x..."}}, "patterns": []}"#;
+    let presets = formats::parse_presets("presets.json", content).unwrap();
+    assert_eq!(presets["shuffle"]["bd"], "x..x..x.");
+    assert_eq!(presets["shuffle"].len(), 2);
+}
+
+#[test]
+fn saving_settings_keeps_a_malformed_project() {
+    let path = std::env::temp_dir().join(format!("four_on_the_floor-malformed-{}.fotf", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, r#"{"config": {"#).unwrap();
+    let config = config::read_config("config.json").unwrap();
+    assert!(config::write_config(path, &config).is_err());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), r#"{"config": {"#);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn moved_steps_keep_their_settings() {
+    let mut pattern = PatternBuilder::new().sound("bd").beats(vec![0.0, 1.0]).build();
+    let mut step = pattern.step_at(1.0);
+    step.velocity = Some(40.0);
+    step.ratchet = 3;
+    pattern.set_step(step);
+    let moved = pattern.remove_step(1.0).unwrap();
+    assert!(pattern.remove_step(1.0).is_none());
+    pattern.insert_step(Step { position: Ticks::from_beats(0.5), ..moved });
+    assert_eq!(pattern.beats().collect::<Vec<_>>(), vec![0.0, 0.5]);
+    assert_eq!((pattern.step_at(0.5).velocity, pattern.step_at(0.5).ratchet), (Some(40.0), 3));
+}
+
+#[test]
+fn remote_loads_stay_in_the_project() {
+    let config = config::read_config("config.json").unwrap();
+    let check = |path: &str| remote::check_pattern_file(&config.base_dir, path, config.loop_beats);
+    assert!(check("patterns.json").is_ok());
+
+    let outside = std::env::temp_dir().join(format!("four_on_the_floor-outside-{}.json", std::process::id()));
+    std::fs::copy("patterns.json", &outside).unwrap();
+    assert!(check(outside.to_str().unwrap()).is_err(), "valid patterns outside the project are refused");
+    std::fs::remove_file(&outside).unwrap();
+
+    let error = check("src/main.rs").unwrap_err();
+    assert!(matches!(error, Error::PatternFileRejected { .. }));
+    assert!(!error.to_string().contains("use "), "the error doesn't quote the file: {}", error);
+}
+
+#[test]
+fn config_errors_name_the_file() {
+    assert!(matches!(config::read_config("missing.json"), Err(Error::Io { path, .. }) if path == "missing.json"));
+    let path = std::env::temp_dir().join(format!("four_on_the_floor-broken-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "{").unwrap();
+    assert!(matches!(config::read_config(path), Err(Error::Parse { path: failed, .. }) if failed == path));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,40 @@
+use std::fs;
+
+use four_on_the_floor::config::ThreadConfig;
+use four_on_the_floor::labels::DuplicateLabels;
+use four_on_the_floor::{sample_cache, SoundBank};
+
+#[test]
+fn decoded_samples_are_cached_by_content() {
+    let root = std::env::temp_dir().join(format!("four_on_the_floor-cache-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let (samples, cache) = (root.join("samples"), root.join("cache"));
+    fs::create_dir_all(&samples).unwrap();
+    let spec = hound::WavSpec { channels: 2, sample_rate: 48000, bits_per_sample: 24, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::create(samples.join("bd.wav"), spec).unwrap();
+    for sample in [0, 1 << 20, -(1 << 20), 1 << 8] {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    sample_cache::configure(Some(cache.clone()));
+    let decoded = SoundBank::new(samples.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap().get("bd").unwrap();
+    let key = sample_cache::content_key(&fs::read(samples.join("bd.wav")).unwrap(), "");
+    assert_eq!(sample_cache::read(&cache, &key).as_ref(), Some(&*decoded));
+    let file_key = sample_cache::file_key(&samples.join("bd.wav"), "").unwrap();
+    assert_eq!(sample_cache::resolve(&cache, &file_key), Some(key.clone()));
+
+    // A renamed file keeps its cache entry
+    fs::rename(samples.join("bd.wav"), samples.join("kick.wav")).unwrap();
+    let cached = SoundBank::new(samples.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap().get("kick").unwrap();
+    assert_eq!(cached, decoded);
+    let entries = fs::read_dir(&cache).unwrap().filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|e| e == "pcm"));
+    assert_eq!(entries.count(), 1);
+    let file_key = sample_cache::file_key(&samples.join("kick.wav"), "").unwrap();
+    assert_eq!(sample_cache::resolve(&cache, &file_key), Some(key));
+
+    // Processing settings keep entries apart
+    assert_ne!(sample_cache::content_key(b"same", ""), sample_cache::content_key(b"same", "time_stretch 1.5"));
+    sample_cache::configure(None);
+    fs::remove_dir_all(root).unwrap();
+}
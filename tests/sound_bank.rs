@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use four_on_the_floor::config::ThreadConfig;
+use four_on_the_floor::error::Error;
+use four_on_the_floor::labels::DuplicateLabels;
+use four_on_the_floor::SoundBank;
+
+fn write_wav(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let spec = hound::WavSpec { channels: 1, sample_rate: 44100, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    writer.write_sample(0i16).unwrap();
+    writer.finalize().unwrap();
+}
+
+fn sample_dir(name: &str, files: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("four_on_the_floor-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for file in files {
+        write_wav(&dir.join(file));
+    }
+    dir
+}
+
+#[test]
+fn nested_folders_are_namespaced() {
+    let dir = sample_dir("nested", &["bd.wav", "snares/909/snare.wav", "kicks/808.wav", ".hidden/hh.wav"]);
+    let bank = SoundBank::new(dir.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap();
+    assert_eq!(bank.labels(), vec!["bd", "kicks/808", "snares/909/snare"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn file_name_settings_apply_to_the_label() {
+    let dir = sample_dir("settings", &["perc/rim@velocity=90.wav"]);
+    let bank = SoundBank::new(dir.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap();
+    assert_eq!(bank.labels(), vec!["perc/rim"]);
+    assert_eq!(bank.fixed_velocity("perc/rim"), Some(90.0));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn lazy_banks_decode_on_demand() {
+    let dir = sample_dir("lazy", &["bd.wav", "sd.wav", "hats/ch.wav"]);
+    let bank = SoundBank::lazy(dir.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap();
+    assert_eq!(bank.labels(), vec!["bd", "hats/ch", "sd"]);
+    assert!(bank.contains("sd") && !bank.contains("cp"));
+    bank.preload(["bd"]);
+    assert!(bank.get("hats/ch").is_some());
+    bank.prefetch();
+    assert_eq!(bank.labels(), vec!["bd", "hats/ch", "sd"]);
+    assert!(bank.get("sd").is_some());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn background_preloads_leave_the_bank_usable() {
+    let dir = sample_dir("background", &["bd.wav", "sd.wav"]);
+    let bank = Arc::new(SoundBank::lazy(dir.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap());
+    bank.preload_in_background(["bd", "bd", "sd", "cp"]);
+    assert!(bank.get("bd").is_some() && bank.get("sd").is_some());
+    bank.preload_in_background(["bd"]);
+    assert_eq!(bank.labels(), vec!["bd", "sd"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn new_files_are_added_once_settled() {
+    let dir = sample_dir("new-files", &["bd.wav"]);
+    let bank = SoundBank::new(dir.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).unwrap();
+    write_wav(&dir.join("toms/lo.wav"));
+    assert!(bank.load_new_files().unwrap().is_empty(), "a file just written is not picked up yet");
+
+    let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::File::options().write(true).open(dir.join("toms/lo.wav")).unwrap().set_modified(an_hour_ago).unwrap();
+    assert_eq!(bank.load_new_files().unwrap(), vec!["toms/lo"]);
+    assert!(bank.get("toms/lo").is_some());
+    assert!(bank.load_new_files().unwrap().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn duplicate_labels_follow_the_configured_handling() {
+    let first = sample_dir("duplicates-first", &["bd.wav", "sd.wav"]);
+    let second = sample_dir("duplicates-909", &["bd.wav", "bd@gain=0.5.wav"]);
+    let directories = std::env::join_paths([&first, &second]).unwrap();
+    let load = |duplicates| SoundBank::new(directories.to_str().unwrap(), &ThreadConfig::default(), duplicates).unwrap().labels();
+    let prefix = format!("{}/bd", second.file_name().unwrap().to_str().unwrap());
+    assert_eq!(load(DuplicateLabels::First), vec!["bd", "sd"]);
+    assert_eq!(load(DuplicateLabels::Suffix), vec!["bd", "bd-2", "bd-3", "sd"]);
+    assert_eq!(load(DuplicateLabels::Directory), vec!["bd".to_string(), prefix.clone(), format!("{}-2", prefix), "sd".to_string()]);
+    fs::remove_dir_all(first).unwrap();
+    fs::remove_dir_all(second).unwrap();
+}
+
+#[test]
+fn missing_directories_name_the_path() {
+    let missing = std::env::temp_dir().join(format!("four_on_the_floor-missing-{}", std::process::id()));
+    let error = SoundBank::new(missing.to_str().unwrap(), &ThreadConfig::default(), DuplicateLabels::First).err();
+    assert!(matches!(error, Some(Error::Io { path, .. }) if path == missing.display().to_string()));
+}
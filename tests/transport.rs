@@ -0,0 +1,81 @@
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use four_on_the_floor::transport::{Clock, LiveQuantize, Transport};
+
+#[test]
+fn live_hits_wait_for_the_next_grid_point() {
+    assert_eq!(LiveQuantize::Off.delay(1.3), 0.0);
+    assert_eq!(LiveQuantize::Sixteenth.delay(1.125), 0.125);
+    assert_eq!(LiveQuantize::Eighth.delay(1.05), 0.0, "slightly late hits play right away");
+    assert_eq!(LiveQuantize::Beat.delay(2.5), 0.5);
+    assert_eq!(LiveQuantize::parse("8th"), Ok(LiveQuantize::Eighth));
+    assert!(LiveQuantize::parse("32nd").is_err());
+}
+
+#[test]
+fn bar_limit_stops_after_the_given_bars() {
+    let transport = Transport::new(120, Vec::new(), Vec::new());
+    assert!(!transport.start_bar());
+
+    transport.set_bar_limit(Some(2));
+    assert!(!transport.start_bar());
+    assert!(!transport.start_bar());
+    assert!(transport.start_bar());
+    assert!(transport.start_bar());
+}
+
+#[test]
+fn playback_waits_for_the_transport_to_start() {
+    let transport = Arc::new(Transport::new(120, Vec::new(), Vec::new()));
+    let starter = Arc::clone(&transport);
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        starter.start();
+    });
+    transport.wait_for_start(&AtomicBool::new(true));
+    assert!(transport.started());
+
+    let stopped = Transport::new(120, Vec::new(), Vec::new());
+    let waited = Instant::now();
+    stopped.wait_for_start(&AtomicBool::new(false));
+    assert!(!stopped.started());
+    assert!(waited.elapsed() < Duration::from_millis(100), "a stop doesn't wait for a start");
+}
+
+#[test]
+fn ticks_stay_on_the_grid_over_long_runs() {
+    let epoch = Instant::now();
+    let mut clock = Clock::new(133, epoch);
+    // 500 bars of 4 beats, 8 ticks each
+    for _ in 0..500 * 32 {
+        clock.advance();
+    }
+    assert_eq!(clock.next_due() - epoch, Duration::from_nanos(2000 * 60_000_000_000 / 133));
+
+    // A tempo change counts on from the tick it takes effect on
+    let changed_at = clock.next_due();
+    clock.set_bpm(120);
+    for _ in 0..8 {
+        clock.advance();
+    }
+    assert_eq!(clock.next_due() - changed_at, Duration::from_millis(500));
+}
+
+#[test]
+fn stalls_over_a_bar_drop_the_missed_ticks() {
+    // A bar at 120 BPM is 2 s, so half a bar behind still catches up
+    let mut clock = Clock::new(120, Instant::now() - Duration::from_secs(1));
+    clock.sleep_until_due();
+    assert!(clock.lateness() >= Duration::from_secs(1));
+
+    let mut clock = Clock::new(120, Instant::now() - Duration::from_secs(3));
+    clock.sleep_until_due();
+    assert!(clock.lateness() < Duration::from_millis(100), "the overdue ticks are dropped");
+    let resumed = clock.next_due();
+    clock.advance();
+    assert_eq!(clock.next_due() - resumed, Duration::from_micros(62_500));
+}